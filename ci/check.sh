@@ -10,7 +10,7 @@ cargo_audit() {
 
 clippy() {
   rustup component add clippy
-  cargo clippy --all-targets -- --deny warnings
+  cargo clippy --all-targets --all-features -- --deny warnings
 }
 
 build_release() {
@@ -29,7 +29,7 @@ test_release() {
       export DMENV_NO_VENV_STDLIB=1
       ;;
     esac
-    cargo test --release
+    cargo test --release --features testing
 }
 
 main() {
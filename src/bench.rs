@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+/// Statistics about the timings of one operation, for `dmenv bench`
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub median: Duration,
+}
+
+impl Stats {
+    /// Returns None if there are no timings
+    pub fn new(timings: &[Duration]) -> Option<Self> {
+        if timings.is_empty() {
+            return None;
+        }
+        let mut sorted = timings.to_vec();
+        sorted.sort();
+        let count = sorted.len();
+        let total: Duration = sorted.iter().sum();
+        let median = if count % 2 == 0 {
+            (sorted[count / 2 - 1] + sorted[count / 2]) / 2
+        } else {
+            sorted[count / 2]
+        };
+        Some(Stats {
+            min: sorted[0],
+            max: sorted[count - 1],
+            mean: total / count as u32,
+            median,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        format!(
+            "min {}  median {}  mean {}  max {}",
+            seconds(self.min),
+            seconds(self.median),
+            seconds(self.mean),
+            seconds(self.max)
+        )
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_millis() as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let timings: Vec<_> = [3, 1, 2, 10]
+            .iter()
+            .map(|x| Duration::from_secs(*x))
+            .collect();
+        let stats = Stats::new(&timings).unwrap();
+        assert_eq!(stats.min, Duration::from_secs(1));
+        assert_eq!(stats.max, Duration::from_secs(10));
+        assert_eq!(stats.mean, Duration::from_secs(4));
+        assert_eq!(stats.median, Duration::from_millis(2500));
+        assert_eq!(
+            stats.render(),
+            "min 1.00s  median 2.50s  mean 4.00s  max 10.00s"
+        );
+        assert!(Stats::new(&[]).is_none());
+    }
+}
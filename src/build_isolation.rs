@@ -0,0 +1,158 @@
+//! Control over the environment used to build packages from source (PEP 517).
+//!
+//! By default, pip builds each sdist in an isolated environment, with the
+//! latest versions of its build requirements. Heavy native packages often need
+//! a controlled build environment instead: `dmenv lock --no-build-isolation
+//! --build-requirement cython==0.29.36` installs the build requirements in the
+//! virtualenv first, and records their versions in the lock header, so that
+//! `dmenv install` does the same:
+//!
+//! ```text
+//! # Generated with dmenv 0.12.0, python 3.7.1, on Linux
+//! # Lock format: 2
+//! # Build isolation: off
+//! # Build requirements: cython==0.29.36 numpy==1.26.0
+//! ```
+use crate::dependencies::{canonical_name, FrozenDependency};
+use crate::error::Error;
+
+pub const ISOLATION_PREFIX: &str = "# Build isolation: ";
+pub const REQUIREMENTS_PREFIX: &str = "# Build requirements: ";
+
+#[derive(Debug, PartialEq)]
+pub struct BuildSettings {
+    pub isolation: bool,
+    /// Installed in the virtualenv before anything else
+    pub requirements: Vec<String>,
+}
+
+impl Default for BuildSettings {
+    fn default() -> Self {
+        BuildSettings {
+            isolation: true,
+            requirements: vec![],
+        }
+    }
+}
+
+impl BuildSettings {
+    /// Read the settings from the header of the lock
+    pub fn from_lock(contents: &str) -> Result<Self, Error> {
+        let mut res = BuildSettings::default();
+        let header = contents
+            .lines()
+            .enumerate()
+            .take_while(|(_, x)| x.starts_with('#'));
+        for (i, line) in header {
+            if let Some(value) = line.strip_prefix(ISOLATION_PREFIX) {
+                res.isolation = match value.trim() {
+                    "on" => true,
+                    "off" => false,
+                    other => {
+                        return Err(Error::MalformedLock {
+                            line: i + 1,
+                            details: format!("invalid build isolation: '{}'", other),
+                        })
+                    }
+                }
+            } else if let Some(value) = line.strip_prefix(REQUIREMENTS_PREFIX) {
+                res.requirements = value.split_whitespace().map(|x| x.to_string()).collect();
+            }
+        }
+        Ok(res)
+    }
+
+    /// Lines to write in the header of the lock. Nothing for the defaults, so
+    /// that most locks don't change
+    pub fn header(&self) -> String {
+        let mut res = String::new();
+        if !self.isolation {
+            res.push_str(&format!("{}off\n", ISOLATION_PREFIX));
+        }
+        if !self.requirements.is_empty() {
+            res.push_str(&format!(
+                "{}{}\n",
+                REQUIREMENTS_PREFIX,
+                self.requirements.join(" ")
+            ));
+        }
+        res
+    }
+
+    /// Replace the requirements by the versions installed in the virtualenv
+    pub fn pin(&mut self, frozen: &[FrozenDependency]) -> Result<(), Error> {
+        let mut res = vec![];
+        for requirement in &self.requirements {
+            let name = canonical_name(requirement_name(requirement));
+            let dep = frozen
+                .iter()
+                .find(|x| canonical_name(&x.name) == name)
+                .ok_or_else(|| Error::Other {
+                    message: format!(
+                        "build requirement '{}' is not installed in the virtualenv",
+                        requirement
+                    ),
+                })?;
+            res.push(format!("{}=={}", dep.name, dep.version));
+        }
+        res.sort_by_key(|x| x.to_lowercase());
+        self.requirements = res;
+        Ok(())
+    }
+}
+
+/// `numpy` for `numpy>=1.26`, or `numpy[extra]==1.26`
+fn requirement_name(requirement: &str) -> &str {
+    let end = requirement
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or(requirement.len());
+    &requirement[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let settings = BuildSettings {
+            isolation: false,
+            requirements: vec!["cython==0.29.36".to_string()],
+        };
+        let contents = format!("# Lock format: 2\n{}foo==0.42\n", settings.header());
+        assert_eq!(BuildSettings::from_lock(&contents).unwrap(), settings);
+
+        assert_eq!(BuildSettings::default().header(), "");
+        assert_eq!(
+            BuildSettings::from_lock("foo==0.42\n").unwrap(),
+            BuildSettings::default()
+        );
+        assert!(BuildSettings::from_lock("# Build isolation: maybe\n").is_err());
+    }
+
+    #[test]
+    fn test_pin() {
+        let mut settings = BuildSettings {
+            isolation: false,
+            requirements: vec!["numpy>=1.26".to_string(), "Cython".to_string()],
+        };
+        let frozen = vec![
+            FrozenDependency {
+                name: "Cython".to_string(),
+                version: "0.29.36".to_string(),
+            },
+            FrozenDependency {
+                name: "numpy".to_string(),
+                version: "1.26.0".to_string(),
+            },
+        ];
+        settings.pin(&frozen).unwrap();
+        assert_eq!(
+            settings.requirements,
+            vec!["Cython==0.29.36", "numpy==1.26.0"]
+        );
+
+        settings.requirements.push("scipy".to_string());
+        assert!(settings.pin(&frozen).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Batches of bumps, for bots that update many dependencies at once, like
+//! renovate. The instructions are read as a JSON array:
+//!
+//! ```text
+//! [
+//!   {"name": "attrs", "version": "23.2.0"},
+//!   {"name": "foo", "version": "v1.2", "git": true},
+//!   {"name": "idna", "version": "3.6", "marker": "python_version >= '3.8'", "override_pin": true}
+//! ]
+//! ```
+//!
+//! The bumps are applied all together: if one of them fails, the lock is
+//! left untouched. There is a JSON result for each instruction:
+//!
+//! ```text
+//! {"name": "attrs", "status": "bumped"}
+//! {"name": "foo", "status": "up_to_date"}
+//! {"name": "idna", "status": "error", "message": "'idna' not found in lock"}
+//! ```
+use crate::error::Error;
+use crate::json;
+use crate::lock::Lock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub name: String,
+    pub version: String,
+    pub git: bool,
+    pub marker: Option<String>,
+    pub override_pin: bool,
+}
+
+impl Instruction {
+    fn from_json(value: &json::Value) -> Result<Self, String> {
+        let string = |key: &str| match value.get(key) {
+            None | Some(json::Value::Null) => Ok(None),
+            Some(x) => x
+                .as_str()
+                .map(|x| Some(x.to_string()))
+                .ok_or_else(|| format!("'{}' should be a string", key)),
+        };
+        let flag = |key: &str| match value.get(key) {
+            None | Some(json::Value::Null) => Ok(false),
+            Some(x) => x
+                .as_bool()
+                .ok_or_else(|| format!("'{}' should be a boolean", key)),
+        };
+        if !matches!(value, json::Value::Object(_)) {
+            return Err("expected an object".to_string());
+        }
+        Ok(Instruction {
+            name: string("name")?.ok_or("missing 'name'")?,
+            version: string("version")?.ok_or("missing 'version'")?,
+            git: flag("git")?,
+            marker: string("marker")?,
+            override_pin: flag("override_pin")?,
+        })
+    }
+}
+
+/// Parse the JSON array of instructions
+pub fn parse(text: &str) -> Result<Vec<Instruction>, Error> {
+    let invalid = |message: String| Error::Other {
+        message: format!("Invalid bump instructions: {}", message),
+    };
+    let items = match json::parse(text).map_err(invalid)? {
+        json::Value::Array(items) => items,
+        _ => return Err(invalid("expected an array".to_string())),
+    };
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, x)| Instruction::from_json(x).map_err(|e| invalid(format!("item {}: {}", i, e))))
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Bumped,
+    UpToDate,
+    Error(String),
+    /// Would have been applied, but another bump of the batch failed
+    Aborted,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BumpResult {
+    pub name: String,
+    pub status: Status,
+}
+
+impl BumpResult {
+    pub fn to_json(&self) -> String {
+        let status = match self.status {
+            Status::Bumped => "bumped",
+            Status::UpToDate => "up_to_date",
+            Status::Error(_) => "error",
+            Status::Aborted => "aborted",
+        };
+        let mut res = format!(
+            "{{\"name\": {}, \"status\": {}",
+            json::string(&self.name),
+            json::string(status)
+        );
+        if let Status::Error(message) = &self.status {
+            res.push_str(&format!(", \"message\": {}", json::string(message)));
+        }
+        res.push('}');
+        res
+    }
+}
+
+/// Apply every instruction to the lock. Returns true if the lock should be
+/// saved: nothing failed and something changed
+pub fn apply(lock: &mut Lock, instructions: &[Instruction]) -> (bool, Vec<BumpResult>) {
+    let mut results = vec![];
+    for instruction in instructions {
+        let name = &instruction.name;
+        let marker = instruction.marker.as_deref();
+        lock.set_override_pins(instruction.override_pin);
+        let res = if instruction.git {
+            lock.git_bump(name, &instruction.version, marker)
+        } else {
+            lock.bump(name, &instruction.version, marker)
+        };
+        let status = match res {
+            Ok(true) => Status::Bumped,
+            Ok(false) => Status::UpToDate,
+            Err(e) => Status::Error(e.to_string()),
+        };
+        results.push(BumpResult {
+            name: name.to_string(),
+            status,
+        });
+    }
+    lock.set_override_pins(false);
+    let failed = results.iter().any(|x| matches!(x.status, Status::Error(_)));
+    if failed {
+        for result in results.iter_mut() {
+            if result.status == Status::Bumped {
+                result.status = Status::Aborted;
+            }
+        }
+    }
+    let changed = results.iter().any(|x| x.status == Status::Bumped);
+    (changed, results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = "\
+attrs==19.1.0
+foo==0.42  # pin: see #42
+git+https://gitlab.local/bar@v1.0#egg=bar
+";
+
+    fn instruction(name: &str, version: &str) -> Instruction {
+        Instruction {
+            name: name.to_string(),
+            version: version.to_string(),
+            git: false,
+            marker: None,
+            override_pin: false,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let instructions = parse(
+            r#"[{"name": "attrs", "version": "23.2.0"},
+                {"name": "bar", "version": "v1.1", "git": true, "marker": null}]"#,
+        )
+        .unwrap();
+        assert_eq!(instructions[0], instruction("attrs", "23.2.0"));
+        assert!(instructions[1].git);
+
+        let err = parse(r#"[{"name": "attrs"}]"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid bump instructions: item 0: missing 'version'"
+        );
+        assert!(parse(r#"{"name": "attrs", "version": "1.0"}"#).is_err());
+        assert!(parse(r#"[{"name": "attrs", "version": "1.0", "git": "yes"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut lock = Lock::from_string(LOCK).unwrap();
+        let mut bar = instruction("bar", "v1.1");
+        bar.git = true;
+        let mut foo = instruction("foo", "0.43");
+        foo.override_pin = true;
+        let (changed, results) = apply(&mut lock, &[instruction("attrs", "19.1.0"), bar, foo]);
+        assert!(changed);
+        let statuses: Vec<_> = results.iter().map(|x| &x.status).collect();
+        assert_eq!(
+            statuses,
+            vec![&Status::UpToDate, &Status::Bumped, &Status::Bumped]
+        );
+        assert!(lock.to_string().contains("foo==0.43"));
+    }
+
+    #[test]
+    fn test_apply_aborts_on_error() {
+        let mut lock = Lock::from_string(LOCK).unwrap();
+        let (changed, results) = apply(
+            &mut lock,
+            &[
+                instruction("attrs", "23.2.0"),
+                instruction("foo", "0.43"),
+                instruction("nope", "1.0"),
+            ],
+        );
+        assert!(!changed);
+        assert_eq!(results[0].status, Status::Aborted);
+        assert_eq!(
+            results[2].to_json(),
+            r#"{"name": "nope", "status": "error", "message": "'nope' not found in lock"}"#
+        );
+        match &results[1].status {
+            Status::Error(message) => assert!(message.contains("pinned")),
+            status => panic!("unexpected status: {:?}", status),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// Formats supported by `dmenv bundle`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BundleFormat {
+    /// A single `.pyz` file, runnable with `python foo.pyz`
+    /// (see https://docs.python.org/3/library/zipapp.html)
+    Zipapp,
+    /// A zip file for AWS Lambda, optionally with the dependencies
+    /// in a separate layer
+    Lambda,
+}
+
+impl std::str::FromStr for BundleFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zipapp" => Ok(BundleFormat::Zipapp),
+            "lambda" => Ok(BundleFormat::Lambda),
+            _ => Err(Error::Other {
+                message: format!(
+                    "unknown bundle format '{}', expected 'zipapp' or 'lambda'",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+impl BundleFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            BundleFormat::Zipapp => "pyz",
+            BundleFormat::Lambda => "zip",
+        }
+    }
+}
+
+/// Path of the layer archive, next to the function archive:
+/// `foo.zip` -> `foo-layer.zip`
+pub fn layer_path(function_path: &Path) -> PathBuf {
+    let stem = function_path
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    function_path.with_file_name(format!("{}-layer.zip", stem))
+}
+
+/// Convert a full Python version (3.7.1) to the X.Y form
+/// expected by `pip --python-version`
+pub fn short_version(version: &str) -> String {
+    version.split('.').take(2).collect::<Vec<_>>().join(".")
+}
+
+/// Return all the files in `dir` (recursively)
+pub fn walk_files(dir: &Path) -> Vec<PathBuf> {
+    let mut res = vec![];
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return res,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            res.extend(walk_files(&path));
+        } else {
+            res.push(path);
+        }
+    }
+    res
+}
+
+/// Return the compiled extension modules found in `dir`
+// Note: those can't be imported from a zip file
+pub fn find_extension_modules(dir: &Path) -> Vec<PathBuf> {
+    walk_files(dir)
+        .into_iter()
+        .filter(|p| is_extension_module(p))
+        .collect()
+}
+
+fn is_extension_module(path: &Path) -> bool {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("so") | Some("pyd") | Some("dylib") => true,
+        _ => false,
+    }
+}
+
+/// Make sure the entry point looks like `package.module:function`,
+/// as expected by `python -m zipapp`
+pub fn check_entry_point(entry_point: &str) -> Result<(), Error> {
+    let parts: Vec<_> = entry_point.split(':').collect();
+    if parts.len() != 2 || parts.iter().any(|x| x.trim().is_empty()) {
+        return Err(Error::Other {
+            message: format!(
+                "invalid entry point '{}': should look like `package.module:function`",
+                entry_point
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_extension_module() {
+        assert!(is_extension_module(Path::new(
+            "foo/_speedups.cpython-37m.so"
+        )));
+        assert!(is_extension_module(Path::new("foo/_speedups.pyd")));
+        assert!(!is_extension_module(Path::new("foo/__init__.py")));
+    }
+
+    #[test]
+    fn test_layer_path() {
+        assert_eq!(
+            layer_path(Path::new("/path/to/demo.zip")),
+            Path::new("/path/to/demo-layer.zip")
+        );
+    }
+
+    #[test]
+    fn test_short_version() {
+        assert_eq!(short_version("3.7.1"), "3.7");
+        assert_eq!(short_version("3.10"), "3.10");
+    }
+
+    #[test]
+    fn test_check_entry_point() {
+        check_entry_point("demo.main:main").unwrap();
+        check_entry_point("demo").unwrap_err();
+        check_entry_point("demo:").unwrap_err();
+    }
+}
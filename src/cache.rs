@@ -0,0 +1,53 @@
+use crate::hash;
+use crate::settings::Settings;
+use crate::state;
+
+/// Compute a key identifying the contents of a virtualenv, for use as a CI cache key.
+///
+/// The key changes when the lock contents, the Python version, the platform or
+/// the settings change, and looks like: `dmenv-linux-py3.7.1-9f3c6a82b1e04d57`
+pub fn key(
+    lock_contents: &[u8],
+    python_version: &str,
+    platform: &str,
+    settings: &Settings,
+) -> String {
+    let mut data = lock_contents.to_vec();
+    data.extend(format!("\npython_version = {}\n", python_version).as_bytes());
+    data.extend(format!("platform = {}\n", platform).as_bytes());
+    for (key, value) in state::settings_snapshot(settings) {
+        data.extend(format!("settings.{} = {}\n", key, value).as_bytes());
+    }
+    format!(
+        "dmenv-{}-py{}-{}",
+        platform,
+        python_version,
+        hash::hash_hex(&data)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_is_stable() {
+        let settings = Settings::default();
+        let actual = key(b"foo==0.42\n", "3.7.1", "linux", &settings);
+        assert!(actual.starts_with("dmenv-linux-py3.7.1-"));
+        assert_eq!(actual, key(b"foo==0.42\n", "3.7.1", "linux", &settings));
+    }
+
+    #[test]
+    fn test_key_changes() {
+        let settings = Settings::default();
+        let production = Settings {
+            production: true,
+            ..Default::default()
+        };
+        let actual = key(b"foo==0.42\n", "3.7.1", "linux", &settings);
+        assert_ne!(actual, key(b"foo==0.43\n", "3.7.1", "linux", &settings));
+        assert_ne!(actual, key(b"foo==0.42\n", "3.7.2", "linux", &settings));
+        assert_ne!(actual, key(b"foo==0.42\n", "3.7.1", "linux", &production));
+    }
+}
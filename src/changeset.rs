@@ -0,0 +1,418 @@
+//! Machine-readable description of what a command changed in the lock.
+//!
+//! When `DMENV_CHANGESET` is set, `dmenv lock`, `dmenv bump-in-lock` and
+//! `dmenv pin` write `<lock>.changes.json` next to the lock, for tools
+//! like bots opening pull requests or generating changelogs:
+//!
+//! ```json
+//! {"lock": "requirements.lock", "command": "dmenv bump-in-lock attrs 19.3.0",
+//!  "after_hash": "...", "changes": [
+//!   {"package": "attrs", "marker": null, "old": "19.1.0", "new": "19.3.0",
+//!    "direct": true, "reason": "requested",
+//!    "old_line": "attrs==19.1.0", "new_line": "attrs==19.3.0"}]}
+//! ```
+//!
+//! `old` is null for added packages, and `new` for removed ones. `direct`
+//! is null when dmenv could not find the declared dependencies of the project.
+//! `dmenv undo` uses the lines to revert only these changes when the lock
+//! was edited after the command.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::dependencies::{canonical_name, LockedDependency};
+use crate::error::Error;
+use crate::hash;
+use crate::json::{self, Value};
+use crate::lock::{split_trailing_comment, Lock};
+
+/// Where the changeset of the last command is written: next to the lock
+pub fn path(lock: &Path) -> PathBuf {
+    let name = lock.file_name().unwrap_or_default().to_string_lossy();
+    lock.with_file_name(format!("{}.changes.json", name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// The package was named on the command line, as in `dmenv bump-in-lock foo 1.2`
+    Requested,
+    /// The change comes from pip resolving the dependencies again
+    Resolved,
+}
+
+impl Reason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Reason::Requested => "requested",
+            Reason::Resolved => "resolved",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub package: String,
+    pub marker: Option<String>,
+    /// Version, or git reference. None if the package was added
+    pub old: Option<String>,
+    /// None if the package was removed
+    pub new: Option<String>,
+    pub old_line: Option<String>,
+    pub new_line: Option<String>,
+    pub direct: Option<bool>,
+    pub reason: Reason,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Changeset {
+    pub lock_name: String,
+    pub args: String,
+    /// Hash of the lock written by the command, like `lock_backup::Backup::after_hash`
+    pub after_hash: String,
+    pub changes: Vec<Change>,
+}
+
+// Version (or git reference) and line of each dependency, indexed by
+// canonical name and marker
+fn entries(lock: &Lock) -> BTreeMap<(String, Option<String>), (String, String)> {
+    let mut res = BTreeMap::new();
+    for dep in lock.dependencies() {
+        let value = match dep {
+            LockedDependency::Simple(x) => x.version.value.to_string(),
+            LockedDependency::Git(x) => x.git_ref.value.to_string(),
+            LockedDependency::Opaque(_) => continue,
+        };
+        let key = (canonical_name(&dep.name()), dep.marker());
+        res.insert(key, (value, dep.line()));
+    }
+    res
+}
+
+/// Changes from the `before` lock to the `after` one, sorted by package.
+/// `requested` are the packages named on the command line, and `direct`
+/// the declared dependencies of the project, if known
+pub fn diff(
+    before: &str,
+    after: &str,
+    requested: &[String],
+    direct: Option<&[String]>,
+) -> Result<Vec<Change>, Error> {
+    let before = entries(&Lock::from_string(before)?);
+    let after = entries(&Lock::from_string(after)?);
+    let requested: Vec<_> = requested.iter().map(|x| canonical_name(x)).collect();
+    let direct: Option<Vec<_>> = direct.map(|x| x.iter().map(|x| canonical_name(x)).collect());
+    let mut keys: Vec<_> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let mut res = vec![];
+    for key in keys {
+        let old = before.get(key);
+        let new = after.get(key);
+        if old.map(|x| &x.1) == new.map(|x| &x.1) {
+            continue;
+        }
+        let (name, marker) = key;
+        res.push(Change {
+            package: name.to_string(),
+            marker: marker.clone(),
+            old: old.map(|x| x.0.to_string()),
+            new: new.map(|x| x.0.to_string()),
+            old_line: old.map(|x| x.1.to_string()),
+            new_line: new.map(|x| x.1.to_string()),
+            direct: direct.as_ref().map(|x| x.contains(name)),
+            reason: if requested.contains(name) {
+                Reason::Requested
+            } else {
+                Reason::Resolved
+            },
+        });
+    }
+    Ok(res)
+}
+
+/// Names of the requirements in a `requirements.in` file, or in the
+/// `requires.txt` file of an `.egg-info` directory
+pub fn requirement_names(contents: &str) -> Vec<String> {
+    let mut res = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+        // Note: also skips pip options and the `[extra]` sections of requires.txt
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with('-')
+            || line.starts_with('[')
+        {
+            continue;
+        }
+        let end = line
+            .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+            .unwrap_or(line.len());
+        if end > 0 {
+            res.push(line[..end].to_string());
+        }
+    }
+    res
+}
+
+fn optional(value: &Option<String>) -> String {
+    match value {
+        Some(value) => json::string(value),
+        None => "null".to_string(),
+    }
+}
+
+impl Changeset {
+    pub fn new(lock_name: &str, args: &str, after: &str, changes: Vec<Change>) -> Self {
+        Changeset {
+            lock_name: lock_name.to_string(),
+            args: args.to_string(),
+            after_hash: hash::hash_hex(after.as_bytes()),
+            changes,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let changes: Vec<_> = self
+            .changes
+            .iter()
+            .map(|x| {
+                format!(
+                    "    {{\"package\": {}, \"marker\": {}, \"old\": {}, \"new\": {}, \
+                     \"direct\": {}, \"reason\": {}, \"old_line\": {}, \"new_line\": {}}}",
+                    json::string(&x.package),
+                    optional(&x.marker),
+                    optional(&x.old),
+                    optional(&x.new),
+                    x.direct
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "null".to_string()),
+                    json::string(x.reason.as_str()),
+                    optional(&x.old_line),
+                    optional(&x.new_line),
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"lock\": {},\n  \"command\": {},\n  \"after_hash\": {},\n  \"changes\": [{}{}{}]\n}}\n",
+            json::string(&self.lock_name),
+            json::string(&self.args),
+            json::string(&self.after_hash),
+            if changes.is_empty() { "" } else { "\n" },
+            changes.join(",\n"),
+            if changes.is_empty() { "" } else { "\n  " },
+        )
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let value = json::parse(text)?;
+        let string = |value: &Value, key: &str| -> Result<String, String> {
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .map(|x| x.to_string())
+                .ok_or_else(|| format!("missing string: {}", key))
+        };
+        let optional = |value: &Value, key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .map(|x| x.to_string())
+        };
+        let items = match value.get("changes") {
+            Some(Value::Array(items)) => items,
+            _ => return Err("missing array: changes".to_string()),
+        };
+        let mut changes = vec![];
+        for item in items {
+            changes.push(Change {
+                package: string(item, "package")?,
+                marker: optional(item, "marker"),
+                old: optional(item, "old"),
+                new: optional(item, "new"),
+                old_line: optional(item, "old_line"),
+                new_line: optional(item, "new_line"),
+                direct: item.get("direct").and_then(Value::as_bool),
+                reason: match string(item, "reason")?.as_str() {
+                    "requested" => Reason::Requested,
+                    _ => Reason::Resolved,
+                },
+            });
+        }
+        Ok(Changeset {
+            lock_name: string(&value, "lock")?,
+            args: string(&value, "command")?,
+            after_hash: string(&value, "after_hash")?,
+            changes,
+        })
+    }
+
+    /// Revert the changes in `current`, keeping everything else as is.
+    /// None if a line written by the command is no longer in the lock
+    pub fn revert(&self, current: &str) -> Option<String> {
+        let newline = if current.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut lines: Vec<String> = current.split('\n').map(|x| x.to_string()).collect();
+        let mut removed = vec![];
+        for change in &self.changes {
+            let new_line = match &change.new_line {
+                Some(new_line) => new_line,
+                None => {
+                    removed.extend(change.old_line.clone());
+                    continue;
+                }
+            };
+            let index = lines
+                .iter()
+                .position(|x| split_trailing_comment(x.trim()).0 == new_line)?;
+            match &change.old_line {
+                Some(old_line) => {
+                    let line = &lines[index];
+                    let start = line.find(new_line.as_str())?;
+                    lines[index] = format!(
+                        "{}{}{}",
+                        &line[..start],
+                        old_line,
+                        &line[start + new_line.len()..]
+                    );
+                }
+                None => {
+                    lines.remove(index);
+                }
+            }
+        }
+        let mut res = lines.join("\n");
+        for line in removed {
+            if !res.is_empty() && !res.ends_with('\n') {
+                res.push_str(newline);
+            }
+            res.push_str(&line);
+            res.push_str(newline);
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = "\
+attrs==19.1.0
+django==2.2.0 ; python_version < '3.8'
+six==1.12.0
+git+https://github.com/acme/foo@0.41#egg=foo
+";
+
+    const AFTER: &str = "\
+attrs==19.3.0 # allow-old: needed by the legacy API
+django==2.2.0 ; python_version < '3.8'
+git+https://github.com/acme/foo@0.42#egg=foo
+pluggy==0.13.1
+";
+
+    fn changes() -> Vec<Change> {
+        let direct = vec!["attrs".to_string(), "foo".to_string()];
+        diff(BEFORE, AFTER, &["Attrs".to_string()], Some(&direct)).unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let changes = changes();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|x| {
+                (
+                    x.package.as_str(),
+                    x.old.as_deref(),
+                    x.new.as_deref(),
+                    x.direct,
+                    x.reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "attrs",
+                    Some("19.1.0"),
+                    Some("19.3.0"),
+                    Some(true),
+                    Reason::Requested
+                ),
+                (
+                    "foo",
+                    Some("0.41"),
+                    Some("0.42"),
+                    Some(true),
+                    Reason::Resolved
+                ),
+                (
+                    "pluggy",
+                    None,
+                    Some("0.13.1"),
+                    Some(false),
+                    Reason::Resolved
+                ),
+                ("six", Some("1.12.0"), None, Some(false), Reason::Resolved),
+            ]
+        );
+        assert_eq!(changes[0].new_line, Some("attrs==19.3.0".to_string()));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let changeset = Changeset::new(
+            "requirements.lock",
+            "dmenv bump-in-lock attrs \"19.3.0\"",
+            AFTER,
+            changes(),
+        );
+        let text = changeset.to_json();
+        assert_eq!(Changeset::from_json(&text).unwrap(), changeset);
+        let empty = Changeset::new("requirements.lock", "dmenv lock", AFTER, vec![]);
+        assert!(empty.to_json().contains("\"changes\": []"));
+        assert_eq!(Changeset::from_json(&empty.to_json()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_revert_keeps_later_edits() {
+        let changeset = Changeset::new("requirements.lock", "dmenv lock", AFTER, changes());
+        let edited = AFTER.replace("django==2.2.0", "django==2.2.1");
+        assert_eq!(
+            changeset.revert(&edited).unwrap(),
+            "\
+attrs==19.1.0 # allow-old: needed by the legacy API
+django==2.2.1 ; python_version < '3.8'
+git+https://github.com/acme/foo@0.41#egg=foo
+six==1.12.0
+"
+        );
+    }
+
+    #[test]
+    fn test_revert_line_changed_since() {
+        let changeset = Changeset::new("requirements.lock", "dmenv lock", AFTER, changes());
+        let edited = AFTER.replace("pluggy==0.13.1", "pluggy==0.13.2");
+        assert_eq!(changeset.revert(&edited), None);
+    }
+
+    #[test]
+    fn test_requirement_names() {
+        let contents = "\
+# comment
+requests>=2.0
+-r base.in
+Django[bcrypt] ; python_version >= '3.6'
+
+[dev]
+pytest
+";
+        assert_eq!(
+            requirement_names(contents),
+            vec!["requests", "Django", "pytest"]
+        );
+    }
+}
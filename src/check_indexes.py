@@ -0,0 +1,37 @@
+"""Check that package indexes can be reached, with HEAD requests.
+
+Usage: python -c <this script> <url> ...
+
+Print `ok <url>` or `error <url> <message>` for each URL. Any HTTP response,
+even an error like 401 or 404, means the index can be reached.
+"""
+import sys
+import urllib.error
+import urllib.request
+
+TIMEOUT = 5
+
+
+def check(url):
+    request = urllib.request.Request(url, method="HEAD")
+    try:
+        urllib.request.urlopen(request, timeout=TIMEOUT)
+    except urllib.error.HTTPError:
+        pass
+    except Exception as e:
+        # URLError has a `reason`, timeouts don't
+        return str(getattr(e, "reason", e))
+    return None
+
+
+def main():
+    for url in sys.argv[1:]:
+        error = check(url)
+        if error:
+            print("error", url, " ".join(error.split()))
+        else:
+            print("ok", url)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::template::{self, Context};
+
+/// Whether dmenv runs inside a GitHub Actions workflow
+pub fn in_github_actions() -> bool {
+    std::env::var("GITHUB_ACTIONS")
+        .map(|x| x == "true")
+        .unwrap_or(false)
+}
+
+/// Format a workflow command, so that errors and warnings
+/// appear inline on pull requests
+//
+// Note: messages must fit in one line, see
+// https://help.github.com/en/actions/reference/workflow-commands-for-github-actions
+pub fn github_annotation(level: &str, message: &str) -> String {
+    let message = message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    format!("::{}::{}", level, message)
+}
+
+/// Everything needed to cache the virtualenv in a GitHub Actions workflow
+pub struct GithubWorkflow<'a> {
+    pub python_version: &'a str,
+    /// Path of the virtualenv, relative to the project if possible
+    pub venv: &'a str,
+    pub lock: &'a str,
+    pub cache_key: &'a str,
+}
+
+impl<'a> GithubWorkflow<'a> {
+    /// Steps to paste in the `steps:` section of a job
+    pub fn render_steps(&self, templates: &[PathBuf]) -> Result<String, Error> {
+        let context = Context::new()
+            .text("python_version", self.python_version)
+            .text("venv", self.venv)
+            .text("lock", self.lock)
+            .text("cache_key", self.cache_key);
+        template::render("github-steps.yml", templates, &context)
+    }
+
+    /// Output parameters, for use in composite actions
+    pub fn render_outputs(&self) -> String {
+        format!(
+            "::set-output name=cache-key::{}\n::set-output name=venv-path::{}\n",
+            self.cache_key, self.venv
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow() -> GithubWorkflow<'static> {
+        GithubWorkflow {
+            python_version: "3.7",
+            venv: ".venv/dev/3.7.1",
+            lock: "requirements.lock",
+            cache_key: "dmenv-linux-py3.7.1-0123456789abcdef",
+        }
+    }
+
+    #[test]
+    fn test_github_annotation() {
+        let actual = github_annotation("error", "lock is out of date\n 100% sure");
+        assert_eq!(actual, "::error::lock is out of date%0A 100%25 sure");
+    }
+
+    #[test]
+    fn test_render_steps() {
+        let actual = workflow().render_steps(&[]).unwrap();
+        assert!(actual.contains("python-version: \"3.7\"\n"));
+        assert!(actual.contains("path: .venv/dev/3.7.1\n"));
+        assert!(actual.contains("key: dmenv-linux-py3.7.1-0123456789abcdef\n"));
+    }
+
+    #[test]
+    fn test_render_outputs() {
+        let actual = workflow().render_outputs();
+        assert!(actual.contains("::set-output name=venv-path::.venv/dev/3.7.1\n"));
+    }
+}
@@ -2,7 +2,17 @@ use colored::*;
 use regex::Regex;
 use structopt::StructOpt;
 
+use crate::bundle::BundleFormat;
+use crate::ci;
+use crate::env_file::EnvFormat;
 use crate::error::Error;
+use crate::events::{self, Event};
+use crate::indexes::Index;
+use crate::lock_hashes::Algorithm;
+use crate::migrate::Deprecation;
+use crate::project_version::VersionBump;
+use crate::service::ServiceFormat;
+use crate::venv_manager::Installer;
 
 #[derive(StructOpt)]
 #[structopt(
@@ -16,6 +26,19 @@ pub struct Command {
     #[structopt(long = "project", help = "path to use as the project directory")]
     pub project_path: Option<String>,
 
+    #[structopt(
+        long = "package-dir",
+        help = "directory containing setup.py, relative to the project"
+    )]
+    pub package_dir: Option<String>,
+
+    #[structopt(
+        long = "extra-package-dir",
+        help = "other directory containing a setup.py to install in development mode (may be repeated)",
+        raw(number_of_values = "1")
+    )]
+    pub extra_package_dirs: Vec<String>,
+
     #[structopt(
         long = "--system-site-packages",
         help = "Give the virtual environment access to the system site-packages dir"
@@ -25,48 +48,345 @@ pub struct Command {
     #[structopt(long = "production", help = "Ignore dev dependencies")]
     pub production: bool,
 
+    #[structopt(
+        long = "strict",
+        help = "Reject lines of the lock dmenv does not understand instead of keeping them as is"
+    )]
+    pub strict: bool,
+
+    #[structopt(
+        long = "quiet",
+        help = "Write the output of pip to a log in the virtualenv instead of the terminal"
+    )]
+    pub quiet: bool,
+
+    #[structopt(
+        long = "ascii",
+        help = "Plain ASCII output, without colors nor progress bars"
+    )]
+    pub ascii: bool,
+
+    #[structopt(
+        long = "event-stream",
+        help = "Write JSON events to 'stdout', 'fd:<n>' or a file, for frontends"
+    )]
+    pub event_stream: Option<String>,
+
+    #[structopt(
+        long = "index-url",
+        help = "Use this package index for every package instead of the configured ones",
+        parse(try_from_str = "parse_index_url")
+    )]
+    pub index: Option<Index>,
+
+    #[structopt(
+        long = "find-links",
+        help = "Also look for packages in this directory, URL, or s3:// or gs:// bucket (may be repeated)",
+        raw(number_of_values = "1")
+    )]
+    pub find_links: Vec<String>,
+
+    #[structopt(
+        long = "variant",
+        help = "Use the package indexes of this variant, from the [variants] table of dmenv.toml"
+    )]
+    pub variant: Option<String>,
+
     #[structopt(subcommand)]
     pub sub_cmd: SubCommand,
 }
 
 #[derive(StructOpt)]
 pub enum SubCommand {
+    #[structopt(
+        name = "bench",
+        about = "Measure how long install and lock take, with and without caches"
+    )]
+    Bench {
+        #[structopt(subcommand)]
+        bench_cmd: BenchCommand,
+    },
+
+    #[structopt(
+        name = "bundle",
+        about = "Bundle the project and its dependencies into a single file"
+    )]
+    Bundle {
+        #[structopt(
+            long = "format",
+            help = "'zipapp' or 'lambda'",
+            default_value = "zipapp"
+        )]
+        format: BundleFormat,
+
+        #[structopt(
+            long = "entry-point",
+            help = "Function to run, like `package.module:function` (zipapp only)"
+        )]
+        entry_point: Option<String>,
+
+        #[structopt(long = "output", help = "Path of the bundle, relative to the project")]
+        output: Option<String>,
+
+        #[structopt(
+            long = "platform",
+            help = "Platform of the binary wheels to use (lambda only)",
+            default_value = "manylinux2014_x86_64"
+        )]
+        platform: String,
+
+        #[structopt(
+            long = "layer",
+            help = "Put dependencies in a separate layer (lambda only)"
+        )]
+        layer: bool,
+    },
+
+    #[structopt(name = "cache", about = "Cache virtualenvs on CI")]
+    Cache {
+        #[structopt(subcommand)]
+        cache_cmd: CacheCommand,
+    },
+
+    #[structopt(name = "ci", about = "Integrate dmenv with CI services")]
+    Ci {
+        #[structopt(subcommand)]
+        ci_cmd: CiCommand,
+    },
+
     #[structopt(name = "clean", about = "Clean existing virtualenv")]
     Clean {},
 
     #[structopt(name = "develop", about = "Run setup.py develop")]
-    Develop {},
+    Develop {
+        #[structopt(
+            long = "uninstall",
+            help = "Remove the project from the virtualenv instead"
+        )]
+        uninstall: bool,
+    },
+
+    #[structopt(
+        name = "doctor",
+        about = "Check the Python interpreter, the virtualenv, the lock and the package indexes"
+    )]
+    Doctor {},
+
+    #[structopt(
+        name = "explain-exit-codes",
+        about = "Show what each exit code of dmenv means"
+    )]
+    ExplainExitCodes {},
+
+    #[structopt(
+        name = "explain-lock-line",
+        about = "Show how a line of the lock is parsed, and what dmenv does with it"
+    )]
+    ExplainLockLine {
+        #[structopt(help = "line number, starting at 1")]
+        number: usize,
+    },
+
+    #[structopt(
+        name = "env",
+        about = "Show the environment variables needed to use the virtualenv without dmenv"
+    )]
+    Env {
+        #[structopt(
+            long = "output",
+            help = "Write to this file (relative to the project) instead of stdout"
+        )]
+        output: Option<String>,
+
+        #[structopt(
+            long = "format",
+            help = "'docker' (for `docker run --env-file`) or 'shell'",
+            default_value = "docker"
+        )]
+        format: EnvFormat,
+
+        #[structopt(subcommand)]
+        env_cmd: Option<EnvCommand>,
+    },
+
+    #[structopt(
+        name = "fmt",
+        about = "Run the formatter of the project (black by default) in the virtualenv"
+    )]
+    Fmt {
+        #[structopt(long = "check", help = "Fail instead of changing the files")]
+        check: bool,
+    },
+
+    #[structopt(
+        name = "foreach",
+        about = "Run a dmenv command in every project under a directory"
+    )]
+    Foreach {
+        #[structopt(
+            long = "root",
+            help = "Where to look for projects (default: the current directory)"
+        )]
+        root: Option<String>,
+
+        #[structopt(long = "list", help = "Only list the projects found")]
+        list: bool,
+
+        #[structopt(
+            long = "fail-fast",
+            help = "Stop at the first project where the command fails"
+        )]
+        fail_fast: bool,
+
+        #[structopt(
+            long = "report",
+            help = "Write a JSON report about each project (HTML if the path ends with .html)"
+        )]
+        report: Option<String>,
+
+        #[structopt(name = "command")]
+        cmd: Vec<String>,
+    },
 
     #[structopt(name = "install", about = "Install all dependencies")]
     Install {
         #[structopt(long = "--no-develop", help = "Do not run setup.py develop")]
         no_develop: bool,
+
+        #[structopt(
+            long = "installer",
+            help = "How to install dependencies: 'pip' or 'native' (experimental)",
+            default_value = "pip"
+        )]
+        installer: Installer,
+
+        #[structopt(
+            long = "allow-foreign-venv",
+            help = "Use the active virtualenv even if it does not belong to the project"
+        )]
+        allow_foreign_venv: bool,
+
+        #[structopt(
+            long = "no-build-isolation",
+            help = "Build packages in the virtualenv, even if the lock does not say so"
+        )]
+        no_build_isolation: bool,
+
+        #[structopt(
+            long = "extra",
+            help = "Also install the lock of this extra, see `dmenv lock --extra` (may be repeated)"
+        )]
+        extras: Vec<String>,
+
+        #[structopt(
+            long = "enforce-policy",
+            help = "Fail if locked releases are older than allowed by `max_release_age`"
+        )]
+        enforce_policy: bool,
+
+        #[structopt(
+            long = "check-wheels",
+            help = "Warn up front about locked packages without a wheel for this interpreter"
+        )]
+        check_wheels: bool,
     },
 
     #[structopt(name = "bump-in-lock", about = "Bump a dependency in the lock file")]
     BumpInLock {
         #[structopt(help = "name")]
-        name: String,
+        name: Option<String>,
 
         #[structopt(long = "--git")]
         git: bool,
 
         #[structopt(help = "version")]
-        version: String,
+        version: Option<String>,
+
+        #[structopt(
+            long = "marker",
+            help = "Only bump the entry with this environment marker"
+        )]
+        marker: Option<String>,
+
+        #[structopt(
+            long = "override-pin",
+            help = "Bump the dependency even if it is pinned"
+        )]
+        override_pin: bool,
+
+        #[structopt(
+            long = "stdin-json",
+            help = "Read a JSON array of bumps from stdin, apply them all or none, and print a JSON result for each"
+        )]
+        stdin_json: bool,
+    },
+
+    #[structopt(name = "hash", about = "Manage the hashes of the archives in the lock")]
+    Hash {
+        #[structopt(subcommand)]
+        hash_cmd: HashCommand,
+    },
+
+    #[structopt(
+        name = "history",
+        about = "Show the commands that changed the lock or the virtualenv"
+    )]
+    History {},
+
+    #[structopt(
+        name = "info",
+        about = "Show paths and Python details, as key=value lines, for scripts"
+    )]
+    Info {
+        #[structopt(long = "paths", help = "Only show the paths")]
+        paths: bool,
     },
 
     #[structopt(name = "init", about = "Initialize a new project")]
     Init {
-        #[structopt(help = "Project name")]
-        name: String,
+        #[structopt(help = "Project name (default: name of the project directory)")]
+        name: Option<String>,
 
-        #[structopt(long = "version", help = "Project version", default_value = "0.1.0")]
-        version: String,
+        #[structopt(
+            long = "version",
+            help = "Project version (default: latest git tag, or 0.1.0)"
+        )]
+        version: Option<String>,
 
-        #[structopt(long = "author", help = "Author name")]
+        #[structopt(long = "author", help = "Author name (default: from git config)")]
         author: Option<String>,
+
+        #[structopt(
+            long = "existing",
+            help = "Adopt a project with an existing setup.py: create the virtualenv and the lock"
+        )]
+        existing: bool,
+
+        #[structopt(
+            long = "interactive",
+            help = "Ask questions about the project to generate its files"
+        )]
+        interactive: bool,
+
+        #[structopt(
+            long = "yes",
+            help = "Do not ask for confirmation (before changing setup.py, or using inferred values)"
+        )]
+        yes: bool,
     },
 
+    #[structopt(
+        name = "lint",
+        about = "Run the linter of the project (flake8 by default) in the virtualenv"
+    )]
+    Lint {},
+
+    #[structopt(
+        name = "logs",
+        about = "Show the output of pip for the last run with --quiet"
+    )]
+    Logs {},
+
     #[structopt(name = "lock", about = "(Re)-generate requirements.lock")]
     Lock {
         #[structopt(
@@ -78,6 +398,132 @@ pub enum SubCommand {
 
         #[structopt(long = "platform", help = "Restrict platform")]
         sys_platform: Option<String>,
+
+        #[structopt(
+            long = "override-pin",
+            help = "Change the version of pinned dependencies if needed"
+        )]
+        override_pin: bool,
+
+        #[structopt(
+            long = "allow-foreign-venv",
+            help = "Use the active virtualenv even if it does not belong to the project"
+        )]
+        allow_foreign_venv: bool,
+
+        #[structopt(
+            long = "no-build-isolation",
+            help = "Build packages in the virtualenv instead of isolated environments, and record it in the lock"
+        )]
+        no_build_isolation: bool,
+
+        #[structopt(
+            long = "build-requirement",
+            help = "Install this in the virtualenv before building packages, and pin it in the lock (may be repeated)"
+        )]
+        build_requirements: Vec<String>,
+
+        #[structopt(
+            long = "extra",
+            help = "Also lock this extra of setup.py, in a separate lock (may be repeated)"
+        )]
+        extras: Vec<String>,
+
+        #[structopt(
+            long = "hashes",
+            help = "Add the sha256 hashes of the locked releases from the package index, so that pip requires them"
+        )]
+        hashes: bool,
+
+        #[structopt(
+            long = "check",
+            help = "Check the existing lock against the package and release age policies instead of re-generating it"
+        )]
+        check: bool,
+
+        #[structopt(subcommand)]
+        lock_cmd: Option<LockCommand>,
+    },
+
+    #[structopt(
+        name = "migrate",
+        about = "Upgrade files written by older versions of dmenv"
+    )]
+    Migrate {
+        #[structopt(
+            long = "check",
+            help = "Only show what would be migrated, and fail if anything would be"
+        )]
+        check: bool,
+    },
+
+    #[structopt(
+        name = "outdated",
+        about = "Show the locked releases behind the latest ones on the package index"
+    )]
+    Outdated {
+        #[structopt(long = "json", help = "Print a JSON array instead of a table")]
+        json: bool,
+    },
+
+    #[structopt(
+        name = "pin",
+        about = "Keep a dependency at its locked version, and record why"
+    )]
+    Pin {
+        #[structopt(help = "name")]
+        name: String,
+
+        #[structopt(long = "reason", help = "Why the dependency is pinned")]
+        reason: String,
+
+        #[structopt(
+            long = "marker",
+            help = "Only pin the entry with this environment marker"
+        )]
+        marker: Option<String>,
+    },
+
+    #[structopt(name = "pins", about = "List pinned dependencies")]
+    Pins {},
+
+    #[structopt(
+        name = "precommit",
+        about = "Keep pre-commit hooks in sync with the lock"
+    )]
+    Precommit {
+        #[structopt(subcommand)]
+        precommit_cmd: PrecommitCommand,
+    },
+
+    #[structopt(
+        name = "prefetch",
+        about = "Download dependencies from the lock into the dmenv cache"
+    )]
+    Prefetch {
+        #[structopt(long = "watch", help = "Prefetch again each time the lock changes")]
+        watch: bool,
+    },
+
+    #[structopt(
+        name = "query",
+        about = "Fast queries for shell completions and editor plugins"
+    )]
+    Query {
+        #[structopt(subcommand)]
+        query_cmd: QueryCommand,
+    },
+
+    #[structopt(name = "remote", about = "Manage virtualenvs on remote hosts")]
+    Remote {
+        #[structopt(subcommand)]
+        remote_cmd: RemoteCommand,
+    },
+
+    #[structopt(name = "restore", about = "Restore the virtualenv from a snapshot")]
+    Restore {
+        #[structopt(name = "archive", help = "Path of the snapshot")]
+        archive: String,
     },
 
     #[structopt(name = "run", about = "Run the given binary from the virtualenv")]
@@ -88,40 +534,646 @@ pub enum SubCommand {
         )]
         no_exec: bool,
 
+        #[structopt(
+            long = "reinstall-if-stale",
+            help = "Run `dmenv install` first if the lock changed since the last install"
+        )]
+        reinstall_if_stale: bool,
+
+        #[structopt(
+            long = "fail-if-stale",
+            help = "Fail if the lock changed since the last install"
+        )]
+        fail_if_stale: bool,
+
+        #[structopt(
+            long = "allow-venv-mutation",
+            help = "Allow commands like `pip install` that change the virtualenv"
+        )]
+        allow_venv_mutation: bool,
+
+        #[structopt(
+            long = "preset",
+            help = "Apply this preset from dmenv.toml: environment, working directory and resource limits"
+        )]
+        preset: Option<String>,
+
+        #[structopt(
+            long = "explain",
+            help = "Show the command, working directory and environment changes before running it"
+        )]
+        explain: bool,
+
         #[structopt(name = "command")]
         cmd: Vec<String>,
     },
 
+    #[structopt(
+        name = "python",
+        about = "Run the Python interpreter of the virtualenv, like `dmenv run python`",
+        raw(setting = "structopt::clap::AppSettings::TrailingVarArg"),
+        raw(setting = "structopt::clap::AppSettings::AllowLeadingHyphen"),
+        raw(setting = "structopt::clap::AppSettings::DisableVersion"),
+        raw(setting = "structopt::clap::AppSettings::DisableHelpFlags")
+    )]
+    Python {
+        #[structopt(
+            long = "--no-exec",
+            help = "On Unix, fork a new process instead of using exec(). On Windows, this is a no op"
+        )]
+        no_exec: bool,
+
+        #[structopt(
+            long = "reinstall-if-stale",
+            help = "Run `dmenv install` first if the lock changed since the last install"
+        )]
+        reinstall_if_stale: bool,
+
+        #[structopt(
+            long = "fail-if-stale",
+            help = "Fail if the lock changed since the last install"
+        )]
+        fail_if_stale: bool,
+
+        #[structopt(name = "args", help = "Arguments for Python, like `-m http.server`")]
+        args: Vec<String>,
+    },
+
+    #[structopt(
+        name = "pip",
+        about = "Run pip in the virtualenv, like `dmenv run python -m pip`",
+        raw(setting = "structopt::clap::AppSettings::TrailingVarArg"),
+        raw(setting = "structopt::clap::AppSettings::AllowLeadingHyphen"),
+        raw(setting = "structopt::clap::AppSettings::DisableVersion"),
+        raw(setting = "structopt::clap::AppSettings::DisableHelpFlags")
+    )]
+    Pip {
+        #[structopt(
+            long = "--no-exec",
+            help = "On Unix, fork a new process instead of using exec(). On Windows, this is a no op"
+        )]
+        no_exec: bool,
+
+        #[structopt(
+            long = "reinstall-if-stale",
+            help = "Run `dmenv install` first if the lock changed since the last install"
+        )]
+        reinstall_if_stale: bool,
+
+        #[structopt(
+            long = "fail-if-stale",
+            help = "Fail if the lock changed since the last install"
+        )]
+        fail_if_stale: bool,
+
+        #[structopt(
+            long = "allow-venv-mutation",
+            help = "Allow commands like `pip install` that change the virtualenv"
+        )]
+        allow_venv_mutation: bool,
+
+        #[structopt(name = "args", help = "Arguments for pip, like `list --outdated`")]
+        args: Vec<String>,
+    },
+
+    #[structopt(
+        name = "script",
+        about = "Run single-file scripts with inline dependencies (PEP 723)"
+    )]
+    Script {
+        #[structopt(subcommand)]
+        script_cmd: ScriptCommand,
+    },
+
+    #[structopt(name = "scripts", about = "Manage the scripts of the project")]
+    Scripts {
+        #[structopt(subcommand)]
+        scripts_cmd: ScriptsCommand,
+    },
+
+    #[structopt(name = "service", about = "Run the project as a service")]
+    Service {
+        #[structopt(subcommand)]
+        service_cmd: ServiceCommand,
+    },
+
+    #[structopt(
+        name = "shim",
+        about = "Write a script in the tools bin directory running the command from the virtualenv"
+    )]
+    Shim {
+        #[structopt(name = "command", help = "Console script of the project, like foo-cli")]
+        command: String,
+    },
+
+    #[structopt(
+        name = "simulate-ci",
+        about = "Install the production lock in a scratch virtualenv, the way CI does"
+    )]
+    SimulateCi {
+        #[structopt(
+            long = "vendor",
+            help = "Install offline, from the wheels in this directory"
+        )]
+        vendor: Option<String>,
+
+        #[structopt(long = "report", help = "Write a JSON report to this file")]
+        report: Option<String>,
+    },
+
+    #[structopt(
+        name = "snapshot",
+        about = "Archive the virtualenv so that it can be restored on another machine"
+    )]
+    Snapshot {
+        #[structopt(
+            long = "output",
+            help = "Path of the snapshot, relative to the project"
+        )]
+        output: Option<String>,
+    },
+
     #[structopt(name = "show:deps", about = "Show dependencies information")]
     ShowDeps {},
 
-    #[structopt(name = "show:venv_path", about = "Show path of the virtualenv")]
+    #[structopt(
+        name = "status",
+        about = "Show what was installed in the virtualenv and whether it is up to date"
+    )]
+    Status {},
+
+    #[structopt(
+        name = "show:venv_path",
+        about = "Show path of the virtualenv (prefer `dmenv info --paths`)"
+    )]
     ShowVenvPath {},
 
     #[structopt(
         name = "show:bin_path",
-        about = "Show path of the virtualenv's binaries"
+        about = "Show path of the virtualenv's binaries (prefer `dmenv info --paths`)"
     )]
     ShowVenvBin {},
 
+    #[structopt(
+        name = "tool",
+        about = "Install command line tools in their own virtualenvs"
+    )]
+    Tool {
+        #[structopt(subcommand)]
+        tool_cmd: ToolCommand,
+    },
+
+    #[structopt(
+        name = "undo",
+        about = "Restore the lock as it was before the last command that changed it"
+    )]
+    Undo {},
+
     #[structopt(name = "upgrade-pip", about = "Upgrade pip in the virtualenv")]
     UpgradePip {},
+
+    #[structopt(
+        name = "rename",
+        about = "Rename the project, its top-level package and its entry points"
+    )]
+    Rename {
+        #[structopt(name = "name")]
+        name: String,
+    },
+
+    #[structopt(name = "version", about = "Bump the version of the project")]
+    Version {
+        #[structopt(name = "bump", help = "'major', 'minor', 'patch', or the new version")]
+        bump: VersionBump,
+
+        #[structopt(long = "commit", help = "Commit the change and create a tag")]
+        commit: bool,
+
+        #[structopt(long = "build", help = "Build a wheel in dist/ afterwards")]
+        build: bool,
+    },
+
+    #[structopt(
+        name = "watch",
+        about = "Re-run install (or the given command) when setup.py or the lock change"
+    )]
+    Watch {
+        #[structopt(name = "command")]
+        cmd: Vec<String>,
+    },
+
+    #[structopt(name = "venv", about = "Manage dmenv virtualenvs")]
+    Venv {
+        #[structopt(subcommand)]
+        venv_cmd: VenvCommand,
+    },
+}
+
+impl SubCommand {
+    /// Commands changing the lock or the virtualenv, see `dmenv history`
+    pub fn is_recorded_in_history(&self) -> bool {
+        match self {
+            SubCommand::Install { .. }
+            | SubCommand::Lock { .. }
+            | SubCommand::BumpInLock { .. }
+            | SubCommand::Clean {}
+            | SubCommand::Migrate { check: false }
+            | SubCommand::Hash {
+                hash_cmd: HashCommand::Add { .. },
+            }
+            | SubCommand::Undo {} => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub enum RemoteCommand {
+    #[structopt(
+        name = "install",
+        about = "Install dependencies from the lock on a remote host, over SSH"
+    )]
+    Install {
+        #[structopt(long = "host", help = "Host to connect to, like user@server")]
+        host: String,
+
+        #[structopt(long = "port", help = "SSH port")]
+        port: Option<u16>,
+
+        #[structopt(
+            long = "path",
+            help = "Path of the project on the remote host (default: dmenv/<project name>)"
+        )]
+        path: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ServiceCommand {
+    #[structopt(
+        name = "generate",
+        about = "Generate a service running a script from the virtualenv"
+    )]
+    Generate {
+        #[structopt(name = "entry_point", help = "Name of the script in the virtualenv")]
+        entry_point: String,
+
+        #[structopt(
+            long = "format",
+            help = "'systemd' or 'supervisor'",
+            default_value = "systemd"
+        )]
+        format: ServiceFormat,
+
+        #[structopt(
+            long = "output",
+            help = "Write to this file (relative to the project) instead of stdout"
+        )]
+        output: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ToolCommand {
+    #[structopt(
+        name = "install",
+        about = "Install the tool and expose its commands in the tools bin directory"
+    )]
+    Install {
+        #[structopt(name = "name")]
+        name: String,
+    },
+
+    #[structopt(name = "list", about = "List installed tools and their commands")]
+    List {},
+
+    #[structopt(
+        name = "sync",
+        about = "Install the versions of the tools listed in tools.lock"
+    )]
+    Sync {},
+
+    #[structopt(name = "upgrade", about = "Upgrade the tool to its latest version")]
+    Upgrade {
+        #[structopt(name = "name")]
+        name: String,
+    },
+
+    #[structopt(name = "uninstall", about = "Remove the tool and its commands")]
+    Uninstall {
+        #[structopt(name = "name")]
+        name: String,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ScriptCommand {
+    #[structopt(
+        name = "run",
+        about = "Run the script in a cached virtualenv containing its dependencies"
+    )]
+    Run {
+        #[structopt(name = "script")]
+        script: String,
+
+        #[structopt(name = "args")]
+        args: Vec<String>,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ScriptsCommand {
+    #[structopt(
+        name = "regen",
+        about = "Regenerate the scripts of the project's entry points, without a full reinstall"
+    )]
+    Regen {},
+}
+
+#[derive(StructOpt)]
+pub enum CacheCommand {
+    #[structopt(
+        name = "key",
+        about = "Show a key that changes when the virtualenv contents should change"
+    )]
+    Key {},
+}
+
+#[derive(StructOpt)]
+pub enum CiCommand {
+    #[structopt(
+        name = "github",
+        about = "Show GitHub Actions steps caching the virtualenv"
+    )]
+    Github {
+        #[structopt(
+            long = "set-output",
+            help = "Print output parameters instead, for use in composite actions"
+        )]
+        set_output: bool,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum EnvCommand {
+    // Note: flags of this command are used by tox and nox plugins,
+    // so they should not change
+    #[structopt(
+        name = "create",
+        about = "Create a virtualenv in the given directory from a lock file"
+    )]
+    Create {
+        #[structopt(long = "path", help = "Path of the virtualenv")]
+        path: String,
+
+        #[structopt(
+            long = "python",
+            help = "Python binary, or version (like 3.7) to look for in PATH"
+        )]
+        python: Option<String>,
+
+        #[structopt(long = "from-lock", help = "Lock file to install")]
+        from_lock: String,
+
+        #[structopt(long = "develop", help = "Also run setup.py develop")]
+        develop: bool,
+
+        #[structopt(long = "installer", help = "'pip' or 'native'", default_value = "pip")]
+        installer: Installer,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum HashCommand {
+    #[structopt(
+        name = "add",
+        about = "Add hashes of the archives in the wheels cache or in a vendor directory to the lock"
+    )]
+    Add {
+        #[structopt(
+            long = "algorithm",
+            help = "'sha256', 'sha384' or 'sha512' (default: $DMENV_HASH_ALGORITHM or sha256)"
+        )]
+        algorithm: Option<Algorithm>,
+
+        #[structopt(
+            long = "vendor",
+            help = "Directory containing archives, relative to the project"
+        )]
+        vendor: Option<String>,
+    },
+
+    #[structopt(
+        name = "verify",
+        about = "Check the archives in the wheels cache or in a vendor directory against the hashes of the lock"
+    )]
+    Verify {
+        #[structopt(
+            long = "vendor",
+            help = "Directory containing archives, relative to the project"
+        )]
+        vendor: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum PrecommitCommand {
+    #[structopt(
+        name = "sync",
+        about = "Pin hooks in .pre-commit-config.yaml to the versions from the lock"
+    )]
+    Sync {
+        #[structopt(
+            long = "check",
+            help = "Fail if some hooks are not in sync instead of writing the file"
+        )]
+        check: bool,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum LockCommand {
+    #[structopt(
+        name = "stats",
+        about = "Show statistics about the lock: kinds of dependencies, markers, age of the releases, and a health score"
+    )]
+    Stats {
+        #[structopt(long = "json", help = "Print the statistics as JSON")]
+        json: bool,
+
+        #[structopt(
+            long = "offline",
+            help = "Do not query the package index: skip the age of the releases and the health score"
+        )]
+        offline: bool,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum BenchCommand {
+    #[structopt(name = "install", about = "Benchmark `dmenv install`")]
+    Install {
+        #[structopt(long = "iterations", default_value = "3")]
+        iterations: usize,
+
+        #[structopt(
+            long = "installer",
+            help = "How to install dependencies: 'pip' or 'native' (experimental)",
+            default_value = "pip"
+        )]
+        installer: Installer,
+    },
+
+    #[structopt(name = "lock", about = "Benchmark `dmenv lock`")]
+    Lock {
+        #[structopt(long = "iterations", default_value = "3")]
+        iterations: usize,
+    },
+}
+
+/// Queries never start Python nor change anything, so that they are fast
+/// enough to be used while completing a command line. They print one item
+/// per line, optionally followed by a tab and a description.
+#[derive(StructOpt)]
+pub enum QueryCommand {
+    #[structopt(
+        name = "packages",
+        about = "List the packages in the lock, with their versions"
+    )]
+    Packages {},
+
+    #[structopt(name = "pythons", about = "List Python interpreters found in PATH")]
+    Pythons {},
+
+    #[structopt(name = "scripts", about = "List the scripts of the virtualenv")]
+    Scripts {},
+}
+
+#[derive(StructOpt)]
+pub enum VenvCommand {
+    #[structopt(
+        name = "dedupe",
+        about = "Find packages installed in several virtualenvs"
+    )]
+    Dedupe {
+        #[structopt(long = "report", help = "Only report how much space could be saved")]
+        report: bool,
+    },
+
+    #[structopt(name = "freeze-perms", about = "Make the virtualenv read-only")]
+    FreezePerms {},
+
+    #[structopt(name = "unfreeze-perms", about = "Make the virtualenv writable again")]
+    UnfreezePerms {},
 }
 
 pub fn print_error(description: &str) {
     eprintln!("{}: {}", "Error".bold().red(), description);
+    if ci::in_github_actions() {
+        println!("{}", ci::github_annotation("error", description));
+    }
 }
 
 pub fn print_warning(description: &str) {
+    events::emit(&Event::Warning {
+        message: description,
+    });
+    print_warning_line(description);
+}
+
+/// Like `print_warning()`, but frontends get a `deprecation` event instead
+pub fn print_deprecation(deprecation: &Deprecation) {
+    events::emit(&Event::Deprecation {
+        what: &deprecation.what,
+        instead: &deprecation.instead,
+        removed_in: deprecation.removed_in,
+    });
+    print_warning_line(&deprecation.to_string());
+}
+
+fn print_warning_line(description: &str) {
     eprintln!("{}: {}", "Warning".bold().yellow(), description);
+    if ci::in_github_actions() {
+        println!("{}", ci::github_annotation("warning", description));
+    }
+}
+
+/// Ask a question on stdin, returning `default` if the answer is empty
+/// (or if stdin could not be read)
+pub fn ask(question: &str, default: &str) -> String {
+    if default.is_empty() {
+        print!("{} {}: ", "?".blue(), question);
+    } else {
+        print!("{} {} [{}]: ", "?".blue(), question, default);
+    }
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return default.to_string();
+    }
+    let answer = answer.trim();
+    if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    }
+}
+
+/// Ask a yes/no question on stdin. Anything but `y` or `yes`
+/// (including an error when reading stdin) means no
+pub fn confirm(question: &str) -> bool {
+    print!("{} {} [y/N] ", "?".blue(), question);
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    let answer = answer.trim().to_lowercase();
+    answer == "y" || answer == "yes"
+}
+
+/// True if a human can answer questions, that is, if stdin is a terminal
+/// and we are not running on CI
+pub fn is_interactive() -> bool {
+    if std::env::var("CI").is_ok() {
+        return false;
+    }
+    unsafe { libc::isatty(0) == 1 }
+}
+
+/// Whether to stick to plain ASCII output: with `--ascii`, when `DMENV_ASCII`
+/// is set, or on dumb terminals
+pub fn ascii_output(ascii_flag: bool) -> bool {
+    ascii_flag
+        || std::env::var("DMENV_ASCII").is_ok()
+        || std::env::var("TERM").map(|x| x == "dumb").unwrap_or(false)
+}
+
+/// Disable colors, and tell the processes we start (pip, external commands,
+/// nested dmenv calls ...) to do the same, and not to draw progress bars
+pub fn use_ascii_output() {
+    colored::control::set_override(false);
+    std::env::set_var("DMENV_ASCII", "1");
+    std::env::set_var("NO_COLOR", "1");
+    std::env::set_var("PIP_PROGRESS_BAR", "off");
 }
 
 pub fn print_info_1(message: &str) {
-    println!("{} {}", "::".blue(), message);
+    events::start_phase(message);
+    print_human(&format!("{} {}", "::".blue(), message));
 }
 
 pub fn print_info_2(message: &str) {
-    println!("{} {}", "->".blue(), message);
+    print_human(&format!("{} {}", "->".blue(), message));
+}
+
+// Note: stdout is reserved for events with `--event-stream stdout`
+fn print_human(line: &str) {
+    if events::on_stdout() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
 }
 
 // Make sure the `--python-version` option used in `dmenv lock`
@@ -140,6 +1192,10 @@ fn parse_python_version(string: &str) -> Result<String, Error> {
     Ok(string.to_string())
 }
 
+fn parse_index_url(string: &str) -> Result<Index, Error> {
+    Index::from_url(string)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use crate::dedupe::site_packages_dirs;
+use crate::error::Error;
+
+/// Name of the `.pth` file starting coverage measurement in every Python process
+/// of the virtualenv, as described in coverage.py's documentation about sub-processes
+pub const PTH_FILENAME: &str = "dmenv-coverage.pth";
+
+// Note: lines starting with `import` in .pth files are executed when Python starts.
+// Don't fail when coverage is not installed. `process_startup()` does nothing
+// unless COVERAGE_PROCESS_START is set.
+const PTH_CONTENTS: &str = "import importlib.util; importlib.util.find_spec('coverage') \
+                            and __import__('coverage').process_startup()\n";
+
+/// Write the `.pth` file in the site-packages of the virtualenv, if needed.
+/// Returns true if the file was written
+pub fn install_hook(venv: &Path) -> Result<bool, Error> {
+    let mut res = false;
+    for site_packages in site_packages_dirs(venv) {
+        let path = site_packages.join(PTH_FILENAME);
+        if path.exists() {
+            continue;
+        }
+        std::fs::write(&path, PTH_CONTENTS).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        res = true;
+    }
+    Ok(res)
+}
@@ -0,0 +1,267 @@
+use colored::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// A package installed in one of the virtualenvs, as
+/// described by its `.dist-info/RECORD` file
+#[derive(Debug, PartialEq)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub size: u64,
+}
+
+/// How much space a given package (name and version) takes
+/// across all the virtualenvs
+#[derive(Debug, PartialEq)]
+pub struct DedupeEntry {
+    pub name: String,
+    pub version: String,
+    pub copies: usize,
+    pub size: u64,
+}
+
+impl DedupeEntry {
+    /// Space saved if only one copy of the package was kept
+    pub fn savings(&self) -> u64 {
+        self.size * (self.copies as u64 - 1)
+    }
+}
+
+/// Look for virtualenvs (directories containing a `pyvenv.cfg` file)
+/// in the given roots. Roots may overlap, each virtualenv is only listed once
+pub fn find_venvs(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut res = vec![];
+    for root in roots {
+        find_venvs_in(root, 0, &mut res);
+    }
+    res.sort();
+    res.dedup();
+    res
+}
+
+// Note: virtualenvs managed by dmenv are at most at depth 3
+// (<root>/<dev|prod>/<version>/<project>), so don't look further.
+fn find_venvs_in(dir: &Path, depth: usize, res: &mut Vec<PathBuf>) {
+    if dir.join("pyvenv.cfg").exists() {
+        res.push(dir.to_path_buf());
+        return;
+    }
+    if depth >= 3 {
+        return;
+    }
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            find_venvs_in(&path, depth + 1, res);
+        }
+    }
+}
+
+/// Get the packages installed in the given virtualenv
+pub fn scan_venv(venv: &Path) -> Vec<InstalledPackage> {
+    let mut res = vec![];
+    for site_packages in site_packages_dirs(venv) {
+        let entries = match std::fs::read_dir(&site_packages) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.ends_with(".dist-info") {
+                continue;
+            }
+            let stem = file_name.trim_end_matches(".dist-info");
+            let mut parts = stem.rsplitn(2, '-');
+            let version = parts.next().unwrap_or_default().to_string();
+            let name = parts.next().unwrap_or_default().to_string();
+            let record = match std::fs::read_to_string(entry.path().join("RECORD")) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let size = record_size(&record, &site_packages);
+            res.push(InstalledPackage {
+                name,
+                version,
+                size,
+            });
+        }
+    }
+    res
+}
+
+// On Windows: <venv>/Lib/site-packages
+// Otherwise: <venv>/lib/pythonX.Y/site-packages (<venv>/lib/pypyX.Y/site-packages for PyPy)
+pub fn site_packages_dirs(venv: &Path) -> Vec<PathBuf> {
+    let windows_path = venv.join("Lib").join("site-packages");
+    if windows_path.exists() {
+        return vec![windows_path];
+    }
+    // PyPy before 3.8 puts site-packages at the root of the virtualenv
+    let pypy_path = venv.join("site-packages");
+    if pypy_path.exists() {
+        return vec![pypy_path];
+    }
+    let lib = venv.join("lib");
+    let entries = match std::fs::read_dir(&lib) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|e| e.path().join("site-packages"))
+        .filter(|p| p.exists())
+        .collect()
+}
+
+/// Compute the total size of the files listed in a RECORD file.
+//
+// Each line looks like `path,sha256=...,size`. The size is
+// not always present, in which case we look at the actual file.
+pub fn record_size(record: &str, site_packages: &Path) -> u64 {
+    let mut res = 0;
+    for line in record.lines() {
+        // Note: the path may contain commas, but not the hash nor the size
+        let mut parts = line.rsplitn(3, ',');
+        let size = parts.next().unwrap_or_default();
+        let _hash = parts.next();
+        let path = match parts.next() {
+            Some(path) => path.trim_matches('"'),
+            None => continue,
+        };
+        if let Ok(size) = size.parse::<u64>() {
+            res += size;
+        } else if let Ok(metadata) = std::fs::metadata(site_packages.join(path)) {
+            res += metadata.len();
+        }
+    }
+    res
+}
+
+/// Group packages by name and version, keeping only the ones installed more than once.
+/// Entries are sorted by decreasing savings.
+pub fn aggregate(packages: &[InstalledPackage]) -> Vec<DedupeEntry> {
+    let mut by_key: BTreeMap<(String, String), DedupeEntry> = BTreeMap::new();
+    for package in packages {
+        let key = (package.name.to_lowercase(), package.version.clone());
+        let entry = by_key.entry(key).or_insert_with(|| DedupeEntry {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            copies: 0,
+            size: package.size,
+        });
+        entry.copies += 1;
+    }
+    let mut res: Vec<_> = by_key.into_iter().map(|(_, v)| v).collect();
+    res.retain(|x| x.copies > 1);
+    res.sort_by(|x, y| y.savings().cmp(&x.savings()));
+    res
+}
+
+pub fn human_size(size: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+/// Print how much space would be saved by storing each package only once
+pub fn print_report(roots: &[PathBuf]) -> Result<(), Error> {
+    let venvs = find_venvs(roots);
+    let mut packages = vec![];
+    for venv in &venvs {
+        packages.extend(scan_venv(venv));
+    }
+    println!("Scanned {} virtualenv(s)", venvs.len());
+    let entries = aggregate(&packages);
+    if entries.is_empty() {
+        println!("No package is installed more than once");
+        return Ok(());
+    }
+    let name_width = entries
+        .iter()
+        .map(|x| x.name.len() + x.version.len() + 1)
+        .max()
+        .unwrap_or_default();
+    let mut total = 0;
+    for entry in &entries {
+        let package = format!("{}-{}", entry.name, entry.version);
+        println!(
+            "{:width$}  {:>3} copies  {:>10} each  {:>10} saved",
+            package,
+            entry.copies,
+            human_size(entry.size),
+            human_size(entry.savings()),
+            width = name_width,
+        );
+        total += entry.savings();
+    }
+    println!(
+        "{} {}",
+        "Total space that could be saved:".bold(),
+        human_size(total)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, version: &str, size: u64) -> InstalledPackage {
+        InstalledPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_record_size() {
+        let record = r#"foo/__init__.py,sha256=abc,100
+"foo/with,comma.py",sha256=def,20
+foo-1.0.dist-info/RECORD,,
+"#;
+        assert_eq!(record_size(record, Path::new("/no/such")), 120);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let packages = vec![
+            package("foo", "1.0", 100),
+            package("bar", "2.0", 1000),
+            package("Foo", "1.0", 100),
+            package("bar", "2.0", 1000),
+            package("bar", "2.1", 1000),
+            package("foo", "1.0", 100),
+        ];
+        let actual = aggregate(&packages);
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].name, "bar");
+        assert_eq!(actual[0].savings(), 1000);
+        assert_eq!(actual[1].name, "foo");
+        assert_eq!(actual[1].copies, 3);
+        assert_eq!(actual[1].savings(), 200);
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(12), "12 B");
+        assert_eq!(human_size(2048), "2.0 KB");
+        assert_eq!(human_size(3 * 1024 * 1024 + 512 * 1024), "3.5 MB");
+    }
+}
@@ -69,6 +69,8 @@ impl FrozenDependency {
 pub enum LockedDependency {
     Git(GitDependency),
     Simple(SimpleDependency),
+    /// A line dmenv does not understand (like pip options), kept as is
+    Opaque(String),
 }
 
 impl LockedDependency {
@@ -79,6 +81,7 @@ impl LockedDependency {
         match self {
             LockedDependency::Git(x) => x.line.to_string(),
             LockedDependency::Simple(x) => x.line.to_string(),
+            LockedDependency::Opaque(x) => x.to_string(),
         }
     }
 
@@ -86,6 +89,23 @@ impl LockedDependency {
         match self {
             LockedDependency::Git(x) => x.name.to_string(),
             LockedDependency::Simple(x) => x.name.to_string(),
+            // Note: opaque lines are never matched by name
+            LockedDependency::Opaque(_) => String::new(),
+        }
+    }
+
+    /// The environment marker of the dependency, like `python_version < '3.10'`
+    pub fn marker(&self) -> Option<String> {
+        if let LockedDependency::Opaque(_) = self {
+            return None;
+        }
+        let line = self.line();
+        let index = line.find(';')?;
+        let marker = line[index + 1..].trim();
+        if marker.is_empty() {
+            None
+        } else {
+            Some(marker.to_string())
         }
     }
 
@@ -171,6 +191,33 @@ impl LockedDependency {
     }
 }
 
+/// Normalize a package name as described in PEP 503, so that `Foo.Bar` and `foo-bar`
+/// are the same package
+pub fn canonical_name(name: &str) -> String {
+    let mut res = String::new();
+    for c in name.to_lowercase().chars() {
+        if c == '-' || c == '_' || c == '.' {
+            if !res.ends_with('-') {
+                res.push('-');
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Compare two environment markers, ignoring whitespace and the kind of quotes
+pub fn same_marker(a: &str, b: &str) -> bool {
+    let normalize = |x: &str| -> String {
+        x.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| if c == '"' { '\'' } else { c })
+            .collect()
+    };
+    normalize(a) == normalize(b)
+}
+
 #[derive(Debug)]
 // Container for a git ref or a version number.
 // We keep a record of the coordinates of the spec inside
@@ -227,6 +274,11 @@ impl SimpleDependency {
         self.line = format!("{} ; python_version {}", self.line, python_version);
     }
 
+    /// Only install this dependency when the marker matches
+    pub fn add_marker(&mut self, marker: &str) {
+        self.line = format!("{} ; {}", self.line, marker);
+    }
+
     /// Make this dependency specific to a Python platform
     pub fn sys_platform(&mut self, sys_platform: &str) {
         self.line = format!("{} ; sys_platform == '{}'", self.line, sys_platform);
@@ -358,4 +410,10 @@ mod tests {
         dep.bump("deadbeef");
         assert_eq!(dep.line, "git@master.com:foo@deadbeef#egg=foo");
     }
+
+    #[test]
+    fn test_canonical_name() {
+        assert_eq!(canonical_name("Foo.Bar__baz"), "foo-bar-baz");
+        assert_eq!(canonical_name("foo-bar"), "foo-bar");
+    }
 }
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use crate::dedupe::site_packages_dirs;
+use crate::hash;
+
+/// What is installed in a virtualenv, according to the RECORD
+/// files of its `.dist-info` directories
+#[derive(Debug, PartialEq)]
+pub struct VenvDigest {
+    /// `name==version`, sorted
+    pub packages: Vec<String>,
+    /// Hash of all the RECORD files: changes when a package is added,
+    /// removed, or re-installed with different files
+    pub records_hash: String,
+}
+
+impl VenvDigest {
+    pub fn compute(venv: &Path) -> Self {
+        let mut records = vec![];
+        for site_packages in site_packages_dirs(venv) {
+            let entries = match std::fs::read_dir(&site_packages) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if !file_name.ends_with(".dist-info") {
+                    continue;
+                }
+                let record = std::fs::read(entry.path().join("RECORD")).unwrap_or_default();
+                records.push((file_name, record));
+            }
+        }
+        records.sort();
+
+        let mut data = vec![];
+        let mut packages = vec![];
+        for (file_name, record) in &records {
+            data.extend(file_name.as_bytes());
+            data.extend(record);
+            let stem = file_name.trim_end_matches(".dist-info");
+            let mut parts = stem.rsplitn(2, '-');
+            let version = parts.next().unwrap_or_default();
+            let name = parts.next().unwrap_or_default();
+            packages.push(format!("{}=={}", name, version));
+        }
+        VenvDigest {
+            packages,
+            records_hash: hash::hash_hex(&data),
+        }
+    }
+}
+
+/// Return the packages added and removed between `old` and `new`
+pub fn diff(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let added = new.iter().filter(|x| !old.contains(x)).cloned().collect();
+    let removed = old.iter().filter(|x| !new.contains(x)).cloned().collect();
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = to_strings(&["attrs==19.1.0", "pytest==4.6.2"]);
+        let new = to_strings(&["attrs==19.1.0", "pytest==5.0.0", "requests==2.22.0"]);
+        let (added, removed) = diff(&old, &new);
+        assert_eq!(added, to_strings(&["pytest==5.0.0", "requests==2.22.0"]));
+        assert_eq!(removed, to_strings(&["pytest==4.6.2"]));
+    }
+
+    #[test]
+    fn test_compute_no_venv() {
+        let digest = VenvDigest::compute(Path::new("/no/such/venv"));
+        assert!(digest.packages.is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+/// Names of the scripts declared in the `console_scripts` and `gui_scripts`
+/// sections of an entry_points.txt file
+pub fn script_names(contents: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut in_scripts = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_scripts = line == "[console_scripts]" || line == "[gui_scripts]";
+            continue;
+        }
+        if !in_scripts {
+            continue;
+        }
+        if let Some(index) = line.find('=') {
+            res.push(line[..index].trim().to_string());
+        }
+    }
+    res
+}
+
+/// Find the `.egg-info` directory of the project, written by `setup.py egg_info`
+/// either next to setup.py or in `src/`
+pub fn find_egg_info(project_path: &Path, project_name: &str) -> Option<PathBuf> {
+    let normalize = |x: &str| x.to_lowercase().replace('-', "_").replace('.', "_");
+    let expected = format!("{}.egg-info", normalize(project_name));
+    for base in &[project_path.to_path_buf(), project_path.join("src")] {
+        let entries = match std::fs::read_dir(base) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if normalize(&file_name) == normalize(&expected) {
+                return Some(entry.path());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_names() {
+        let contents = r#"
+[console_scripts]
+demo = demo:main
+demo-admin=demo.admin:main
+
+[gui_scripts]
+demo-gui = demo.gui:main
+
+[pytest11]
+demo = demo.plugin
+"#;
+        assert_eq!(
+            script_names(contents),
+            vec!["demo", "demo-admin", "demo-gui"]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use crate::error::Error;
+use crate::msys;
+
+/// PATH used when variables can not be expanded (docker env files, service definitions)
+// Note: this is the default PATH of the official Docker images
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Formats supported by `dmenv env`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvFormat {
+    /// `KEY=value` lines, as read by `docker run --env-file` or
+    /// systemd's `EnvironmentFile`
+    Docker,
+    /// `export KEY="value"` lines, to be sourced by a POSIX shell
+    Shell,
+}
+
+impl std::str::FromStr for EnvFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "docker" => Ok(EnvFormat::Docker),
+            "shell" => Ok(EnvFormat::Shell),
+            _ => Err(Error::Other {
+                message: format!("unknown format '{}', expected 'docker' or 'shell'", s),
+            }),
+        }
+    }
+}
+
+/// Render the environment variables required to use the virtualenv
+/// without dmenv
+pub fn render(format: EnvFormat, project: &Path, venv: &Path, bin_path: &Path) -> String {
+    match format {
+        EnvFormat::Docker => format!(
+            "VIRTUAL_ENV={}\nPATH={}:{}\nPYTHONPATH={}\n",
+            venv.display(),
+            bin_path.display(),
+            DEFAULT_PATH,
+            project.display()
+        ),
+        // Note: PYTHONPATH is read by Python, so it keeps the native path
+        // even in Git Bash and the like
+        EnvFormat::Shell => format!(
+            "export VIRTUAL_ENV=\"{}\"\nexport PATH=\"{}:$PATH\"\nexport PYTHONPATH=\"{}\"\n",
+            msys::shell_path(venv),
+            msys::shell_path(bin_path),
+            project.display()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_docker() {
+        let actual = render(
+            EnvFormat::Docker,
+            Path::new("/app"),
+            Path::new("/app/.venv"),
+            Path::new("/app/.venv/bin"),
+        );
+        assert!(actual.contains("VIRTUAL_ENV=/app/.venv\n"));
+        assert!(actual.contains("PATH=/app/.venv/bin:/usr/local/sbin:"));
+        assert!(actual.contains("PYTHONPATH=/app\n"));
+    }
+
+    #[test]
+    fn test_render_shell() {
+        let actual = render(
+            EnvFormat::Shell,
+            Path::new("/app"),
+            Path::new("/app/.venv"),
+            Path::new("/app/.venv/bin"),
+        );
+        assert!(actual.contains("export PATH=\"/app/.venv/bin:$PATH\"\n"));
+    }
+}
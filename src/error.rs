@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::exit_code;
+
 /// Every variant matches a type of error we
 /// want the end-use to see.
 // Note: errors from external crates should be wrapped
@@ -28,6 +30,10 @@ pub enum Error {
     ProcessOutError {
         io_error: std::io::Error,
     },
+    /// A process started by dmenv (pip, python, git ...) exited with an error
+    ProcessFailed {
+        message: String,
+    },
 
     PipUpgradeFailed {},
     BrokenPipFreezeLine {
@@ -35,12 +41,18 @@ pub enum Error {
     },
 
     MissingSetupPy {},
+    MissingRequirementsIn {
+        expected_path: PathBuf,
+    },
     MissingLock {
         expected_path: PathBuf,
     },
     MissingVenv {
         path: PathBuf,
     },
+    StaleVenv {
+        lock_path: PathBuf,
+    },
 
     FileExists {
         path: PathBuf,
@@ -49,12 +61,21 @@ pub enum Error {
     Other {
         message: String,
     },
+    /// The command line is invalid
+    Usage {
+        message: String,
+    },
 
     MalformedLock {
         line: usize,
         details: String,
     },
 
+    IndexesUnreachable {
+        details: Vec<String>,
+        hints: Vec<String>,
+    },
+
     NothingToBump {
         name: String,
     },
@@ -62,6 +83,36 @@ pub enum Error {
     MultipleBumps {
         name: String,
     },
+
+    PinnedDependency {
+        name: String,
+        reason: String,
+    },
+
+    ForbiddenPackages {
+        details: Vec<String>,
+    },
+    /// See `policy::FreshnessPolicy`
+    StaleReleases {
+        details: Vec<String>,
+    },
+    /// See `policy::check_no_vcs()`
+    VcsInProduction {
+        lines: Vec<String>,
+    },
+
+    NoMatchingPython {
+        requested: String,
+        available: Vec<String>,
+    },
+    ForeignVenv {
+        path: PathBuf,
+        details: Vec<String>,
+    },
+    UnsupportedDmenvVersion {
+        required: String,
+        current: String,
+    },
 }
 
 /// Implement Display for our Error type
@@ -70,6 +121,7 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let message = match self {
             Error::Other { message } => message.to_string(),
+            Error::Usage { message } => message.to_string(),
 
             Error::NulByteFound { arg } => format!("nul byte found in arg: {:?}", arg),
 
@@ -87,10 +139,15 @@ impl std::fmt::Display for Error {
             Error::ProcessOutError { io_error } => {
                 format!("could not get process output: {}", io_error)
             }
+            Error::ProcessFailed { message } => message.to_string(),
 
             Error::MissingSetupPy {} => {
                 "setup.py not found.\n You may want to run `dmenv init` now".to_string()
             }
+            Error::MissingRequirementsIn { expected_path } => format!(
+                "{} not found.\n Put the dependencies of the project in this file",
+                expected_path.display()
+            ),
             Error::MissingLock { expected_path } => format!(
                 "{} not found.\n You may want to run `dmenv lock` now",
                 expected_path.display()
@@ -101,6 +158,12 @@ impl std::fmt::Display for Error {
                 message
             }
 
+            Error::StaleVenv { lock_path } => format!(
+                "environment out of date: {} changed since last install.\n \
+                 Run `dmenv install` or use `dmenv run --reinstall-if-stale`",
+                lock_path.display()
+            ),
+
             Error::BrokenPipFreezeLine { line } => {
                 format!("could not parse `pip freeze` output at line: '{}'", line)
             }
@@ -113,11 +176,92 @@ impl std::fmt::Display for Error {
             Error::MalformedLock { line, details } => {
                 format!("Malformed lock at line {}\n:{}", line, details)
             }
+            Error::IndexesUnreachable { details, hints } => {
+                let mut message = "could not reach any package index\n".to_string();
+                for detail in details {
+                    message.push_str(&format!(" * {}\n", detail));
+                }
+                for hint in hints {
+                    message.push_str(&format!("Note: {}\n", hint));
+                }
+                message.push_str("Set DMENV_NO_INDEX_CHECK=1 to skip this check");
+                message
+            }
             Error::NothingToBump { name } => format!("'{}' not found in lock", name),
             Error::MultipleBumps { name } => {
-                format!("multiple matches found for '{}' in lock", name)
+                format!(
+                    "multiple matches found for '{}' in lock. Use --marker to select one",
+                    name
+                )
+            }
+            Error::PinnedDependency { name, reason } => format!(
+                "'{}' is pinned in the lock ({}). Use --override-pin to change its version anyway",
+                name, reason
+            ),
+            Error::ForbiddenPackages { details } => {
+                let mut message = "forbidden packages found\n".to_string();
+                for detail in details {
+                    message.push_str(&format!(" * {}\n", detail));
+                }
+                message.trim_end().to_string()
+            }
+            Error::StaleReleases { details } => {
+                let mut message = "locked releases are too old\n".to_string();
+                for detail in details {
+                    message.push_str(&format!(" * {}\n", detail));
+                }
+                message.push_str(
+                    "Bump them, or annotate them in the lock with `# allow-old: <reason>`",
+                );
+                message
+            }
+            Error::VcsInProduction { lines } => {
+                let mut message =
+                    "git and URL dependencies are forbidden in production by forbid_vcs_in_production in dmenv.toml\n"
+                        .to_string();
+                for line in lines {
+                    message.push_str(&format!(" * {}\n", line));
+                }
+                message.push_str("Publish them to a package index, and lock released versions instead");
+                message
+            }
+            Error::NoMatchingPython {
+                requested,
+                available,
+            } => {
+                let mut message = format!("no Python {} found in PATH\n", requested);
+                if available.is_empty() {
+                    message.push_str("No other Python version found either");
+                } else {
+                    message.push_str("Available versions:\n");
+                    for version in available {
+                        message.push_str(&format!(" * {}\n", version));
+                    }
+                }
+                message.trim_end().to_string()
+            }
+            Error::ForeignVenv { path, details } => {
+                let mut message =
+                    format!("refusing to use the active virtualenv {}\n", path.display());
+                for detail in details {
+                    message.push_str(&format!(" * {}\n", detail));
+                }
+                message.push_str("Deactivate it, or use --allow-foreign-venv");
+                message
             }
+            Error::UnsupportedDmenvVersion { required, current } => format!(
+                "this project requires dmenv {}, but this is dmenv {}\n\
+                 Install a matching version, for instance with `cargo install dmenv --version '{}'`",
+                required, current, required
+            ),
         };
         write!(f, "{}", message)
     }
 }
+
+impl Error {
+    /// Exit code of dmenv when a command fails with this error, see `exit_code::TABLE`
+    pub fn exit_code(&self) -> i32 {
+        exit_code::for_error(self)
+    }
+}
@@ -0,0 +1,220 @@
+//! Newline-delimited JSON events describing what dmenv is doing, for IDE
+//! plugins and other frontends. See `--event-stream`.
+//!
+//! ```text
+//! {"event": "phase_started", "phase": "Preparing project for development"}
+//! {"event": "package_installed", "name": "attrs", "version": "19.1.0"}
+//! {"event": "phase_finished", "phase": "Preparing project for development", "duration_ms": 5430, "success": true}
+//! ```
+use std::cell::RefCell;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::json;
+
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    PhaseStarted {
+        phase: &'a str,
+    },
+    PhaseFinished {
+        phase: &'a str,
+        duration: Duration,
+        success: bool,
+    },
+    PackageInstalled {
+        name: &'a str,
+        version: &'a str,
+    },
+    Warning {
+        message: &'a str,
+    },
+    Deprecation {
+        what: &'a str,
+        instead: &'a str,
+        removed_in: &'a str,
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+impl<'a> Event<'a> {
+    pub fn to_json(&self) -> String {
+        let fields = match self {
+            Event::PhaseStarted { phase } => vec![
+                ("event", json::string("phase_started")),
+                ("phase", json::string(phase)),
+            ],
+            Event::PhaseFinished {
+                phase,
+                duration,
+                success,
+            } => vec![
+                ("event", json::string("phase_finished")),
+                ("phase", json::string(phase)),
+                ("duration_ms", duration.as_millis().to_string()),
+                ("success", success.to_string()),
+            ],
+            Event::PackageInstalled { name, version } => vec![
+                ("event", json::string("package_installed")),
+                ("name", json::string(name)),
+                ("version", json::string(version)),
+            ],
+            Event::Warning { message } => vec![
+                ("event", json::string("warning")),
+                ("message", json::string(message)),
+            ],
+            Event::Deprecation {
+                what,
+                instead,
+                removed_in,
+            } => vec![
+                ("event", json::string("deprecation")),
+                ("what", json::string(what)),
+                ("instead", json::string(instead)),
+                ("removed_in", json::string(removed_in)),
+            ],
+            Event::Error { message } => vec![
+                ("event", json::string("error")),
+                ("message", json::string(message)),
+            ],
+        };
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(key, value)| format!("\"{}\": {}", key, value))
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+}
+
+struct Stream {
+    out: Box<dyn Write>,
+    on_stdout: bool,
+    phase: Option<(String, Instant)>,
+}
+
+thread_local! {
+    static STREAM: RefCell<Option<Stream>> = RefCell::new(None);
+}
+
+/// Start writing events to `dest`: `stdout`, `fd:<n>` for an already open
+/// file descriptor (Unix only), or the path of a file (or of a named pipe)
+pub fn open(dest: &str) -> Result<(), Error> {
+    let on_stdout = dest == "stdout";
+    let out: Box<dyn Write> = if on_stdout {
+        Box::new(std::io::stdout())
+    } else if let Some(fd) = dest.strip_prefix("fd:") {
+        open_fd(fd)?
+    } else {
+        let file = std::fs::File::create(dest).map_err(|e| Error::WriteError {
+            path: dest.into(),
+            io_error: e,
+        })?;
+        Box::new(file)
+    };
+    STREAM.with(|x| {
+        *x.borrow_mut() = Some(Stream {
+            out,
+            on_stdout,
+            phase: None,
+        })
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn open_fd(fd: &str) -> Result<Box<dyn Write>, Error> {
+    use std::os::unix::io::FromRawFd;
+    let fd: i32 = fd.parse().map_err(|_| Error::Usage {
+        message: format!("invalid file descriptor for --event-stream: {}", fd),
+    })?;
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    Ok(Box::new(file))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: &str) -> Result<Box<dyn Write>, Error> {
+    Err(Error::Usage {
+        message: "--event-stream fd:<n> is only supported on Unix".to_string(),
+    })
+}
+
+pub fn enabled() -> bool {
+    STREAM.with(|x| x.borrow().is_some())
+}
+
+/// True when events are written to stdout. Everything else dmenv (or the
+/// processes it starts) would print there then goes to stderr
+pub fn on_stdout() -> bool {
+    STREAM.with(|x| x.borrow().as_ref().map(|x| x.on_stdout).unwrap_or(false))
+}
+
+pub fn emit(event: &Event) {
+    STREAM.with(|x| {
+        if let Some(stream) = x.borrow_mut().as_mut() {
+            // Note: don't fail the command if the frontend went away
+            let _ = writeln!(stream.out, "{}", event.to_json());
+            let _ = stream.out.flush();
+        }
+    });
+}
+
+/// Finish the current phase, if any, and start a new one
+pub fn start_phase(phase: &str) {
+    finish_phase(true);
+    emit(&Event::PhaseStarted { phase });
+    STREAM.with(|x| {
+        if let Some(stream) = x.borrow_mut().as_mut() {
+            stream.phase = Some((phase.to_string(), Instant::now()));
+        }
+    });
+}
+
+/// Called once the command is done. Stops writing events
+pub fn finish(res: &Result<(), Error>) {
+    if let Err(error) = res {
+        emit(&Event::Error {
+            message: &error.to_string(),
+        });
+    }
+    finish_phase(res.is_ok());
+    STREAM.with(|x| *x.borrow_mut() = None);
+}
+
+fn finish_phase(success: bool) {
+    let phase = STREAM.with(|x| x.borrow_mut().as_mut().and_then(|x| x.phase.take()));
+    if let Some((phase, start)) = phase {
+        emit(&Event::PhaseFinished {
+            phase: &phase,
+            duration: start.elapsed(),
+            success,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let event = Event::PhaseFinished {
+            phase: "Locking dependencies",
+            duration: Duration::from_millis(1234),
+            success: false,
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"event": "phase_finished", "phase": "Locking dependencies", "duration_ms": 1234, "success": false}"#
+        );
+        let event = Event::Warning {
+            message: "\"foo\" is deprecated",
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"event": "warning", "message": "\"foo\" is deprecated"}"#
+        );
+    }
+}
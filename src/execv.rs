@@ -10,10 +10,11 @@ fn to_c_string<S: AsRef<OsStr>>(string: S) -> Result<CString, Error> {
     })
 }
 
-/// Wrap execv() C function from libc crate
+/// Wrap execve() C function from libc crate: the command gets the environment
+/// of dmenv, plus the variables in `env`
 // Note: Use by `dmenv run` so that killing the dmenv process
 // does not create an orphan process
-pub fn execv<Cmd, Args>(cmd: Cmd, args: Args) -> Result<(), Error>
+pub fn execv<Cmd, Args>(cmd: Cmd, args: Args, env: &[(String, String)]) -> Result<(), Error>
 where
     Cmd: AsRef<OsStr>,
     Args: IntoIterator,
@@ -27,13 +28,27 @@ where
         }
         let mut args_ptr: Vec<_> = args_cstring.iter().map(|x| x.as_ptr()).collect();
         args_ptr.push(std::ptr::null());
+        let mut env_cstring = Vec::new();
+        for (key, value) in std::env::vars_os() {
+            if env.iter().all(|(k, _)| OsStr::new(k) != key) {
+                let mut var = key;
+                var.push("=");
+                var.push(value);
+                env_cstring.push(to_c_string(&var)?);
+            }
+        }
+        for (key, value) in env {
+            env_cstring.push(to_c_string(format!("{}={}", key, value))?);
+        }
+        let mut env_ptr: Vec<_> = env_cstring.iter().map(|x| x.as_ptr()).collect();
+        env_ptr.push(std::ptr::null());
 
         unsafe {
-            libc::execv(cmd_cstring.as_ptr(), args_ptr.as_ptr());
+            libc::execve(cmd_cstring.as_ptr(), args_ptr.as_ptr(), env_ptr.as_ptr());
         }
     }
 
     Err(Error::ProcessStartError {
-        message: format!("execv() failed: {}", std::io::Error::last_os_error()),
+        message: format!("execve() failed: {}", std::io::Error::last_os_error()),
     })
 }
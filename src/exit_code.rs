@@ -0,0 +1,112 @@
+use crate::error::Error;
+
+/// Exit codes of dmenv. They are part of its interface: scripts and CI
+/// jobs may rely on them, so never change the meaning of an existing one
+pub const SUCCESS: i32 = 0;
+pub const FAILURE: i32 = 1;
+pub const USAGE: i32 = 2;
+pub const STALE: i32 = 3;
+pub const POLICY: i32 = 4;
+pub const SUBPROCESS: i32 = 5;
+pub const NETWORK: i32 = 6;
+pub const MISSING_FILE: i32 = 7;
+pub const LOCK: i32 = 8;
+pub const ENVIRONMENT: i32 = 9;
+
+/// Shown by `dmenv explain-exit-codes`
+pub const TABLE: &[(i32, &str)] = &[
+    (SUCCESS, "success"),
+    (
+        FAILURE,
+        "any other error (I/O errors, invalid configuration ...)",
+    ),
+    (USAGE, "invalid command line"),
+    (STALE, "the virtualenv is out of date with the lock"),
+    (
+        POLICY,
+        "a policy was violated (forbidden package, pinned dependency, release too old, git dependency in production)",
+    ),
+    (
+        SUBPROCESS,
+        "a process started by dmenv (pip, python, git ...) failed",
+    ),
+    (NETWORK, "no package index could be reached"),
+    (
+        MISSING_FILE,
+        "a required file is missing (setup.py, requirements.in, lock, virtualenv)",
+    ),
+    (
+        LOCK,
+        "the lock could not be parsed, or does not contain what was asked",
+    ),
+    (
+        ENVIRONMENT,
+        "unsuitable environment (no matching Python, foreign virtualenv, wrong dmenv version)",
+    ),
+];
+
+// Note: no wildcard, so that new variants of Error have to be given a code
+pub fn for_error(error: &Error) -> i32 {
+    match error {
+        Error::Usage { .. } => USAGE,
+        Error::StaleVenv { .. } => STALE,
+        Error::ForbiddenPackages { .. }
+        | Error::StaleReleases { .. }
+        | Error::VcsInProduction { .. }
+        | Error::PinnedDependency { .. } => POLICY,
+        Error::ProcessStartError { .. }
+        | Error::ProcessWaitError { .. }
+        | Error::ProcessOutError { .. }
+        | Error::ProcessFailed { .. }
+        | Error::PipUpgradeFailed {} => SUBPROCESS,
+        Error::IndexesUnreachable { .. } => NETWORK,
+        Error::MissingSetupPy {}
+        | Error::MissingRequirementsIn { .. }
+        | Error::MissingLock { .. }
+        | Error::MissingVenv { .. } => MISSING_FILE,
+        Error::MalformedLock { .. }
+        | Error::BrokenPipFreezeLine { .. }
+        | Error::NothingToBump { .. }
+        | Error::MultipleBumps { .. } => LOCK,
+        Error::NoMatchingPython { .. }
+        | Error::ForeignVenv { .. }
+        | Error::UnsupportedDmenvVersion { .. } => ENVIRONMENT,
+        Error::ReadError { .. }
+        | Error::WriteError { .. }
+        | Error::NulByteFound { .. }
+        | Error::FileExists { .. }
+        | Error::Other { .. } => FAILURE,
+    }
+}
+
+pub fn print_table() {
+    for (code, description) in TABLE {
+        println!("{:>3}  {}", code, description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_for_error() {
+        let error = Error::StaleVenv {
+            lock_path: PathBuf::from("requirements.lock"),
+        };
+        assert_eq!(for_error(&error), STALE);
+        let error = Error::ProcessFailed {
+            message: "command failed".to_string(),
+        };
+        assert_eq!(for_error(&error), SUBPROCESS);
+    }
+
+    #[test]
+    fn test_table_codes_are_unique() {
+        let mut codes: Vec<_> = TABLE.iter().map(|(code, _)| *code).collect();
+        codes.dedup();
+        assert_eq!(codes.len(), TABLE.len());
+        assert_eq!(codes, (0..TABLE.len() as i32).collect::<Vec<_>>());
+    }
+}
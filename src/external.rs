@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::cmd::Command;
+use crate::error::Error;
+
+// Global options taking a value, as defined in `cmd::Command`
+const GLOBAL_OPTIONS_WITH_VALUE: &[&str] = &[
+    "--python",
+    "--project",
+    "--package-dir",
+    "--extra-package-dir",
+    "--index-url",
+    "--event-stream",
+];
+
+/// An executable named `dmenv-<name>` found in PATH, used as `dmenv <name>`
+/// when `<name>` is not a built-in sub-command
+#[derive(Debug)]
+pub struct ExternalCommand {
+    pub name: String,
+    pub path: PathBuf,
+    /// Arguments given after the name of the command
+    pub args: Vec<String>,
+    global_args: Vec<String>,
+}
+
+impl ExternalCommand {
+    /// `args` are the arguments of dmenv, including the program name
+    pub fn find(args: &[String]) -> Option<Self> {
+        // Built-in sub-commands always win
+        if Command::from_iter_safe(args).is_ok() {
+            return None;
+        }
+        let (global_args, name, args) = split_args(args)?;
+        let path = which::which(format!("dmenv-{}", name)).ok()?;
+        Some(ExternalCommand {
+            name,
+            path,
+            args,
+            global_args,
+        })
+    }
+
+    /// Parse the global options given before the name of the external command
+    // Note: `info` is only used to get a valid command line, it is not run
+    pub fn global_command(&self) -> Result<Command, Error> {
+        let mut args = self.global_args.clone();
+        args.push("info".to_string());
+        Command::from_iter_safe(args).map_err(|e| Error::Usage { message: e.message })
+    }
+}
+
+/// Split `dmenv --python python3.8 foo --bar` into the global arguments,
+/// the name of the sub-command, and its arguments
+fn split_args(args: &[String]) -> Option<(Vec<String>, String, Vec<String>)> {
+    let mut global_args = vec![args.first()?.to_string()];
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') {
+            let rest = iter.cloned().collect();
+            return Some((global_args, arg.to_string(), rest));
+        }
+        global_args.push(arg.to_string());
+        if GLOBAL_OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            global_args.push(iter.next()?.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_args() {
+        let args = to_strings(&["dmenv", "--python", "python3", "--production", "foo", "-x"]);
+        let (global_args, name, rest) = split_args(&args).unwrap();
+        assert_eq!(
+            global_args,
+            to_strings(&["dmenv", "--python", "python3", "--production"])
+        );
+        assert_eq!(name, "foo");
+        assert_eq!(rest, to_strings(&["-x"]));
+    }
+
+    #[test]
+    fn test_split_args_no_command() {
+        assert!(split_args(&to_strings(&["dmenv", "--project", "foo"])).is_none());
+    }
+}
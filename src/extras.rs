@@ -0,0 +1,213 @@
+//! Locks for the extras of the project, like `postgres` in `setup.py`:
+//!
+//! ```text
+//! extras_require={"dev": [...], "postgres": ["psycopg2"]}
+//! ```
+//!
+//! `dmenv lock --extra postgres` writes `requirements-postgres.lock` next to
+//! the lock, with everything needed for the project and the extra. Its
+//! versions are resolved with the ones of the lock as constraints, so that
+//! `dmenv install --extra postgres` installs both locks without conflicts.
+//! Locks of extras that already exist are generated again by `dmenv lock`.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::dependencies::canonical_name;
+use crate::error::Error;
+use crate::lock_hashes;
+use crate::runner::{CommandRunner, Process};
+
+pub const LOCK_PREFIX: &str = "# Extra: ";
+
+/// `requirements-postgres.lock` for `requirements.lock`
+pub fn lock_path(lock: &Path, extra: &str) -> PathBuf {
+    let stem = lock
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    lock.with_file_name(format!("{}-{}.lock", stem, extra))
+}
+
+/// Name of the extra recorded in the header of a lock, if any
+pub fn from_lock(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .take_while(|x| x.starts_with('#'))
+        .find_map(|x| x.strip_prefix(LOCK_PREFIX))
+        .map(|x| x.trim().to_string())
+}
+
+pub fn lock_header(extra: &str) -> String {
+    format!("{}{}\n", LOCK_PREFIX, extra)
+}
+
+/// Extras with a lock next to the given one
+pub fn locked_extras(lock: &Path) -> Result<Vec<String>, Error> {
+    let dir = match lock.parent() {
+        Some(dir) if dir.is_dir() => dir,
+        _ => return Ok(vec![]),
+    };
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::ReadError {
+        path: dir.to_path_buf(),
+        io_error: e,
+    })?;
+    let prefix = lock_path(lock, "")
+        .file_name()
+        .map(|x| x.to_string_lossy().trim_end_matches(".lock").to_string())
+        .unwrap_or_default();
+    let mut res = vec![];
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let filename = entry.file_name().to_string_lossy().to_string();
+        if !filename.starts_with(&prefix) || !filename.ends_with(".lock") {
+            continue;
+        }
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        if let Some(extra) = from_lock(&contents) {
+            if lock_path(lock, &extra) == path {
+                res.push(extra);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Constraints for pip, from the lock: options and URLs are not allowed there
+pub fn constraints(lock_contents: &str) -> String {
+    let mut res = String::new();
+    for entry in lock_hashes::entries(lock_contents) {
+        res.push_str(&format!("{}=={}", entry.name, entry.version));
+        if let Some(marker) = &entry.marker {
+            res.push_str(&format!(" ; {}", marker));
+        }
+        res.push('\n');
+    }
+    res
+}
+
+/// Lines of the lock for each package in the report of `pip install --dry-run`
+pub fn read_report(
+    runner: &dyn CommandRunner,
+    python: &Path,
+    report: &Path,
+) -> Result<Vec<String>, Error> {
+    let script = include_str!("pip_report.py");
+    let process = Process::new(python)
+        .arg("-c")
+        .arg(script)
+        .arg(&report.to_string_lossy());
+    let output = runner.output(&process)?;
+    if !output.success {
+        return Err(Error::Other {
+            message: format!("Failed to run pip_report script: {}", output.stderr),
+        });
+    }
+    let mut res: Vec<_> = output.stdout.lines().map(|x| x.to_string()).collect();
+    res.sort_by_key(|x| x.to_lowercase());
+    Ok(res)
+}
+
+/// A package locked with different versions
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub name: String,
+    /// Name of the lock, and version
+    pub versions: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let versions: Vec<_> = self
+            .versions
+            .iter()
+            .map(|(lock, version)| format!("{} in {}", version, lock))
+            .collect();
+        write!(f, "{}: {}", self.name, versions.join(", "))
+    }
+}
+
+/// Packages pinned to different versions in the given locks, by name of lock.
+/// Entries with markers are ignored, since they may only apply to other environments
+pub fn conflicts(locks: &[(String, String)]) -> Vec<Conflict> {
+    let mut pins: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for (lock, contents) in locks {
+        for entry in lock_hashes::entries(contents) {
+            if entry.marker.is_some() {
+                continue;
+            }
+            pins.entry(canonical_name(&entry.name))
+                .or_default()
+                .push((lock.to_string(), entry.version));
+        }
+    }
+    pins.into_iter()
+        .filter(|(_, versions)| versions.iter().any(|(_, x)| x != &versions[0].1))
+        .map(|(name, versions)| Conflict { name, versions })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_path() {
+        assert_eq!(
+            lock_path(Path::new("/work/production.lock"), "postgres"),
+            Path::new("/work/production-postgres.lock")
+        );
+    }
+
+    #[test]
+    fn test_lock_header() {
+        let contents = format!(
+            "# Lock format: 2\n{}psycopg2==2.9.9\n",
+            lock_header("postgres")
+        );
+        assert_eq!(from_lock(&contents).unwrap(), "postgres");
+        assert!(from_lock("psycopg2==2.9.9\n").is_none());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let lock = "\
+# Generated with dmenv 0.12.0, python 3.7.1, on Linux
+-e git+https://gitlab.local/foo@master#egg=foo
+bar==42 ; python_version < '3.8' \\
+    --hash=sha256:0123
+baz==1.0  # pin: see #42
+";
+        assert_eq!(
+            constraints(lock),
+            "bar==42 ; python_version < '3.8'\nbaz==1.0\n"
+        );
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let locks = vec![
+            (
+                "requirements.lock".to_string(),
+                "attrs==23.1.0\nidna==3.4 ; python_version < '3.8'\n".to_string(),
+            ),
+            (
+                "requirements-postgres.lock".to_string(),
+                "attrs==23.1.0\nidna==3.6\nsix==1.16.0\n".to_string(),
+            ),
+            (
+                "requirements-redis.lock".to_string(),
+                "Six==1.15.0\nidna==3.6\n".to_string(),
+            ),
+        ];
+        let conflicts = conflicts(&locks);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "six: 1.16.0 in requirements-postgres.lock, 1.15.0 in requirements-redis.lock"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! `--find-links` locations for pip, including buckets in object storage
+//! (`s3://bucket/wheels`, `gs://bucket/wheels`), which pip can't read.
+//!
+//! For those, dmenv lists the archives of the bucket and signs a URL for each
+//! of them with the `aws` or `gcloud` command line tools, using the
+//! credentials they are configured with. The signed URLs are written in a
+//! temporary HTML page, given to pip as a find-links location, so that pip
+//! only downloads the archives it needs.
+use std::path::PathBuf;
+
+use crate::error::Error;
+
+/// How long the signed URLs are valid, in seconds
+const SIGNED_URL_EXPIRY: u32 = 3600;
+
+/// Extensions of the files pip can install from a find-links location
+const ARCHIVE_EXTENSIONS: &[&str] = &[".whl", ".tar.gz", ".zip", ".tar.bz2"];
+
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    /// A directory or an URL pip understands as is
+    Plain(String),
+    /// `s3://bucket/prefix/`
+    S3(String),
+    /// `gs://bucket/prefix/`
+    Gcs(String),
+}
+
+impl Location {
+    pub fn parse(location: &str) -> Self {
+        // Note: listing a prefix without a trailing slash lists the prefix itself
+        let as_dir = |x: &str| format!("{}/", x.trim_end_matches('/'));
+        if location.starts_with("s3://") {
+            Location::S3(as_dir(location))
+        } else if location.starts_with("gs://") {
+            Location::Gcs(as_dir(location))
+        } else {
+            Location::Plain(location.to_string())
+        }
+    }
+
+    /// URLs of the archives at this location, signed so that pip can download
+    /// them without credentials
+    fn signed_urls(&self) -> Result<Vec<(String, String)>, Error> {
+        let objects = match self {
+            Location::Plain(_) => return Ok(vec![]),
+            Location::S3(prefix) => {
+                parse_s3_listing(prefix, &run_tool(&["aws", "s3", "ls", prefix])?)
+            }
+            Location::Gcs(prefix) => {
+                parse_gcs_listing(&run_tool(&["gcloud", "storage", "ls", prefix])?)
+            }
+        };
+        let mut res = vec![];
+        for object in objects {
+            let filename = object.rsplit('/').next().unwrap_or_default().to_string();
+            if !is_archive(&filename) {
+                continue;
+            }
+            let url = match self {
+                Location::S3(_) => presign_s3(&object)?,
+                _ => sign_gcs(&object)?,
+            };
+            res.push((filename, url));
+        }
+        Ok(res)
+    }
+}
+
+/// The find-links locations to give to pip. Buckets are replaced by a
+/// temporary page, removed when this is dropped
+pub struct FindLinks {
+    pub locations: Vec<String>,
+    page_dir: Option<PathBuf>,
+}
+
+impl FindLinks {
+    pub fn resolve(locations: &[String]) -> Result<Self, Error> {
+        let mut res = FindLinks {
+            locations: vec![],
+            page_dir: None,
+        };
+        let mut links = vec![];
+        for location in locations {
+            match Location::parse(location) {
+                Location::Plain(location) => res.locations.push(location),
+                bucket => links.extend(bucket.signed_urls()?),
+            }
+        }
+        if links.is_empty() {
+            return Ok(res);
+        }
+        let dir = std::env::temp_dir().join(format!("dmenv-find-links-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| Error::WriteError {
+            path: dir.to_path_buf(),
+            io_error: e,
+        })?;
+        let page = dir.join("index.html");
+        std::fs::write(&page, links_page(&links)).map_err(|e| Error::WriteError {
+            path: page.to_path_buf(),
+            io_error: e,
+        })?;
+        res.locations.push(page.to_string_lossy().to_string());
+        res.page_dir = Some(dir);
+        Ok(res)
+    }
+
+    /// Value of `PIP_FIND_LINKS`
+    pub fn pip_value(&self) -> String {
+        self.locations.join(" ")
+    }
+}
+
+impl Drop for FindLinks {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.page_dir {
+            // Note: the signed URLs expire anyway, so don't fail if this does
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn is_archive(filename: &str) -> bool {
+    ARCHIVE_EXTENSIONS.iter().any(|x| filename.ends_with(x))
+}
+
+/// Run `aws` or `gcloud`, letting them ask for credentials on stderr
+fn run_tool(args: &[&str]) -> Result<String, Error> {
+    let output = std::process::Command::new(args[0])
+        .args(&args[1..])
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| Error::Other {
+            message: format!("could not run '{}': {}", args[0], e),
+        })?;
+    if !output.status.success() {
+        return Err(Error::Other {
+            message: format!("'{}' failed", args.join(" ")),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn presign_s3(object: &str) -> Result<String, Error> {
+    let expiry = SIGNED_URL_EXPIRY.to_string();
+    let output = run_tool(&["aws", "s3", "presign", object, "--expires-in", &expiry])?;
+    Ok(output.trim().to_string())
+}
+
+fn sign_gcs(object: &str) -> Result<String, Error> {
+    let duration = format!("--duration={}s", SIGNED_URL_EXPIRY);
+    let output = run_tool(&["gcloud", "storage", "sign-url", object, &duration])?;
+    parse_gcs_signed_url(&output).ok_or_else(|| Error::Other {
+        message: format!("no signed URL for {} in the output of gcloud", object),
+    })
+}
+
+/// `aws s3 ls` prints `<date> <time> <size> <key>` for each object, and
+/// `PRE <prefix>/` for sub-directories, which are skipped
+fn parse_s3_listing(prefix: &str, output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // Skip the date, the time and the size. Note: keys may contain spaces
+            let mut rest = line;
+            for _ in 0..3 {
+                rest = rest.trim_start();
+                rest = &rest[rest.find(char::is_whitespace)?..];
+            }
+            let key = rest.trim();
+            if key.is_empty() {
+                return None;
+            }
+            Some(format!("{}{}", prefix, key))
+        })
+        .collect()
+}
+
+/// `gcloud storage ls` prints one URL per line, sub-directories end with `/`
+fn parse_gcs_listing(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| x.starts_with("gs://") && !x.ends_with('/'))
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/// `gcloud storage sign-url` prints a YAML document with a `signed_url` key
+fn parse_gcs_signed_url(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|x| x.trim().strip_prefix("signed_url:"))
+        .map(|x| x.trim().to_string())
+}
+
+/// A page with a link to each archive, as expected by `pip --find-links`
+fn links_page(links: &[(String, String)]) -> String {
+    let mut res = "<!DOCTYPE html>\n<html><body>\n".to_string();
+    for (filename, url) in links {
+        res.push_str(&format!(
+            "<a href=\"{}\">{}</a><br>\n",
+            escape_html(url),
+            escape_html(filename)
+        ));
+    }
+    res.push_str("</body></html>\n");
+    res
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            Location::parse("s3://acme/wheels"),
+            Location::S3("s3://acme/wheels/".to_string())
+        );
+        assert_eq!(
+            Location::parse("gs://acme/wheels/"),
+            Location::Gcs("gs://acme/wheels/".to_string())
+        );
+        assert_eq!(
+            Location::parse("https://acme.corp/wheels/"),
+            Location::Plain("https://acme.corp/wheels/".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_s3_listing() {
+        let output = "\
+                           PRE old/
+2023-01-01 12:00:00      12345 foo-1.0-py3-none-any.whl
+2023-01-02 12:00:00        678 my bar-2.0.tar.gz
+";
+        assert_eq!(
+            parse_s3_listing("s3://acme/wheels/", output),
+            vec![
+                "s3://acme/wheels/foo-1.0-py3-none-any.whl",
+                "s3://acme/wheels/my bar-2.0.tar.gz"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_gcs_output() {
+        let listing = "gs://acme/wheels/old/\ngs://acme/wheels/foo-1.0-py3-none-any.whl\n";
+        assert_eq!(
+            parse_gcs_listing(listing),
+            vec!["gs://acme/wheels/foo-1.0-py3-none-any.whl"]
+        );
+        let signed = "---\nexpiration: '2023-01-01 13:00:00'\nhttp_verb: GET\nresource: gs://acme/wheels/foo-1.0-py3-none-any.whl\nsigned_url: https://storage.googleapis.com/acme/wheels/foo-1.0-py3-none-any.whl?X-Goog-Signature=abc\n";
+        assert_eq!(
+            parse_gcs_signed_url(signed).unwrap(),
+            "https://storage.googleapis.com/acme/wheels/foo-1.0-py3-none-any.whl?X-Goog-Signature=abc"
+        );
+    }
+
+    #[test]
+    fn test_links_page() {
+        let links = vec![(
+            "foo-1.0-py3-none-any.whl".to_string(),
+            "https://acme.s3.amazonaws.com/foo-1.0-py3-none-any.whl?a=1&b=2".to_string(),
+        )];
+        let page = links_page(&links);
+        assert!(page.contains(
+            "<a href=\"https://acme.s3.amazonaws.com/foo-1.0-py3-none-any.whl?a=1&amp;b=2\">foo-1.0-py3-none-any.whl</a>"
+        ));
+    }
+}
@@ -0,0 +1,440 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use colored::*;
+
+use crate::cmd::{print_info_1, print_info_2};
+use crate::error::Error;
+use crate::json;
+use crate::lock::Lock;
+use crate::paths::{DEV_LOCK_FILENAME, PROD_LOCK_FILENAME};
+use crate::project_config::ProjectConfig;
+
+/// A directory containing one of those files is a dmenv project
+const PROJECT_MARKERS: &[&str] = &[DEV_LOCK_FILENAME, PROD_LOCK_FILENAME, "dmenv.toml"];
+
+/// Result of running the command in one project
+#[derive(Debug)]
+pub struct Outcome {
+    pub project: PathBuf,
+    /// None if dmenv could not be started, or was killed by a signal
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Find the dmenv projects under `root` (including `root` itself), sorted by path.
+// Note: hidden directories (like .git and .venv) are skipped, and symlinks are not followed
+pub fn discover(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut res = vec![];
+    let mut to_visit = vec![root.to_path_buf()];
+    while let Some(dir) = to_visit.pop() {
+        if PROJECT_MARKERS.iter().any(|x| dir.join(x).is_file()) {
+            res.push(dir.clone());
+        }
+        let entries = std::fs::read_dir(&dir).map_err(|e| Error::ReadError {
+            path: dir.to_path_buf(),
+            io_error: e,
+        })?;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let is_dir = entry.file_type().map(|x| x.is_dir()).unwrap_or(false);
+            if is_dir && !is_skipped(&entry.file_name().to_string_lossy()) {
+                to_visit.push(entry.path());
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+fn is_skipped(dir_name: &str) -> bool {
+    dir_name.starts_with('.') || dir_name == "node_modules" || dir_name == "__pycache__"
+}
+
+/// Run dmenv with the given arguments in each project, then print a summary
+pub fn run(
+    projects: &[PathBuf],
+    root: &Path,
+    args: &[String],
+    fail_fast: bool,
+) -> Result<Vec<Outcome>, Error> {
+    let dmenv = std::env::current_exe().map_err(|e| Error::Other {
+        message: format!("could not find the dmenv executable: {}", e),
+    })?;
+    let mut outcomes = vec![];
+    for (i, project) in projects.iter().enumerate() {
+        print_info_1(&format!(
+            "{} ({}/{})",
+            relative(project, root).as_str().bold(),
+            i + 1,
+            projects.len()
+        ));
+        let start = Instant::now();
+        let status = std::process::Command::new(&dmenv)
+            .arg("--project")
+            .arg(project)
+            .args(args)
+            .status();
+        let outcome = Outcome {
+            project: project.to_path_buf(),
+            exit_code: status.ok().and_then(|x| x.code()),
+            duration: start.elapsed(),
+        };
+        let failed = !outcome.success();
+        outcomes.push(outcome);
+        if failed && fail_fast {
+            break;
+        }
+    }
+
+    print_info_2("Summary");
+    for line in render_summary(&outcomes, root) {
+        println!("{}", line);
+    }
+    Ok(outcomes)
+}
+
+/// Fail if the command failed in any project
+pub fn check(outcomes: &[Outcome]) -> Result<(), Error> {
+    let failures = outcomes.iter().filter(|x| !x.success()).count();
+    if failures != 0 {
+        return Err(Error::ProcessFailed {
+            message: format!("command failed in {} project(s)", failures),
+        });
+    }
+    Ok(())
+}
+
+/// One line per project, with aligned columns
+pub fn render_summary(outcomes: &[Outcome], root: &Path) -> Vec<String> {
+    let names: Vec<_> = outcomes
+        .iter()
+        .map(|x| relative(&x.project, root))
+        .collect();
+    let width = names.iter().map(|x| x.len()).max().unwrap_or_default();
+    outcomes
+        .iter()
+        .zip(names)
+        .map(|(outcome, name)| {
+            let result = match outcome.exit_code {
+                Some(0) => "ok".to_string(),
+                Some(code) => format!("failed (exit code {})", code),
+                None => "failed".to_string(),
+            };
+            format!(
+                "{:<width$}  {:>7}  {}",
+                name,
+                format!("{:.1}s", outcome.duration.as_millis() as f64 / 1000.0),
+                result,
+                width = width
+            )
+        })
+        .collect()
+}
+
+/// What `--report` tells about a project, found without running anything in it
+#[derive(Debug, Default)]
+pub struct ProjectReport {
+    pub project: String,
+    /// None if the project has no lock
+    pub lock: Option<String>,
+    /// dmenv version, Python version and platform used to generate the lock,
+    /// from its first line
+    pub locked_with: Option<(String, String, String)>,
+    pub lock_age_days: Option<u64>,
+    /// True if setup.py, pyproject.toml or requirements*.in changed after the lock
+    pub lock_outdated: bool,
+    pub packages: usize,
+    /// From `dmenv.toml`
+    pub python: Option<String>,
+    pub outcome: Option<(Option<i32>, Duration)>,
+}
+
+impl ProjectReport {
+    pub fn new(project: &Path, root: &Path) -> Self {
+        let mut res = ProjectReport {
+            project: relative(project, root),
+            python: ProjectConfig::from_project(project)
+                .ok()
+                .and_then(|x| x.python),
+            ..Default::default()
+        };
+        let lock_path = [DEV_LOCK_FILENAME, PROD_LOCK_FILENAME]
+            .iter()
+            .map(|x| project.join(x))
+            .find(|x| x.is_file());
+        let lock_path = match lock_path {
+            Some(lock_path) => lock_path,
+            None => return res,
+        };
+        res.lock = lock_path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string());
+        let contents = std::fs::read_to_string(&lock_path).unwrap_or_default();
+        res.locked_with = contents.lines().next().and_then(parse_lock_header);
+        if let Ok(lock) = Lock::from_string(&contents) {
+            let mut names = lock.names();
+            names.dedup();
+            res.packages = names.len();
+        }
+        let lock_modified = modified(&lock_path);
+        res.lock_age_days = lock_modified
+            .and_then(|x| SystemTime::now().duration_since(x).ok())
+            .map(|x| x.as_secs() / (24 * 3600));
+        let inputs = [
+            "setup.py",
+            "pyproject.toml",
+            "requirements.in",
+            "requirements-dev.in",
+        ];
+        res.lock_outdated = inputs
+            .iter()
+            .filter_map(|x| modified(&project.join(x)))
+            .any(|x| Some(x) > lock_modified);
+        res
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |value: &Option<String>| match value {
+            Some(value) => json::string(value),
+            None => "null".to_string(),
+        };
+        let locked_with = match &self.locked_with {
+            Some((dmenv, python, platform)) => format!(
+                "{{\"dmenv\": {}, \"python\": {}, \"platform\": {}}}",
+                json::string(dmenv),
+                json::string(python),
+                json::string(platform)
+            ),
+            None => "null".to_string(),
+        };
+        let (exit_code, duration_ms) = match &self.outcome {
+            Some((exit_code, duration)) => (
+                exit_code.map_or("null".to_string(), |x| x.to_string()),
+                duration.as_millis().to_string(),
+            ),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let fields = [
+            ("project", json::string(&self.project)),
+            ("lock", optional(&self.lock)),
+            ("locked_with", locked_with),
+            (
+                "lock_age_days",
+                self.lock_age_days
+                    .map_or("null".to_string(), |x| x.to_string()),
+            ),
+            ("lock_outdated", self.lock_outdated.to_string()),
+            ("packages", self.packages.to_string()),
+            ("python", optional(&self.python)),
+            ("exit_code", exit_code),
+            ("duration_ms", duration_ms),
+        ];
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(key, value)| format!("\"{}\": {}", key, value))
+            .collect();
+        format!("    {{{}}}", fields.join(", "))
+    }
+
+    fn to_html(&self) -> String {
+        let cells = [
+            self.project.clone(),
+            self.lock.clone().unwrap_or_default(),
+            self.locked_with
+                .as_ref()
+                .map(|(_, python, platform)| format!("{} ({})", python, platform))
+                .unwrap_or_default(),
+            self.lock_age_days
+                .map(|x| format!("{} days", x))
+                .unwrap_or_default(),
+            if self.lock_outdated { "yes" } else { "no" }.to_string(),
+            self.packages.to_string(),
+            self.python.clone().unwrap_or_default(),
+            match &self.outcome {
+                Some((Some(0), _)) => "ok".to_string(),
+                Some((Some(code), _)) => format!("failed ({})", code),
+                Some((None, _)) => "failed".to_string(),
+                None => String::new(),
+            },
+        ];
+        let cells: Vec<_> = cells
+            .iter()
+            .map(|x| format!("<td>{}</td>", html_escape(x)))
+            .collect();
+        format!("<tr>{}</tr>", cells.join(""))
+    }
+}
+
+/// Parse `# Generated with dmenv 0.11.1, python 3.7.2, on Linux`
+fn parse_lock_header(line: &str) -> Option<(String, String, String)> {
+    let rest = line.strip_prefix("# Generated with dmenv ")?;
+    let mut parts = rest.splitn(3, ", ");
+    let dmenv = parts.next()?;
+    let python = parts.next()?.strip_prefix("python ")?;
+    let platform = parts.next()?.strip_prefix("on ")?;
+    Some((
+        dmenv.to_string(),
+        python.to_string(),
+        platform.trim().to_string(),
+    ))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().ok()?.modified().ok()
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write the report of `--report`, as HTML if `path` ends with `.html`, as JSON otherwise
+pub fn write_report(
+    path: &Path,
+    root: &Path,
+    projects: &[PathBuf],
+    outcomes: &[Outcome],
+) -> Result<(), Error> {
+    let reports: Vec<_> = projects
+        .iter()
+        .map(|project| {
+            let mut report = ProjectReport::new(project, root);
+            report.outcome = outcomes
+                .iter()
+                .find(|x| &x.project == project)
+                .map(|x| (x.exit_code, x.duration));
+            report
+        })
+        .collect();
+    let is_html = path.extension().map_or(false, |x| x == "html");
+    let contents = if is_html {
+        render_html(root, &reports)
+    } else {
+        render_json(root, &reports)
+    };
+    std::fs::write(path, contents).map_err(|e| Error::WriteError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })
+}
+
+fn render_json(root: &Path, reports: &[ProjectReport]) -> String {
+    let projects: Vec<_> = reports.iter().map(|x| x.to_json()).collect();
+    format!(
+        "{{\n  \"dmenv_version\": {},\n  \"root\": {},\n  \"projects\": [\n{}\n  ]\n}}\n",
+        json::string(env!("CARGO_PKG_VERSION")),
+        json::string(&root.display().to_string()),
+        projects.join(",\n")
+    )
+}
+
+fn render_html(root: &Path, reports: &[ProjectReport]) -> String {
+    let headers = [
+        "Project",
+        "Lock",
+        "Locked with",
+        "Lock age",
+        "Lock outdated",
+        "Packages",
+        "Python (dmenv.toml)",
+        "Result",
+    ];
+    let headers: Vec<_> = headers.iter().map(|x| format!("<th>{}</th>", x)).collect();
+    let rows: Vec<_> = reports.iter().map(|x| x.to_html()).collect();
+    let title = format!(
+        "dmenv projects in {}",
+        html_escape(&root.display().to_string())
+    );
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n\
+         <body>\n<h1>{title}</h1>\n<table>\n<tr>{}</tr>\n{}\n</table>\n</body>\n</html>\n",
+        headers.join(""),
+        rows.join("\n"),
+        title = title
+    )
+}
+
+fn relative(project: &Path, root: &Path) -> String {
+    match project.strip_prefix(root) {
+        Ok(path) if path.as_os_str().is_empty() => ".".to_string(),
+        Ok(path) => path.display().to_string(),
+        Err(_) => project.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_skipped() {
+        assert!(is_skipped(".venv"));
+        assert!(is_skipped(".git"));
+        assert!(is_skipped("node_modules"));
+        assert!(!is_skipped("backend"));
+    }
+
+    #[test]
+    fn test_parse_lock_header() {
+        assert_eq!(
+            parse_lock_header("# Generated with dmenv 0.11.1, python 3.7.2, on Linux").unwrap(),
+            (
+                "0.11.1".to_string(),
+                "3.7.2".to_string(),
+                "Linux".to_string()
+            )
+        );
+        assert!(parse_lock_header("attrs==19.1.0").is_none());
+    }
+
+    #[test]
+    fn test_project_report() {
+        let report = ProjectReport {
+            project: "services/billing".to_string(),
+            lock: Some("requirements.lock".to_string()),
+            lock_age_days: Some(12),
+            packages: 3,
+            outcome: Some((Some(4), Duration::from_millis(1500))),
+            ..Default::default()
+        };
+        assert_eq!(
+            report.to_json(),
+            "    {\"project\": \"services/billing\", \"lock\": \"requirements.lock\", \
+             \"locked_with\": null, \"lock_age_days\": 12, \"lock_outdated\": false, \
+             \"packages\": 3, \"python\": null, \"exit_code\": 4, \"duration_ms\": 1500}"
+        );
+        assert!(report.to_html().contains("<td>failed (4)</td>"));
+    }
+
+    #[test]
+    fn test_render_summary() {
+        let root = Path::new("/src");
+        let outcomes = vec![
+            Outcome {
+                project: PathBuf::from("/src"),
+                exit_code: Some(0),
+                duration: Duration::from_millis(1234),
+            },
+            Outcome {
+                project: PathBuf::from("/src/services/billing"),
+                exit_code: Some(3),
+                duration: Duration::from_millis(45600),
+            },
+        ];
+        assert_eq!(
+            render_summary(&outcomes, root),
+            vec![
+                ".                    1.2s  ok",
+                "services/billing    45.6s  failed (exit code 3)",
+            ]
+        );
+    }
+}
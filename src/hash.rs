@@ -0,0 +1,27 @@
+/// Compute a 64-bit FNV-1a hash of the given bytes, as an hex string
+//
+// Note: the result is written in files and must stay the same across
+// dmenv versions and platforms, so we can't use `std::hash`, whose algorithm
+// is not specified. This is *not* a cryptographic hash, and should only be
+// used to detect changes.
+pub fn hash_hex(data: &[u8]) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    let mut hash = OFFSET_BASIS;
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(PRIME);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(hash_hex(b""), "cbf29ce484222325");
+        assert_eq!(hash_hex(b"a"), "af63dc4c8601ec8c");
+    }
+}
@@ -0,0 +1,27 @@
+"""Compute the digests of files, for the `--hash` options of the lock.
+
+Usage: python -c <this script> <algorithm> <path> ...
+
+Print `<hex digest> <path>` for each path.
+"""
+import hashlib
+import sys
+
+CHUNK_SIZE = 1024 * 1024
+
+
+def digest(algorithm, path):
+    res = hashlib.new(algorithm)
+    with open(path, "rb") as f:
+        for chunk in iter(lambda: f.read(CHUNK_SIZE), b""):
+            res.update(chunk)
+    return res.hexdigest()
+
+
+def main():
+    algorithm = sys.argv[1]
+    for path in sys.argv[2:]:
+        print(digest(algorithm, path), path)
+
+
+main()
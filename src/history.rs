@@ -0,0 +1,144 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// Where the history of the commands changing the lock or the virtualenv is
+/// kept, relative to the project
+pub fn history_path(project: &Path) -> PathBuf {
+    project.join(".dmenv").join("history")
+}
+
+/// One line of the history file. Fields are separated by tabs:
+///
+/// ```text
+/// 1571234567	0.12.0	dmenv lock	5f3c8a2d1e4b6a79	ok
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub timestamp: u64,
+    pub dmenv_version: String,
+    pub args: String,
+    /// Hash of the lock after the command ran, or `-` if there was no lock
+    pub lock_hash: String,
+    /// `ok`, or `failed: ` followed by the first line of the error
+    pub outcome: String,
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        let fields = [
+            self.timestamp.to_string(),
+            self.dmenv_version.to_string(),
+            self.args.to_string(),
+            self.lock_hash.to_string(),
+            self.outcome.to_string(),
+        ];
+        let fields: Vec<_> = fields.iter().map(|x| sanitize(x)).collect();
+        fields.join("\t")
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, '\t');
+        Some(Entry {
+            timestamp: fields.next()?.parse().ok()?,
+            dmenv_version: fields.next()?.to_string(),
+            args: fields.next()?.to_string(),
+            lock_hash: fields.next()?.to_string(),
+            outcome: fields.next()?.to_string(),
+        })
+    }
+}
+
+// Tabs and newlines would break the format
+fn sanitize(field: &str) -> String {
+    field.replace(|c| c == '\t' || c == '\n' || c == '\r', " ")
+}
+
+pub fn record(project: &Path, entry: &Entry) -> Result<(), Error> {
+    let path = history_path(project);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::WriteError {
+            path: parent.to_path_buf(),
+            io_error: e,
+        })?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+    writeln!(file, "{}", entry.to_line()).map_err(|e| Error::WriteError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })
+}
+
+/// Read the history, oldest entries first. Lines that can't be parsed are skipped
+pub fn read(project: &Path) -> Result<Vec<Entry>, Error> {
+    let path = history_path(project);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })?;
+    Ok(contents.lines().filter_map(Entry::from_line).collect())
+}
+
+/// Format a UNIX timestamp as `YYYY-MM-DD HH:MM:SS` (UTC)
+// Note: see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let entry = Entry {
+            timestamp: 1_571_234_567,
+            dmenv_version: "0.12.0".to_string(),
+            args: "dmenv bump-in-lock foo\t0.43".to_string(),
+            lock_hash: "-".to_string(),
+            outcome: "failed: 'foo' not found in lock".to_string(),
+        };
+        let line = entry.to_line();
+        let actual = Entry::from_line(&line).unwrap();
+        assert_eq!(actual.args, "dmenv bump-in-lock foo 0.43");
+        assert_eq!(actual.outcome, entry.outcome);
+        assert!(Entry::from_line("not an entry").is_none());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1_571_234_567), "2019-10-16 14:02:47");
+    }
+}
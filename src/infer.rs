@@ -0,0 +1,71 @@
+use std::path::Path;
+
+// Values used by `dmenv init` when they are not given on the command line.
+//
+// Note: all of these are best-effort: errors (no git binary, not a git
+// repository, no tags ...) just mean nothing could be inferred.
+
+/// Use the name of the project directory
+pub fn project_name(project_path: &Path) -> Option<String> {
+    project_path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+}
+
+/// Use `user.name` and `user.email` from git config, like `Jane Doe <jane@example.com>`
+pub fn author(project_path: &Path) -> Option<String> {
+    let name = git_output(project_path, &["config", "user.name"]);
+    let email = git_output(project_path, &["config", "user.email"]);
+    match (name, email) {
+        (Some(name), Some(email)) => Some(format!("{} <{}>", name, email)),
+        (Some(name), None) => Some(name),
+        (None, Some(email)) => Some(email),
+        (None, None) => None,
+    }
+}
+
+/// Use the latest git tag, without the `v` prefix
+pub fn version(project_path: &Path) -> Option<String> {
+    let tag = git_output(project_path, &["describe", "--tags", "--abbrev=0"])?;
+    Some(version_from_tag(&tag))
+}
+
+pub fn version_from_tag(tag: &str) -> String {
+    tag.trim_start_matches('v').to_string()
+}
+
+fn git_output(project_path: &Path, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .current_dir(project_path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let res = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if res.is_empty() {
+        None
+    } else {
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_name() {
+        assert_eq!(
+            project_name(Path::new("/home/jane/foo")),
+            Some("foo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_version_from_tag() {
+        assert_eq!(version_from_tag("v1.2.0"), "1.2.0");
+        assert_eq!(version_from_tag("0.4"), "0.4");
+    }
+}
@@ -2,3 +2,4 @@ import platform
 
 print(platform.python_version())
 print(platform.system())
+print(platform.python_implementation())
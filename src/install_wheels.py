@@ -0,0 +1,427 @@
+"""Install pure-Python wheels listed in a lock file without going through pip.
+
+Usage: python -c <this script> <lock path> --cache <dir> --fallback <path>
+                                [--store <dir>] [--target <dir>]
+
+Every line of the lock that can not be handled here (sdists, binary wheels,
+markers, git URLs, packages already installed with a different version ...)
+is written to the fallback path so that dmenv can hand it over to pip.
+
+When --store is given, files are written only once in this content-addressed
+store and hard-linked into the virtualenv.
+
+When --target is given, packages are installed in this directory instead of the
+virtualenv (like `pip install --target`).
+
+When DMENV_INSTALL_WHEELS_INDEXES is set (to `<patterns>=<url>` entries separated by
+spaces), the JSON API of these indexes is used instead of the one of pypi.org. Indexes
+are tried in order, and those with patterns other than `*` are the only ones used for
+the packages matching them (see indexes.rs). Indexes without a JSON API (static
+directories, devpi `+simple` URLs ...) are read through their simple API instead.
+"""
+import argparse
+import base64
+import configparser
+import csv
+import fnmatch
+import hashlib
+import html.parser
+import http.client
+import json
+import os
+import re
+import sys
+import sysconfig
+import urllib.parse
+import urllib.request
+import zipfile
+
+PYPI_JSON_URL = "https://pypi.org/pypi/{name}/{version}/json"
+
+SCRIPT_TEMPLATE = """#!{python}
+# -*- coding: utf-8 -*-
+import re
+import sys
+from {module} import {import_name}
+if __name__ == '__main__':
+    sys.argv[0] = re.sub(r'(-script\\.pyw|\\.exe)?$', '', sys.argv[0])
+    sys.exit({call}())
+"""
+
+
+# Set from the command line, see `link_from_store()`
+STORE_DIR = None
+
+# Set from DMENV_INSTALL_WHEELS_INDEXES: list of (patterns, url), see `index_urls()`
+INDEXES = []
+
+# Maps host names to the Authorization header to use, from index URLs
+# containing credentials
+AUTH_HEADERS = {}
+
+# Maps the sub-directories of `.data` to `sysconfig` paths
+SCHEME_KEYS = {"headers": "include"}
+
+
+class Fallback(Exception):
+    pass
+
+
+class LinkParser(html.parser.HTMLParser):
+    """Collect the targets of the links of a simple API page"""
+
+    def __init__(self):
+        super().__init__()
+        self.links = []
+
+    def handle_starttag(self, tag, attrs):
+        href = dict(attrs).get("href")
+        if tag == "a" and href:
+            self.links.append(href)
+
+
+def canonical_name(name):
+    return re.sub(r"[-_.]+", "-", name).lower()
+
+
+def parse_lock_line(line):
+    """Return (name, version) for lines like `foo==1.2`, None otherwise"""
+    # Trailing comments, like `foo==1.2  # pinned`
+    line = re.sub(r"\s+#.*$", "", line)
+    if ";" in line or "#" in line:
+        return None
+    match = re.match(r"^([A-Za-z0-9][A-Za-z0-9._-]*)\s*==\s*([^\s]+)$", line)
+    if not match:
+        return None
+    return match.group(1), match.group(2)
+
+
+def installed_versions(purelib):
+    res = {}
+    for entry in os.listdir(purelib):
+        if not entry.endswith(".dist-info"):
+            continue
+        name, version = entry[: -len(".dist-info")].rsplit("-", 1)
+        res[canonical_name(name)] = version
+    return res
+
+
+def is_pure_python_wheel(filename):
+    # {name}-{version}(-{build})?-{python}-{abi}-{platform}.whl
+    parts = filename[: -len(".whl")].split("-")
+    if len(parts) not in (5, 6):
+        return False
+    python_tags, abi, platform = parts[-3:]
+    current = "py{}{}".format(*sys.version_info[:2])
+    compatible = set(python_tags.split(".")) & {"py3", current}
+    return abi == "none" and platform == "any" and bool(compatible)
+
+
+def parse_index(arg):
+    patterns, url = arg.split("=", 1)
+    parts = urllib.parse.urlsplit(url)
+    if parts.username:
+        user_pass = "{}:{}".format(
+            urllib.parse.unquote(parts.username),
+            urllib.parse.unquote(parts.password or ""),
+        )
+        token = base64.b64encode(user_pass.encode("utf-8")).decode("ascii")
+        AUTH_HEADERS[parts.hostname] = "Basic " + token
+        netloc = parts.netloc.rsplit("@", 1)[1]
+        url = urllib.parse.urlunsplit(parts._replace(netloc=netloc))
+    return [canonical_name(x) for x in patterns.split(",")], url
+
+
+def urlopen(url):
+    request = urllib.request.Request(url)
+    auth_header = AUTH_HEADERS.get(urllib.parse.urlsplit(url).hostname)
+    if auth_header:
+        request.add_header("Authorization", auth_header)
+    return urllib.request.urlopen(request)
+
+
+def index_urls(name):
+    """URLs of the indexes to use for this package, in order"""
+
+    def matches(patterns):
+        return any(fnmatch.fnmatchcase(canonical_name(name), x) for x in patterns)
+
+    urls = [
+        url for patterns, url in INDEXES if "*" not in patterns and matches(patterns)
+    ]
+    if not urls:
+        urls = [url for patterns, url in INDEXES if "*" in patterns]
+    return urls
+
+
+def json_urls(name, version):
+    """URLs of the JSON API to query for this release, in order"""
+    if not INDEXES:
+        return [PYPI_JSON_URL.format(name=name, version=version)]
+    res = []
+    for url in index_urls(name):
+        # Indexes compatible with PyPI serve the JSON API next to the simple one
+        base, n = re.subn(r"/simple/?$", "/pypi", url)
+        if n:
+            res.append("{}/{}/{}/json".format(base, name, version))
+    return res
+
+
+def find_wheel(name, version):
+    errors = []
+    release = None
+    for url in json_urls(name, version):
+        try:
+            with urlopen(url) as response:
+                release = json.loads(response.read().decode("utf-8"))
+            break
+        except Exception as e:
+            errors.append("could not query {}: {}".format(url, e))
+    if release is None:
+        return find_wheel_in_simple_index(name, version, errors)
+    for candidate in release.get("urls", []):
+        if candidate["packagetype"] != "bdist_wheel":
+            continue
+        if is_pure_python_wheel(candidate["filename"]):
+            return candidate
+    raise Fallback("no pure-Python wheel found")
+
+
+def find_wheel_in_simple_index(name, version, errors):
+    """Look for the wheel in the simple API pages of the indexes. Only links
+    with a sha256 fragment are used, so that downloads can be checked"""
+    for url in index_urls(name):
+        page_url = "{}/{}/".format(url.rstrip("/"), canonical_name(name))
+        try:
+            with urlopen(page_url) as response:
+                parser = LinkParser()
+                parser.feed(response.read().decode("utf-8"))
+        except Exception as e:
+            errors.append("could not query {}: {}".format(page_url, e))
+            continue
+        for link in parser.links:
+            wheel_url, _, fragment = urllib.parse.urljoin(page_url, link).partition("#")
+            path = urllib.parse.urlsplit(wheel_url).path
+            filename = urllib.parse.unquote(path.rsplit("/", 1)[-1])
+            if not filename.endswith(".whl") or not fragment.startswith("sha256="):
+                continue
+            parts = filename.split("-")
+            if canonical_name(parts[0]) != canonical_name(name) or parts[1] != version:
+                continue
+            if is_pure_python_wheel(filename):
+                return {
+                    "filename": filename,
+                    "url": wheel_url,
+                    "digests": {"sha256": fragment[len("sha256=") :]},
+                }
+        errors.append("no pure-Python wheel found in {}".format(page_url))
+    raise Fallback("; ".join(errors) or "no index with a JSON API")
+
+
+def sha256(data):
+    return hashlib.sha256(data).hexdigest()
+
+
+def download(wheel, cache_dir):
+    path = os.path.join(cache_dir, wheel["filename"])
+    expected = wheel["digests"]["sha256"]
+    if os.path.exists(path):
+        with open(path, "rb") as f:
+            if sha256(f.read()) == expected:
+                return path
+    # Note: HTTPError and URLError are OSErrors too
+    try:
+        with urlopen(wheel["url"]) as response:
+            data = response.read()
+    except (OSError, http.client.HTTPException) as e:
+        raise Fallback("could not download {}: {}".format(wheel["filename"], e))
+    if sha256(data) != expected:
+        raise Fallback("checksum mismatch for {}".format(wheel["filename"]))
+    # Write the file atomically, so that an interrupted download can't
+    # leave a truncated wheel in the cache
+    tmp = "{}.{}.tmp".format(path, os.getpid())
+    with open(tmp, "wb") as f:
+        f.write(data)
+    os.replace(tmp, path)
+    return path
+
+
+def record_hash(data):
+    digest = hashlib.sha256(data).digest()
+    return "sha256=" + base64.urlsafe_b64encode(digest).decode("ascii").rstrip("=")
+
+
+def link_from_store(path, data):
+    """Hard-link `path` to a file of the store having the same contents,
+    writing it in the store first if needed.
+    Return False if the link could not be created"""
+    digest = sha256(data)
+    stored = os.path.join(STORE_DIR, digest[:2], digest[2:])
+    if not os.path.exists(stored):
+        os.makedirs(os.path.dirname(stored), exist_ok=True)
+        tmp = "{}.{}.tmp".format(stored, os.getpid())
+        with open(tmp, "wb") as f:
+            f.write(data)
+        os.replace(tmp, stored)
+    if os.path.exists(path):
+        os.remove(path)
+    try:
+        os.link(stored, path)
+    except OSError:
+        # For instance when the store and the virtualenv are on different devices
+        return False
+    return True
+
+
+def write_file(path, data, records, purelib, shared=True):
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    if not (shared and STORE_DIR and link_from_store(path, data)):
+        with open(path, "wb") as f:
+            f.write(data)
+    relpath = os.path.relpath(path, purelib)
+    records.append((relpath, record_hash(data), str(len(data))))
+
+
+def write_script(path, data, records, purelib):
+    if data.startswith(b"#!python"):
+        data = b"#!" + sys.executable.encode() + data[len(b"#!python") :]
+    # Scripts are specific to each virtualenv and get chmod'ed,
+    # so never share them
+    write_file(path, data, records, purelib, shared=False)
+    os.chmod(path, 0o755)
+
+
+def generate_entry_points(entry_points, scripts_dir, records, purelib):
+    parser = configparser.ConfigParser(delimiters=("=",))
+    parser.optionxform = str
+    parser.read_string(entry_points)
+    for section in ("console_scripts", "gui_scripts"):
+        if not parser.has_section(section):
+            continue
+        for name, value in parser.items(section):
+            module, _, attrs = value.strip().partition(":")
+            attrs = attrs.split("[")[0].strip()
+            import_name = attrs.split(".")[0]
+            contents = SCRIPT_TEMPLATE.format(
+                python=sys.executable,
+                module=module.strip(),
+                import_name=import_name,
+                call=attrs,
+            )
+            path = os.path.join(scripts_dir, name)
+            write_script(path, contents.encode("utf-8"), records, purelib)
+
+
+def is_safe_name(name):
+    """Whether the member of a wheel is written below the installation directories"""
+    parts = name.replace("\\", "/").split("/")
+    return not (name.startswith(("/", "\\")) or ":" in parts[0] or ".." in parts)
+
+
+def install_wheel(wheel_path, paths):
+    purelib = paths["purelib"]
+    records = []
+    with zipfile.ZipFile(wheel_path) as archive:
+        names = archive.namelist()
+        unsafe = [n for n in names if not is_safe_name(n)]
+        if unsafe:
+            sys.exit(
+                "{}: refusing to write outside of the virtualenv: {}".format(
+                    os.path.basename(wheel_path), ", ".join(unsafe)
+                )
+            )
+        dist_info = next(n.split("/")[0] for n in names if ".dist-info/" in n)
+        wheel_metadata = archive.read(dist_info + "/WHEEL").decode("utf-8")
+        if "Root-Is-Purelib: true" not in wheel_metadata:
+            raise Fallback("wheel is not purelib")
+        entry_points = dist_info + "/entry_points.txt"
+        if os.name == "nt" and entry_points in names:
+            # Windows needs .exe launchers, leave that to pip
+            raise Fallback("entry points on Windows")
+        data_dir = dist_info[: -len(".dist-info")] + ".data"
+        for name in names:
+            if name.endswith("/") or name == dist_info + "/RECORD":
+                continue
+            data = archive.read(name)
+            if name.startswith(data_dir + "/"):
+                _, key, relpath = name.split("/", 2)
+                dest = os.path.join(paths[SCHEME_KEYS.get(key, key)], relpath)
+                if key == "scripts":
+                    write_script(dest, data, records, purelib)
+                    continue
+            else:
+                dest = os.path.join(purelib, name)
+            write_file(dest, data, records, purelib)
+        if entry_points in names:
+            contents = archive.read(entry_points).decode("utf-8")
+            generate_entry_points(contents, paths["scripts"], records, purelib)
+
+    installer_path = os.path.join(purelib, dist_info, "INSTALLER")
+    write_file(installer_path, b"dmenv\n", records, purelib)
+    record_path = os.path.join(purelib, dist_info, "RECORD")
+    with open(record_path, "w", newline="") as f:
+        writer = csv.writer(f)
+        writer.writerows(records)
+        writer.writerow((os.path.relpath(record_path, purelib), "", ""))
+
+
+def target_paths(target):
+    return {
+        "purelib": target,
+        "platlib": target,
+        "scripts": os.path.join(target, "bin"),
+        "data": target,
+        "include": os.path.join(target, "include"),
+    }
+
+
+def main():
+    global STORE_DIR
+    parser = argparse.ArgumentParser()
+    parser.add_argument("lock_path")
+    parser.add_argument("--cache", required=True)
+    parser.add_argument("--fallback", required=True)
+    parser.add_argument("--store")
+    parser.add_argument("--target")
+    args = parser.parse_args()
+    STORE_DIR = args.store
+    indexes = os.environ.get("DMENV_INSTALL_WHEELS_INDEXES", "").split()
+    INDEXES.extend(parse_index(x) for x in indexes)
+    if args.target:
+        os.makedirs(args.target, exist_ok=True)
+        paths = target_paths(args.target)
+    else:
+        paths = sysconfig.get_paths()
+    installed = installed_versions(paths["purelib"])
+    lock_path, cache_dir, fallback_path = args.lock_path, args.cache, args.fallback
+    fallback = []
+    # Note: utf-8-sig also handles locks starting with a byte order mark
+    with open(lock_path, encoding="utf-8-sig") as f:
+        lines = [l.strip() for l in f.readlines()]
+    for line in lines:
+        if not line or line.startswith("#"):
+            continue
+        parsed = parse_lock_line(line)
+        if not parsed:
+            fallback.append(line)
+            continue
+        name, version = parsed
+        current = installed.get(canonical_name(name))
+        if current == version:
+            continue
+        try:
+            if current:
+                raise Fallback("{} is already installed".format(current))
+            wheel = find_wheel(name, version)
+            wheel_path = download(wheel, cache_dir)
+            install_wheel(wheel_path, paths)
+            print("+", name, version)
+        except Fallback as e:
+            print("->", name, version, "will be installed by pip:", e)
+            fallback.append(line)
+    with open(fallback_path, "w") as f:
+        f.write("\n".join(fallback))
+
+
+if __name__ == "__main__":
+    main()
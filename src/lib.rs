@@ -1,93 +1,717 @@
 use colored::*;
-use std::path::PathBuf;
+use std::path::Path;
 
+mod bench;
+mod build_isolation;
+mod bump_batch;
+mod bundle;
+mod cache;
+mod changeset;
+mod ci;
 mod cmd;
+mod coverage;
+mod dedupe;
 mod dependencies;
+mod digest;
+mod entry_points;
+mod env_file;
 mod error;
+mod events;
 #[cfg(unix)]
 mod execv;
+pub mod exit_code;
+mod external;
+mod extras;
+mod find_links;
+mod foreach;
+mod hash;
+mod history;
+mod index_auth;
+mod indexes;
+mod infer;
+mod json;
 mod lock;
+mod lock_backup;
+mod lock_hashes;
+mod lock_stats;
+mod logs;
+mod manifest;
+mod marker;
+mod migrate;
+mod msys;
+mod notices;
+mod outdated;
+mod overrides;
 mod paths;
+mod pip_config;
+mod platform_deps;
+mod plugin;
+mod policy;
+mod precommit;
+mod presets;
+mod project_config;
+mod project_version;
+mod protect;
+mod pyproject;
 mod python_info;
+mod release_info;
+mod relocate;
+mod remote;
+mod rename;
+mod required_version;
+mod run_env;
+mod runner;
+mod scaffold;
+mod script_metadata;
+mod service;
 mod settings;
+mod setup_py;
+mod shared;
+mod shim;
+mod simulate_ci;
+mod state;
+mod template;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod tools;
+mod tty;
+mod typosquat;
+mod variants;
 mod venv_manager;
+mod watch;
+mod wheel_check;
+#[cfg(windows)]
+mod win_console;
 #[cfg(windows)]
 mod win_job;
+mod wsl;
 
+pub use crate::bump_batch::{BumpResult, Instruction as BumpInstruction, Status as BumpStatus};
 pub use crate::cmd::Command;
-use crate::cmd::SubCommand;
+use crate::cmd::{
+    ascii_output, print_warning, use_ascii_output, BenchCommand, CacheCommand, CiCommand,
+    EnvCommand, HashCommand, LockCommand, PrecommitCommand, QueryCommand, RemoteCommand,
+    ScriptCommand, ScriptsCommand, ServiceCommand, SubCommand, ToolCommand, VenvCommand,
+};
 pub use crate::cmd::{print_error, print_info_1, print_info_2};
 pub use crate::error::Error;
+pub use crate::external::ExternalCommand;
 use crate::paths::PathsResolver;
 pub use crate::paths::{DEV_LOCK_FILENAME, PROD_LOCK_FILENAME};
-use crate::python_info::PythonInfo;
+pub use crate::plugin::{Context, Plugin};
+use crate::project_config::ProjectConfig;
+use crate::python_info::{binary_from_spec, PythonInfo};
+use crate::remote::Remote;
+use crate::runner::SystemRunner;
+pub use crate::runner::{CommandRunner, Process, ProcessOutput};
+use crate::script_metadata::ScriptMetadata;
 pub use crate::settings::Settings;
 use crate::venv_manager::VenvManager;
-use crate::venv_manager::{InstallOptions, LockOptions};
+use crate::venv_manager::{BundleOptions, InstallOptions, LockOptions, RunOptions};
+#[cfg(windows)]
+pub use crate::win_console::Console;
+use crate::wsl::CrossMount;
 
 pub fn run(cmd: Command) -> Result<(), Error> {
-    let settings = Settings::from_shell(&cmd);
-    let project_path = if let Some(project_path) = cmd.project_path {
-        PathBuf::from(project_path)
-    } else {
-        std::env::current_dir().map_err(|e| Error::Other {
-            message: format!("Could not get current directory: {}", e),
-        })?
-    };
+    run_with_plugins(cmd, vec![])
+}
+
+/// Like `run()`, calling the hooks of the given plugins. See the `Plugin` trait
+pub fn run_with_plugins(cmd: Command, plugins: Vec<Box<dyn Plugin>>) -> Result<(), Error> {
+    run_with_runner(cmd, plugins, Box::new(SystemRunner))
+}
+
+/// Like `run_with_plugins()`, starting the Python processes with the given
+/// runner. See the `CommandRunner` trait
+pub fn run_with_runner(
+    cmd: Command,
+    plugins: Vec<Box<dyn Plugin>>,
+    runner: Box<dyn CommandRunner>,
+) -> Result<(), Error> {
+    run_main(cmd, &[], plugins, runner)
+}
+
+/// Like `run()`, with the arguments `cmd` was parsed from, including the
+/// program name. See `Settings::command_line`
+pub fn run_with_command_line(cmd: Command, command_line: &[String]) -> Result<(), Error> {
+    run_main(cmd, command_line, vec![], Box::new(SystemRunner))
+}
+
+fn run_main(
+    cmd: Command,
+    command_line: &[String],
+    plugins: Vec<Box<dyn Plugin>>,
+    runner: Box<dyn CommandRunner>,
+) -> Result<(), Error> {
+    if ascii_output(cmd.ascii) {
+        use_ascii_output();
+    }
+    if let Some(dest) = &cmd.event_stream {
+        events::open(dest)?;
+    }
+    let res = run_sub_cmd(cmd, command_line, plugins, runner);
+    events::finish(&res);
+    res
+}
+
+fn run_sub_cmd(
+    cmd: Command,
+    command_line: &[String],
+    plugins: Vec<Box<dyn Plugin>>,
+    runner: Box<dyn CommandRunner>,
+) -> Result<(), Error> {
     // Perform additional sanity checks when using `dmenv run`
     // TODO: try and handle this using StructOpt instead
     if let SubCommand::Run { ref cmd, .. } = cmd.sub_cmd {
         if cmd.is_empty() {
-            return Err(Error::Other {
+            return Err(Error::Usage {
                 message: format!("Missing argument after '{}'", "run".green()),
             });
         }
     }
-    let python_info = PythonInfo::new(&cmd.python_binary)?;
-    let python_version = python_info.version.clone();
-    let resolver = PathsResolver::new(project_path, &python_version, &settings);
-    let paths = resolver.paths()?;
-    let venv_manager = VenvManager::new(paths, python_info, settings);
+    // Note: this does not need a virtualenv, nor even a Python interpreter
+    if let SubCommand::ExplainExitCodes {} = cmd.sub_cmd {
+        exit_code::print_table();
+        return Ok(());
+    }
+    // Note: each project is handled by its own dmenv process
+    if let SubCommand::Foreach {
+        root,
+        list,
+        fail_fast,
+        report,
+        cmd: args,
+    } = &cmd.sub_cmd
+    {
+        return run_foreach(root, *list, *fail_fast, report, args);
+    }
+    let venv_manager = new_venv_manager(&cmd, command_line, runner)?.with_plugins(plugins);
     // Note: keep the `match()` here so that we know every variant of the SubCommand
     // enum is handled.
-    match &cmd.sub_cmd {
-        SubCommand::Install { no_develop } => {
-            let mut install_options = InstallOptions::default();
-            install_options.develop = !no_develop;
+    let res = match &cmd.sub_cmd {
+        SubCommand::Bench { bench_cmd } => match bench_cmd {
+            BenchCommand::Install {
+                iterations,
+                installer,
+            } => {
+                let install_options = InstallOptions {
+                    develop: true,
+                    installer: *installer,
+                    allow_foreign_venv: false,
+                    no_build_isolation: false,
+                    extras: vec![],
+                    enforce_policy: false,
+                    check_wheels: false,
+                };
+                venv_manager.bench("install", *iterations, || {
+                    venv_manager.install(&install_options)
+                })
+            }
+            BenchCommand::Lock { iterations } => {
+                let lock_options = LockOptions::default();
+                venv_manager.bench("lock", *iterations, || venv_manager.lock(&lock_options))
+            }
+        },
+        SubCommand::Install {
+            no_develop,
+            installer,
+            allow_foreign_venv,
+            no_build_isolation,
+            extras,
+            enforce_policy,
+            check_wheels,
+        } => {
+            let install_options = InstallOptions {
+                develop: !no_develop,
+                installer: *installer,
+                allow_foreign_venv: *allow_foreign_venv,
+                no_build_isolation: *no_build_isolation,
+                extras: extras.clone(),
+                enforce_policy: *enforce_policy,
+                check_wheels: *check_wheels,
+            };
             venv_manager.install(&install_options)
         }
+        SubCommand::Bundle {
+            format,
+            entry_point,
+            output,
+            platform,
+            layer,
+        } => {
+            let bundle_options = BundleOptions {
+                format: *format,
+                entry_point: entry_point.clone(),
+                output: output.clone(),
+                platform: platform.clone(),
+                layer: *layer,
+            };
+            venv_manager.bundle(&bundle_options)
+        }
+        SubCommand::Cache { cache_cmd } => match cache_cmd {
+            CacheCommand::Key {} => venv_manager.show_cache_key(),
+        },
+        SubCommand::Ci { ci_cmd } => match ci_cmd {
+            CiCommand::Github { set_output } => venv_manager.ci_github(*set_output),
+        },
         SubCommand::Clean {} => venv_manager.clean(),
-        SubCommand::Develop {} => venv_manager.develop(),
+        SubCommand::Develop { uninstall } => {
+            if *uninstall {
+                venv_manager.develop_uninstall()
+            } else {
+                venv_manager.develop()
+            }
+        }
+        SubCommand::Doctor {} => venv_manager.doctor(),
+        // Note: handled above, before creating the VenvManager
+        SubCommand::ExplainExitCodes {} | SubCommand::Foreach { .. } => Ok(()),
+        SubCommand::ExplainLockLine { number } => venv_manager.explain_lock_line(*number),
+        SubCommand::Env {
+            output,
+            format,
+            env_cmd,
+        } => match env_cmd {
+            None => venv_manager.env(output, *format),
+            Some(EnvCommand::Create {
+                develop, installer, ..
+            }) => {
+                let install_options = InstallOptions {
+                    develop: *develop,
+                    installer: *installer,
+                    allow_foreign_venv: false,
+                    no_build_isolation: false,
+                    extras: vec![],
+                    enforce_policy: false,
+                    check_wheels: false,
+                };
+                venv_manager.install(&install_options)
+            }
+        },
         SubCommand::Init {
             name,
             version,
             author,
-        } => venv_manager.init(&name, &version, author),
+            existing,
+            interactive,
+            yes,
+        } => {
+            if *existing {
+                venv_manager.adopt(*yes)
+            } else if *interactive {
+                venv_manager.init_interactive(name, version, author)
+            } else {
+                venv_manager.init(name, version, author, *yes)
+            }
+        }
+        SubCommand::Fmt { check } => venv_manager.fmt(*check),
+        SubCommand::Hash { hash_cmd } => match hash_cmd {
+            HashCommand::Add { algorithm, vendor } => venv_manager.add_hashes(*algorithm, vendor),
+            HashCommand::Verify { vendor } => venv_manager.verify_hashes(vendor),
+        },
+        SubCommand::History {} => venv_manager.history(),
+        SubCommand::Info { paths } => venv_manager.info(*paths),
+        SubCommand::Lint {} => venv_manager.lint(),
+        SubCommand::Logs {} => venv_manager.logs(),
         SubCommand::Lock {
             python_version,
             sys_platform,
+            override_pin,
+            allow_foreign_venv,
+            no_build_isolation,
+            build_requirements,
+            extras,
+            hashes,
+            check: false,
+            lock_cmd: None,
         } => {
             let lock_options = LockOptions {
                 python_version: python_version.clone(),
                 sys_platform: sys_platform.clone(),
+                override_pins: *override_pin,
+                allow_foreign_venv: *allow_foreign_venv,
+                no_build_isolation: *no_build_isolation,
+                build_requirements: build_requirements.clone(),
+                extras: extras.clone(),
+                hashes: *hashes,
             };
             venv_manager.lock(&lock_options)
         }
-        SubCommand::BumpInLock { name, version, git } => {
-            venv_manager.bump_in_lock(name, version, *git)
-        }
-        SubCommand::Run { ref cmd, no_exec } => {
-            if *no_exec {
-                venv_manager.run_no_exec(cmd)
-            } else {
-                venv_manager.run(cmd)
+        SubCommand::Lock {
+            lock_cmd: Some(LockCommand::Stats { json, offline }),
+            ..
+        } => venv_manager.lock_stats(*json, *offline),
+        SubCommand::Lock { check: true, .. } => venv_manager.check_lock(),
+        SubCommand::Outdated { json } => venv_manager.outdated(*json),
+        SubCommand::BumpInLock {
+            name,
+            version,
+            git,
+            marker,
+            override_pin,
+            stdin_json,
+        } => match (name, version) {
+            _ if *stdin_json => bump_from_stdin(&venv_manager),
+            (Some(name), Some(version)) => {
+                venv_manager.bump_in_lock(name, version, *git, marker, *override_pin)
+            }
+            _ => Err(Error::Usage {
+                message: format!("Missing name or version after '{}'", "bump-in-lock".green()),
+            }),
+        },
+        SubCommand::Pin {
+            name,
+            reason,
+            marker,
+        } => venv_manager.pin(name, reason, marker),
+        SubCommand::Migrate { check } => venv_manager.migrate(*check),
+        SubCommand::Pins {} => venv_manager.pins(),
+        SubCommand::Precommit { precommit_cmd } => match precommit_cmd {
+            PrecommitCommand::Sync { check } => venv_manager.precommit_sync(*check),
+        },
+        SubCommand::Prefetch { watch } => venv_manager.prefetch(*watch),
+        SubCommand::Query { query_cmd } => match query_cmd {
+            QueryCommand::Packages {} => venv_manager.query_packages(),
+            QueryCommand::Pythons {} => venv_manager.query_pythons(),
+            QueryCommand::Scripts {} => venv_manager.query_scripts(),
+        },
+        SubCommand::Remote { remote_cmd } => match remote_cmd {
+            RemoteCommand::Install { host, port, path } => {
+                let remote = Remote {
+                    host: host.clone(),
+                    port: *port,
+                };
+                venv_manager.remote_install(&remote, path)
             }
+        },
+        SubCommand::Restore { archive } => venv_manager.restore(archive),
+        SubCommand::Run {
+            ref cmd,
+            no_exec,
+            reinstall_if_stale,
+            fail_if_stale,
+            allow_venv_mutation,
+            preset,
+            explain,
+        } => {
+            let run_options = RunOptions {
+                no_exec: *no_exec,
+                reinstall_if_stale: *reinstall_if_stale,
+                fail_if_stale: *fail_if_stale,
+                allow_venv_mutation: *allow_venv_mutation,
+                preset: preset.clone(),
+                explain: *explain,
+            };
+            venv_manager.run_command(cmd, &run_options)
+        }
+        // Note: shortcuts for `dmenv run python` and `dmenv run python -m pip`
+        SubCommand::Python {
+            no_exec,
+            reinstall_if_stale,
+            fail_if_stale,
+            args,
+        } => {
+            let run_options = RunOptions {
+                no_exec: *no_exec,
+                reinstall_if_stale: *reinstall_if_stale,
+                fail_if_stale: *fail_if_stale,
+                ..Default::default()
+            };
+            let mut cmd = vec!["python".to_string()];
+            cmd.extend(args.iter().cloned());
+            venv_manager.run_command(&cmd, &run_options)
+        }
+        SubCommand::Pip {
+            no_exec,
+            reinstall_if_stale,
+            fail_if_stale,
+            allow_venv_mutation,
+            args,
+        } => {
+            let run_options = RunOptions {
+                no_exec: *no_exec,
+                reinstall_if_stale: *reinstall_if_stale,
+                fail_if_stale: *fail_if_stale,
+                allow_venv_mutation: *allow_venv_mutation,
+                ..Default::default()
+            };
+            let mut cmd = vec!["python".to_string(), "-m".to_string(), "pip".to_string()];
+            cmd.extend(args.iter().cloned());
+            venv_manager.run_command(&cmd, &run_options)
         }
+        SubCommand::Script { script_cmd } => match script_cmd {
+            ScriptCommand::Run { script, args } => venv_manager.run_script(script, args),
+        },
+        SubCommand::Scripts { scripts_cmd } => match scripts_cmd {
+            ScriptsCommand::Regen {} => venv_manager.regen_scripts(),
+        },
+        SubCommand::Service { service_cmd } => match service_cmd {
+            ServiceCommand::Generate {
+                entry_point,
+                format,
+                output,
+            } => venv_manager.generate_service(entry_point, *format, output),
+        },
+        SubCommand::SimulateCi { vendor, report } => venv_manager.simulate_ci(vendor, report),
+        SubCommand::Snapshot { output } => venv_manager.snapshot(output),
         SubCommand::ShowDeps {} => venv_manager.show_deps(),
+        SubCommand::Status {} => venv_manager.status(),
         SubCommand::ShowVenvPath {} => venv_manager.show_venv_path(),
         SubCommand::ShowVenvBin {} => venv_manager.show_venv_bin_path(),
+        SubCommand::Shim { command } => venv_manager.shim(command),
+        SubCommand::Tool { tool_cmd } => match tool_cmd {
+            ToolCommand::Install { name } => venv_manager.tool_install(name),
+            ToolCommand::List {} => venv_manager.tool_list(),
+            ToolCommand::Sync {} => venv_manager.tool_sync(),
+            ToolCommand::Upgrade { name } => venv_manager.tool_upgrade(name),
+            ToolCommand::Uninstall { name } => venv_manager.tool_uninstall(name),
+        },
+        SubCommand::Undo {} => venv_manager.undo(),
         SubCommand::UpgradePip {} => venv_manager.upgrade_pip(),
+        SubCommand::Rename { name } => venv_manager.rename(name),
+        SubCommand::Version {
+            bump,
+            commit,
+            build,
+        } => venv_manager.bump_version(bump, *commit, *build),
+        SubCommand::Watch { cmd } => venv_manager.watch(cmd),
+        SubCommand::Venv { venv_cmd } => match venv_cmd {
+            VenvCommand::Dedupe { report } => venv_manager.dedupe(*report),
+            VenvCommand::FreezePerms {} => venv_manager.freeze_perms(true),
+            VenvCommand::UnfreezePerms {} => venv_manager.freeze_perms(false),
+        },
+    };
+    if cmd.sub_cmd.is_recorded_in_history() {
+        venv_manager.record_history(&res);
+    }
+    res
+}
+
+/// Run a dmenv command in every project found below `root`, see the `foreach` module
+fn run_foreach(
+    root: &Option<String>,
+    list: bool,
+    fail_fast: bool,
+    report: &Option<String>,
+    args: &[String],
+) -> Result<(), Error> {
+    let root = match root {
+        Some(root) => msys::native_path(root),
+        None => std::env::current_dir().map_err(|e| Error::Other {
+            message: format!("Could not get current directory: {}", e),
+        })?,
+    };
+    let projects = foreach::discover(&root)?;
+    if list {
+        for project in projects {
+            println!("{}", project.display());
+        }
+        return Ok(());
+    }
+    // Note: with `--report`, the command is optional
+    let outcomes = if !args.is_empty() {
+        foreach::run(&projects, &root, args, fail_fast)?
+    } else if report.is_some() {
+        vec![]
+    } else {
+        return Err(Error::Usage {
+            message: format!("Missing argument after '{}'", "foreach".green()),
+        });
+    };
+    if let Some(report) = report {
+        foreach::write_report(Path::new(report), &root, &projects, &outcomes)?;
+        print_info_2(&format!("Report written to {}", report));
+    }
+    foreach::check(&outcomes)
+}
+
+/// Run an external command (`dmenv-<name>` in PATH), giving it the same
+/// context as built-in commands
+pub fn run_external(external: &ExternalCommand) -> Result<(), Error> {
+    let cmd = external.global_command()?;
+    if ascii_output(cmd.ascii) {
+        use_ascii_output();
+    }
+    let venv_manager = new_venv_manager(&cmd, &[], Box::new(SystemRunner))?;
+    venv_manager.run_external(external)
+}
+
+/// Warn when the project is on the other side of WSL, or move the virtualenv
+/// outside the project if DMENV_WSL_NATIVE_VENV is set
+fn check_cross_mount(project_path: &Path, settings: &mut Settings) {
+    let venv_in_project = !settings.venv_outside_project && settings.shared_venv_root.is_none();
+    if !venv_in_project {
+        return;
+    }
+    let cross_mount = match CrossMount::detect(project_path) {
+        Some(cross_mount) => cross_mount,
+        None => return,
+    };
+    if settings.wsl_native_venv {
+        settings.venv_outside_project = true;
+        return;
+    }
+    print_warning(&format!(
+        "The project is {}: the virtualenv will be slow, and its symlinks may not work. \
+         Set DMENV_WSL_NATIVE_VENV to put it outside the project",
+        cross_mount.description()
+    ));
+}
+
+/// Apply a batch of bumps to the lock of the project, all together or not at
+/// all, and return the result of each one. Meant for bots, instead of running
+/// `dmenv bump-in-lock` for each dependency
+pub fn bump_in_lock_batch(
+    cmd: &Command,
+    instructions: &[BumpInstruction],
+) -> Result<Vec<BumpResult>, Error> {
+    let venv_manager = new_venv_manager(cmd, &[], Box::new(SystemRunner))?;
+    venv_manager.bump_batch(instructions)
+}
+
+fn bump_from_stdin(venv_manager: &VenvManager) -> Result<(), Error> {
+    let mut input = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut input).map_err(|e| Error::Other {
+        message: format!("Could not read bump instructions from stdin: {}", e),
+    })?;
+    let instructions = bump_batch::parse(&input)?;
+    let results = venv_manager.bump_batch(&instructions)?;
+    for result in &results {
+        println!("{}", result.to_json());
+    }
+    let failed = results
+        .iter()
+        .filter(|x| matches!(x.status, BumpStatus::Error(_)))
+        .count();
+    if failed > 0 {
+        return Err(Error::Other {
+            message: format!("{} bump(s) failed, lock left unchanged", failed),
+        });
+    }
+    Ok(())
+}
+
+fn new_venv_manager(
+    cmd: &Command,
+    command_line: &[String],
+    runner: Box<dyn CommandRunner>,
+) -> Result<VenvManager, Error> {
+    let mut settings = Settings::from_shell(cmd);
+    settings.command_line = command_line.to_vec();
+    if settings.shared_venv_root.is_some() {
+        shared::set_group_umask();
+    }
+    let project_path = if let Some(project_path) = &cmd.project_path {
+        msys::native_path(project_path)
+    } else {
+        std::env::current_dir().map_err(|e| Error::Other {
+            message: format!("Could not get current directory: {}", e),
+        })?
+    };
+    check_cross_mount(&project_path, &mut settings);
+    required_version::check(&project_path)?;
+    // `dmenv env create` has its own `--python` option, which takes precedence
+    let python_binary = match &cmd.sub_cmd {
+        SubCommand::Env {
+            env_cmd:
+                Some(EnvCommand::Create {
+                    python: Some(python),
+                    ..
+                }),
+            ..
+        } => Some(binary_from_spec(python)),
+        _ => cmd.python_binary.clone(),
+    };
+    // Without an explicit binary, use the Python version from dmenv.toml, if any
+    let config = ProjectConfig::from_project(&project_path)?;
+    if let Some(project_type) = config.project_type {
+        if std::env::var("DMENV_PROJECT_TYPE").is_err() {
+            settings.project_type = project_type;
+        }
+    }
+    settings.overrides = config.overrides.clone();
+    settings.platform_dev_dependencies = config.platform_dev_dependencies.clone();
+    settings.presets = config.presets.clone();
+    if settings.freshness_policy.is_none() {
+        settings.freshness_policy = config.freshness_policy.clone();
+    }
+    settings.forbid_vcs_in_production = config.forbid_vcs_in_production;
+    // Note: the environment variables win over dmenv.toml, see `Settings::from_shell()`
+    if let Some(algorithm) = config.hash_algorithm {
+        if std::env::var("DMENV_HASH_ALGORITHM").is_err() {
+            settings.hash_algorithm = algorithm;
+        }
+    }
+    if let Some(patterns) = &config.deny_packages {
+        if std::env::var("DMENV_DENY_PACKAGES").is_err() {
+            settings
+                .package_policy
+                .deny(patterns, project_config::CONFIG_FILENAME);
+        }
+    }
+    if let Some(patterns) = &config.allow_packages {
+        if std::env::var("DMENV_ALLOW_PACKAGES").is_err() {
+            settings
+                .package_policy
+                .allow(patterns, project_config::CONFIG_FILENAME);
+        }
+    }
+    let python_info = match (&cmd.sub_cmd, &python_binary, &config.python) {
+        // Note: queries must be fast, so never start Python for them. Without
+        // cached information, they just find nothing in the virtualenv
+        (SubCommand::Query { .. }, None, Some(version)) => {
+            python_info::find_matching_cached(version).unwrap_or_default()
+        }
+        (SubCommand::Query { .. }, _, _) => PythonInfo::cached(&python_binary).unwrap_or_default(),
+        (_, None, Some(version)) => python_info::find_matching(version, &*runner)?,
+        _ => PythonInfo::new(&python_binary, &*runner)?,
+    };
+    // `dmenv simulate-ci` installs the production lock, like CI
+    if let SubCommand::SimulateCi { .. } = &cmd.sub_cmd {
+        settings.production = true;
+    }
+    let python_tag = python_info.tag();
+    let resolver = PathsResolver::new(project_path, &python_tag, &settings);
+    let mut paths = resolver.paths()?;
+    // With `dmenv env create`, the caller chooses both the virtualenv and the lock
+    if let SubCommand::Env {
+        env_cmd: Some(EnvCommand::Create {
+            path, from_lock, ..
+        }),
+        ..
+    } = &cmd.sub_cmd
+    {
+        paths.venv = paths.project.join(path);
+        paths.lock = paths.project.join(from_lock);
+    }
+    // `dmenv simulate-ci` starts from scratch, without touching the virtualenv of the project
+    if let SubCommand::SimulateCi { .. } = &cmd.sub_cmd {
+        paths.venv = paths.project.join(".dmenv").join("simulate-ci");
+    }
+    // With `dmenv script run`, the virtualenv depends on the dependencies of the script
+    if let SubCommand::Script {
+        script_cmd: ScriptCommand::Run { script, .. },
+    } = &cmd.sub_cmd
+    {
+        let metadata = ScriptMetadata::from_path(&paths.project.join(script))?;
+        paths.venv = metadata.venv_path(&python_tag)?;
+    }
+    // With `dmenv tool`, each tool has its own virtualenv
+    if let SubCommand::Tool { tool_cmd } = &cmd.sub_cmd {
+        match tool_cmd {
+            ToolCommand::Install { name }
+            | ToolCommand::Upgrade { name }
+            | ToolCommand::Uninstall { name } => paths.venv = tools::venv_path(name)?,
+            ToolCommand::List {} | ToolCommand::Sync {} => (),
+        }
     }
+    variants::apply(
+        &mut settings,
+        &config.variants,
+        config.variant.as_deref(),
+        &paths.lock,
+        &paths.venv,
+    )?;
+    Ok(VenvManager::new(paths, python_info, settings).with_runner(runner))
 }
 
 #[cfg(test)]
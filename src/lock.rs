@@ -1,5 +1,11 @@
-use crate::dependencies::{FrozenDependency, LockedDependency, SimpleDependency};
+use std::collections::BTreeMap;
+
+use crate::dependencies::{
+    canonical_name, same_marker, FrozenDependency, LockedDependency, SimpleDependency,
+};
 use crate::error::Error;
+use crate::marker::Marker;
+use crate::overrides::{self, Override};
 
 // Common trait used by any struct able to bump a dependency
 trait Bumper {
@@ -56,6 +62,233 @@ impl Bumper for GitBumper {
     }
 }
 
+/// What humans wrote around a dependency line: comments on the lines above it,
+/// a trailing `# comment`, and the blank-line separated group it belongs to.
+/// Kept so that notes like `# pinned because of #42` survive re-locks
+#[derive(Debug, Default)]
+struct Annotation {
+    comments: Vec<String>,
+    /// Everything after the requirement, including the leading whitespace
+    trailing: Option<String>,
+    /// Lines following a line ending with `\\`, like `--hash=...`, kept verbatim
+    continuation: Vec<String>,
+    group: usize,
+}
+
+impl Annotation {
+    /// The trailing comment, including the leading whitespace. When the requirement
+    /// is followed by continuation lines, it is at the end of the last one, since
+    /// pip does not allow continuation lines after a comment:
+    ///
+    /// ```text
+    /// foo==0.42 \
+    ///     --hash=sha256:0123...  # pin: see #42
+    /// ```
+    fn comment(&self) -> Option<&str> {
+        match self.continuation.last() {
+            Some(last) => split_trailing_comment(last).1,
+            None => self.trailing.as_deref(),
+        }
+    }
+
+    /// The reason given to `dmenv pin`, stored as a `# pin: <reason>` trailing comment
+    fn pin_reason(&self) -> Option<String> {
+        let comment = self.comment()?.trim().trim_start_matches('#').trim();
+        if !comment.starts_with(PIN_PREFIX) {
+            return None;
+        }
+        Some(comment[PIN_PREFIX.len()..].trim().to_string())
+    }
+
+    /// True for trailing `# allow-old: <reason>` comments, see `policy::FreshnessPolicy`
+    fn allows_old(&self) -> bool {
+        let comment = self.comment().unwrap_or_default();
+        comment
+            .trim()
+            .trim_start_matches('#')
+            .trim()
+            .starts_with(ALLOW_OLD_PREFIX)
+    }
+
+    /// Remove the `--hash` options, which no longer match once the version
+    /// changed, and keep the other ones, like `--config-settings`, as well as
+    /// the trailing comment
+    fn drop_hashes(&mut self) {
+        let comment = self.comment().map(|x| x.to_string());
+        let mut lines: Vec<String> = vec![];
+        for line in &self.continuation {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let options: Vec<_> = split_trailing_comment(line)
+                .0
+                .trim_end_matches('\\')
+                .split_whitespace()
+                .filter(|x| !x.starts_with("--hash="))
+                .collect();
+            if !options.is_empty() {
+                lines.push(format!("{}{}", indent, options.join(" ")));
+            }
+        }
+        if lines.len() == self.continuation.len() {
+            return;
+        }
+        let last = lines.len().saturating_sub(1);
+        self.continuation = lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| if i == last { x } else { format!("{} \\", x) })
+            .collect();
+        let comment = comment.unwrap_or_default();
+        match self.continuation.last_mut() {
+            Some(last) => last.push_str(&comment),
+            None if comment.is_empty() => self.trailing = None,
+            None => self.trailing = Some(comment),
+        }
+    }
+
+    fn set_pin_reason(&mut self, reason: &str) {
+        // Don't lose what was written there before
+        if let Some(comment) = self.comment() {
+            if self.pin_reason().is_none() {
+                let comment = comment.trim().to_string();
+                self.comments.push(comment);
+            }
+        }
+        let comment = format!("  # {} {}", PIN_PREFIX, reason);
+        match self.continuation.last_mut() {
+            Some(last) => *last = format!("{}{}", split_trailing_comment(last).0, comment),
+            None => self.trailing = Some(comment),
+        }
+    }
+
+    /// How to install the dependency, from a `# install: ...` comment, either
+    /// trailing or on the line above (so that it can be combined with `# pin:`,
+    /// or with `--hash` options on the next lines)
+    fn install_hint(&self) -> Result<Option<InstallHint>, String> {
+        let trailing = self.comment().map(|x| x.trim());
+        for comment in self.comments.iter().map(|x| x.as_str()).chain(trailing) {
+            let comment = comment.trim_start_matches('#').trim();
+            if let Some(options) = comment.strip_prefix(INSTALL_PREFIX) {
+                return InstallHint::parse(options).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// True for lines written from the overrides of `dmenv.toml`
+    fn is_override(&self) -> bool {
+        let comment = self.comment().unwrap_or_default();
+        comment
+            .trim()
+            .trim_start_matches('#')
+            .trim()
+            .starts_with(overrides::LOCK_PREFIX)
+    }
+}
+
+const PIN_PREFIX: &str = "pin:";
+const ALLOW_OLD_PREFIX: &str = "allow-old:";
+const INSTALL_PREFIX: &str = "install:";
+
+/// How a dependency must be installed, from a `# install: no-deps, order=-1`
+/// comment in the lock. Used for legacy packages with broken metadata
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct InstallHint {
+    /// Install with `pip install --no-deps`
+    no_deps: bool,
+    /// Batches are installed by increasing order. Dependencies without a
+    /// hint are in order 0
+    order: i32,
+}
+
+impl InstallHint {
+    fn parse(options: &str) -> Result<Self, String> {
+        let mut res = InstallHint::default();
+        for option in options.split(',').map(|x| x.trim()) {
+            if option == "no-deps" {
+                res.no_deps = true;
+                continue;
+            }
+            let value = option.strip_prefix("order=").unwrap_or_default();
+            res.order = value.trim().parse().map_err(|_| {
+                format!(
+                    "invalid install option '{}', expected 'no-deps' or 'order=<number>'",
+                    option
+                )
+            })?;
+        }
+        Ok(res)
+    }
+}
+
+/// Part of the lock installed by a single pip command, see `Lock::install_batches()`
+#[derive(Debug, PartialEq)]
+pub struct InstallBatch {
+    pub order: i32,
+    pub no_deps: bool,
+    pub names: Vec<String>,
+    /// Contents of the requirements file to give to pip, including the pip
+    /// options of the lock, like `--index-url`
+    pub contents: String,
+}
+
+/// Line endings used when writing the lock
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Newline {
+    Lf,
+    CrLf,
+}
+
+impl std::str::FromStr for Newline {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(Newline::Lf),
+            "crlf" => Ok(Newline::CrLf),
+            _ => Err(Error::Other {
+                message: format!("unknown newline style '{}', expected 'lf' or 'crlf'", s),
+            }),
+        }
+    }
+}
+
+const BOM: char = '\u{feff}';
+
+/// A change made to the lock by `Lock::freeze()`
+#[derive(Debug, PartialEq)]
+pub enum LockChange {
+    Added {
+        name: String,
+        version: String,
+    },
+    Changed {
+        name: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl LockChange {
+    pub fn name(&self) -> &str {
+        match self {
+            LockChange::Added { name, .. } | LockChange::Changed { name, .. } => name,
+        }
+    }
+
+    /// Render the change as one line, with names padded to `width` so that
+    /// versions are aligned
+    pub fn render(&self, width: usize) -> String {
+        match self {
+            LockChange::Added { name, version } => {
+                format!("+ {:width$}  {}", name, version, width = width)
+            }
+            LockChange::Changed { name, old, new } => {
+                format!("~ {:width$}  {} -> {}", name, old, new, width = width)
+            }
+        }
+    }
+}
+
 /// Implements various operations on the lock file
 /// Usage:
 /// ```text
@@ -68,39 +301,210 @@ impl Bumper for GitBumper {
 #[derive(Debug)]
 pub struct Lock {
     dependencies: Vec<LockedDependency>,
+    // Note: same length as `dependencies`
+    annotations: Vec<Annotation>,
+    /// Comments not followed by a dependency in the same group, like a header
+    dangling_comments: BTreeMap<usize, Vec<String>>,
     python_version: Option<String>,
     sys_platform: Option<String>,
+    current_markers: Option<Vec<String>>,
+    override_pins: bool,
+    drop_stale_hashes: bool,
+    // Kept so that rewriting the lock does not change every line
+    newline: Newline,
+    bom: bool,
 }
 
 impl Lock {
+    /// Lines dmenv does not understand are kept as they are
     pub fn from_string(string: &str) -> Result<Self, Error> {
+        Self::parse(string, false)
+    }
+
+    /// Like `from_string()`, but fail on lines dmenv does not understand
+    pub fn from_string_strict(string: &str) -> Result<Self, Error> {
+        Self::parse(string, true)
+    }
+
+    fn parse(string: &str, strict: bool) -> Result<Self, Error> {
+        let bom = string.starts_with(BOM);
+        let string = string.trim_start_matches(BOM);
+        // Note: `lines()` handles both `\n` and `\r\n`, so only look at the first line
+        let newline = match string.find('\n') {
+            Some(pos) if string[..pos].ends_with('\r') => Newline::CrLf,
+            _ => Newline::Lf,
+        };
         let mut dependencies = vec![];
-        for (i, line) in string.lines().enumerate() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
+        let mut annotations: Vec<Annotation> = vec![];
+        let mut dangling_comments = BTreeMap::new();
+        let mut line_numbers = vec![];
+        let mut comments = vec![];
+        let mut group = 0;
+        let mut continued = false;
+        for (i, raw_line) in string.lines().enumerate() {
+            let line = raw_line.trim();
+            if continued {
+                if let Some(annotation) = annotations.last_mut() {
+                    // Note: only hashes may follow a dependency, see `lock_hashes`
+                    let (options, _) = split_trailing_comment(line);
+                    let unknown = options
+                        .split_whitespace()
+                        .find(|x| !x.starts_with("--hash="));
+                    match unknown {
+                        Some(option) if strict => {
+                            return Err(Error::MalformedLock {
+                                line: i + 1,
+                                details: format!(
+                                    "expected --hash=<algorithm>:<digest>, got '{}'",
+                                    option
+                                ),
+                            })
+                        }
+                        _ => (),
+                    }
+                    annotation.continuation.push(raw_line.to_string());
+                    continued = line.ends_with('\\');
+                    continue;
+                }
+            }
+            continued = line.ends_with('\\');
+            if line.is_empty() {
+                if !comments.is_empty() {
+                    dangling_comments.insert(group, std::mem::take(&mut comments));
+                }
+                if !dependencies.is_empty() || dangling_comments.contains_key(&group) {
+                    group += 1;
+                }
                 continue;
             }
-            let dep = LockedDependency::from_line(&line).map_err(|e| Error::MalformedLock {
-                line: i + 1,
-                details: e.details,
-            })?;
+            if line.starts_with('#') {
+                comments.push(line.to_string());
+                continue;
+            }
+            let (requirement, trailing) = split_trailing_comment(line);
+            let (dep, trailing) = match LockedDependency::from_line(requirement) {
+                Ok(dep) => (dep, trailing),
+                Err(e) if strict => {
+                    return Err(Error::MalformedLock {
+                        line: i + 1,
+                        details: e.details,
+                    })
+                }
+                Err(_) => (LockedDependency::Opaque(line.to_string()), None),
+            };
             dependencies.push(dep);
+            annotations.push(Annotation {
+                comments: std::mem::take(&mut comments),
+                trailing: trailing.map(|x| x.to_string()),
+                continuation: vec![],
+                group,
+            });
+            line_numbers.push(i + 1);
+        }
+        if !comments.is_empty() {
+            dangling_comments.insert(group, comments);
         }
+        Self::check_duplicates(&dependencies, &line_numbers)?;
         Ok(Lock {
             dependencies,
+            annotations,
+            dangling_comments,
             python_version: None,
             sys_platform: None,
+            current_markers: None,
+            override_pins: false,
+            drop_stale_hashes: false,
+            newline,
+            bom,
         })
     }
 
+    /// Make sure dependencies listed more than once are for different environments,
+    /// like `foo==1.0 ; python_version < '3.10'` and `foo==2.0 ; python_version >= '3.10'`
+    // Note: markers we can't parse are assumed to be disjoint.
+    fn check_duplicates(
+        dependencies: &[LockedDependency],
+        line_numbers: &[usize],
+    ) -> Result<(), Error> {
+        for (j, dep) in dependencies.iter().enumerate() {
+            if let LockedDependency::Opaque(_) = dep {
+                continue;
+            }
+            for (i, previous) in dependencies[..j].iter().enumerate() {
+                if previous.name() != dep.name() {
+                    continue;
+                }
+                let overlapping = match (previous.marker(), dep.marker()) {
+                    (Some(a), Some(b)) => match (Marker::parse(&a), Marker::parse(&b)) {
+                        (Some(a), Some(b)) => !a.is_disjoint(&b),
+                        _ => false,
+                    },
+                    _ => true,
+                };
+                if overlapping {
+                    return Err(Error::MalformedLock {
+                        line: line_numbers[j],
+                        details: format!(
+                            "'{}' is already listed at line {} for an overlapping environment",
+                            dep.name(),
+                            line_numbers[i]
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Serialize the lock to a string
     pub fn to_string(&self) -> String {
         // Dependencies are sorted according to their *lowercase* name.
         // This is consistent with how `pip freeze` is implemented.
         // See bottom of pip/_internal/operations/freeze.py:freeze()
-        let mut lines: Vec<_> = self.dependencies.iter().map(|x| x.line()).collect();
-        lines.sort_by(|x, y| x.to_lowercase().cmp(&y.to_lowercase()));
-        lines.join("\n") + "\n"
+        // Blank-line separated groups are kept, and sorted independently.
+        let last_group = self
+            .annotations
+            .iter()
+            .map(|x| x.group)
+            .chain(self.dangling_comments.keys().cloned())
+            .max()
+            .unwrap_or_default();
+        let mut groups = vec![];
+        for group in 0..=last_group {
+            let mut entries: Vec<_> = self
+                .dependencies
+                .iter()
+                .zip(&self.annotations)
+                .filter(|(_, annotation)| annotation.group == group)
+                .collect();
+            entries.sort_by_key(|(dep, _)| dep.line().to_lowercase());
+            let mut lines = vec![];
+            for (dep, annotation) in entries {
+                lines.extend(annotation.comments.iter().cloned());
+                let trailing = annotation.trailing.as_deref().unwrap_or_default();
+                lines.push(format!("{}{}", dep.line(), trailing));
+                lines.extend(annotation.continuation.iter().cloned());
+            }
+            if let Some(comments) = self.dangling_comments.get(&group) {
+                lines.extend(comments.iter().cloned());
+            }
+            if !lines.is_empty() {
+                groups.push(lines.join("\n"));
+            }
+        }
+        let mut res = groups.join("\n\n") + "\n";
+        if self.newline == Newline::CrLf {
+            res = res.replace('\n', "\r\n");
+        }
+        if self.bom {
+            res.insert(0, BOM);
+        }
+        res
+    }
+
+    /// Use these line endings instead of the ones found in the lock
+    pub fn newline(&mut self, newline: Newline) {
+        self.newline = newline
     }
 
     /// Set the python version
@@ -117,35 +521,304 @@ impl Lock {
         self.sys_platform = Some(sys_platform.to_string())
     }
 
+    /// Set the markers matching the current environment
+    // Note: This cause the behavior of `freeze()` to change.
+    // See `patch_existing_deps` for details
+    pub fn current_markers(&mut self, markers: &[String]) {
+        self.current_markers = Some(markers.to_vec())
+    }
+
+    /// Allow changing the version of pinned dependencies
+    // Note: This cause the behavior of `freeze()` and `bump()` to change.
+    pub fn override_pins(&mut self) {
+        self.override_pins = true
+    }
+
+    /// Like `override_pins()`, for the next changes only
+    pub fn set_override_pins(&mut self, override_pins: bool) {
+        self.override_pins = override_pins
+    }
+
+    /// Remove the `--hash` options of the dependencies whose version changes,
+    /// since they no longer match. Otherwise, continuation lines are kept verbatim
+    // Note: This cause the behavior of `freeze()` and `bump()` to change.
+    pub fn drop_stale_hashes(&mut self) {
+        self.drop_stale_hashes = true
+    }
+
+    /// Record why the dependency `name` must stay at its current version
+    pub fn pin(&mut self, name: &str, reason: &str, marker: Option<&str>) -> Result<(), Error> {
+        let mut matches: Vec<_> = self
+            .dependencies
+            .iter()
+            .zip(&mut self.annotations)
+            .filter(|(dep, _)| dep.name() == name && marker_matches(dep, marker))
+            .map(|(_, annotation)| annotation)
+            .collect();
+        match matches.as_mut_slice() {
+            [] => Err(Error::NothingToBump {
+                name: name.to_string(),
+            }),
+            [annotation] => {
+                annotation.set_pin_reason(reason);
+                Ok(())
+            }
+            _ => Err(Error::MultipleBumps {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// Remove the lines written from overrides that are no longer in `dmenv.toml`
+    pub fn remove_stale_overrides(&mut self, current: &[Override]) {
+        let names: Vec<_> = current
+            .iter()
+            .map(|x| canonical_name(&x.replacement_name()))
+            .collect();
+        self.retain(|dep, annotation| {
+            !annotation.is_override() || names.contains(&canonical_name(&dep.name()))
+        });
+    }
+
+    /// Replace the overridden package, and the package installed instead, by
+    /// the given line
+    pub fn apply_override(&mut self, item: &Override, line: &str) -> Result<(), Error> {
+        let dep = LockedDependency::from_line(line).map_err(|e| Error::Other {
+            message: format!("invalid override for {}: {}", item.name, e.details),
+        })?;
+        let names = [
+            canonical_name(&item.name),
+            canonical_name(&item.replacement_name()),
+        ];
+        self.retain(|dep, _| !names.contains(&canonical_name(&dep.name())));
+        self.dependencies.push(dep);
+        let group = self.annotations.iter().map(|x| x.group).max();
+        self.annotations.push(Annotation {
+            trailing: Some(format!("  # {}", item.comment())),
+            group: group.unwrap_or_default(),
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    /// Add the marker to the entries of the dependency `name` that don't have one
+    pub fn add_marker(&mut self, name: &str, marker: &str) {
+        for dep in &mut self.dependencies {
+            if let LockedDependency::Simple(s) = dep {
+                if canonical_name(&s.name) == canonical_name(name) && !s.line.contains(';') {
+                    s.add_marker(marker);
+                }
+            }
+        }
+    }
+
+    /// Keep the dependencies (and their annotations) matching the predicate
+    fn retain(&mut self, keep: impl Fn(&LockedDependency, &Annotation) -> bool) {
+        let entries: Vec<_> = self
+            .dependencies
+            .drain(..)
+            .zip(self.annotations.drain(..))
+            .filter(|(dep, annotation)| keep(dep, annotation))
+            .collect();
+        for (dep, annotation) in entries {
+            self.dependencies.push(dep);
+            self.annotations.push(annotation);
+        }
+    }
+
+    /// The pinned dependencies, as (line, reason) tuples
+    pub fn pins(&self) -> Vec<(String, String)> {
+        let mut res: Vec<_> = self
+            .dependencies
+            .iter()
+            .zip(&self.annotations)
+            .filter_map(|(dep, annotation)| Some((dep.line(), annotation.pin_reason()?)))
+            .collect();
+        res.sort_by_key(|(line, _)| line.to_lowercase());
+        res
+    }
+
+    /// Names of the dependencies the freshness policy does not apply to: pinned
+    /// ones, and those annotated with `# allow-old: <reason>`
+    pub fn allowed_old(&self) -> Vec<String> {
+        self.dependencies
+            .iter()
+            .zip(&self.annotations)
+            .filter(|(_, annotation)| annotation.allows_old() || annotation.pin_reason().is_some())
+            .map(|(dep, _)| dep.name())
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    /// Split the lock according to the `# install: ...` comments, ordered
+    /// by installation order. Empty when the lock has no such comment, so
+    /// that everything is installed at once, as usual
+    pub fn install_batches(&self) -> Result<Vec<InstallBatch>, Error> {
+        let mut options = vec![];
+        let mut entries = vec![];
+        let mut hinted = false;
+        for (dep, annotation) in self.dependencies.iter().zip(&self.annotations) {
+            let trailing = annotation.trailing.as_deref().unwrap_or_default();
+            let mut text = format!("{}{}", dep.line(), trailing);
+            for line in &annotation.continuation {
+                text.push('\n');
+                text.push_str(line);
+            }
+            if let LockedDependency::Opaque(line) = dep {
+                let line = line.trim_start();
+                if line.starts_with('-')
+                    && !line.starts_with("-e")
+                    && !line.starts_with("--editable")
+                {
+                    options.push(text);
+                    continue;
+                }
+            }
+            let hint = annotation.install_hint().map_err(|details| Error::Other {
+                message: format!("{}: {}", dep.line(), details),
+            })?;
+            hinted |= hint.is_some();
+            entries.push((hint.unwrap_or_default(), dep.name(), text));
+        }
+        if !hinted {
+            return Ok(vec![]);
+        }
+        let mut batches: BTreeMap<(i32, bool), (Vec<String>, Vec<String>)> = BTreeMap::new();
+        for (hint, name, text) in entries {
+            let (names, lines) = batches.entry((hint.order, hint.no_deps)).or_default();
+            if !name.is_empty() {
+                names.push(name);
+            }
+            lines.push(text);
+        }
+        Ok(batches
+            .into_iter()
+            .map(|((order, no_deps), (names, lines))| {
+                let mut contents = options.clone();
+                contents.extend(lines);
+                InstallBatch {
+                    order,
+                    no_deps,
+                    names,
+                    contents: contents.join("\n") + "\n",
+                }
+            })
+            .collect())
+    }
+
+    /// Versions of the lock as a pip constraints file, given to every batch of
+    /// `install_batches()` so that pip installs the locked versions of the
+    /// dependencies of a batch, and not the latest ones.
+    // Note: pip refuses extras and editables in constraints, and git
+    // dependencies can't be constrained by a version, so only keep the name,
+    // the version and the marker of the simple dependencies
+    pub fn constraints(&self) -> String {
+        let mut res = String::new();
+        for dep in &self.dependencies {
+            if let LockedDependency::Simple(s) = dep {
+                let name = s.name.split('[').next().unwrap_or_default();
+                res.push_str(&format!("{}=={}", name, s.version.value));
+                if let Some(marker) = dep.marker() {
+                    res.push_str(&format!(" ; {}", marker));
+                }
+                res.push('\n');
+            }
+        }
+        res
+    }
+
+    /// Markers of the dependencies that appear more than once in the lock,
+    /// like `importlib-metadata==6.8.0 ; python_version < '3.10'` and
+    /// `importlib-metadata==7.0.0 ; python_version >= '3.10'`
+    pub fn ambiguous_markers(&self) -> Vec<String> {
+        let duplicates = self.duplicate_names();
+        self.dependencies
+            .iter()
+            .filter(|x| duplicates.contains(&x.name()))
+            .filter_map(|x| x.marker())
+            .collect()
+    }
+
+    fn duplicate_names(&self) -> Vec<String> {
+        let mut counts = BTreeMap::new();
+        for dep in self.dependencies.iter().filter(|x| !x.name().is_empty()) {
+            *counts.entry(dep.name()).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// All the dependencies, in the order of the lock
+    pub fn dependencies(&self) -> &[LockedDependency] {
+        &self.dependencies
+    }
+
+    /// Names of all the dependencies, in the order of the lock
+    pub fn names(&self) -> Vec<String> {
+        self.dependencies
+            .iter()
+            .map(|x| x.name())
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    /// Versions of the simple dependencies, indexed by their *lowercase* name
+    pub fn versions(&self) -> BTreeMap<String, String> {
+        let mut res = BTreeMap::new();
+        for dep in &self.dependencies {
+            if let LockedDependency::Simple(s) = dep {
+                res.insert(s.name.to_lowercase(), s.version.value.clone());
+            }
+        }
+        res
+    }
+
     /// Bump the dependency `name` to new `version`.
     /// Returns a tuple (locked_changed: bool, new_contents: String)
     // Note: the locked_changed boolean is used to improve precision of
     // messages printed by the VenvManager struct.
-    pub fn bump(&mut self, name: &str, version: &str) -> Result<bool, Error> {
+    // If `marker` is given, only the entry with this environment marker is bumped.
+    pub fn bump(&mut self, name: &str, version: &str, marker: Option<&str>) -> Result<bool, Error> {
         let simple_bumper = SimpleBumper::new(version);
-        self.bump_impl(&simple_bumper, name)
+        self.bump_impl(&simple_bumper, name, marker)
     }
 
     /// Bump the git dependency `name` to new `git_ref`.
     /// Returns a tuple (locked_changed: bool, new_contents: String)
     // Note: the locked_changed boolean is used to improve precision of
     // messages printed by the VenvManager struct.
-    pub fn git_bump(&mut self, name: &str, git_ref: &str) -> Result<bool, Error> {
+    pub fn git_bump(
+        &mut self,
+        name: &str,
+        git_ref: &str,
+        marker: Option<&str>,
+    ) -> Result<bool, Error> {
         let git_bumper = GitBumper::new(git_ref);
-        self.bump_impl(&git_bumper, name)
+        self.bump_impl(&git_bumper, name, marker)
     }
 
     // Implement common behavior for any Bumper (regular or git)
-    fn bump_impl<T>(&mut self, bumper: &T, name: &str) -> Result<bool, Error>
+    fn bump_impl<T>(&mut self, bumper: &T, name: &str, marker: Option<&str>) -> Result<bool, Error>
     where
         T: Bumper,
     {
         let mut changed = true;
         let mut num_matches = 0;
-        for dep in &mut self.dependencies {
-            if dep.name() == name {
+        let mut pin_reason = None;
+        for (dep, annotation) in self.dependencies.iter_mut().zip(&mut self.annotations) {
+            if dep.name() != name {
+                continue;
+            }
+            if marker_matches(dep, marker) {
                 num_matches += 1;
                 changed = bumper.bump(dep);
+                pin_reason = annotation.pin_reason();
+                if changed && self.drop_stale_hashes {
+                    annotation.drop_hashes();
+                }
             }
         }
         if num_matches == 0 {
@@ -158,6 +831,14 @@ impl Lock {
                 name: name.to_string(),
             });
         }
+        if let Some(reason) = pin_reason {
+            if changed && !self.override_pins {
+                return Err(Error::PinnedDependency {
+                    name: name.to_string(),
+                    reason,
+                });
+            }
+        }
         Ok(changed)
     }
 
@@ -166,18 +847,22 @@ impl Lock {
     // make sure no existing information in the lock is lost
     // This in not an actual merge because we only modify existing lines
     // or add new ones (no deletion ocurrs).
-    pub fn freeze(&mut self, deps: &[FrozenDependency]) {
-        self.patch_existing_deps(deps);
-        self.add_missing_deps(deps);
+    // Fails if a pinned dependency would change, unless `override_pins()` was called.
+    // Returns the changes, in order: changed dependencies first, then added ones.
+    pub fn freeze(&mut self, deps: &[FrozenDependency]) -> Result<Vec<LockChange>, Error> {
+        let mut changes = self.patch_existing_deps(deps)?;
+        changes.extend(self.add_missing_deps(deps));
+        Ok(changes)
     }
 
     /// Add dependencies from `frozen_deps` that were missing in the lock
-    fn add_missing_deps(&mut self, frozen_deps: &[FrozenDependency]) {
+    fn add_missing_deps(&mut self, frozen_deps: &[FrozenDependency]) -> Vec<LockChange> {
         let known_names: &Vec<_> = &mut self.dependencies.iter().map(|d| d.name()).collect();
         let new_deps: Vec<_> = frozen_deps
             .iter()
             .filter(|x| !known_names.contains(&&x.name))
             .collect();
+        let mut changes = vec![];
         for dep in new_deps {
             // If self.python_version or self.sys_platform is not None,
             // make sure to append that data.
@@ -193,39 +878,305 @@ impl Lock {
             if let Some(sys_platform) = &self.sys_platform {
                 locked_dep.sys_platform(sys_platform);
             }
-            println!("+ {}", locked_dep.line);
+            // Note: the version includes the markers added above, if any
+            let version = locked_dep.line.splitn(2, "==").nth(1).unwrap_or_default();
+            changes.push(LockChange::Added {
+                name: dep.name.to_string(),
+                version: version.to_string(),
+            });
             self.dependencies.push(LockedDependency::Simple(locked_dep));
+            // New dependencies go to the last group with dependencies
+            let group = self.annotations.iter().map(|x| x.group).max();
+            self.annotations.push(Annotation {
+                group: group.unwrap_or_default(),
+                ..Default::default()
+            });
         }
+        changes
     }
 
     /// Modify dependencies that were in the lock to match those passed in `frozen_deps`
-    fn patch_existing_deps(&mut self, frozen_deps: &[FrozenDependency]) {
-        for dep in &mut self.dependencies {
+    fn patch_existing_deps(
+        &mut self,
+        frozen_deps: &[FrozenDependency],
+    ) -> Result<Vec<LockChange>, Error> {
+        let duplicates = self.duplicate_names();
+        let mut changes = vec![];
+        for (dep, annotation) in self.dependencies.iter_mut().zip(&mut self.annotations) {
+            // When a dependency appears several times with different markers,
+            // only the entry matching the current environment was installed
+            // by pip, so leave the other ones alone.
+            if let Some(current_markers) = &self.current_markers {
+                if duplicates.contains(&dep.name()) {
+                    if let Some(marker) = dep.marker() {
+                        if !current_markers.iter().any(|x| same_marker(x, &marker)) {
+                            continue;
+                        }
+                    }
+                }
+            }
             match dep {
                 // frozen deps *never* contain git information (because `pip freeze`
                 // only returns names and versions), so always keep those in the lock.
                 LockedDependency::Git(_) => (),
+                LockedDependency::Opaque(_) => (),
                 LockedDependency::Simple(s) => {
-                    Self::patch_existing_dep(s, frozen_deps);
+                    let pin_reason = if self.override_pins {
+                        None
+                    } else {
+                        annotation.pin_reason()
+                    };
+                    if let Some(change) = Self::patch_existing_dep(s, frozen_deps, pin_reason)? {
+                        if self.drop_stale_hashes {
+                            annotation.drop_hashes();
+                        }
+                        changes.push(change);
+                    }
                 }
             }
         }
+        Ok(changes)
     }
 
     /// Modify an existing dependency to match the frozen version
-    fn patch_existing_dep(dep: &mut SimpleDependency, frozen_deps: &[FrozenDependency]) {
+    fn patch_existing_dep(
+        dep: &mut SimpleDependency,
+        frozen_deps: &[FrozenDependency],
+        pin_reason: Option<String>,
+    ) -> Result<Option<LockChange>, Error> {
         let frozen_match = frozen_deps.iter().find(|x| x.name == dep.name);
         let frozen_version = match frozen_match {
-            None => return,
+            None => return Ok(None),
             Some(frozen) => &frozen.version,
         };
         if &dep.version.value == frozen_version {
-            return;
+            return Ok(None);
+        }
+        if let Some(reason) = pin_reason {
+            return Err(Error::PinnedDependency {
+                name: dep.name.to_string(),
+                reason,
+            });
+        }
+
+        let change = LockChange::Changed {
+            name: dep.name.to_string(),
+            old: dep.version.value.to_string(),
+            new: frozen_version.to_string(),
+        };
+        dep.freeze(&frozen_version);
+        Ok(Some(change))
+    }
+}
+
+/// Describe how line `number` (starting at 1) of the lock is parsed, and what the
+/// various commands do with it, as (key, value) tuples.
+// Note: uses the same code as `Lock::from_string()`, so that the explanation can't
+// drift from the actual behavior
+pub fn explain_line(contents: &str, number: usize) -> Result<Vec<(&'static str, String)>, Error> {
+    let lines: Vec<_> = contents.trim_start_matches(BOM).lines().collect();
+    if number == 0 || number > lines.len() {
+        return Err(Error::Other {
+            message: format!("no line {} in lock ({} lines)", number, lines.len()),
+        });
+    }
+    let line = lines[number - 1].trim();
+    let mut res = vec![("line", line.to_string())];
+    if line.is_empty() {
+        res.push((
+            "kind",
+            "blank (separates groups of dependencies)".to_string(),
+        ));
+        return Ok(res);
+    }
+    let continued_from = (1..number)
+        .rev()
+        .take_while(|i| lines[i - 1].trim_end().ends_with('\\'))
+        .last();
+    if let Some(start) = continued_from {
+        res.push((
+            "kind",
+            format!("continuation of line {} (kept verbatim)", start),
+        ));
+        return Ok(res);
+    }
+    if line.starts_with('#') {
+        res.push((
+            "kind",
+            "comment (kept with the next dependency)".to_string(),
+        ));
+        return Ok(res);
+    }
+    let (requirement, trailing) = split_trailing_comment(line);
+    let dep = match LockedDependency::from_line(requirement) {
+        Ok(dep) => dep,
+        Err(e) => {
+            res.push((
+                "kind",
+                "opaque (kept verbatim, rejected with --strict)".to_string(),
+            ));
+            res.push(("error", e.details));
+            return Ok(res);
+        }
+    };
+    let name = dep.name();
+    let mut continuation = vec![];
+    let mut continued = line.ends_with('\\');
+    for next in &lines[number..] {
+        if !continued {
+            break;
+        }
+        continuation.push(next.to_string());
+        continued = next.trim_end().ends_with('\\');
+    }
+    let annotation = Annotation {
+        trailing: trailing.map(|x| x.to_string()),
+        continuation,
+        ..Default::default()
+    };
+    match &dep {
+        LockedDependency::Simple(s) => {
+            res.push(("kind", "simple".to_string()));
+            res.push(("name", name.to_string()));
+            res.push(("version", s.version.value.to_string()));
+        }
+        LockedDependency::Git(g) => {
+            res.push(("kind", "git".to_string()));
+            res.push(("name", name.to_string()));
+            let url_end = requirement.len() - requirement.rsplit('@').next().unwrap_or("").len();
+            res.push((
+                "url",
+                requirement[..url_end].trim_end_matches('@').to_string(),
+            ));
+            res.push(("ref", g.git_ref.value.to_string()));
+        }
+        // Note: from_line() never returns opaque dependencies
+        LockedDependency::Opaque(_) => res.push(("kind", "opaque".to_string())),
+    }
+    let marker = dep.marker();
+    match &marker {
+        None => res.push(("marker", "none".to_string())),
+        Some(marker) => {
+            let support = if Marker::parse(marker).is_some() {
+                "understood by dmenv"
+            } else {
+                "not understood by dmenv, assumed not to overlap with other markers"
+            };
+            res.push(("marker", format!("{} ({})", marker, support)));
+        }
+    }
+    if let Some(comment) = annotation.comment() {
+        res.push(("comment", comment.trim().to_string()));
+    }
+    let pin_reason = annotation.pin_reason();
+    if let Some(reason) = &pin_reason {
+        res.push(("pinned", reason.to_string()));
+    }
+
+    // Other entries for the same dependency, ignoring lines that can't be parsed
+    let others: Vec<_> = lines
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i + 1 != number)
+        .filter_map(|(i, x)| {
+            let x = x.trim();
+            if x.is_empty() || x.starts_with('#') {
+                return None;
+            }
+            let other = LockedDependency::from_line(split_trailing_comment(x).0).ok()?;
+            if other.name() == name {
+                Some(i + 1)
+            } else {
+                None
+            }
+        })
+        .collect();
+    if !others.is_empty() {
+        let others: Vec<_> = others.iter().map(|x| x.to_string()).collect();
+        res.push(("also listed at lines", others.join(", ")));
+    }
+    // Check duplicates like `Lock::from_string()` does, ignoring the lines after
+    // this one and the lines that can't be parsed (numbers are kept)
+    let previous_lines: Vec<_> = lines[..number]
+        .iter()
+        .map(|x| {
+            let requirement = split_trailing_comment(x.trim()).0;
+            if requirement.is_empty() || LockedDependency::from_line(requirement).is_ok() {
+                *x
+            } else {
+                ""
+            }
+        })
+        .collect();
+    if let Err(Error::MalformedLock { line, details }) =
+        Lock::from_string(&previous_lines.join("\n"))
+    {
+        if line == number {
+            res.push(("error", details));
         }
+    }
 
-        println!("{}: {} -> {}", dep.name, dep.version.value, &frozen_version);
-        dep.freeze(&frozen_version)
+    let install = match (&dep, &marker) {
+        (_, Some(_)) => "pip, only if the marker matches the environment",
+        (LockedDependency::Git(_), None) => "pip",
+        (_, None) => "pip, or the native installer if a pure-Python wheel exists",
+    };
+    res.push(("install", install.to_string()));
+    let freeze = match &dep {
+        LockedDependency::Git(_) | LockedDependency::Opaque(_) => "kept as is".to_string(),
+        LockedDependency::Simple(_) => match &pin_reason {
+            Some(_) => "fails if the version changes, unless --override-pin is used".to_string(),
+            None if !others.is_empty() && marker.is_some() => {
+                "version updated from `pip freeze` only if the marker matches the environment"
+                    .to_string()
+            }
+            None => "version updated from `pip freeze`".to_string(),
+        },
+    };
+    res.push(("lock", freeze));
+    let mut bump = match &dep {
+        LockedDependency::Git(_) => format!("dmenv bump-in-lock --git {} <ref>", name),
+        _ => format!("dmenv bump-in-lock {} <version>", name),
+    };
+    if !others.is_empty() {
+        if let Some(marker) = &marker {
+            bump.push_str(&format!(" --marker \"{}\"", marker));
+        }
     }
+    if pin_reason.is_some() {
+        bump.push_str(" --override-pin");
+    }
+    res.push(("bump", bump));
+    Ok(res)
+}
+
+fn marker_matches(dep: &LockedDependency, marker: Option<&str>) -> bool {
+    match (marker, dep.marker()) {
+        (None, _) => true,
+        (Some(expected), Some(actual)) => same_marker(expected, &actual),
+        (Some(_), None) => false,
+    }
+}
+
+/// Split `foo==1.2  # pinned, see #42` into the requirement and the trailing comment.
+// Note: as in pip, `#` starts a comment only when preceded by whitespace, so that
+// `#egg=` in git URLs is left alone
+// A `\\` at the end of the line (followed by lines like `--hash=...`) is returned
+// as a trailing comment too.
+pub fn split_trailing_comment(line: &str) -> (&str, Option<&str>) {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            let requirement = line[..i].trim_end();
+            return (requirement, Some(&line[requirement.len()..]));
+        }
+        previous = c;
+    }
+    if line.ends_with('\\') {
+        let requirement = line[..line.len() - 1].trim_end();
+        return (requirement, Some(&line[requirement.len()..]));
+    }
+    (line, None)
 }
 
 #[cfg(test)]
@@ -244,7 +1195,7 @@ mod tests {
     #[test]
     fn malformed_lock() {
         let lock_contents = "bar==42\ngit://foo/bar.git@master#egggg=bar";
-        let actual = Lock::from_string(&lock_contents);
+        let actual = Lock::from_string_strict(&lock_contents);
         let actual = actual.unwrap_err();
         match actual {
             Error::MalformedLock { line, .. } => assert_eq!(line, 2),
@@ -252,22 +1203,154 @@ mod tests {
         }
     }
 
+    #[test]
+    fn keep_unknown_lines() {
+        let lock_contents = "\
+--index-url https://pypi.acme.corp/simple
+bar==42 \\
+    --hash=sha256:0123 \\
+    --hash=sha256:4567
+foo==0.42
+";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        assert_eq!(lock.to_string(), lock_contents);
+        assert_eq!(lock.names(), vec!["bar", "foo"]);
+
+        lock.bump("foo", "0.43", None).unwrap();
+        lock.freeze(&[FrozenDependency::new("bar", "43")]).unwrap();
+        let expected = lock_contents
+            .replace("bar==42", "bar==43")
+            .replace("0.42", "0.43");
+        assert_eq!(lock.to_string(), expected);
+
+        Lock::from_string_strict(lock_contents).unwrap_err();
+    }
+
+    #[test]
+    fn strict_lock_with_hashes() {
+        let lock_contents = "\
+bar==42 \\
+    --hash=sha256:0123 \\
+    --hash=sha256:4567  # pin: see #42
+foo==0.42 --hash=sha256:89ab
+";
+        let lock = Lock::from_string_strict(lock_contents).unwrap();
+        assert_eq!(lock.to_string(), lock_contents);
+        assert_eq!(lock.names(), vec!["bar", "foo"]);
+
+        let lock_contents = "bar==42 \\\n    --index-url https://pypi.acme.corp/simple\n";
+        match Lock::from_string_strict(lock_contents).unwrap_err() {
+            Error::MalformedLock { line, .. } => assert_eq!(line, 2),
+            e => panic!("Expecting MalformedLock, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn drop_stale_hashes() {
+        let lock_contents = "\
+bar==42 \\
+    --config-settings=--build-option=--fast \\
+    --hash=sha256:0123 --hash=sha256:4567
+foo==0.42 \\
+    --hash=sha256:89ab
+qux==2.0 \\
+    --hash=sha256:0000  # allow-old: unmaintained
+spam==1.0 \\
+    --hash=sha256:cdef
+";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        lock.drop_stale_hashes();
+        lock.freeze(&[
+            FrozenDependency::new("bar", "43"),
+            FrozenDependency::new("qux", "2.1"),
+        ])
+        .unwrap();
+        lock.bump("foo", "0.43", None).unwrap();
+        assert_eq!(
+            lock.to_string(),
+            "\
+bar==43 \\
+    --config-settings=--build-option=--fast
+foo==0.43
+qux==2.1  # allow-old: unmaintained
+spam==1.0 \\
+    --hash=sha256:cdef
+"
+        );
+    }
+
+    #[test]
+    fn comment_after_hashes() {
+        let lock_contents = "\
+bar==42 \\
+    --hash=sha256:0123  # pin: see #42
+foo==0.42 \\
+    --hash=sha256:4567
+";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        assert_eq!(lock.to_string(), lock_contents);
+        assert_eq!(lock.allowed_old(), vec!["bar"]);
+        let actual = Lock::from_string(lock_contents)
+            .unwrap()
+            .bump("bar", "43", None)
+            .unwrap_err();
+        assert!(matches!(actual, Error::PinnedDependency { .. }));
+
+        lock.pin("foo", "see #43", None).unwrap();
+        assert_eq!(
+            lock.to_string(),
+            "\
+bar==42 \\
+    --hash=sha256:0123  # pin: see #42
+foo==0.42 \\
+    --hash=sha256:4567  # pin: see #43
+"
+        );
+    }
+
+    #[test]
+    fn duplicate_entries() {
+        let lock_contents = "foo==0.42\n# comment\nfoo==0.43 ; sys_platform == 'win32'\n";
+        let actual = Lock::from_string(&lock_contents).unwrap_err();
+        match actual {
+            Error::MalformedLock { line, .. } => assert_eq!(line, 3),
+            _ => panic!("Expecting MalformedLock, got: {}", actual),
+        }
+    }
+
+    #[test]
+    fn duplicate_entries_with_overlapping_markers() {
+        let lock_contents =
+            "foo==0.42 ; python_version < '3.10'\nfoo==0.43 ; python_version >= '3.9'\n";
+        Lock::from_string(&lock_contents).unwrap_err();
+    }
+
     #[test]
     fn simple_bump() {
         let lock_contents = "bar==0.3\nfoo==0.42\n";
         let mut lock = Lock::from_string(lock_contents).unwrap();
-        let changed = lock.bump("foo", "0.43").unwrap();
+        let changed = lock.bump("foo", "0.43", None).unwrap();
         assert!(changed);
         let expected = lock_contents.replace("0.42", "0.43");
         let actual = lock.to_string();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn versions() {
+        let lock_contents = "Bar==0.3\nfoo==0.42\ngit+https://git.local/baz@master#egg=baz\n";
+        let lock = Lock::from_string(lock_contents).unwrap();
+        let actual = lock.versions();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual["bar"], "0.3");
+        assert_eq!(actual["foo"], "0.42");
+    }
+
     #[test]
     fn dep_not_found() {
         let lock_contents = "bar==0.3\nfoo==0.42\n";
         let mut lock = Lock::from_string(lock_contents).unwrap();
-        let actual = lock.bump("no-such", "0.43");
+        let actual = lock.bump("no-such", "0.43", None);
         match actual {
             Err(Error::NothingToBump { name }) => assert_eq!(name, "no-such"),
             _ => panic!("Expecting NothingToBump, got: {:?}", actual),
@@ -278,7 +1361,7 @@ mod tests {
     fn idem_potent_change() {
         let lock_contents = "bar==0.3\nfoo==0.42\n";
         let mut lock = Lock::from_string(lock_contents).unwrap();
-        let changed = lock.bump("bar", "0.3").unwrap();
+        let changed = lock.bump("bar", "0.3", None).unwrap();
         let actual = lock.to_string();
         assert!(!changed);
         assert_eq!(actual, lock_contents.to_string());
@@ -290,16 +1373,60 @@ mod tests {
         let lock_contents = format!("git@example.com/bar.git@{}#egg=bar\n", old_sha1);
         let mut lock = Lock::from_string(&lock_contents).unwrap();
         let new_sha1 = "cda431";
-        let changed = lock.git_bump("bar", new_sha1).unwrap();
+        let changed = lock.git_bump("bar", new_sha1, None).unwrap();
         assert!(changed);
         let expected = lock_contents.replace(old_sha1, new_sha1);
         let actual = lock.to_string();
         assert_eq!(actual, expected);
     }
 
+    const MARKERS_LOCK: &str =
+        "foo==0.42 ; python_version < '3.10'\nfoo==0.50 ; python_version >= '3.10'\n";
+
+    #[test]
+    fn bump_multiple_matches() {
+        let mut lock = Lock::from_string(MARKERS_LOCK).unwrap();
+        let actual = lock.bump("foo", "0.43", None);
+        match actual {
+            Err(Error::MultipleBumps { name }) => assert_eq!(name, "foo"),
+            _ => panic!("Expecting MultipleBumps, got: {:?}", actual),
+        }
+    }
+
+    #[test]
+    fn bump_with_marker() {
+        let mut lock = Lock::from_string(MARKERS_LOCK).unwrap();
+        let changed = lock
+            .bump("foo", "0.43", Some("python_version<\"3.10\""))
+            .unwrap();
+        assert!(changed);
+        let expected = MARKERS_LOCK.replace("0.42", "0.43");
+        assert_eq!(lock.to_string(), expected);
+    }
+
+    #[test]
+    fn ambiguous_markers() {
+        let lock_contents = format!("bar==1.3 ; sys_platform == 'win32'\n{}", MARKERS_LOCK);
+        let lock = Lock::from_string(&lock_contents).unwrap();
+        assert_eq!(
+            lock.ambiguous_markers(),
+            vec!["python_version < '3.10'", "python_version >= '3.10'"]
+        );
+    }
+
+    #[test]
+    fn freeze_only_current_markers() {
+        let mut lock = Lock::from_string(MARKERS_LOCK).unwrap();
+        lock.current_markers(&["python_version >= '3.10'".to_string()]);
+        lock.freeze(&[FrozenDependency::new("foo", "0.51")])
+            .unwrap();
+        let expected = MARKERS_LOCK.replace("0.50", "0.51");
+        assert_eq!(lock.to_string(), expected);
+    }
+
     fn assert_freeze(contents: &str, frozen: &[FrozenDependency], expected: &str) {
         let mut lock = Lock::from_string(contents).unwrap();
-        lock.freeze(frozen);
+        lock.freeze(frozen).unwrap();
         let actual = lock.to_string();
         assert_eq!(actual, expected);
     }
@@ -352,11 +1479,40 @@ mod tests {
         lock.freeze(&[
             FrozenDependency::new("foo", "0.42"),
             FrozenDependency::new("bar", "1.3"),
-        ]);
+        ])
+        .unwrap();
         let actual = lock.to_string();
         assert_eq!(actual, "bar==1.3 ; python_version < '3.6'\nfoo==0.42\n");
     }
 
+    #[test]
+    fn freeze_reports_changes() {
+        let mut lock = Lock::from_string("foo==0.42\nbar==1.0\n").unwrap();
+        let changes = lock
+            .freeze(&[
+                FrozenDependency::new("foo", "0.42"),
+                FrozenDependency::new("bar", "1.1"),
+                FrozenDependency::new("requests", "2.22.0"),
+            ])
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                LockChange::Changed {
+                    name: "bar".to_string(),
+                    old: "1.0".to_string(),
+                    new: "1.1".to_string(),
+                },
+                LockChange::Added {
+                    name: "requests".to_string(),
+                    version: "2.22.0".to_string(),
+                },
+            ]
+        );
+        assert_eq!(changes[0].render(8), "~ bar       1.0 -> 1.1");
+        assert_eq!(changes[1].render(8), "+ requests  2.22.0");
+    }
+
     #[test]
     fn freeze_different_platform() {
         let mut lock = Lock::from_string("foo==0.42\n").unwrap();
@@ -364,9 +1520,248 @@ mod tests {
         lock.freeze(&[
             FrozenDependency::new("foo", "0.42"),
             FrozenDependency::new("winapi", "1.3"),
-        ]);
+        ])
+        .unwrap();
         let actual = lock.to_string();
         assert_eq!(actual, "foo==0.42\nwinapi==1.3 ; sys_platform == 'win32'\n");
     }
 
+    #[test]
+    fn keep_annotations() {
+        let lock_contents = "\
+# Generated by dmenv
+
+# Web
+django==3.2  # LTS, don't upgrade to 4.x
+git@example.com/bar.git@dae42f#egg=bar  # fork with our patches
+
+# Tests
+pytest==6.2
+";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        assert_eq!(lock.to_string(), lock_contents);
+
+        lock.bump("django", "3.2.1", None).unwrap();
+        lock.freeze(&[
+            FrozenDependency::new("pytest", "7.0"),
+            FrozenDependency::new("attrs", "21.4"),
+        ])
+        .unwrap();
+        let expected = "\
+# Generated by dmenv
+
+# Web
+django==3.2.1  # LTS, don't upgrade to 4.x
+git@example.com/bar.git@dae42f#egg=bar  # fork with our patches
+
+attrs==21.4
+# Tests
+pytest==7.0
+";
+        assert_eq!(lock.to_string(), expected);
+    }
+
+    #[test]
+    fn test_split_trailing_comment() {
+        assert_eq!(
+            split_trailing_comment("foo==1.2  # see #42"),
+            ("foo==1.2", Some("  # see #42"))
+        );
+        assert_eq!(
+            split_trailing_comment("git@host:foo@master#egg=foo"),
+            ("git@host:foo@master#egg=foo", None)
+        );
+    }
+
+    #[test]
+    fn pins() {
+        let lock_contents = "bar==1.3  # see #42\nfoo==0.42\n";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        lock.pin("bar", "CVE-2021-1234", None).unwrap();
+        assert_eq!(
+            lock.to_string(),
+            "# see #42\nbar==1.3  # pin: CVE-2021-1234\nfoo==0.42\n"
+        );
+        assert_eq!(
+            lock.pins(),
+            vec![("bar==1.3".to_string(), "CVE-2021-1234".to_string())]
+        );
+        lock.pin("no-such", "", None).unwrap_err();
+    }
+
+    #[test]
+    fn allowed_old() {
+        let lock_contents = "\
+attrs==19.1.0  # allow-old: last version supporting Python 2
+bar==1.3  # pin: CVE-2021-1234
+foo==0.42  # see #42
+";
+        let lock = Lock::from_string(lock_contents).unwrap();
+        assert_eq!(lock.allowed_old(), vec!["attrs", "bar"]);
+    }
+
+    #[test]
+    fn install_batches() {
+        let lock_contents = "\
+--index-url https://pypi.example.com/simple
+attrs==19.1.0
+# install: no-deps, order=-1
+legacy-setup==0.1  # pin: broken metadata
+pytest==8.3.3
+zope.interface==4.7.1  # install: no-deps
+";
+        let lock = Lock::from_string(lock_contents).unwrap();
+        let batches = lock.install_batches().unwrap();
+        let summary: Vec<_> = batches
+            .iter()
+            .map(|x| (x.order, x.no_deps, x.names.join(" ")))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (-1, true, "legacy-setup".to_string()),
+                (0, false, "attrs pytest".to_string()),
+                (0, true, "zope.interface".to_string()),
+            ]
+        );
+        assert_eq!(
+            batches[0].contents,
+            "--index-url https://pypi.example.com/simple\nlegacy-setup==0.1  # pin: broken metadata\n"
+        );
+
+        assert_eq!(
+            lock.constraints(),
+            "attrs==19.1.0\nlegacy-setup==0.1\npytest==8.3.3\nzope.interface==4.7.1\n"
+        );
+
+        let lock = Lock::from_string("attrs==19.1.0\n").unwrap();
+        assert!(lock.install_batches().unwrap().is_empty());
+        let lock = Lock::from_string("attrs==19.1.0  # install: nodeps\n").unwrap();
+        lock.install_batches().unwrap_err();
+    }
+
+    #[test]
+    fn constraints() {
+        let lock_contents = "\
+bar[baz]==42 ; python_version < '3.8' \\
+    --hash=sha256:0123
+-e ./lib#egg=lib
+foo==0.42  # pin: see #42
+git+https://github.com/acme/spam@v1.0#egg=spam
+";
+        let lock = Lock::from_string(lock_contents).unwrap();
+        assert_eq!(
+            lock.constraints(),
+            "bar==42 ; python_version < '3.8'\nfoo==0.42\n"
+        );
+    }
+
+    #[test]
+    fn pinned_dependencies_are_not_changed() {
+        let lock_contents = "bar==1.3  # pin: CVE-2021-1234\nfoo==0.42\n";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        lock.bump("bar", "1.4", None).unwrap_err();
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        lock.freeze(&[FrozenDependency::new("bar", "1.4")])
+            .unwrap_err();
+        // Same version: nothing to refuse
+        lock.freeze(&[FrozenDependency::new("bar", "1.3")]).unwrap();
+
+        lock.override_pins();
+        lock.freeze(&[FrozenDependency::new("bar", "1.4")]).unwrap();
+        assert_eq!(
+            lock.to_string(),
+            "bar==1.4  # pin: CVE-2021-1234\nfoo==0.42\n"
+        );
+    }
+
+    #[test]
+    fn add_marker() {
+        let lock_contents = "foo==0.42\nPyWin32==306\n";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        lock.add_marker("pywin32", "sys_platform == 'win32'");
+        let expected = "foo==0.42\nPyWin32==306 ; sys_platform == 'win32'\n";
+        assert_eq!(lock.to_string(), expected);
+        lock.add_marker("pywin32", "sys_platform == 'win32'");
+        assert_eq!(lock.to_string(), expected);
+    }
+
+    #[test]
+    fn overrides() {
+        let lock_contents = "bar==1.3\nFoo==0.42\nfoo-fork==0.43\n";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        let fork = Override {
+            name: "foo".to_string(),
+            requirement: "git+https://github.com/me/foo-fork@fix-42#egg=foo-fork".to_string(),
+        };
+        lock.apply_override(&fork, &fork.requirement).unwrap();
+        let forced = Override {
+            name: "bar".to_string(),
+            requirement: "bar==1.2".to_string(),
+        };
+        lock.apply_override(&forced, "bar==1.2").unwrap();
+        let expected = "\
+bar==1.2  # override: bar==1.2
+git+https://github.com/me/foo-fork@fix-42#egg=foo-fork  # override: replaces foo
+";
+        assert_eq!(lock.to_string(), expected);
+
+        let mut lock = Lock::from_string(expected).unwrap();
+        lock.remove_stale_overrides(&[forced]);
+        assert_eq!(lock.names(), vec!["bar"]);
+    }
+
+    fn explain(contents: &str, number: usize) -> BTreeMap<&'static str, String> {
+        explain_line(contents, number)
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn explain_simple_line() {
+        let lock_contents = "# Web\nbar==1.3  # pin: CVE-2021-1234\n";
+        assert!(explain(lock_contents, 1)["kind"].starts_with("comment"));
+        let actual = explain(lock_contents, 2);
+        assert_eq!(actual["kind"], "simple");
+        assert_eq!(actual["version"], "1.3");
+        assert_eq!(actual["pinned"], "CVE-2021-1234");
+        assert_eq!(
+            actual["bump"],
+            "dmenv bump-in-lock bar <version> --override-pin"
+        );
+        explain_line(lock_contents, 3).unwrap_err();
+    }
+
+    #[test]
+    fn explain_git_line_with_marker() {
+        let lock_contents = "git+https://example.com/foo.git@v1.0#egg=foo ; os_name == 'nt'\n";
+        let actual = explain(lock_contents, 1);
+        assert_eq!(actual["kind"], "git");
+        assert_eq!(actual["url"], "git+https://example.com/foo.git");
+        assert_eq!(actual["ref"], "v1.0");
+        assert!(actual["marker"].starts_with("os_name == 'nt' (understood"));
+    }
+
+    #[test]
+    fn explain_malformed_line() {
+        let lock_contents = "foo==0.42\nfoo==0.43\nbar\n";
+        let actual = explain(lock_contents, 2);
+        assert_eq!(actual["also listed at lines"], "1");
+        assert!(actual["error"].contains("already listed at line 1"));
+        let actual = explain(lock_contents, 3);
+        assert!(actual["kind"].starts_with("opaque"));
+    }
+
+    #[test]
+    fn keep_newlines_and_bom() {
+        let lock_contents = "\u{feff}bar==1.3\r\nfoo==0.42\r\n";
+        let mut lock = Lock::from_string(lock_contents).unwrap();
+        assert_eq!(lock.names(), vec!["bar", "foo"]);
+        lock.bump("foo", "0.43", None).unwrap();
+        assert_eq!(lock.to_string(), lock_contents.replace("0.42", "0.43"));
+
+        lock.newline(Newline::Lf);
+        assert_eq!(lock.to_string(), "\u{feff}bar==1.3\nfoo==0.43\n");
+    }
 }
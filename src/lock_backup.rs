@@ -0,0 +1,172 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::hash;
+
+/// Number of backups kept in the project, see `prune()`
+const KEPT_BACKUPS: usize = 20;
+
+/// Contents of the lock before a command changed it, so that `dmenv undo`
+/// can restore it.
+///
+/// Backups are stored in `.dmenv/lock-backups/` in the project. The first line
+/// contains tab-separated fields (lock file name, hash of the lock written by
+/// the command, whether the lock existed before, command line), followed by the
+/// previous contents of the lock
+#[derive(Debug, PartialEq)]
+pub struct Backup {
+    pub lock_name: String,
+    /// Hash of the contents written by the command. If the lock no longer
+    /// has this hash, it was edited afterwards and undoing would lose those changes
+    pub after_hash: String,
+    /// None if the lock did not exist before the command
+    pub before: Option<String>,
+    pub args: String,
+}
+
+impl fmt::Display for Backup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let existed = if self.before.is_some() { "yes" } else { "no" };
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\n{}",
+            self.lock_name,
+            self.after_hash,
+            existed,
+            self.args,
+            self.before.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl Backup {
+    pub fn new(lock_name: &str, before: Option<&str>, after: &str, args: &str) -> Self {
+        Backup {
+            lock_name: lock_name.to_string(),
+            after_hash: hash::hash_hex(after.as_bytes()),
+            before: before.map(|x| x.to_string()),
+            args: args.replace(|c| c == '\t' || c == '\n' || c == '\r', " "),
+        }
+    }
+
+    fn from_string(contents: &str) -> Option<Self> {
+        let index = contents.find('\n')?;
+        let mut fields = contents[..index].splitn(4, '\t');
+        let lock_name = fields.next()?.to_string();
+        let after_hash = fields.next()?.to_string();
+        let existed = fields.next()? == "yes";
+        let args = fields.next()?.to_string();
+        let before = if existed {
+            Some(contents[index + 1..].to_string())
+        } else {
+            None
+        };
+        Some(Backup {
+            lock_name,
+            after_hash,
+            before,
+            args,
+        })
+    }
+
+    /// Check that the current contents of the lock are the ones written by the command
+    pub fn matches(&self, current: &str) -> bool {
+        hash::hash_hex(current.as_bytes()) == self.after_hash
+    }
+}
+
+fn backups_dir(project: &Path) -> PathBuf {
+    project.join(".dmenv").join("lock-backups")
+}
+
+/// All the backups of the project, oldest first
+// Note: backups are named with an increasing sequence number, so that
+// sorting them by name sorts them by date
+fn list(project: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(backups_dir(project)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut res: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().map_or(false, |x| x == "backup"))
+        .collect();
+    res.sort();
+    res
+}
+
+pub fn save(project: &Path, backup: &Backup) -> Result<(), Error> {
+    let dir = backups_dir(project);
+    std::fs::create_dir_all(&dir).map_err(|e| Error::WriteError {
+        path: dir.to_path_buf(),
+        io_error: e,
+    })?;
+    let existing = list(project);
+    let next = existing
+        .last()
+        .and_then(|x| x.file_stem())
+        .and_then(|x| x.to_string_lossy().parse::<u64>().ok())
+        .map_or(1, |x| x + 1);
+    let path = dir.join(format!("{:08}.backup", next));
+    std::fs::write(&path, backup.to_string()).map_err(|e| Error::WriteError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })?;
+    prune(&existing);
+    Ok(())
+}
+
+// Note: errors are ignored, old backups are not important enough to fail for
+fn prune(existing: &[PathBuf]) {
+    // `existing` does not contain the backup that was just saved
+    if existing.len() < KEPT_BACKUPS {
+        return;
+    }
+    for path in &existing[..existing.len() + 1 - KEPT_BACKUPS] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Most recent backup of the given lock, with its path
+pub fn latest(project: &Path, lock_name: &str) -> Result<Option<(PathBuf, Backup)>, Error> {
+    for path in list(project).into_iter().rev() {
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        if let Some(backup) = Backup::from_string(&contents) {
+            if backup.lock_name == lock_name {
+                return Ok(Some((path, backup)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let backup = Backup::new(
+            "requirements.lock",
+            Some("foo==0.42\n"),
+            "foo==0.43\n",
+            "dmenv bump-in-lock foo 0.43",
+        );
+        let actual = Backup::from_string(&backup.to_string()).unwrap();
+        assert_eq!(actual, backup);
+        assert!(actual.matches("foo==0.43\n"));
+        assert!(!actual.matches("foo==0.44\n"));
+    }
+
+    #[test]
+    fn test_lock_did_not_exist() {
+        let backup = Backup::new("requirements.lock", None, "foo==0.42\n", "dmenv lock");
+        let actual = Backup::from_string(&backup.to_string()).unwrap();
+        assert!(actual.before.is_none());
+    }
+}
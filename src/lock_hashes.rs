@@ -0,0 +1,410 @@
+//! `--hash` options of the lock, which make pip check the archives it
+//! downloads. They follow the requirement, on continuation lines:
+//!
+//! ```text
+//! foo==0.42 \
+//!     --hash=sha256:0123...
+//! ```
+//!
+//! A trailing comment, like the reason of a pin, goes at the end of the last
+//! line, since pip does not allow continuation lines after a comment.
+//!
+//! `dmenv hash add` computes them from the archives in the wheels cache (see
+//! `dmenv prefetch`) or in a vendor directory, and `dmenv hash verify` checks
+//! these archives against the lock, for instance when a mirror serves rebuilt
+//! archives under the same name.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::dependencies::canonical_name;
+use crate::error::Error;
+use crate::runner::{CommandRunner, Process};
+
+/// Algorithms pip accepts in `--hash` options
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm::Sha256
+    }
+}
+
+impl Algorithm {
+    /// Name used by pip and by Python's `hashlib`
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha384 => "sha384",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha384" => Ok(Algorithm::Sha384),
+            "sha512" => Ok(Algorithm::Sha512),
+            _ => Err(Error::Other {
+                message: format!(
+                    "unknown hash algorithm '{}', expected 'sha256', 'sha384' or 'sha512'",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+/// A `name==version` line of the lock, and its hashes
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// As written in the lock
+    pub name: String,
+    pub version: String,
+    /// Environment marker, like `python_version < '3.8'`
+    pub marker: Option<String>,
+    /// Like `sha256:0123...`
+    pub hashes: Vec<String>,
+    /// Trailing comment, like the reason of a pin, with its leading whitespace
+    comment: Option<String>,
+}
+
+impl Entry {
+    fn parse(block: &[&str]) -> Option<Self> {
+        let first = block[0].trim();
+        if first.is_empty() || first.starts_with('#') || first.starts_with('-') {
+            return None;
+        }
+        let requirement = without_comment(first).trim_end_matches('\\');
+        let name_end = requirement
+            .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))?;
+        let rest = &requirement[name_end..];
+        // Skip the extras, like `foo[bar]==0.42`
+        let rest = match rest.strip_prefix('[') {
+            Some(extras) => &extras[extras.find(']')? + 1..],
+            None => rest,
+        };
+        let version = rest.trim_start().strip_prefix("==")?.trim_start();
+        let version_end = version
+            .find(|c: char| c.is_whitespace() || c == ';')
+            .unwrap_or(version.len());
+        let marker = version[version_end..]
+            .trim_start()
+            .strip_prefix(';')
+            .map(|x| x.trim().to_string());
+        let hashes = block
+            .iter()
+            .flat_map(|x| without_comment(x).split_whitespace())
+            .filter_map(|x| x.strip_prefix("--hash="))
+            .map(|x| x.to_string())
+            .collect();
+        Some(Entry {
+            name: requirement[..name_end].to_string(),
+            version: version[..version_end].to_string(),
+            marker,
+            hashes,
+            comment: trailing_comment(block),
+        })
+    }
+
+    /// Algorithms of the hashes, without duplicates
+    pub fn algorithms(&self) -> Vec<&str> {
+        let mut res: Vec<_> = self
+            .hashes
+            .iter()
+            .filter_map(|x| x.split(':').next())
+            .collect();
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    /// True if the archive was built for this entry
+    pub fn matches(&self, archive: &Path) -> bool {
+        let filename = archive
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match archive_name_version(&filename) {
+            Some((name, version)) => name == canonical_name(&self.name) && version == self.version,
+            None => false,
+        }
+    }
+}
+
+/// Position of a comment in the line. As in pip, `#` only starts a comment
+/// when preceded by whitespace
+fn comment_start(line: &str) -> Option<usize> {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return Some(i);
+        }
+        previous = c;
+    }
+    None
+}
+
+fn without_comment(line: &str) -> &str {
+    match comment_start(line) {
+        Some(pos) => line[..pos].trim_end(),
+        None => line.trim_end(),
+    }
+}
+
+/// Comment of the first line or of the last one, with its leading whitespace
+// Note: a `\\` at the end of a comment does not continue it
+fn trailing_comment(block: &[&str]) -> Option<String> {
+    let mut lines = vec![block[0]];
+    lines.extend(block.last().filter(|_| block.len() > 1));
+    lines.iter().find_map(|x| {
+        let comment = &x[without_comment(x).len()..];
+        if comment.trim().is_empty() {
+            return None;
+        }
+        Some(
+            comment
+                .trim_end()
+                .trim_end_matches('\\')
+                .trim_end()
+                .to_string(),
+        )
+    })
+}
+
+/// Lines of the lock, each one with the continuation lines following it
+fn blocks(contents: &str) -> Vec<Vec<&str>> {
+    let mut res: Vec<Vec<&str>> = vec![];
+    let mut continued = false;
+    for line in contents.lines() {
+        match res.last_mut() {
+            Some(block) if continued => block.push(line),
+            _ => res.push(vec![line]),
+        }
+        continued = line.trim_end().ends_with('\\');
+    }
+    res
+}
+
+pub fn entries(contents: &str) -> Vec<Entry> {
+    blocks(contents.trim_start_matches('\u{feff}'))
+        .iter()
+        .filter_map(|x| Entry::parse(x))
+        .collect()
+}
+
+/// Replace the hashes of the entries found in `hashes`, by `(name, version)`.
+/// Trailing comments are moved to the last line, after the hashes
+pub fn with_hashes(contents: &str, hashes: &BTreeMap<(String, String), Vec<String>>) -> String {
+    let bom = contents.starts_with('\u{feff}');
+    let crlf = contents.contains("\r\n");
+    let mut lines = vec![];
+    for block in blocks(contents.trim_start_matches('\u{feff}')) {
+        let entry = Entry::parse(&block);
+        let new_hashes = entry
+            .as_ref()
+            .and_then(|x| hashes.get(&(x.name.to_string(), x.version.to_string())));
+        let (entry, new_hashes) = match (entry, new_hashes) {
+            (Some(entry), Some(new_hashes)) => (entry, new_hashes),
+            _ => {
+                lines.extend(block.iter().map(|x| x.to_string()));
+                continue;
+            }
+        };
+        let strip = |x: &str| {
+            without_comment(x)
+                .trim_end_matches('\\')
+                .trim_end()
+                .to_string()
+        };
+        let mut new_block = vec![strip(block[0])];
+        // Keep the other options, like `--config-settings`
+        for line in &block[1..] {
+            let options = strip(line);
+            if options.trim().is_empty()
+                || options.split_whitespace().all(|x| x.starts_with("--hash="))
+            {
+                continue;
+            }
+            new_block.push(options);
+        }
+        new_block.extend(new_hashes.iter().map(|x| format!("    --hash={}", x)));
+        let last = new_block.len() - 1;
+        for (i, line) in new_block.into_iter().enumerate() {
+            if i < last {
+                lines.push(format!("{} \\", line));
+            } else {
+                lines.push(format!(
+                    "{}{}",
+                    line,
+                    entry.comment.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+    }
+    let mut res = lines.join("\n");
+    if contents.ends_with('\n') {
+        res.push('\n');
+    }
+    if crlf {
+        res = res.replace('\n', "\r\n");
+    }
+    if bom {
+        res.insert(0, '\u{feff}');
+    }
+    res
+}
+
+/// Canonical name and version of a wheel or an sdist, from its filename
+fn archive_name_version(filename: &str) -> Option<(String, String)> {
+    if let Some(stem) = filename.strip_suffix(".whl") {
+        let mut parts = stem.splitn(3, '-');
+        let name = parts.next()?;
+        let version = parts.next()?;
+        return Some((canonical_name(name), version.to_string()));
+    }
+    let stem = [".tar.gz", ".zip", ".tar.bz2"]
+        .iter()
+        .find_map(|x| filename.strip_suffix(x))?;
+    let pos = stem.rfind('-')?;
+    Some((canonical_name(&stem[..pos]), stem[pos + 1..].to_string()))
+}
+
+/// Wheels and sdists in the given directories, ignoring the ones that don't exist
+pub fn find_archives(dirs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut res = vec![];
+    for dir in dirs.iter().filter(|x| x.is_dir()) {
+        let entries = std::fs::read_dir(dir).map_err(|e| Error::ReadError {
+            path: dir.to_path_buf(),
+            io_error: e,
+        })?;
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let filename = entry.file_name().to_string_lossy().to_string();
+            if archive_name_version(&filename).is_some() {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Digest of each file, like `sha256:0123...`, using the given Python interpreter
+pub fn digests(
+    runner: &dyn CommandRunner,
+    python: &Path,
+    algorithm: &str,
+    paths: &[PathBuf],
+) -> Result<BTreeMap<PathBuf, String>, Error> {
+    let mut res = BTreeMap::new();
+    if paths.is_empty() {
+        return Ok(res);
+    }
+    let paths_str: Vec<_> = paths.iter().map(|x| x.to_string_lossy()).collect();
+    let script = include_str!("hash_files.py");
+    let process = Process::new(python)
+        .arg("-c")
+        .arg(script)
+        .arg(algorithm)
+        .args(&paths_str);
+    let output = runner.output(&process)?;
+    if !output.success {
+        return Err(Error::Other {
+            message: format!("Failed to run hash_files script: {}", output.stderr),
+        });
+    }
+    for line in output.stdout.lines() {
+        let mut parts = line.splitn(2, ' ');
+        if let (Some(digest), Some(path)) = (parts.next(), parts.next()) {
+            res.insert(PathBuf::from(path), format!("{}:{}", algorithm, digest));
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = "\
+# Generated with dmenv 0.12.0, python 3.7.1, on Linux
+bar[baz]==42 ; python_version < '3.8' \\
+    --hash=sha256:0123 \\
+    --hash=sha512:4567
+foo==0.42  # pin: see #42
+Spam_Eggs==1.0
+";
+
+    #[test]
+    fn test_entries() {
+        let entries = entries(LOCK);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "bar");
+        assert_eq!(entries[0].version, "42");
+        assert_eq!(entries[0].marker.as_deref(), Some("python_version < '3.8'"));
+        assert_eq!(entries[0].hashes, vec!["sha256:0123", "sha512:4567"]);
+        assert_eq!(entries[0].algorithms(), vec!["sha256", "sha512"]);
+        assert_eq!(entries[1].comment.as_deref(), Some("  # pin: see #42"));
+        assert!(entries[2].comment.is_none());
+        assert!(entries[2].hashes.is_empty());
+        assert!(entries[2].matches(Path::new("/cache/spam_eggs-1.0-py3-none-any.whl")));
+        assert!(entries[2].matches(Path::new("Spam-Eggs-1.0.tar.gz")));
+        assert!(!entries[2].matches(Path::new("spam_eggs-1.0.1-py3-none-any.whl")));
+    }
+
+    #[test]
+    fn test_with_hashes() {
+        let mut hashes = BTreeMap::new();
+        for (name, version) in &[("bar", "42"), ("foo", "0.42"), ("Spam_Eggs", "1.0")] {
+            let value = vec!["sha512:89ab".to_string(), "sha512:cdef".to_string()];
+            hashes.insert((name.to_string(), version.to_string()), value);
+        }
+        let actual = with_hashes(LOCK, &hashes);
+        assert_eq!(
+            actual,
+            "\
+# Generated with dmenv 0.12.0, python 3.7.1, on Linux
+bar[baz]==42 ; python_version < '3.8' \\
+    --hash=sha512:89ab \\
+    --hash=sha512:cdef
+foo==0.42 \\
+    --hash=sha512:89ab \\
+    --hash=sha512:cdef  # pin: see #42
+Spam_Eggs==1.0 \\
+    --hash=sha512:89ab \\
+    --hash=sha512:cdef
+"
+        );
+    }
+
+    #[test]
+    fn test_with_hashes_after_comment() {
+        // Adding hashes again leaves the comment on the last line
+        let lock = "foo==0.42 \\\n    --hash=sha256:0123  # pin: see #42\n";
+        let entries = entries(lock);
+        assert_eq!(entries[0].hashes, vec!["sha256:0123"]);
+        assert_eq!(entries[0].comment.as_deref(), Some("  # pin: see #42"));
+        let mut hashes = BTreeMap::new();
+        let value = vec!["sha256:4567".to_string()];
+        hashes.insert(("foo".to_string(), "0.42".to_string()), value);
+        assert_eq!(
+            with_hashes(lock, &hashes),
+            "foo==0.42 \\\n    --hash=sha256:4567  # pin: see #42\n"
+        );
+    }
+
+    #[test]
+    fn test_algorithm() {
+        assert_eq!("sha512".parse::<Algorithm>().unwrap(), Algorithm::Sha512);
+        assert!("md5".parse::<Algorithm>().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Statistics about a lock, for `dmenv lock stats`: kinds of dependencies,
+//! environment markers, age of the locked releases and how far behind their
+//! latest major versions they are, summed up in a health score.
+//!
+//! The health score goes from 0 to 100. It is the average over the simple
+//! dependencies known by the package index of:
+//!  - 1 point,
+//!  - minus half a point per major version behind the latest release,
+//!  - minus a quarter of a point if the locked release is more than two years old,
+//!
+//! without going below 0.
+use crate::dependencies::{canonical_name, LockedDependency};
+use crate::json;
+use crate::lock::Lock;
+use crate::release_info::Release;
+
+/// A dependency behind the latest major version of its package
+#[derive(Debug, PartialEq)]
+pub struct Outdated {
+    pub name: String,
+    pub locked: String,
+    pub latest: String,
+    pub majors_behind: u64,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub simple: usize,
+    pub git: usize,
+    pub url: usize,
+    pub editable: usize,
+    pub with_marker: usize,
+    /// Number of locked releases less than 6 months old, between 6 and 12
+    /// months, between 1 and 2 years, more than 2 years, and of unknown age
+    pub ages: [usize; 5],
+    pub outdated: Vec<Outdated>,
+    /// None when the index knows none of the dependencies
+    pub score: Option<u32>,
+}
+
+const AGE_LABELS: [&str; 5] = [
+    "under_6_months",
+    "6_to_12_months",
+    "1_to_2_years",
+    "over_2_years",
+    "unknown",
+];
+
+/// Compute the statistics of the lock. `releases` may be empty, when the
+/// package index was not queried
+pub fn compute(lock: &Lock, releases: &[Release]) -> Stats {
+    let mut stats = Stats::default();
+    let mut points = vec![];
+    for dep in lock.dependencies() {
+        let line = dep.line();
+        let line = line.trim_start();
+        let editable = line.starts_with("-e ") || line.starts_with("--editable");
+        match dep {
+            _ if editable => stats.editable += 1,
+            LockedDependency::Simple(_) => stats.simple += 1,
+            LockedDependency::Git(_) if line.contains("git+") => stats.git += 1,
+            LockedDependency::Git(_) => stats.url += 1,
+            LockedDependency::Opaque(_) if line.contains("://") && !line.starts_with('-') => {
+                stats.url += 1
+            }
+            // Note: pip options, like `--index-url`
+            LockedDependency::Opaque(_) => continue,
+        }
+        if dep.marker().is_some() {
+            stats.with_marker += 1;
+        }
+        let simple = match dep {
+            LockedDependency::Simple(simple) if !editable => simple,
+            _ => continue,
+        };
+        let name = canonical_name(&simple.name);
+        let release = releases.iter().find(|x| canonical_name(&x.name) == name);
+        let age = release.and_then(|x| x.age_days);
+        stats.ages[match age {
+            Some(days) if days < 183 => 0,
+            Some(days) if days < 365 => 1,
+            Some(days) if days < 730 => 2,
+            Some(_) => 3,
+            None => 4,
+        }] += 1;
+        let latest = match release.and_then(|x| x.latest.as_ref()) {
+            Some(latest) => latest,
+            None => continue,
+        };
+        let locked = &simple.version.value;
+        let majors_behind = match (major(locked), major(latest)) {
+            (Some(locked), Some(latest)) => latest.saturating_sub(locked),
+            _ => 0,
+        };
+        if majors_behind > 0 {
+            stats.outdated.push(Outdated {
+                name: simple.name.to_string(),
+                locked: locked.to_string(),
+                latest: latest.to_string(),
+                majors_behind,
+            });
+        }
+        let mut point = 1.0 - 0.5 * majors_behind as f64;
+        if age.map(|x| x >= 730).unwrap_or(false) {
+            point -= 0.25;
+        }
+        points.push(point.max(0.0));
+    }
+    if !points.is_empty() {
+        let average = points.iter().sum::<f64>() / points.len() as f64;
+        stats.score = Some((average * 100.0).round() as u32);
+    }
+    stats
+}
+
+/// First number of the version, like 3 for `3.2.1`, or 2024 for `2024.1`
+fn major(version: &str) -> Option<u64> {
+    let version = version.rsplit('!').next()?;
+    let digits: String = version.chars().take_while(|x| x.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+impl Stats {
+    pub fn total(&self) -> usize {
+        self.simple + self.git + self.url + self.editable
+    }
+
+    fn marker_percent(&self) -> usize {
+        if self.total() == 0 {
+            0
+        } else {
+            self.with_marker * 100 / self.total()
+        }
+    }
+
+    pub fn render(&self) -> Vec<String> {
+        let mut res = vec![
+            format!(
+                "Dependencies: {} ({} simple, {} git, {} url, {} editable)",
+                self.total(),
+                self.simple,
+                self.git,
+                self.url,
+                self.editable
+            ),
+            format!(
+                "With environment markers: {} ({}%)",
+                self.with_marker,
+                self.marker_percent()
+            ),
+            format!(
+                "Age of locked releases: {} under 6 months, {} between 6 and 12 months, \
+                 {} between 1 and 2 years, {} over 2 years, {} unknown",
+                self.ages[0], self.ages[1], self.ages[2], self.ages[3], self.ages[4]
+            ),
+            format!("Behind the latest major version: {}", self.outdated.len()),
+        ];
+        for outdated in &self.outdated {
+            res.push(format!(
+                "  {}: {} -> {} ({} major(s) behind)",
+                outdated.name, outdated.locked, outdated.latest, outdated.majors_behind
+            ));
+        }
+        res.push(match self.score {
+            Some(score) => format!("Health score: {}/100", score),
+            None => "Health score: unknown".to_string(),
+        });
+        res
+    }
+
+    pub fn to_json(&self) -> String {
+        let ages: Vec<_> = AGE_LABELS
+            .iter()
+            .zip(&self.ages)
+            .map(|(label, count)| format!("{}: {}", json::string(label), count))
+            .collect();
+        let outdated: Vec<_> = self
+            .outdated
+            .iter()
+            .map(|x| {
+                format!(
+                    "{{\"name\": {}, \"locked\": {}, \"latest\": {}, \"majors_behind\": {}}}",
+                    json::string(&x.name),
+                    json::string(&x.locked),
+                    json::string(&x.latest),
+                    x.majors_behind
+                )
+            })
+            .collect();
+        format!(
+            "{{\"dependencies\": {{\"total\": {}, \"simple\": {}, \"git\": {}, \"url\": {}, \
+             \"editable\": {}}}, \"with_marker\": {}, \"marker_percent\": {}, \
+             \"ages\": {{{}}}, \"outdated\": [{}], \"score\": {}}}",
+            self.total(),
+            self.simple,
+            self.git,
+            self.url,
+            self.editable,
+            self.with_marker,
+            self.marker_percent(),
+            ages.join(", "),
+            outdated.join(", "),
+            self.score
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "null".to_string())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = "\
+attrs==19.1.0
+django==3.2.25 ; python_version >= '3.8'
+requests==2.31.0
+git+https://github.com/acme/foo@0.42#egg=foo
+https://example.com/bar-1.0.zip#egg=bar
+-e ./lib/baz#egg=baz
+--index-url https://pypi.example.com/simple
+";
+
+    fn release(name: &str, age_days: Option<u64>, latest: &str) -> Release {
+        Release {
+            name: name.to_string(),
+            age_days,
+            latest: Some(latest.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_compute_without_releases() {
+        let lock = Lock::from_string(LOCK).unwrap();
+        let stats = compute(&lock, &[]);
+        assert_eq!(
+            (stats.simple, stats.git, stats.url, stats.editable),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(stats.with_marker, 1);
+        assert_eq!(stats.ages, [0, 0, 0, 0, 3]);
+        assert_eq!(stats.score, None);
+    }
+
+    #[test]
+    fn test_compute() {
+        let lock = Lock::from_string(LOCK).unwrap();
+        let releases = vec![
+            release("Attrs", Some(1700), "19.1.0"),
+            release("django", Some(400), "5.1.2"),
+            release("requests", Some(100), "2.32.3"),
+        ];
+        let stats = compute(&lock, &releases);
+        assert_eq!(stats.ages, [1, 0, 1, 1, 0]);
+        assert_eq!(
+            stats.outdated,
+            vec![Outdated {
+                name: "django".to_string(),
+                locked: "3.2.25".to_string(),
+                latest: "5.1.2".to_string(),
+                majors_behind: 2,
+            }]
+        );
+        // (0.75 + 0 + 1) / 3
+        assert_eq!(stats.score, Some(58));
+        assert!(stats.to_json().contains("\"score\": 58}"));
+    }
+
+    #[test]
+    fn test_major() {
+        assert_eq!(major("3.2.1"), Some(3));
+        assert_eq!(major("2024.1"), Some(2024));
+        assert_eq!(major("1!2.0"), Some(2));
+        assert_eq!(major("dev"), None);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// Number of logs kept in the virtualenv, see `prune()`
+const KEPT_LOGS: usize = 10;
+
+/// Where `dmenv --quiet` writes the output of pip
+pub fn logs_dir(venv: &Path) -> PathBuf {
+    venv.join(".dmenv").join("logs")
+}
+
+/// Path of the log for the current dmenv run. All the pip commands of
+/// one run go to the same file
+// Note: names start with the timestamp, so that sorting them by name
+// sorts them by date
+pub fn new_log_path(venv: &Path, timestamp: u64) -> PathBuf {
+    logs_dir(venv).join(format!("{}-{}.log", timestamp, std::process::id()))
+}
+
+/// Open the log for appending, creating it (and its parent directories) if needed
+pub fn open(path: &Path) -> Result<File, Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::WriteError {
+            path: parent.to_path_buf(),
+            io_error: e,
+        })?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+}
+
+/// All the logs of the virtualenv, oldest first
+pub fn list(venv: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(logs_dir(venv)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut res: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().map_or(false, |x| x == "log"))
+        .collect();
+    res.sort();
+    res
+}
+
+/// Remove old logs, keeping the last KEPT_LOGS ones
+// Note: errors are ignored, the logs are not important enough to fail for
+pub fn prune(venv: &Path) {
+    let logs = list(venv);
+    if logs.len() <= KEPT_LOGS {
+        return;
+    }
+    for log in &logs[..logs.len() - KEPT_LOGS] {
+        let _ = std::fs::remove_file(log);
+    }
+}
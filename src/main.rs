@@ -1,10 +1,41 @@
 use structopt::StructOpt;
 
 fn main() {
-    let cmd = dmenv::Command::from_args();
-    let result = dmenv::run(cmd);
-    if let Err(error) = result {
-        dmenv::print_error(&error.to_string());
-        std::process::exit(1)
+    std::process::exit(run())
+}
+
+// Note: std::process::exit() does not run destructors, so everything
+// has to be dropped before calling it
+fn run() -> i32 {
+    #[cfg(windows)]
+    let _console = dmenv::Console::setup();
+
+    let args: Vec<String> = std::env::args_os()
+        .map(|x| x.to_string_lossy().to_string())
+        .collect();
+    // Note: clap prints the version while parsing, so parse only once before
+    // looking for an external command
+    let result = match dmenv::Command::from_iter_safe(&args) {
+        Ok(cmd) => dmenv::run_with_command_line(cmd, &args),
+        // --help and --version are "errors" too. The message of --version
+        // is empty, and ends the line of the version
+        Err(error) if !error.use_stderr() => {
+            println!("{}", error.message);
+            return dmenv::exit_code::SUCCESS;
+        }
+        Err(error) => match dmenv::ExternalCommand::find(&args) {
+            Some(external) => dmenv::run_external(&external),
+            None => {
+                eprintln!("{}", error.message);
+                return dmenv::exit_code::USAGE;
+            }
+        },
     };
+    match result {
+        Ok(()) => dmenv::exit_code::SUCCESS,
+        Err(error) => {
+            dmenv::print_error(&error.to_string());
+            error.exit_code()
+        }
+    }
 }
@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::dedupe::site_packages_dirs;
+use crate::error::Error;
+
+/// Name of the file, written in the virtualenv, listing what `dmenv develop` added to it
+pub const MANIFEST_FILENAME: &str = "dmenv-develop.manifest";
+
+/// Files in the parts of the virtualenv `dmenv develop` writes to (scripts and
+/// site-packages), and the lines of its `.pth` files, at a given time.
+/// Paths are relative to the virtualenv
+pub struct Snapshot {
+    files: BTreeSet<PathBuf>,
+    /// Directories at the top of site-packages, see `take()`
+    dirs: BTreeSet<PathBuf>,
+    pth_lines: BTreeMap<PathBuf, Vec<String>>,
+}
+
+impl Snapshot {
+    /// The directories at the top of site-packages (one per installed package)
+    /// are only walked when they are not in `previous`: `develop` adds new
+    /// ones, but never writes in existing ones, and walking every installed
+    /// package twice would be slow
+    pub fn take(venv: &Path, previous: Option<&Snapshot>) -> Result<Self, Error> {
+        let mut res = Snapshot {
+            files: BTreeSet::new(),
+            dirs: BTreeSet::new(),
+            pth_lines: BTreeMap::new(),
+        };
+        for scripts in &[venv.join("bin"), venv.join("Scripts")] {
+            if scripts.is_dir() {
+                res.walk(venv, scripts)?;
+            }
+        }
+        for site_packages in site_packages_dirs(venv) {
+            for entry in read_dir(&site_packages)? {
+                let path = entry.path();
+                let file_type = match entry.file_type() {
+                    Ok(file_type) => file_type,
+                    Err(_) => continue,
+                };
+                if !file_type.is_dir() {
+                    res.add_file(venv, &path)?;
+                    continue;
+                }
+                let relative_path = path.strip_prefix(venv).unwrap_or(&path).to_path_buf();
+                let is_new = match previous {
+                    Some(previous) => !previous.dirs.contains(&relative_path),
+                    None => false,
+                };
+                if is_new && entry.file_name() != "__pycache__" {
+                    res.walk(venv, &path)?;
+                }
+                res.dirs.insert(relative_path);
+            }
+        }
+        Ok(res)
+    }
+
+    fn walk(&mut self, venv: &Path, dir: &Path) -> Result<(), Error> {
+        for entry in read_dir(dir)? {
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            if file_type.is_dir() {
+                // Byte-code is written whenever Python feels like it
+                if entry.file_name() != "__pycache__" {
+                    self.walk(venv, &path)?;
+                }
+                continue;
+            }
+            self.add_file(venv, &path)?;
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, venv: &Path, path: &Path) -> Result<(), Error> {
+        let relative_path = path.strip_prefix(venv).unwrap_or(path).to_path_buf();
+        if path.extension().map_or(false, |x| x == "pth") {
+            let contents = std::fs::read_to_string(path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            let lines = contents.lines().map(|x| x.to_string()).collect();
+            self.pth_lines.insert(relative_path.clone(), lines);
+        }
+        self.files.insert(relative_path);
+        Ok(())
+    }
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<std::fs::DirEntry>, Error> {
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::ReadError {
+        path: dir.to_path_buf(),
+        io_error: e,
+    })?;
+    Ok(entries.filter_map(Result::ok).collect())
+}
+
+/// What was added to the virtualenv by `dmenv develop`: new files (egg-links,
+/// entry point scripts ...) and new lines in existing `.pth` files
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    files: BTreeSet<PathBuf>,
+    pth_lines: BTreeSet<(PathBuf, String)>,
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for file in &self.files {
+            writeln!(f, "file {}", file.to_string_lossy())?;
+        }
+        for (path, line) in &self.pth_lines {
+            writeln!(f, "pth {} {}", path.to_string_lossy(), line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Manifest {
+    pub fn diff(before: &Snapshot, after: &Snapshot) -> Self {
+        let mut res = Manifest::default();
+        for file in after.files.difference(&before.files) {
+            res.files.insert(file.to_path_buf());
+        }
+        for (path, lines) in &after.pth_lines {
+            // Lines of new .pth files are removed with the file itself
+            let old_lines = match before.pth_lines.get(path) {
+                Some(old_lines) => old_lines,
+                None => continue,
+            };
+            for line in lines.iter().filter(|x| !old_lines.contains(x)) {
+                res.pth_lines.insert((path.to_path_buf(), line.to_string()));
+            }
+        }
+        res
+    }
+
+    /// Add everything listed in `other`, for instance when `dmenv develop`
+    /// is run several times
+    pub fn merge(&mut self, other: Manifest) {
+        self.files.extend(other.files);
+        self.pth_lines.extend(other.pth_lines);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.pth_lines.is_empty()
+    }
+
+    pub fn from_string(string: &str) -> Result<Self, Error> {
+        let mut res = Manifest::default();
+        for line in string.lines() {
+            // Note: file paths may contain spaces, but the paths of `.pth` files
+            // (in site-packages) don't
+            let mut words = line.splitn(2, ' ');
+            let (kind, rest) = (words.next(), words.next().unwrap_or_default());
+            let mut words = rest.splitn(2, ' ');
+            match (kind, words.next(), words.next()) {
+                (Some("file"), _, _) if !rest.is_empty() => {
+                    res.files.insert(PathBuf::from(rest));
+                }
+                (Some("pth"), Some(path), Some(pth_line)) => {
+                    res.pth_lines
+                        .insert((PathBuf::from(path), pth_line.to_string()));
+                }
+                _ => {
+                    return Err(Error::Other {
+                        message: format!("Invalid line in {}: {}", MANIFEST_FILENAME, line),
+                    })
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Remove the files and the `.pth` lines from the virtualenv.
+    /// Returns the relative paths of the files that were changed
+    pub fn remove(&self, venv: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut res = vec![];
+        for file in &self.files {
+            let path = venv.join(file);
+            if !path.exists() {
+                continue;
+            }
+            std::fs::remove_file(&path).map_err(|e| Error::Other {
+                message: format!("could not remove {}: {}", path.display(), e),
+            })?;
+            res.push(file.to_path_buf());
+        }
+        // Also remove directories left empty, like `<name>.dist-info`.
+        // Note: remove_dir() fails on non-empty directories, which is what we want
+        let mut parents: Vec<_> = self.files.iter().filter_map(|x| x.parent()).collect();
+        parents.sort_by_key(|x| std::cmp::Reverse(x.components().count()));
+        parents.dedup();
+        for parent in parents {
+            if parent.as_os_str().is_empty() {
+                continue;
+            }
+            let _ = std::fs::remove_dir(venv.join(parent));
+        }
+        let pth_files: BTreeSet<_> = self.pth_lines.iter().map(|(path, _)| path).collect();
+        for file in pth_files {
+            let path = venv.join(file);
+            if !path.exists() {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            let to_remove: Vec<_> = self
+                .pth_lines
+                .iter()
+                .filter(|(x, _)| x == file)
+                .map(|(_, line)| line)
+                .collect();
+            let kept: Vec<_> = contents
+                .lines()
+                .filter(|x| !to_remove.iter().any(|line| line == x))
+                .collect();
+            let mut new_contents = kept.join("\n");
+            if !new_contents.is_empty() {
+                new_contents.push('\n');
+            }
+            std::fs::write(&path, new_contents).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            res.push(file.to_path_buf());
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(files: &[&str], pth_lines: &[(&str, &[&str])]) -> Snapshot {
+        Snapshot {
+            files: files.iter().map(PathBuf::from).collect(),
+            dirs: BTreeSet::new(),
+            pth_lines: pth_lines
+                .iter()
+                .map(|(path, lines)| {
+                    let lines = lines.iter().map(|x| x.to_string()).collect();
+                    (PathBuf::from(path), lines)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = snapshot(
+            &["bin/python", "lib/easy-install.pth"],
+            &[("lib/easy-install.pth", &["/other"])],
+        );
+        let after = snapshot(
+            &[
+                "bin/demo",
+                "bin/python",
+                "lib/demo.egg-link",
+                "lib/easy-install.pth",
+            ],
+            &[("lib/easy-install.pth", &["/other", "/project"])],
+        );
+        let manifest = Manifest::diff(&before, &after);
+        assert_eq!(
+            manifest.to_string(),
+            "file bin/demo\nfile lib/demo.egg-link\npth lib/easy-install.pth /project\n"
+        );
+    }
+
+    #[test]
+    fn test_take() {
+        let tmp_dir = tempdir::TempDir::new("test-dmenv").unwrap();
+        let venv = tmp_dir.path();
+        let write = |path: &str, contents: &str| {
+            let path = venv.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("bin/python", "");
+        write("lib/python3.7/site-packages/attr/__init__.py", "");
+        write("lib/python3.7/site-packages/easy-install.pth", "/other\n");
+        let before = Snapshot::take(venv, None).unwrap();
+        assert!(!before
+            .files
+            .contains(Path::new("lib/python3.7/site-packages/attr/__init__.py")));
+
+        write("bin/demo", "");
+        write("lib/python3.7/site-packages/attr/_version.py", "");
+        write("lib/python3.7/site-packages/demo-0.1.dist-info/RECORD", "");
+        write(
+            "lib/python3.7/site-packages/easy-install.pth",
+            "/other\n/project\n",
+        );
+        let after = Snapshot::take(venv, Some(&before)).unwrap();
+        assert_eq!(
+            Manifest::diff(&before, &after).to_string(),
+            "\
+file bin/demo
+file lib/python3.7/site-packages/demo-0.1.dist-info/RECORD
+pth lib/python3.7/site-packages/easy-install.pth /project
+"
+        );
+    }
+
+    #[test]
+    fn test_from_string() {
+        let contents = "\
+file bin/demo
+file lib/site-packages/demo app.egg-link
+pth lib/easy-install.pth /path with spaces
+";
+        let manifest = Manifest::from_string(contents).unwrap();
+        assert_eq!(manifest.to_string(), contents);
+        Manifest::from_string("egg-link demo").unwrap_err();
+        Manifest::from_string("file").unwrap_err();
+    }
+}
@@ -0,0 +1,385 @@
+use std::cmp::Ordering;
+
+/// A small subset of PEP 508 environment markers, just enough to tell whether
+/// two lock entries can be installed in the same environment.
+///
+/// Markers are stored in disjunctive normal form: the marker matches
+/// if all the clauses of any of the conjunctions match.
+#[derive(Debug, PartialEq)]
+pub struct Marker {
+    conjunctions: Vec<Vec<Clause>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Clause {
+    variable: String,
+    op: String,
+    value: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Variable(String),
+    Value(String),
+    Op(String),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+// Variables compared as versions. All the others are compared as strings.
+const VERSION_VARIABLES: &[&str] = &[
+    "python_version",
+    "python_full_version",
+    "implementation_version",
+];
+
+impl Marker {
+    /// Returns None if the marker uses syntax we don't know about
+    pub fn parse(string: &str) -> Option<Self> {
+        let tokens = tokenize(string)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let conjunctions = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return None;
+        }
+        Some(Marker { conjunctions })
+    }
+
+    /// True if no environment can match both markers
+    pub fn is_disjoint(&self, other: &Marker) -> bool {
+        for left in &self.conjunctions {
+            for right in &other.conjunctions {
+                let mut clauses = left.clone();
+                clauses.extend(right.iter().cloned());
+                if is_satisfiable(&clauses) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let res = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        res
+    }
+
+    fn parse_or(&mut self) -> Option<Vec<Vec<Clause>>> {
+        let mut res = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            res.extend(self.parse_and()?);
+        }
+        Some(res)
+    }
+
+    fn parse_and(&mut self) -> Option<Vec<Vec<Clause>>> {
+        let mut res = self.parse_atom()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            let right = self.parse_atom()?;
+            let mut product = vec![];
+            for left_clauses in &res {
+                for right_clauses in &right {
+                    let mut clauses = left_clauses.clone();
+                    clauses.extend(right_clauses.iter().cloned());
+                    product.push(clauses);
+                }
+            }
+            res = product;
+        }
+        Some(res)
+    }
+
+    fn parse_atom(&mut self) -> Option<Vec<Vec<Clause>>> {
+        match self.next()? {
+            Token::Open => {
+                let res = self.parse_or()?;
+                if self.next()? != Token::Close {
+                    return None;
+                }
+                Some(res)
+            }
+            Token::Variable(variable) => {
+                let op = match self.next()? {
+                    Token::Op(op) => op,
+                    _ => return None,
+                };
+                let value = match self.next()? {
+                    Token::Value(value) => value,
+                    _ => return None,
+                };
+                Some(vec![vec![Clause {
+                    variable,
+                    op,
+                    value,
+                }]])
+            }
+            Token::Value(value) => {
+                // Like `'3.6' <= python_version`
+                let op = match self.next()? {
+                    Token::Op(op) => flip(&op)?,
+                    _ => return None,
+                };
+                let variable = match self.next()? {
+                    Token::Variable(variable) => variable,
+                    _ => return None,
+                };
+                Some(vec![vec![Clause {
+                    variable,
+                    op,
+                    value,
+                }]])
+            }
+            _ => None,
+        }
+    }
+}
+
+fn flip(op: &str) -> Option<String> {
+    let res = match op {
+        "<" => ">",
+        "<=" => ">=",
+        ">" => "<",
+        ">=" => "<=",
+        "==" | "!=" => op,
+        _ => return None,
+    };
+    Some(res.to_string())
+}
+
+fn tokenize(string: &str) -> Option<Vec<Token>> {
+    let mut res = vec![];
+    let chars: Vec<_> = string.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            res.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            res.push(Token::Close);
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..].iter().position(|x| *x == c)? + i + 1;
+            res.push(Token::Value(chars[i + 1..end].iter().collect()));
+            i = end + 1;
+        } else if "<>=!~".contains(c) {
+            let start = i;
+            while i < chars.len() && "<>=!~".contains(chars[i]) {
+                i += 1;
+            }
+            res.push(Token::Op(chars[start..i].iter().collect()));
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let token = match word.as_str() {
+                "and" => Token::And,
+                "or" => Token::Or,
+                // `in` and `not in` are substring checks, we don't support them
+                "in" | "not" => return None,
+                _ => Token::Variable(word),
+            };
+            res.push(token);
+        } else {
+            return None;
+        }
+    }
+    Some(res)
+}
+
+/// Check whether all the clauses can be true at the same time.
+// Note: when in doubt (operators like `~=`, string ordering ...), assume they can.
+fn is_satisfiable(clauses: &[Clause]) -> bool {
+    let mut variables: Vec<_> = clauses.iter().map(|x| &x.variable).collect();
+    variables.sort();
+    variables.dedup();
+    variables.into_iter().all(|variable| {
+        let clauses: Vec<_> = clauses.iter().filter(|x| &x.variable == variable).collect();
+        if VERSION_VARIABLES.contains(&variable.as_str()) {
+            versions_satisfiable(&clauses)
+        } else {
+            strings_satisfiable(&clauses)
+        }
+    })
+}
+
+fn strings_satisfiable(clauses: &[&Clause]) -> bool {
+    let equal: Vec<_> = clauses
+        .iter()
+        .filter(|x| x.op == "==")
+        .map(|x| &x.value)
+        .collect();
+    let not_equal: Vec<_> = clauses
+        .iter()
+        .filter(|x| x.op == "!=")
+        .map(|x| &x.value)
+        .collect();
+    let pinned = match equal.first() {
+        None => return true,
+        Some(pinned) => pinned,
+    };
+    equal.iter().all(|x| x == pinned) && !not_equal.contains(pinned)
+}
+
+fn versions_satisfiable(clauses: &[&Clause]) -> bool {
+    // Lower and upper bounds, with a boolean telling whether the bound is inclusive
+    let mut lower: Option<(&str, bool)> = None;
+    let mut upper: Option<(&str, bool)> = None;
+    let mut excluded = vec![];
+    for clause in clauses {
+        let value = clause.value.as_str();
+        match clause.op.as_str() {
+            "==" => {
+                lower = Some(max_bound(lower, (value, true)));
+                upper = Some(min_bound(upper, (value, true)));
+            }
+            ">=" => lower = Some(max_bound(lower, (value, true))),
+            ">" => lower = Some(max_bound(lower, (value, false))),
+            "<=" => upper = Some(min_bound(upper, (value, true))),
+            "<" => upper = Some(min_bound(upper, (value, false))),
+            "!=" => excluded.push(value),
+            _ => (),
+        }
+    }
+    let (lower, upper) = match (lower, upper) {
+        (Some(lower), Some(upper)) => (lower, upper),
+        _ => return true,
+    };
+    match compare_versions(lower.0, upper.0) {
+        Ordering::Greater => false,
+        Ordering::Less => true,
+        Ordering::Equal => {
+            let inclusive = lower.1 && upper.1;
+            let pinned = lower.0;
+            inclusive
+                && !excluded
+                    .iter()
+                    .any(|x| compare_versions(x, pinned) == Ordering::Equal)
+        }
+    }
+}
+
+fn max_bound<'a>(current: Option<(&'a str, bool)>, new: (&'a str, bool)) -> (&'a str, bool) {
+    let current = match current {
+        None => return new,
+        Some(current) => current,
+    };
+    match compare_versions(current.0, new.0) {
+        Ordering::Greater => current,
+        Ordering::Less => new,
+        Ordering::Equal => (current.0, current.1 && new.1),
+    }
+}
+
+fn min_bound<'a>(current: Option<(&'a str, bool)>, new: (&'a str, bool)) -> (&'a str, bool) {
+    let current = match current {
+        None => return new,
+        Some(current) => current,
+    };
+    match compare_versions(current.0, new.0) {
+        Ordering::Less => current,
+        Ordering::Greater => new,
+        Ordering::Equal => (current.0, current.1 && new.1),
+    }
+}
+
+/// Compare versions like `3.6` and `3.10.1`, component by component
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |x: &str| -> Vec<u64> { x.split('.').map(|x| x.parse().unwrap_or(0)).collect() };
+    let mut a = parse(a);
+    let mut b = parse(b);
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    a.cmp(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disjoint(a: &str, b: &str) -> bool {
+        let a = Marker::parse(a).unwrap();
+        let b = Marker::parse(b).unwrap();
+        a.is_disjoint(&b)
+    }
+
+    #[test]
+    fn test_parse() {
+        let marker = Marker::parse(
+            "python_version < '3.10' and (os_name == \"nt\" or '3' < python_version)",
+        )
+        .unwrap();
+        assert_eq!(marker.conjunctions.len(), 2);
+        assert_eq!(marker.conjunctions[1][1].op, ">");
+        assert!(Marker::parse("'linux' in sys_platform").is_none());
+        assert!(Marker::parse("python_version < ").is_none());
+    }
+
+    #[test]
+    fn test_disjoint_versions() {
+        assert!(disjoint(
+            "python_version < '3.10'",
+            "python_version >= '3.10'"
+        ));
+        assert!(disjoint(
+            "python_version <= '3.9'",
+            "python_version > '3.9'"
+        ));
+        assert!(disjoint(
+            "python_version == '3.9'",
+            "python_version != '3.9'"
+        ));
+        assert!(!disjoint(
+            "python_version < '3.10'",
+            "python_version >= '3.9'"
+        ));
+        assert!(!disjoint(
+            "python_version < '3.10'",
+            "sys_platform == 'win32'"
+        ));
+    }
+
+    #[test]
+    fn test_disjoint_strings() {
+        assert!(disjoint(
+            "sys_platform == 'win32'",
+            "sys_platform == 'linux'"
+        ));
+        assert!(disjoint("os_name == 'nt'", "os_name != 'nt'"));
+        assert!(!disjoint(
+            "sys_platform != 'win32'",
+            "sys_platform != 'linux'"
+        ));
+    }
+
+    #[test]
+    fn test_disjoint_or() {
+        let windows_or_old = "sys_platform == 'win32' or python_version < '3.6'";
+        assert!(disjoint(
+            windows_or_old,
+            "sys_platform == 'linux' and python_version >= '3.6'"
+        ));
+        assert!(!disjoint(windows_or_old, "sys_platform == 'linux'"));
+    }
+}
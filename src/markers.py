@@ -0,0 +1,19 @@
+""" Print the environment markers given on the command line that
+match the current environment, one per line.
+
+Usage:
+    markers.py <marker>...
+"""
+import sys
+
+from pip._vendor.packaging.markers import Marker
+
+
+def main():
+    for marker in sys.argv[1:]:
+        if Marker(marker).evaluate():
+            print(marker)
+
+
+if __name__ == "__main__":
+    main()
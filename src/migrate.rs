@@ -0,0 +1,175 @@
+//! Versions of the files written by dmenv, deprecations, and how to
+//! migrate files written by older versions (see `dmenv migrate`).
+//!
+//! The lock starts with a header telling how it was generated:
+//!
+//! ```text
+//! # Generated with dmenv 0.12.0, python 3.7.1, on Linux
+//! # Lock format: 2
+//! ```
+//!
+//! Locks without a `Lock format` line were written before formats were
+//! versioned, and are format 1.
+use std::fmt;
+
+use crate::build_isolation;
+use crate::error::Error;
+use crate::variants;
+
+/// Format of the locks written by this version of dmenv
+pub const LOCK_FORMAT: u32 = 2;
+
+/// Locks in a format older than this one print a deprecation warning
+// Note: format 1 is only deprecated once a released dmenv can migrate it,
+// see `dmenv migrate`
+const OLDEST_LOCK_FORMAT: u32 = 1;
+
+/// Format of the `dmenv.toml` files this version of dmenv reads
+pub const CONFIG_FORMAT: u32 = 1;
+
+const GENERATED_PREFIX: &str = "# Generated with dmenv ";
+const FORMAT_PREFIX: &str = "# Lock format: ";
+
+/// Something that still works, but will be removed in a future version
+#[derive(Debug, PartialEq)]
+pub struct Deprecation {
+    pub what: String,
+    /// What to do instead
+    pub instead: String,
+    /// Version of dmenv in which it will be removed
+    pub removed_in: &'static str,
+}
+
+impl fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is deprecated and will be removed in dmenv {}: {}",
+            self.what, self.removed_in, self.instead
+        )
+    }
+}
+
+pub fn lock_header(dmenv_version: &str, python_version: &str, platform: &str) -> String {
+    format!(
+        "{}{}, python {}, on {}\n{}{}\n",
+        GENERATED_PREFIX, dmenv_version, python_version, platform, FORMAT_PREFIX, LOCK_FORMAT
+    )
+}
+
+/// The lock without the header written by dmenv, if any, including the
+/// variant and the build settings (see the `variants` and `build_isolation`
+/// modules)
+pub fn strip_lock_header(contents: &str) -> &str {
+    let mut res = contents;
+    let prefixes = [
+        GENERATED_PREFIX,
+        FORMAT_PREFIX,
+        variants::LOCK_PREFIX,
+        build_isolation::ISOLATION_PREFIX,
+        build_isolation::REQUIREMENTS_PREFIX,
+    ];
+    while prefixes.iter().any(|x| res.starts_with(x)) {
+        res = res.find('\n').map(|x| &res[x + 1..]).unwrap_or_default();
+    }
+    res
+}
+
+pub fn lock_format(contents: &str) -> Result<u32, Error> {
+    let found = contents
+        .lines()
+        .enumerate()
+        .take_while(|(_, x)| x.starts_with('#'))
+        .find_map(|(i, x)| x.strip_prefix(FORMAT_PREFIX).map(|x| (i + 1, x.trim())));
+    let (line, value) = match found {
+        None => return Ok(1),
+        Some(found) => found,
+    };
+    value.parse().map_err(|_| Error::MalformedLock {
+        line,
+        details: format!("invalid lock format: '{}'", value),
+    })
+}
+
+/// Fail on locks written by a newer dmenv, and return a deprecation for
+/// locks in a deprecated format
+pub fn check_lock_format(contents: &str) -> Result<Option<Deprecation>, Error> {
+    let format = lock_format(contents)?;
+    if format > LOCK_FORMAT {
+        return Err(Error::Other {
+            message: format!(
+                "The lock uses format {}, but this version of dmenv only supports formats up to {}.\n Please upgrade dmenv",
+                format, LOCK_FORMAT
+            ),
+        });
+    }
+    if format < OLDEST_LOCK_FORMAT {
+        return Ok(Some(Deprecation {
+            what: format!("Lock format {}", format),
+            instead: "run `dmenv migrate` to upgrade the lock".to_string(),
+            removed_in: "1.0",
+        }));
+    }
+    Ok(None)
+}
+
+/// Contents of the lock upgraded to the current format, or None if
+/// it is already up to date
+pub fn migrate_lock(contents: &str) -> Result<Option<String>, Error> {
+    if lock_format(contents)? >= LOCK_FORMAT {
+        return Ok(None);
+    }
+    let format_line = format!("{}{}\n", FORMAT_PREFIX, LOCK_FORMAT);
+    let res = if contents.starts_with(GENERATED_PREFIX) {
+        let end = contents.find('\n').map(|x| x + 1).unwrap_or(contents.len());
+        let mut generated = contents[..end].to_string();
+        if !generated.ends_with('\n') {
+            generated.push('\n');
+        }
+        generated + &format_line + &contents[end..]
+    } else {
+        format_line + contents
+    };
+    Ok(Some(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_header() {
+        let header = lock_header("0.12.0", "3.7.1", "Linux");
+        assert_eq!(
+            header,
+            "# Generated with dmenv 0.12.0, python 3.7.1, on Linux\n# Lock format: 2\n"
+        );
+        let contents = header + "# Build isolation: off\n# Variant: cpu\n# Web\ndjango==3.2\n";
+        assert_eq!(lock_format(&contents).unwrap(), LOCK_FORMAT);
+        assert_eq!(strip_lock_header(&contents), "# Web\ndjango==3.2\n");
+    }
+
+    #[test]
+    fn test_check_lock_format() {
+        assert!(check_lock_format("foo==0.42\n").unwrap().is_none());
+        assert!(check_lock_format("# Lock format: 2\nfoo==0.42\n")
+            .unwrap()
+            .is_none());
+        assert!(check_lock_format("# Lock format: 3\nfoo==0.42\n").is_err());
+        assert!(check_lock_format("# Lock format: two\nfoo==0.42\n").is_err());
+    }
+
+    #[test]
+    fn test_migrate_lock() {
+        let contents = "# Generated with dmenv 0.11.1, python 3.7.1, on Linux\nfoo==0.42\n";
+        let actual = migrate_lock(contents).unwrap().unwrap();
+        assert_eq!(
+            actual,
+            "# Generated with dmenv 0.11.1, python 3.7.1, on Linux\n# Lock format: 2\nfoo==0.42\n"
+        );
+        assert!(migrate_lock(&actual).unwrap().is_none());
+
+        let actual = migrate_lock("foo==0.42\n").unwrap().unwrap();
+        assert_eq!(actual, "# Lock format: 2\nfoo==0.42\n");
+    }
+}
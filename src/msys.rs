@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+/// POSIX layers on Windows (Git Bash, MSYS2, Cygwin). Their shells use paths like
+/// `/c/Users/jane` (or `/cygdrive/c/Users/jane`), which native programs like dmenv
+/// and Python don't understand, and they can't use `C:\Users\jane` in `PATH`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PosixLayer {
+    /// MSYS2 and Git Bash, where drives are mounted at `/c`, `/d` ...
+    Msys,
+    /// Cygwin, where drives are mounted at `/cygdrive/c` ...
+    Cygwin,
+}
+
+impl PosixLayer {
+    /// Always None outside Windows
+    pub fn detect() -> Option<Self> {
+        if !cfg!(windows) {
+            return None;
+        }
+        if std::env::var_os("MSYSTEM").is_some() {
+            return Some(PosixLayer::Msys);
+        }
+        match std::env::var("OSTYPE") {
+            Ok(ostype) if ostype.starts_with("cygwin") => Some(PosixLayer::Cygwin),
+            _ => None,
+        }
+    }
+
+    fn drive_prefix(self) -> &'static str {
+        match self {
+            PosixLayer::Msys => "/",
+            PosixLayer::Cygwin => "/cygdrive/",
+        }
+    }
+
+    /// Convert `C:\Users\jane` to `/c/Users/jane`
+    pub fn to_posix(self, path: &str) -> String {
+        let path = path.trim_start_matches(r"\\?\");
+        let mut chars = path.chars();
+        let res = match (chars.next(), chars.next()) {
+            (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => format!(
+                "{}{}{}",
+                self.drive_prefix(),
+                drive.to_ascii_lowercase(),
+                chars.as_str()
+            ),
+            _ => path.to_string(),
+        };
+        res.replace('\\', "/")
+    }
+}
+
+/// Convert `/c/Users/jane` or `/cygdrive/c/Users/jane` to `C:\Users\jane`.
+/// Returns None for anything else (including paths like `/usr/bin`, which only
+/// make sense inside the POSIX layer)
+pub fn to_windows(path: &str) -> Option<String> {
+    let rest = if path.starts_with("/cygdrive/") {
+        &path["/cygdrive".len()..]
+    } else {
+        path
+    };
+    let mut chars = rest.chars();
+    if chars.next() != Some('/') {
+        return None;
+    }
+    let drive = chars.next().filter(|x| x.is_ascii_alphabetic())?;
+    let tail = chars.as_str();
+    if !tail.is_empty() && !tail.starts_with('/') {
+        return None;
+    }
+    Some(format!(
+        "{}:\\{}",
+        drive.to_ascii_uppercase(),
+        tail.trim_start_matches('/').replace('/', "\\")
+    ))
+}
+
+/// Path coming from the user or the environment (`--project`, `VIRTUAL_ENV`),
+/// converted if needed so that dmenv and the processes it spawns can use it
+pub fn native_path(path: &str) -> PathBuf {
+    if PosixLayer::detect().is_some() {
+        if let Some(converted) = to_windows(path) {
+            return PathBuf::from(converted);
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Path meant to be used by the shell of the user (`show:bin_path`, `dmenv env`)
+pub fn shell_path(path: &Path) -> String {
+    match PosixLayer::detect() {
+        Some(layer) => layer.to_posix(&path.to_string_lossy()),
+        None => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_posix() {
+        assert_eq!(
+            PosixLayer::Msys.to_posix(r"C:\Users\jane\foo\.venv"),
+            "/c/Users/jane/foo/.venv"
+        );
+        assert_eq!(
+            PosixLayer::Cygwin.to_posix(r"\\?\D:\work"),
+            "/cygdrive/d/work"
+        );
+        assert_eq!(PosixLayer::Msys.to_posix(r"relative\path"), "relative/path");
+    }
+
+    #[test]
+    fn test_to_windows() {
+        assert_eq!(
+            to_windows("/c/Users/jane/foo").unwrap(),
+            r"C:\Users\jane\foo"
+        );
+        assert_eq!(to_windows("/cygdrive/d/work").unwrap(), r"D:\work");
+        assert_eq!(to_windows("/c").unwrap(), r"C:\");
+        assert!(to_windows("/usr/bin").is_none());
+        assert!(to_windows(r"C:\Users\jane").is_none());
+        assert!(to_windows("relative/path").is_none());
+    }
+}
@@ -0,0 +1,240 @@
+//! License texts of the dependencies shipped in a bundle.
+//!
+//! Redistributing a bundle means redistributing its dependencies, and most
+//! licenses require their text to be shipped along. `dmenv bundle` collects
+//! the license files found in the `.dist-info` (or `.egg-info`) directory of
+//! each installed dependency, and writes them all in a `THIRD_PARTY_NOTICES`
+//! file at the root of the bundle.
+use std::path::{Path, PathBuf};
+
+use crate::bundle;
+use crate::error::Error;
+
+pub const NOTICES_FILENAME: &str = "THIRD_PARTY_NOTICES";
+
+const SEPARATOR: &str =
+    "================================================================================";
+
+#[derive(Debug, PartialEq)]
+pub struct Notice {
+    pub name: String,
+    pub version: String,
+    /// `License-Expression` or `License` field of the metadata
+    pub license: Option<String>,
+    /// Path relative to the metadata directory, and contents
+    pub files: Vec<(String, String)>,
+}
+
+/// Name, version and license of a package, from the contents of its
+/// `METADATA` (or `PKG-INFO`) file
+fn parse_metadata(contents: &str) -> (String, String, Option<String>) {
+    let mut name = String::new();
+    let mut version = String::new();
+    let mut license = None;
+    let mut expression = None;
+    // Note: the headers end at the first empty line, the description follows
+    for line in contents.lines().take_while(|x| !x.trim().is_empty()) {
+        let index = match line.find(':') {
+            Some(index) => index,
+            None => continue,
+        };
+        let value = line[index + 1..].trim().to_string();
+        match &line[..index] {
+            "Name" => name = value,
+            "Version" => version = value,
+            "License" if !value.is_empty() && value != "UNKNOWN" => license = Some(value),
+            "License-Expression" => expression = Some(value),
+            _ => (),
+        }
+    }
+    (name, version, expression.or(license))
+}
+
+/// Whether a file of a metadata directory holds a license text, like
+/// `LICENSE.txt`, `COPYING` or `NOTICE`
+fn is_license_file(name: &str) -> bool {
+    let name = name.to_uppercase();
+    ["LICENSE", "LICENCE", "COPYING", "NOTICE", "AUTHORS"]
+        .iter()
+        .any(|x| name.starts_with(x))
+}
+
+/// Collect the notices of the packages installed in `dir`, sorted by name
+pub fn collect(dir: &Path) -> Vec<Notice> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut res = vec![];
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let metadata_name = match path.extension().and_then(|x| x.to_str()) {
+            Some("dist-info") => "METADATA",
+            Some("egg-info") => "PKG-INFO",
+            _ => continue,
+        };
+        let metadata = std::fs::read_to_string(path.join(metadata_name)).unwrap_or_default();
+        let (name, version, license) = parse_metadata(&metadata);
+        if name.is_empty() {
+            continue;
+        }
+        res.push(Notice {
+            name,
+            version,
+            license,
+            files: license_files(&path),
+        });
+    }
+    res.sort_by_key(|x| x.name.to_lowercase());
+    res
+}
+
+// Note: wheels built with recent tools put license files in a `licenses/`
+// sub-directory (PEP 639), older ones next to METADATA
+fn license_files(metadata_dir: &Path) -> Vec<(String, String)> {
+    let mut paths: Vec<PathBuf> = bundle::walk_files(metadata_dir)
+        .into_iter()
+        .filter(|x| {
+            let relative = x.strip_prefix(metadata_dir).unwrap_or(x);
+            let file_name = x.file_name().unwrap_or_default().to_string_lossy();
+            relative.starts_with("licenses") || is_license_file(&file_name)
+        })
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|x| {
+            let contents = std::fs::read_to_string(&x).ok()?;
+            let relative = x.strip_prefix(metadata_dir).unwrap_or(&x);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            Some((relative, contents))
+        })
+        .collect()
+}
+
+/// Contents of the THIRD_PARTY_NOTICES file
+pub fn render(project: &str, notices: &[Notice]) -> String {
+    let mut res = format!(
+        "Third-party software bundled with {}\n\nThis bundle contains the following packages:\n\n",
+        project
+    );
+    for notice in notices {
+        res.push_str(&format!("  {} {}\n", notice.name, notice.version));
+    }
+    for notice in notices {
+        res.push_str(&format!(
+            "\n{}\n{} {}\nLicense: {}\n",
+            SEPARATOR,
+            notice.name,
+            notice.version,
+            notice.license.as_deref().unwrap_or("unknown")
+        ));
+        if notice.files.is_empty() {
+            res.push_str("\nNo license file found in the package\n");
+        }
+        for (path, contents) in &notice.files {
+            res.push_str(&format!("\n--- {} ---\n\n{}", path, contents));
+            if !contents.ends_with('\n') {
+                res.push('\n');
+            }
+        }
+    }
+    res
+}
+
+/// Write the notices of the packages installed in `deps_dir` to `output`.
+/// Return the names of the packages without any license file
+pub fn write(project: &str, deps_dir: &Path, output: &Path) -> Result<Vec<String>, Error> {
+    let notices = collect(deps_dir);
+    std::fs::write(output, render(project, &notices)).map_err(|e| Error::WriteError {
+        path: output.to_path_buf(),
+        io_error: e,
+    })?;
+    Ok(notices
+        .into_iter()
+        .filter(|x| x.files.is_empty())
+        .map(|x| x.name)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let contents = "\
+Metadata-Version: 2.1
+Name: attrs
+Version: 19.1.0
+License: MIT
+
+License: this is the description
+";
+        assert_eq!(
+            parse_metadata(contents),
+            (
+                "attrs".to_string(),
+                "19.1.0".to_string(),
+                Some("MIT".to_string())
+            )
+        );
+        let contents =
+            "Name: foo\nVersion: 1.0\nLicense: UNKNOWN\nLicense-Expression: MIT OR Apache-2.0\n";
+        assert_eq!(
+            parse_metadata(contents).2,
+            Some("MIT OR Apache-2.0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_license_file() {
+        assert!(is_license_file("LICENSE"));
+        assert!(is_license_file("License.txt"));
+        assert!(is_license_file("COPYING.rst"));
+        assert!(!is_license_file("METADATA"));
+        assert!(!is_license_file("RECORD"));
+    }
+
+    #[test]
+    fn test_collect() {
+        let tmp_dir = tempdir::TempDir::new("test-dmenv").unwrap();
+        let deps = tmp_dir.path();
+        let attrs = deps.join("attrs-19.1.0.dist-info");
+        std::fs::create_dir_all(attrs.join("licenses")).unwrap();
+        std::fs::write(
+            attrs.join("METADATA"),
+            "Name: attrs\nVersion: 19.1.0\nLicense: MIT\n",
+        )
+        .unwrap();
+        std::fs::write(attrs.join("RECORD"), "").unwrap();
+        std::fs::write(attrs.join("licenses").join("LICENSE"), "MIT License").unwrap();
+        let foo = deps.join("foo-0.42.dist-info");
+        std::fs::create_dir_all(&foo).unwrap();
+        std::fs::write(foo.join("METADATA"), "Name: foo\nVersion: 0.42\n").unwrap();
+        std::fs::create_dir_all(deps.join("attr")).unwrap();
+
+        let notices = collect(deps);
+        assert_eq!(
+            notices,
+            vec![
+                Notice {
+                    name: "attrs".to_string(),
+                    version: "19.1.0".to_string(),
+                    license: Some("MIT".to_string()),
+                    files: vec![("licenses/LICENSE".to_string(), "MIT License".to_string())],
+                },
+                Notice {
+                    name: "foo".to_string(),
+                    version: "0.42".to_string(),
+                    license: None,
+                    files: vec![],
+                },
+            ]
+        );
+        let rendered = render("demo", &notices);
+        assert!(rendered.contains("  attrs 19.1.0\n  foo 0.42\n"));
+        assert!(rendered.contains("--- licenses/LICENSE ---\n\nMIT License\n"));
+        assert!(rendered.contains("foo 0.42\nLicense: unknown\n\nNo license file found"));
+    }
+}
@@ -0,0 +1,160 @@
+//! Locked releases behind the latest ones on the package index, for `dmenv outdated`
+use std::cmp::Ordering;
+
+use crate::dependencies::canonical_name;
+use crate::json;
+use crate::marker::compare_versions;
+use crate::release_info::Release;
+
+#[derive(Debug, PartialEq)]
+pub struct Row {
+    pub name: String,
+    pub locked: String,
+    pub latest: String,
+}
+
+/// Compare the locked `versions` (name, version) with the latest `releases`,
+/// keeping the order of `versions`. Packages the index knows nothing about
+/// are left out
+pub fn compare(versions: &[(String, String)], releases: &[Release]) -> Vec<Row> {
+    let mut res = vec![];
+    for (name, locked) in versions {
+        let release = releases
+            .iter()
+            .find(|x| canonical_name(&x.name) == canonical_name(name));
+        let latest = match release.and_then(|x| x.latest.as_ref()) {
+            Some(latest) => latest,
+            None => continue,
+        };
+        if compare_versions(latest, locked) == Ordering::Greater {
+            res.push(Row {
+                name: name.to_string(),
+                locked: locked.to_string(),
+                latest: latest.to_string(),
+            });
+        }
+    }
+    res
+}
+
+/// Lines of the table shown by `dmenv outdated`
+pub fn render(rows: &[Row]) -> Vec<String> {
+    let header = ("Package", "Locked", "Latest");
+    let name_width = rows
+        .iter()
+        .map(|x| x.name.len())
+        .chain(std::iter::once(header.0.len()))
+        .max()
+        .unwrap_or_default();
+    let locked_width = rows
+        .iter()
+        .map(|x| x.locked.len())
+        .chain(std::iter::once(header.1.len()))
+        .max()
+        .unwrap_or_default();
+    let line = |name: &str, locked: &str, latest: &str| {
+        format!(
+            "{:name_width$}  {:locked_width$}  {}",
+            name,
+            locked,
+            latest,
+            name_width = name_width,
+            locked_width = locked_width
+        )
+    };
+    let mut res = vec![
+        line(header.0, header.1, header.2),
+        line(
+            &"-".repeat(name_width),
+            &"-".repeat(locked_width),
+            &"-".repeat(header.2.len()),
+        ),
+    ];
+    for row in rows {
+        res.push(line(&row.name, &row.locked, &row.latest));
+    }
+    res
+}
+
+pub fn to_json(rows: &[Row]) -> String {
+    let items: Vec<_> = rows
+        .iter()
+        .map(|x| {
+            format!(
+                "{{\"name\": {}, \"locked\": {}, \"latest\": {}}}",
+                json::string(&x.name),
+                json::string(&x.locked),
+                json::string(&x.latest)
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(name: &str, latest: Option<&str>) -> Release {
+        Release {
+            name: name.to_string(),
+            age_days: None,
+            latest: latest.map(|x| x.to_string()),
+        }
+    }
+
+    fn rows() -> Vec<Row> {
+        let versions: Vec<_> = [
+            ("attrs", "19.1.0"),
+            ("path.py", "11.5.0"),
+            ("pytest", "8.3.3"),
+            ("six", "1.16.0"),
+        ]
+        .iter()
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect();
+        let releases = vec![
+            release("attrs", Some("24.2.0")),
+            release("Path.py", Some("12.5.0")),
+            release("pytest", Some("8.3.3")),
+            release("six", None),
+        ];
+        compare(&versions, &releases)
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(
+            rows(),
+            vec![
+                Row {
+                    name: "attrs".to_string(),
+                    locked: "19.1.0".to_string(),
+                    latest: "24.2.0".to_string(),
+                },
+                Row {
+                    name: "path.py".to_string(),
+                    locked: "11.5.0".to_string(),
+                    latest: "12.5.0".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(&rows()),
+            vec![
+                "Package  Locked  Latest",
+                "-------  ------  ------",
+                "attrs    19.1.0  24.2.0",
+                "path.py  11.5.0  12.5.0",
+            ]
+        );
+        assert_eq!(
+            to_json(&rows()[..1]),
+            "[{\"name\": \"attrs\", \"locked\": \"19.1.0\", \"latest\": \"24.2.0\"}]"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Overrides of dependencies, for instance while waiting for a fix to be
+//! released upstream. They are defined in the `[overrides]` table of
+//! `dmenv.toml`, by name of the package to override:
+//!
+//! ```text
+//! [overrides]
+//! # Use the fork until https://github.com/acme/foo/pull/42 is released
+//! foo = "git+https://github.com/me/foo-fork@fix-42#egg=foo-fork"
+//! # Whatever setup.py says
+//! bar = "bar==1.2"
+//! ```
+//!
+//! `dmenv lock` installs them after the dependencies of the project, and
+//! writes them in the lock with a `# override: ` comment, so that they stand
+//! out in reviews.
+use crate::dependencies::{canonical_name, FrozenDependency};
+
+pub const LOCK_PREFIX: &str = "override:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    /// Name of the package to override
+    pub name: String,
+    /// What pip installs instead: a requirement like `bar==1.2`, or a git URL
+    pub requirement: String,
+}
+
+impl Override {
+    pub fn is_url(&self) -> bool {
+        self.requirement.contains("://") || self.requirement.starts_with("git@")
+    }
+
+    /// Name of the package installed instead, as written in the requirement
+    pub fn replacement_name(&self) -> String {
+        if self.is_url() {
+            let egg = self.requirement.rsplit("#egg=").next().unwrap_or_default();
+            return egg.to_string();
+        }
+        let end = self
+            .requirement
+            .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+            .unwrap_or(self.requirement.len());
+        self.requirement[..end].to_string()
+    }
+
+    /// True if another package is installed instead, like a fork
+    pub fn replaces(&self) -> bool {
+        canonical_name(&self.replacement_name()) != canonical_name(&self.name)
+    }
+
+    /// Line of the lock: the URL as is, or the installed version
+    pub fn lock_line(&self, frozen: &[FrozenDependency]) -> Option<String> {
+        if self.is_url() {
+            return Some(self.requirement.to_string());
+        }
+        let name = canonical_name(&self.replacement_name());
+        frozen
+            .iter()
+            .find(|x| canonical_name(&x.name) == name)
+            .map(|x| format!("{}=={}", x.name, x.version))
+    }
+
+    /// Trailing comment of the line in the lock
+    pub fn comment(&self) -> String {
+        if self.replaces() {
+            format!("{} replaces {}", LOCK_PREFIX, self.name)
+        } else {
+            format!("{} {}", LOCK_PREFIX, self.requirement)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fork() -> Override {
+        Override {
+            name: "foo".to_string(),
+            requirement: "git+https://github.com/me/foo-fork@fix-42#egg=foo-fork".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_replacement() {
+        let fork = fork();
+        assert_eq!(fork.replacement_name(), "foo-fork");
+        assert!(fork.replaces());
+        assert_eq!(fork.comment(), "override: replaces foo");
+        assert_eq!(fork.lock_line(&[]).unwrap(), fork.requirement);
+    }
+
+    #[test]
+    fn test_forced_version() {
+        let forced = Override {
+            name: "Bar".to_string(),
+            requirement: "bar>=1.2,<1.3".to_string(),
+        };
+        assert!(!forced.replaces());
+        assert_eq!(forced.comment(), "override: bar>=1.2,<1.3");
+        let frozen = vec![FrozenDependency {
+            name: "bar".to_string(),
+            version: "1.2.4".to_string(),
+        }];
+        assert_eq!(forced.lock_line(&frozen).unwrap(), "bar==1.2.4");
+        assert!(forced.lock_line(&[]).is_none());
+    }
+}
@@ -9,12 +9,25 @@ const APP_INFO: AppInfo = AppInfo {
 
 pub const PROD_LOCK_FILENAME: &str = "production.lock";
 pub const DEV_LOCK_FILENAME: &str = "requirements.lock";
+/// Input of `dmenv lock` for requirements-only projects
+pub const REQUIREMENTS_IN_FILENAME: &str = "requirements.in";
+/// Same as REQUIREMENTS_IN_FILENAME, for dev dependencies
+pub const DEV_REQUIREMENTS_IN_FILENAME: &str = "requirements-dev.in";
+/// Versions of the tools used in the project, for `dmenv tool sync`
+pub const TOOLS_LOCK_FILENAME: &str = "tools.lock";
 
 use crate::error::*;
+use crate::msys;
 
 // Container for all the PathsBuf used by the venv_manager
+#[derive(Clone)]
 pub struct Paths {
     pub project: PathBuf,
+    /// Directory containing setup.py. Usually the same as `project`,
+    /// except in repositories where the Python code lives in a sub-directory
+    pub package: PathBuf,
+    /// Other packages installed in development mode in the virtualenv
+    pub extra_packages: Vec<PathBuf>,
     pub venv: PathBuf,
     pub lock: PathBuf,
     pub setup_py: PathBuf,
@@ -22,6 +35,9 @@ pub struct Paths {
 
 pub struct PathsResolver {
     venv_outside_project: bool,
+    shared_venv_root: Option<PathBuf>,
+    package_dir: Option<PathBuf>,
+    extra_package_dirs: Vec<PathBuf>,
     production: bool,
     python_version: String,
     project_path: PathBuf,
@@ -36,6 +52,9 @@ impl PathsResolver {
     pub fn new(project_path: PathBuf, python_version: &str, settings: &Settings) -> Self {
         PathsResolver {
             venv_outside_project: settings.venv_outside_project,
+            shared_venv_root: settings.shared_venv_root.clone(),
+            package_dir: settings.package_dir.clone(),
+            extra_package_dirs: settings.extra_package_dirs.clone(),
             project_path,
             python_version: python_version.into(),
             production: settings.production,
@@ -48,17 +67,35 @@ impl PathsResolver {
         } else {
             DEV_LOCK_FILENAME
         };
+        let package_path = match &self.package_dir {
+            Some(package_dir) => self.project_path.join(package_dir),
+            None => self.project_path.clone(),
+        };
         Ok(Paths {
             project: self.project_path.clone(),
             venv: self.get_venv_path()?,
             lock: self.project_path.join(lock_path),
-            setup_py: self.project_path.join("setup.py"),
+            setup_py: package_path.join("setup.py"),
+            package: package_path,
+            extra_packages: self
+                .extra_package_dirs
+                .iter()
+                .map(|x| self.project_path.join(x))
+                .collect(),
         })
     }
 
     fn get_venv_path(&self) -> Result<PathBuf, Error> {
         if let Ok(existing_venv) = std::env::var("VIRTUAL_ENV") {
-            return Ok(PathBuf::from(existing_venv));
+            return Ok(msys::native_path(&existing_venv));
+        }
+        self.default_venv_path()
+    }
+
+    /// Where the virtualenv would be if no virtualenv was activated
+    pub fn default_venv_path(&self) -> Result<PathBuf, Error> {
+        if let Some(shared_venv_root) = &self.shared_venv_root {
+            return self.get_venv_path_shared(shared_venv_root);
         }
         if self.venv_outside_project {
             self.get_venv_path_outside()
@@ -68,10 +105,50 @@ impl PathsResolver {
     }
 
     fn get_venv_path_inside(&self) -> Result<PathBuf, Error> {
+        let mut res = self.project_path.clone();
+        res.extend(&self.venv_components());
+        Ok(res)
+    }
+
+    /// Path of the virtualenv relative to the project when it is inside it,
+    /// with `/` as separator.
+    // Note: used for remote hosts, so it must not depend on the local platform
+    pub fn remote_venv_path(&self) -> String {
+        self.venv_components().join("/")
+    }
+
+    fn venv_components(&self) -> [&str; 3] {
+        let subdir = if self.production { "prod" } else { "dev" };
+        [".venv", subdir, &self.python_version]
+    }
+
+    /// Where dmenv <= 0.11 put the virtualenv, before production and development
+    /// virtualenvs were separated. None if 0.11 had no equivalent
+    pub fn legacy_venv_path(&self) -> Result<Option<PathBuf>, Error> {
+        if self.production || self.shared_venv_root.is_some() {
+            return Ok(None);
+        }
+        if !self.venv_outside_project {
+            return Ok(Some(
+                self.project_path.join(".venv").join(&self.python_version),
+            ));
+        }
+        let data_dir = get_cache_path("venv")?;
+        let project_name = self.project_path.file_name().ok_or_else(|| Error::Other {
+            message: format!("project path: {:?} has no file name", self.project_path),
+        })?;
+        Ok(Some(data_dir.join(&self.python_version).join(project_name)))
+    }
+
+    /// Get a virtualenv path in the shared root, so that all the users working on
+    /// the same project use the same virtualenv
+    fn get_venv_path_shared(&self, shared_venv_root: &PathBuf) -> Result<PathBuf, Error> {
         let subdir = if self.production { "prod" } else { "dev" };
-        let res = self
-            .project_path
-            .join(".venv")
+        let project_name = self.project_path.file_name().ok_or_else(|| Error::Other {
+            message: format!("project path: {:?} has no file name", self.project_path),
+        })?;
+        let res = shared_venv_root
+            .join(project_name)
             .join(subdir)
             .join(&self.python_version);
         Ok(res)
@@ -79,16 +156,9 @@ impl PathsResolver {
 
     /// Get a suitable virtualenv path in the HOME directory.
     //
-    // Note: use app_dir UserCache so that we honor XDG spec on Linux,
-    // and use otherwise "expected" paths on macOS and Windows
-    // (`Library/Cachches` and `AppData\Local` respectively)
+    // Note: see `get_cache_path()`
     fn get_venv_path_outside(&self) -> Result<PathBuf, Error> {
-        let data_dir =
-            app_dirs::app_dir(AppDataType::UserCache, &APP_INFO, "venv").map_err(|e| {
-                Error::Other {
-                    message: format!("Could not create dmenv cache path: {}", e.to_string()),
-                }
-            })?;
+        let data_dir = get_cache_path("venv")?;
         let subdir = if self.production { "prod" } else { "dev" };
         let project_name = self.project_path.file_name().ok_or_else(|| Error::Other {
             message: format!("project path: {:?} has no file name", self.project_path),
@@ -101,6 +171,24 @@ impl PathsResolver {
     }
 }
 
+/// Get (and create if needed) a sub-directory of dmenv's cache
+//
+// Note: use app_dir UserCache so that we honor XDG spec on Linux,
+// and use otherwise "expected" paths on macOS and Windows
+// (`Library/Cachches` and `AppData\Local` respectively)
+pub fn get_cache_path(name: &str) -> Result<PathBuf, Error> {
+    app_dirs::app_dir(AppDataType::UserCache, &APP_INFO, name).map_err(|e| Error::Other {
+        message: format!("Could not create dmenv cache path: {}", e.to_string()),
+    })
+}
+
+/// Root of the dmenv cache. Unlike `get_cache_path()`, does not create anything
+pub fn get_cache_root() -> Result<PathBuf, Error> {
+    app_dirs::get_app_root(AppDataType::UserCache, &APP_INFO).map_err(|e| Error::Other {
+        message: format!("Could not get dmenv cache path: {}", e.to_string()),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,4 +207,35 @@ mod tests {
         assert_eq!(paths.project, project_path);
         assert!(paths.venv.to_string_lossy().contains(python_version));
     }
+
+    #[test]
+    fn test_shared_venv_path() {
+        let mut settings = Settings::default();
+        settings.shared_venv_root = Some(PathBuf::from("/opt/venvs"));
+        let paths_resolver =
+            PathsResolver::new(PathBuf::from("/home/jane/foo"), "3.7.1", &settings);
+        let paths = paths_resolver.paths().unwrap();
+        if std::env::var("VIRTUAL_ENV").is_err() {
+            assert_eq!(paths.venv, Path::new("/opt/venvs/foo/dev/3.7.1"));
+        }
+    }
+
+    #[test]
+    fn test_remote_venv_path() {
+        let mut settings = Settings::default();
+        settings.production = true;
+        let paths_resolver = PathsResolver::new(PathBuf::from("/tmp/foo"), "3.7.1", &settings);
+        assert_eq!(paths_resolver.remote_venv_path(), ".venv/prod/3.7.1");
+    }
+
+    #[test]
+    fn test_package_dir() {
+        let mut settings = Settings::default();
+        settings.package_dir = Some(PathBuf::from("python"));
+        let paths_resolver = PathsResolver::new(PathBuf::from("/tmp/foo"), "3.7.1", &settings);
+        let paths = paths_resolver.paths().unwrap();
+        assert_eq!(paths.setup_py, Path::new("/tmp/foo/python/setup.py"));
+        assert_eq!(paths.lock, Path::new("/tmp/foo/requirements.lock"));
+        assert!(paths.extra_packages.is_empty());
+    }
 }
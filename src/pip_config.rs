@@ -0,0 +1,352 @@
+//! pip's own configuration: its configuration files and its `PIP_*`
+//! environment variables. The index settings of dmenv take precedence over
+//! them, so `dmenv doctor` shows what pip ends up using.
+//!
+//! Files are read in the same order as pip: `PIP_CONFIG_FILE`, the global
+//! files, the user files (unless `PIP_CONFIG_FILE` exists), then the
+//! `pip.conf` (or `pip.ini`) of the virtualenv. Later files win, and
+//! environment variables win over every file.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::indexes;
+
+#[cfg(windows)]
+const CONFIG_BASENAME: &str = "pip.ini";
+#[cfg(not(windows))]
+const CONFIG_BASENAME: &str = "pip.conf";
+
+/// A setting of pip, and where it comes from: a file, an environment
+/// variable, or dmenv itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+    pub source: String,
+}
+
+impl Setting {
+    /// The value, without the credentials of the URLs it may contain
+    pub fn display_value(&self) -> String {
+        let words: Vec<_> = self
+            .value
+            .split_whitespace()
+            .map(indexes::without_credentials)
+            .collect();
+        words.join(" ")
+    }
+}
+
+/// The settings pip uses for `pip install`, by key
+#[derive(Debug, Default, PartialEq)]
+pub struct PipConfig {
+    settings: BTreeMap<String, Setting>,
+}
+
+impl PipConfig {
+    /// Load the configuration pip would use in the given virtualenv
+    pub fn load(venv: &Path) -> Result<Self, Error> {
+        let mut files = vec![];
+        for path in config_files(venv) {
+            if !path.is_file() {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            files.push((path.display().to_string(), contents));
+        }
+        let env: Vec<_> = std::env::vars()
+            .filter(|(name, _)| name.starts_with("PIP_") && name != "PIP_CONFIG_FILE")
+            .collect();
+        Ok(Self::from_sources(&files, &env))
+    }
+
+    /// `files` are `(source, contents)` pairs, lowest priority first
+    fn from_sources(files: &[(String, String)], env: &[(String, String)]) -> Self {
+        // Note: pip merges each section across files, then the options of the
+        // `install` section win over the `global` ones, whatever the file
+        let mut global = BTreeMap::new();
+        let mut install = BTreeMap::new();
+        for (source, contents) in files {
+            for (section, key, value) in parse(contents) {
+                let setting = Setting {
+                    key: key.to_string(),
+                    value,
+                    source: source.to_string(),
+                };
+                match section.as_str() {
+                    "global" => global.insert(key, setting),
+                    "install" => install.insert(key, setting),
+                    _ => None,
+                };
+            }
+        }
+        let mut settings = global;
+        settings.extend(install);
+        for (name, value) in env {
+            let key = normalize_key(&name["PIP_".len()..]);
+            let setting = Setting {
+                key: key.to_string(),
+                value: value.to_string(),
+                source: name.to_string(),
+            };
+            settings.insert(key, setting);
+        }
+        PipConfig { settings }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Setting> {
+        self.settings.get(key)
+    }
+
+    pub fn settings(&self) -> impl Iterator<Item = &Setting> {
+        self.settings.values()
+    }
+
+    /// True when pip is told not to use any index
+    pub fn no_index(&self) -> bool {
+        self.get("no-index")
+            .map(|x| ["1", "true", "yes", "on"].contains(&x.value.to_lowercase().as_str()))
+            .unwrap_or(false)
+    }
+
+    /// Index URLs from `index-url` and `extra-index-url`
+    pub fn index_urls(&self) -> Vec<String> {
+        ["index-url", "extra-index-url"]
+            .iter()
+            .filter_map(|x| self.get(x))
+            .flat_map(|x| x.value.split_whitespace())
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    /// Replace the index settings with the URLs dmenv gives to pip, the first
+    /// one as `index-url` and the others as `extra-index-url`. Returns a
+    /// warning for each setting that was overridden with another value
+    pub fn override_indexes(&mut self, urls: &[String], source: &str) -> Vec<String> {
+        let mut res = vec![];
+        let values = match urls.split_first() {
+            None => return res,
+            Some((first, [])) => vec![("index-url", first.to_string())],
+            Some((first, rest)) => vec![
+                ("index-url", first.to_string()),
+                ("extra-index-url", rest.join(" ")),
+            ],
+        };
+        for (key, value) in values {
+            let setting = Setting {
+                key: key.to_string(),
+                value,
+                source: source.to_string(),
+            };
+            if let Some(previous) = self.settings.insert(key.to_string(), setting) {
+                if urls.contains(&previous.value) {
+                    continue;
+                }
+                res.push(format!(
+                    "{} = {} from {} is ignored: {} is used instead",
+                    key,
+                    previous.display_value(),
+                    previous.source,
+                    source
+                ));
+            }
+        }
+        res
+    }
+}
+
+/// pip's configuration files, lowest priority first
+fn config_files(venv: &Path) -> Vec<PathBuf> {
+    let mut res = vec![];
+    let env_file = std::env::var_os("PIP_CONFIG_FILE").map(PathBuf::from);
+    if let Some(env_file) = &env_file {
+        res.push(env_file.to_path_buf());
+    }
+    res.extend(global_config_files());
+    if !env_file.map(|x| x.exists()).unwrap_or(false) {
+        res.extend(user_config_files());
+    }
+    res.push(venv.join(CONFIG_BASENAME));
+    res
+}
+
+#[cfg(windows)]
+fn global_config_files() -> Vec<PathBuf> {
+    let program_data = std::env::var_os("ProgramData").map(PathBuf::from);
+    program_data
+        .map(|x| x.join("pip").join(CONFIG_BASENAME))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn global_config_files() -> Vec<PathBuf> {
+    let xdg_dirs = std::env::var("XDG_CONFIG_DIRS").unwrap_or_else(|_| "/etc/xdg".to_string());
+    let mut res: Vec<_> = xdg_dirs
+        .split(':')
+        .filter(|x| !x.is_empty())
+        .map(|x| Path::new(x).join("pip").join(CONFIG_BASENAME))
+        .collect();
+    res.push(PathBuf::from("/etc").join(CONFIG_BASENAME));
+    res
+}
+
+/// The legacy file first, so that the new one wins
+#[cfg(windows)]
+fn user_config_files() -> Vec<PathBuf> {
+    let mut res = vec![];
+    if let Some(home) = std::env::var_os("USERPROFILE") {
+        res.push(Path::new(&home).join("pip").join(CONFIG_BASENAME));
+    }
+    if let Some(app_data) = std::env::var_os("APPDATA") {
+        res.push(Path::new(&app_data).join("pip").join(CONFIG_BASENAME));
+    }
+    res
+}
+
+#[cfg(not(windows))]
+fn user_config_files() -> Vec<PathBuf> {
+    let home = match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home),
+        None => return vec![],
+    };
+    let mut res = vec![home.join(".pip").join(CONFIG_BASENAME)];
+    let mac_dir = home.join("Library/Application Support/pip");
+    let config_dir = if cfg!(target_os = "macos") && mac_dir.is_dir() {
+        mac_dir
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir).join("pip"),
+            None => home.join(".config").join("pip"),
+        }
+    };
+    res.push(config_dir.join(CONFIG_BASENAME));
+    res
+}
+
+/// Like pip, `index_url`, `--index-url` and `INDEX_URL` are all `index-url`
+fn normalize_key(key: &str) -> String {
+    key.trim_start_matches("--")
+        .to_lowercase()
+        .replace('_', "-")
+}
+
+/// `(section, key, value)` entries of an INI file. Indented lines continue the
+/// value of the previous line, like `extra-index-url` with several URLs
+fn parse(contents: &str) -> Vec<(String, String, String)> {
+    let mut res: Vec<(String, String, String)> = vec![];
+    let mut section = String::new();
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            if let Some((_, _, value)) = res.last_mut() {
+                if !value.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(trimmed);
+            }
+            continue;
+        }
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            section = trimmed[1..trimmed.len() - 1].trim().to_string();
+            continue;
+        }
+        let pos = match trimmed.find(&['=', ':'][..]) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let key = normalize_key(trimmed[..pos].trim());
+        let value = trimmed[pos + 1..].trim().to_string();
+        res.push((section.to_string(), key, value));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "\
+# Company settings
+[global]
+index_url = https://pypi.acme.corp/simple
+extra-index-url =
+    https://mirror1.acme.corp/simple
+    https://mirror2.acme.corp/simple
+
+[install]
+; no-binary: :all:
+timeout: 60
+";
+        assert_eq!(
+            parse(contents),
+            vec![
+                (
+                    "global".to_string(),
+                    "index-url".to_string(),
+                    "https://pypi.acme.corp/simple".to_string()
+                ),
+                (
+                    "global".to_string(),
+                    "extra-index-url".to_string(),
+                    "https://mirror1.acme.corp/simple https://mirror2.acme.corp/simple".to_string()
+                ),
+                (
+                    "install".to_string(),
+                    "timeout".to_string(),
+                    "60".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        let files = vec![
+            (
+                "/etc/pip.conf".to_string(),
+                "[install]\ntimeout = 60\n[global]\nindex-url = https://a/simple\n".to_string(),
+            ),
+            (
+                "~/.config/pip/pip.conf".to_string(),
+                "[global]\ntimeout = 10\nindex-url = https://b/simple\n".to_string(),
+            ),
+        ];
+        let env = vec![("PIP_NO_INDEX".to_string(), "1".to_string())];
+        let config = PipConfig::from_sources(&files, &env);
+        // The install section wins, even in a file with a lower priority
+        assert_eq!(config.get("timeout").unwrap().source, "/etc/pip.conf");
+        assert_eq!(config.get("index-url").unwrap().value, "https://b/simple");
+        assert_eq!(config.get("no-index").unwrap().source, "PIP_NO_INDEX");
+        assert!(config.no_index());
+    }
+
+    #[test]
+    fn test_override_indexes() {
+        let files = vec![(
+            "pip.conf".to_string(),
+            "[global]\nindex-url = https://user:secret@a/simple\nextra-index-url = https://b/simple\n"
+                .to_string(),
+        )];
+        let mut config = PipConfig::from_sources(&files, &[]);
+        assert_eq!(config.index_urls().len(), 2);
+        let urls = vec!["https://c/simple".to_string()];
+        let warnings = config.override_indexes(&urls, "DMENV_INDEXES");
+        assert_eq!(
+            warnings,
+            vec!["index-url = https://a/simple from pip.conf is ignored: DMENV_INDEXES is used instead"]
+        );
+        assert_eq!(config.get("index-url").unwrap().source, "DMENV_INDEXES");
+        // Note: pip still uses the extra index
+        assert_eq!(config.get("extra-index-url").unwrap().source, "pip.conf");
+    }
+}
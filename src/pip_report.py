@@ -0,0 +1,34 @@
+"""Read the installation report written by `pip install --dry-run --report`.
+
+Usage: python -c <this script> <report path>
+
+Print a line of the lock for each package pip would install: `name==version`,
+or `git+<url>@<commit>#egg=<name>` for git dependencies. Local directories,
+like the project itself, are skipped.
+"""
+import json
+import sys
+
+
+def lock_line(item):
+    name = item["metadata"]["name"]
+    download_info = item.get("download_info", {})
+    if "dir_info" in download_info:
+        return None
+    vcs_info = download_info.get("vcs_info")
+    if vcs_info:
+        url = download_info["url"]
+        return "%s+%s@%s#egg=%s" % (vcs_info["vcs"], url, vcs_info["commit_id"], name)
+    return "%s==%s" % (name, item["metadata"]["version"])
+
+
+def main():
+    with open(sys.argv[1]) as f:
+        report = json.load(f)
+    for item in report["install"]:
+        line = lock_line(item)
+        if line:
+            print(line)
+
+
+main()
@@ -0,0 +1,77 @@
+//! Development dependencies that only apply to some platforms, like `pywin32`.
+//! They are defined in the `[platform_dev_dependencies]` table of
+//! `dmenv.toml`, with the values of `sys.platform` they apply to:
+//!
+//! ```text
+//! [platform_dev_dependencies]
+//! pywin32 = "win32"
+//! uvloop = "linux, darwin"
+//! ```
+//!
+//! `dmenv lock` installs them when the platform matches, and writes the
+//! markers in the lock (`pywin32==306 ; sys_platform == 'win32'`), so that
+//! they don't have to be written by hand.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlatformDependency {
+    pub name: String,
+    /// Values of `sys.platform`, like `win32`, `linux` or `darwin`
+    pub platforms: Vec<String>,
+}
+
+impl PlatformDependency {
+    /// Parse the list of platforms, like `linux, darwin`
+    pub fn new(name: &str, platforms: &str) -> Result<Self, String> {
+        let platforms: Vec<_> = platforms
+            .split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect();
+        if platforms.is_empty() {
+            return Err(format!("no platforms given for `{}`", name));
+        }
+        if let Some(invalid) = platforms
+            .iter()
+            .find(|x| !x.chars().all(|c| c.is_ascii_alphanumeric()))
+        {
+            return Err(format!(
+                "invalid platform for `{}`: '{}', expected values of sys.platform like 'win32' or 'linux'",
+                name, invalid
+            ));
+        }
+        Ok(PlatformDependency {
+            name: name.to_string(),
+            platforms,
+        })
+    }
+
+    pub fn marker(&self) -> String {
+        let conditions: Vec<_> = self
+            .platforms
+            .iter()
+            .map(|x| format!("sys_platform == '{}'", x))
+            .collect();
+        conditions.join(" or ")
+    }
+
+    /// Requirement for pip, which ignores it on other platforms
+    pub fn requirement(&self) -> String {
+        format!("{} ; {}", self.name, self.marker())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker() {
+        let dep = PlatformDependency::new("uvloop", "linux, darwin").unwrap();
+        assert_eq!(
+            dep.requirement(),
+            "uvloop ; sys_platform == 'linux' or sys_platform == 'darwin'"
+        );
+        assert!(PlatformDependency::new("pywin32", " ").is_err());
+        assert!(PlatformDependency::new("pywin32", "windows 10").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use crate::error::Error;
+
+/// What plugins know about the project when their hooks are called
+pub struct Context<'a> {
+    pub project: &'a Path,
+    pub venv: &'a Path,
+    pub lock: &'a Path,
+    pub python_version: &'a str,
+    pub production: bool,
+}
+
+/// Extend dmenv from another crate: implement this trait and give the plugins to
+/// `dmenv::run_with_plugins()`.
+///
+/// Every hook does nothing by default. Returning an error from a hook aborts the
+/// command, and hooks of the plugins registered after it are not called.
+pub trait Plugin {
+    fn name(&self) -> &str;
+
+    /// Called before `dmenv lock` changes anything
+    fn pre_lock(&self, _context: &Context) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once the lock has been written
+    fn post_lock(&self, _context: &Context) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called before `dmenv install` changes anything
+    fn pre_install(&self, _context: &Context) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once the dependencies (and the project itself) are installed
+    fn post_install(&self, _context: &Context) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called before `dmenv run` starts the command. There is no `post_run` hook,
+    /// because the command usually replaces the dmenv process
+    fn pre_run(&self, _context: &Context, _args: &[String]) -> Result<(), Error> {
+        Ok(())
+    }
+}
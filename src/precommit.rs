@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+/// Name of the pre-commit configuration file, at the root of the project
+pub const CONFIG_FILENAME: &str = ".pre-commit-config.yaml";
+
+/// A change made to the pre-commit configuration
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    /// 1-based line number
+    pub line: usize,
+    pub old: String,
+    pub new: String,
+}
+
+/// Pin the hooks of a pre-commit configuration using the versions from the lock.
+///
+/// * `rev:` of repos whose name matches a locked package (`psf/black`,
+///   `pre-commit/mirrors-mypy`, ...) is set to the locked version
+/// * Entries in `additional_dependencies` are pinned to the locked version
+///
+/// Returns the new contents and the list of changes.
+//
+// Note: we don't want to depend on a YAML parser, and we want to
+// keep comments and formatting intact, so this works line by line and only
+// supports the layout found in pre-commit docs.
+pub fn sync(config: &str, versions: &BTreeMap<String, String>) -> (String, Vec<Change>) {
+    let mut changes = vec![];
+    let mut lines = vec![];
+    let mut repo_package: Option<String> = None;
+    // Indentation of the `additional_dependencies:` key, if we are in a block list
+    let mut deps_indent: Option<usize> = None;
+
+    for (i, line) in config.lines().enumerate() {
+        let indent = line.len() - line.trim_start().len();
+        let trimmed = line.trim_start().trim_start_matches('-').trim_start();
+        if let Some(deps) = deps_indent {
+            if line.trim_start().starts_with("- ") && indent >= deps {
+                let new_line = match line.find("- ") {
+                    Some(pos) => {
+                        let (prefix, item) = line.split_at(pos + 2);
+                        format!("{}{}", prefix, pin_item(item, versions))
+                    }
+                    None => line.to_string(),
+                };
+                push_line(&mut lines, &mut changes, i, line, new_line);
+                continue;
+            }
+            deps_indent = None;
+        }
+
+        let new_line = if let Some(url) = value_of(trimmed, "repo") {
+            repo_package = package_from_url(url);
+            line.to_string()
+        } else if let Some(rev) = value_of(trimmed, "rev") {
+            let version = repo_package
+                .as_ref()
+                .and_then(|x| versions.get(&x.to_lowercase()));
+            match version {
+                Some(version) => {
+                    let new_rev = if rev.starts_with('v') {
+                        format!("v{}", version)
+                    } else {
+                        version.to_string()
+                    };
+                    line.replacen(rev, &new_rev, 1)
+                }
+                None => line.to_string(),
+            }
+        } else if let Some(deps) = value_of(trimmed, "additional_dependencies") {
+            if deps.is_empty() {
+                deps_indent = Some(indent);
+                line.to_string()
+            } else {
+                let pinned = pin_flow_list(deps, versions);
+                line.replacen(deps, &pinned, 1)
+            }
+        } else {
+            line.to_string()
+        };
+        push_line(&mut lines, &mut changes, i, line, new_line);
+    }
+
+    let mut res = lines.join("\n");
+    if config.ends_with('\n') {
+        res.push('\n');
+    }
+    (res, changes)
+}
+
+fn push_line(
+    lines: &mut Vec<String>,
+    changes: &mut Vec<Change>,
+    index: usize,
+    old: &str,
+    new: String,
+) {
+    if new != old {
+        changes.push(Change {
+            line: index + 1,
+            old: old.trim().to_string(),
+            new: new.trim().to_string(),
+        });
+    }
+    lines.push(new);
+}
+
+/// Return the value of a `key: value` line, without quotes nor comments
+fn value_of<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let prefix = format!("{}:", key);
+    if !line.starts_with(&prefix) {
+        return None;
+    }
+    let value = &line[prefix.len()..];
+    let value = match value.find(" #") {
+        Some(pos) => &value[..pos],
+        None => value,
+    };
+    Some(value.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+/// Guess the name of the Python package from the URL of the repo:
+///
+/// * `https://github.com/psf/black` -> `black`
+/// * `https://github.com/pre-commit/mirrors-mypy` -> `mypy`
+/// * `local` and `meta` repos have no package
+fn package_from_url(url: &str) -> Option<String> {
+    if url == "local" || url == "meta" {
+        return None;
+    }
+    let name = url.trim_end_matches('/').rsplit('/').next()?;
+    let name = name.trim_end_matches(".git");
+    let name = name.trim_start_matches("mirrors-");
+    Some(name.to_string())
+}
+
+/// Pin a list written as `[foo, bar>=1.0]`
+fn pin_flow_list(list: &str, versions: &BTreeMap<String, String>) -> String {
+    if !list.starts_with('[') || !list.ends_with(']') {
+        return list.to_string();
+    }
+    let items: Vec<_> = list[1..list.len() - 1]
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| pin_item(x, versions))
+        .collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Pin a single requirement (`foo`, `foo>=1.0`, `"foo==2.0"`) if it is in the lock
+fn pin_item(item: &str, versions: &BTreeMap<String, String>) -> String {
+    let quote = if item.starts_with('"') || item.starts_with('\'') {
+        &item[..1]
+    } else {
+        ""
+    };
+    let requirement = item.trim_matches(|c| c == '"' || c == '\'');
+    let end = requirement
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or_else(|| requirement.len());
+    let name = &requirement[..end];
+    // Don't touch extras nor environment markers
+    let rest = requirement[end..].trim_start();
+    if !rest.is_empty()
+        && !rest.starts_with(|c| c == '=' || c == '<' || c == '>' || c == '~' || c == '!')
+    {
+        return item.to_string();
+    }
+    match versions.get(&name.to_lowercase()) {
+        Some(version) => format!("{}{}=={}{}", quote, name, version, quote),
+        None => item.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions() -> BTreeMap<String, String> {
+        let mut res = BTreeMap::new();
+        res.insert("black".to_string(), "19.3b0".to_string());
+        res.insert("mypy".to_string(), "0.720".to_string());
+        res.insert("attrs".to_string(), "19.1.0".to_string());
+        res
+    }
+
+    #[test]
+    fn test_sync_rev() {
+        let config = r#"repos:
+-   repo: https://github.com/psf/black
+    rev: 19.3b0
+    hooks:
+    - id: black
+-   repo: https://github.com/pre-commit/mirrors-mypy
+    rev: v0.701  # keep me
+    hooks:
+    - id: mypy
+-   repo: https://github.com/pre-commit/pre-commit-hooks
+    rev: v2.2.3
+"#;
+        let (actual, changes) = sync(config, &versions());
+        assert!(actual.contains("    rev: v0.720  # keep me\n"));
+        assert!(actual.contains("    rev: v2.2.3\n"));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].line, 7);
+    }
+
+    #[test]
+    fn test_sync_additional_dependencies() {
+        let config = r#"repos:
+-   repo: local
+    hooks:
+    - id: check
+      additional_dependencies: [attrs, "mypy>=0.7", unknown]
+    - id: other
+      additional_dependencies:
+      - attrs==18.0
+      - unknown
+    - id: last
+"#;
+        let (actual, changes) = sync(config, &versions());
+        assert!(
+            actual.contains("additional_dependencies: [attrs==19.1.0, \"mypy==0.720\", unknown]")
+        );
+        assert!(actual.contains("      - attrs==19.1.0\n      - unknown\n    - id: last\n"));
+        assert_eq!(changes.len(), 2);
+    }
+
+    #[test]
+    fn test_pin_item_keeps_markers() {
+        let actual = pin_item("attrs ; python_version < '3.7'", &versions());
+        assert_eq!(actual, "attrs ; python_version < '3.7'");
+    }
+}
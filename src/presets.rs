@@ -0,0 +1,239 @@
+//! Presets for `dmenv run --preset <name>`, when dmenv starts the processes
+//! of the project, for instance in production. They are defined in
+//! `dmenv.toml`:
+//!
+//! ```text
+//! [presets.worker]
+//! dir = "src"
+//! nice = 10
+//! memory = "2G"
+//! open_files = 4096
+//!
+//! [presets.worker.env]
+//! CELERY_QUEUES = "high"
+//! ```
+//!
+//! Environment variables and the working directory are set for the command.
+//! Resource limits and the nice level are applied to dmenv itself right before
+//! `execv()`, so the command inherits them. They are only supported on Unix.
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// Resource limits, see `setrlimit(2)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    /// Address space, in bytes (RLIMIT_AS)
+    Memory,
+    /// RLIMIT_NOFILE
+    OpenFiles,
+    /// RLIMIT_CPU
+    CpuSeconds,
+    /// RLIMIT_NPROC
+    Processes,
+}
+
+impl Limit {
+    const ALL: &'static [(&'static str, Limit)] = &[
+        ("memory", Limit::Memory),
+        ("open_files", Limit::OpenFiles),
+        ("cpu_seconds", Limit::CpuSeconds),
+        ("processes", Limit::Processes),
+    ];
+
+    fn from_key(key: &str) -> Option<Self> {
+        Limit::ALL.iter().find(|(x, _)| *x == key).map(|(_, x)| *x)
+    }
+
+    fn name(self) -> &'static str {
+        Limit::ALL
+            .iter()
+            .find(|(_, x)| *x == self)
+            .map(|(x, _)| *x)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub env: Vec<(String, String)>,
+    /// Working directory, relative to the project
+    pub dir: Option<String>,
+    pub nice: Option<i32>,
+    pub limits: Vec<(Limit, u64)>,
+}
+
+impl Preset {
+    pub fn new(name: &str) -> Self {
+        Preset {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Set a key of the `[presets.<name>]` table. The value is unquoted already
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let number = |value: &str| -> Result<u64, String> {
+            value
+                .parse()
+                .map_err(|_| format!("`{}` should be a number, got `{}`", key, value))
+        };
+        match key {
+            "dir" => self.dir = Some(value.to_string()),
+            "nice" => {
+                let nice = value
+                    .parse()
+                    .map_err(|_| format!("`nice` should be a number, got `{}`", value))?;
+                self.nice = Some(nice);
+            }
+            "memory" => {
+                let size = parse_size(value).ok_or_else(|| {
+                    format!("`memory` should be a size, like \"512M\", got `{}`", value)
+                })?;
+                self.limits.push((Limit::Memory, size));
+            }
+            _ => match Limit::from_key(key) {
+                Some(limit) => self.limits.push((limit, number(value)?)),
+                None => {
+                    return Err(format!(
+                        "unknown key `{}` in preset `{}`, expected one of: dir, nice, {}",
+                        key,
+                        self.name,
+                        Limit::ALL
+                            .iter()
+                            .map(|(x, _)| *x)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn working_dir(&self, project: &Path) -> Option<PathBuf> {
+        self.dir.as_ref().map(|x| project.join(x))
+    }
+
+    /// Apply the limits and the nice level to the current process. Commands
+    /// started afterwards inherit them
+    pub fn apply(&self) -> Result<(), Error> {
+        #[cfg(unix)]
+        {
+            for (limit, value) in &self.limits {
+                set_limit(*limit, *value)?;
+            }
+            if let Some(nice) = self.nice {
+                set_nice(nice)?;
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            if self.nice.is_some() || !self.limits.is_empty() {
+                return Err(Error::Other {
+                    message: format!(
+                        "preset `{}`: resource limits and nice level are only supported on Unix",
+                        self.name
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `512M`, `2G`, or a number of bytes
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    };
+    let factor: u64 = match unit.trim().to_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+#[cfg(unix)]
+fn set_limit(limit: Limit, value: u64) -> Result<(), Error> {
+    let resource = match limit {
+        Limit::Memory => libc::RLIMIT_AS,
+        Limit::OpenFiles => libc::RLIMIT_NOFILE,
+        Limit::CpuSeconds => libc::RLIMIT_CPU,
+        Limit::Processes => libc::RLIMIT_NPROC,
+    };
+    let rlimit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    let res = unsafe { libc::setrlimit(resource, &rlimit) };
+    if res != 0 {
+        return Err(Error::Other {
+            message: format!(
+                "could not set {} limit to {}: {}",
+                limit.name(),
+                value,
+                std::io::Error::last_os_error()
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> Result<(), Error> {
+    let res = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) };
+    if res != 0 {
+        return Err(Error::Other {
+            message: format!(
+                "could not set nice level to {}: {}",
+                nice,
+                std::io::Error::last_os_error()
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512M"), Some(512 * 1024 * 1024));
+        assert_eq!(parse_size("2g"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("2 TB"), None);
+        assert_eq!(parse_size("M"), None);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut preset = Preset::new("worker");
+        preset.set("dir", "src").unwrap();
+        preset.set("nice", "10").unwrap();
+        preset.set("memory", "1G").unwrap();
+        preset.set("open_files", "4096").unwrap();
+        assert_eq!(preset.nice, Some(10));
+        assert_eq!(
+            preset.limits,
+            vec![(Limit::Memory, 1 << 30), (Limit::OpenFiles, 4096)]
+        );
+        assert_eq!(
+            preset.working_dir(Path::new("/app")).unwrap(),
+            Path::new("/app/src")
+        );
+        assert!(preset.set("open_files", "many").is_err());
+        assert_eq!(
+            preset.set("memroy", "1G").unwrap_err(),
+            "unknown key `memroy` in preset `worker`, expected one of: dir, nice, memory, open_files, cpu_seconds, processes"
+        );
+    }
+}
@@ -0,0 +1,778 @@
+use std::path::Path;
+
+use crate::cmd::print_warning;
+use crate::error::Error;
+use crate::indexes;
+use crate::lock_hashes::Algorithm;
+use crate::migrate;
+use crate::overrides::Override;
+use crate::platform_deps::PlatformDependency;
+use crate::policy::FreshnessPolicy;
+use crate::presets::Preset;
+use crate::settings::ProjectType;
+use crate::typosquat;
+use crate::variants::Variant;
+
+pub const CONFIG_FILENAME: &str = "dmenv.toml";
+
+/// Contents of the optional `dmenv.toml` file at the root of the project:
+///
+/// ```text
+/// python = "3.11"
+/// project_type = "requirements"
+/// ```
+///
+/// Only top-level `key = "value"` lines, and the `[variants]`, `[overrides]`,
+/// `[platform_dev_dependencies]` and `[presets.<name>]` tables (see the
+/// `variants`, `overrides`, `platform_deps` and `presets` modules) are read.
+/// Top-level keys are checked against `SCHEMA`, and table names against
+/// `TABLES`, so that typos are reported instead of ignored. `[tool.<name>]`
+/// tables are left to other tools.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProjectConfig {
+    /// Python version every command should use, unless `--python` is given
+    pub python: Option<String>,
+    /// Type of the project, unless DMENV_PROJECT_TYPE is set
+    pub project_type: Option<ProjectType>,
+    /// Variant used unless `--variant` is given or the lock uses another one
+    pub variant: Option<String>,
+    pub variants: Vec<Variant>,
+    pub overrides: Vec<Override>,
+    pub platform_dev_dependencies: Vec<PlatformDependency>,
+    pub presets: Vec<Preset>,
+    /// How old locked releases may be, unless DMENV_MAX_RELEASE_AGE is set
+    pub freshness_policy: Option<FreshnessPolicy>,
+    /// Reject git and URL dependencies in the production lock
+    pub forbid_vcs_in_production: bool,
+    /// Patterns of packages that must not be locked or installed, unless
+    /// DMENV_DENY_PACKAGES is set
+    pub deny_packages: Option<String>,
+    /// Patterns of the only packages that may be locked or installed, unless
+    /// DMENV_ALLOW_PACKAGES is set
+    pub allow_packages: Option<String>,
+    /// Algorithm of the hashes added to the lock, unless DMENV_HASH_ALGORITHM is set
+    pub hash_algorithm: Option<Algorithm>,
+}
+
+/// Values a key of `dmenv.toml` accepts
+enum Kind {
+    /// Any string, the example is used in error messages
+    String {
+        example: &'static str,
+    },
+    OneOf(&'static [&'static str]),
+    /// `true` or `false`, without quotes
+    Bool,
+    /// Version of the format of the file, see `migrate::CONFIG_FORMAT`
+    Format,
+}
+
+struct Key {
+    name: &'static str,
+    kind: Kind,
+    /// When set, using the key triggers a warning with this message
+    deprecated: Option<&'static str>,
+}
+
+const SCHEMA: &[Key] = &[
+    Key {
+        name: "format",
+        kind: Kind::Format,
+        deprecated: None,
+    },
+    Key {
+        name: "python",
+        kind: Kind::String { example: "3.11" },
+        deprecated: None,
+    },
+    Key {
+        name: "project_type",
+        kind: Kind::OneOf(&["package", "requirements"]),
+        deprecated: None,
+    },
+    Key {
+        name: "variant",
+        kind: Kind::String { example: "cu121" },
+        deprecated: None,
+    },
+    Key {
+        name: "max_release_age",
+        kind: Kind::String {
+            example: "18 months",
+        },
+        deprecated: None,
+    },
+    Key {
+        name: "forbid_vcs_in_production",
+        kind: Kind::Bool,
+        deprecated: None,
+    },
+    Key {
+        name: "deny_packages",
+        kind: Kind::String {
+            example: "pycrypto, reqeusts*",
+        },
+        deprecated: None,
+    },
+    Key {
+        name: "allow_packages",
+        kind: Kind::String {
+            example: "django, django-*",
+        },
+        deprecated: None,
+    },
+    Key {
+        name: "hash_algorithm",
+        kind: Kind::OneOf(&["sha256", "sha384", "sha512"]),
+        deprecated: None,
+    },
+];
+
+/// Tables read by dmenv. `presets` is followed by the name of the preset
+const TABLES: &[&str] = &[
+    "variants",
+    "overrides",
+    "platform_dev_dependencies",
+    "presets",
+];
+
+/// Something wrong in `dmenv.toml`. Only deprecations are not fatal
+#[derive(Debug, PartialEq)]
+struct Problem {
+    line: usize,
+    column: usize,
+    message: String,
+    fatal: bool,
+}
+
+impl ProjectConfig {
+    /// Returns the default (empty) config if there is no `dmenv.toml` in the project
+    pub fn from_project(project: &Path) -> Result<Self, Error> {
+        let path = project.join(CONFIG_FILENAME);
+        if !path.exists() {
+            return Ok(ProjectConfig::default());
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let (config, problems) = parse(&contents, SCHEMA);
+        let mut errors = vec![];
+        for problem in problems {
+            let message = format!(
+                "{}:{}:{}: {}",
+                path.display(),
+                problem.line,
+                problem.column,
+                problem.message
+            );
+            if problem.fatal {
+                errors.push(message);
+            } else {
+                print_warning(&message);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(Error::Other {
+                message: format!("Invalid {}\n{}", CONFIG_FILENAME, errors.join("\n")),
+            });
+        }
+        Ok(config)
+    }
+}
+
+fn parse(contents: &str, schema: &[Key]) -> (ProjectConfig, Vec<Problem>) {
+    let mut res = ProjectConfig::default();
+    let mut problems = vec![];
+    let mut table = None;
+    for (index, raw_line) in contents.lines().enumerate() {
+        let mut problem = |column: usize, message: String, fatal: bool| {
+            problems.push(Problem {
+                line: index + 1,
+                column: column + 1,
+                message,
+                fatal,
+            })
+        };
+        let indent = raw_line.len() - raw_line.trim_start().len();
+        let line = raw_line.trim();
+        if line.starts_with('[') {
+            // Start of a table: no more top-level keys
+            let name = line.trim_matches(|c| c == '[' || c == ']').trim();
+            if let Some(message) = check_table(name) {
+                problem(indent, message, false);
+            }
+            table = Some(name.to_string());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match table.as_deref() {
+            None => (),
+            Some("variants") => {
+                match parse_variant(line) {
+                    Ok(variant) => res.variants.push(variant),
+                    Err(message) => problem(indent, message, true),
+                }
+                continue;
+            }
+            Some("overrides") => {
+                match parse_override(line) {
+                    Ok(item) => res.overrides.push(item),
+                    Err(message) => problem(indent, message, true),
+                }
+                continue;
+            }
+            Some("platform_dev_dependencies") => {
+                match parse_platform_dependency(line) {
+                    Ok(dep) => res.platform_dev_dependencies.push(dep),
+                    Err(message) => problem(indent, message, true),
+                }
+                continue;
+            }
+            Some(name) if name.starts_with("presets.") => {
+                if let Err(message) = parse_preset_line(&mut res.presets, name, line) {
+                    problem(indent, message, true);
+                }
+                continue;
+            }
+            Some(_) => continue,
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => {
+                problem(
+                    indent,
+                    format!("expected `key = value`, got `{}`", line),
+                    true,
+                );
+                continue;
+            }
+        };
+        // Note: the value ends where the line ends
+        let value_column = raw_line.trim_end().len() - value.len();
+        let spec = match schema.iter().find(|x| x.name == key) {
+            Some(spec) => spec,
+            None => {
+                let names: Vec<_> = schema.iter().map(|x| x.name).collect();
+                let message = match did_you_mean(key, &names) {
+                    Some(name) => format!("unknown key `{}`, did you mean `{}`?", key, name),
+                    None => format!(
+                        "unknown key `{}`, expected one of: {}",
+                        key,
+                        names.join(", ")
+                    ),
+                };
+                problem(indent, message, true);
+                continue;
+            }
+        };
+        if let Some(message) = spec.deprecated {
+            problem(
+                indent,
+                format!("`{}` is deprecated: {}", key, message),
+                false,
+            );
+        }
+        if let Kind::Format = spec.kind {
+            let format = value.split('#').next().unwrap_or_default().trim();
+            match format.parse::<u32>() {
+                Ok(format) if format > migrate::CONFIG_FORMAT => {
+                    let message = format!(
+                        "format {} is not supported by this version of dmenv (expected at most {}), please upgrade dmenv",
+                        format,
+                        migrate::CONFIG_FORMAT
+                    );
+                    problem(value_column, message, true);
+                }
+                Ok(_) => (),
+                Err(_) => {
+                    let message = format!(
+                        "`format` should be a number, like `format = {}`, got `{}`",
+                        migrate::CONFIG_FORMAT,
+                        value
+                    );
+                    problem(value_column, message, true);
+                }
+            }
+            continue;
+        }
+        if let Kind::Bool = spec.kind {
+            let flag = match value.split('#').next().unwrap_or_default().trim() {
+                "true" => true,
+                "false" => false,
+                _ => {
+                    let message = format!(
+                        "`{}` should be true or false, like `{} = true`, got `{}`",
+                        key, key, value
+                    );
+                    problem(value_column, message, true);
+                    continue;
+                }
+            };
+            if key == "forbid_vcs_in_production" {
+                res.forbid_vcs_in_production = flag;
+            }
+            continue;
+        }
+        let string = match parse_string(value) {
+            Some(string) => string,
+            None => {
+                let example = match &spec.kind {
+                    Kind::String { example } => example,
+                    Kind::OneOf(values) => &values[0],
+                    Kind::Bool | Kind::Format => unreachable!(),
+                };
+                let message = format!(
+                    "`{}` should be a string, like `{} = \"{}\"`, got `{}`",
+                    key, key, example, value
+                );
+                problem(value_column, message, true);
+                continue;
+            }
+        };
+        if let Kind::OneOf(values) = &spec.kind {
+            if !values.contains(&string.as_str()) {
+                let message = match did_you_mean(&string, values) {
+                    Some(value) => format!(
+                        "unknown {} \"{}\", did you mean \"{}\"?",
+                        key, string, value
+                    ),
+                    None => format!(
+                        "unknown {} \"{}\", expected one of: {}",
+                        key,
+                        string,
+                        values.join(", ")
+                    ),
+                };
+                problem(value_column, message, true);
+                continue;
+            }
+        }
+        match key {
+            "python" => res.python = Some(string),
+            // Note: values were checked against the schema above
+            "project_type" => res.project_type = string.parse().ok(),
+            "variant" => res.variant = Some(string),
+            "max_release_age" => match FreshnessPolicy::new(&string, CONFIG_FILENAME) {
+                Ok(policy) => res.freshness_policy = Some(policy),
+                Err(message) => problem(value_column, message, true),
+            },
+            "deny_packages" => res.deny_packages = Some(string),
+            "allow_packages" => res.allow_packages = Some(string),
+            "hash_algorithm" => res.hash_algorithm = string.parse().ok(),
+            _ => (),
+        }
+    }
+    (res, problems)
+}
+
+/// Warn about tables dmenv does not read, except the ones of other tools,
+/// since their contents would be silently ignored
+fn check_table(name: &str) -> Option<String> {
+    let mut parts = name.splitn(2, '.');
+    let first = parts.next().unwrap_or_default();
+    let rest = parts.next();
+    let known = match rest {
+        Some(_) => first == "presets" || first == "tool",
+        None => TABLES.contains(&first) && first != "presets",
+    };
+    if known {
+        return None;
+    }
+    let message = match did_you_mean(first, TABLES) {
+        Some("presets") => format!(
+            "unknown table `[{}]`, did you mean `[presets.{}]`?",
+            name,
+            rest.unwrap_or("<name>")
+        ),
+        Some(table) => format!("unknown table `[{}]`, did you mean `[{}]`?", name, table),
+        None => format!(
+            "unknown table `[{}]`, expected one of: {}, presets.<name>, tool.<name>",
+            name,
+            TABLES[..TABLES.len() - 1].join(", ")
+        ),
+    };
+    Some(format!("{} - its contents are ignored", message))
+}
+
+/// `name = "patterns=url ..."`, with the syntax of `DMENV_INDEXES`
+fn parse_variant(line: &str) -> Result<Variant, String> {
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next().unwrap_or_default().trim();
+    let value = parts.next().map(|x| x.trim()).unwrap_or_default();
+    let example = "torch=https://download.pytorch.org/whl/cu121";
+    let spec = parse_string(value).ok_or_else(|| {
+        format!(
+            "variant `{}` should be a string, like `{} = \"{}\"`, got `{}`",
+            name, name, example, value
+        )
+    })?;
+    let indexes =
+        indexes::parse(&spec).map_err(|e| format!("invalid variant `{}`: {}", name, e))?;
+    Ok(Variant {
+        name: name.to_string(),
+        indexes,
+    })
+}
+
+/// `name = "requirement"`, where the requirement is installed instead of `name`
+fn parse_override(line: &str) -> Result<Override, String> {
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next().unwrap_or_default().trim();
+    let value = parts.next().map(|x| x.trim()).unwrap_or_default();
+    let requirement = parse_string(value).ok_or_else(|| {
+        format!(
+            "override of `{}` should be a string, like `{} = \"{}==1.2\"`, got `{}`",
+            name, name, name, value
+        )
+    })?;
+    let res = Override {
+        name: name.to_string(),
+        requirement,
+    };
+    if res.is_url() && !res.requirement.contains("#egg=") {
+        return Err(format!(
+            "override of `{}` should end with `#egg=<name>`, like `{}#egg={}`",
+            name, res.requirement, name
+        ));
+    }
+    Ok(res)
+}
+
+/// `name = "platform, ..."`
+fn parse_platform_dependency(line: &str) -> Result<PlatformDependency, String> {
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next().unwrap_or_default().trim();
+    let value = parts.next().map(|x| x.trim()).unwrap_or_default();
+    let platforms = parse_string(value).ok_or_else(|| {
+        format!(
+            "platforms of `{}` should be a string, like `{} = \"win32\"`, got `{}`",
+            name, name, value
+        )
+    })?;
+    PlatformDependency::new(name, &platforms)
+}
+
+/// `key = value` in `[presets.<name>]`, or `VAR = "value"` in `[presets.<name>.env]`
+fn parse_preset_line(presets: &mut Vec<Preset>, table: &str, line: &str) -> Result<(), String> {
+    let table = table.trim_start_matches("presets.");
+    let (name, is_env) = match table.strip_suffix(".env") {
+        Some(name) => (name, true),
+        None => (table, false),
+    };
+    let mut parts = line.splitn(2, '=');
+    let key = parts.next().unwrap_or_default().trim();
+    let value = parts.next().map(|x| x.trim()).unwrap_or_default();
+    // Note: numbers, like `nice = 10`, may be written without quotes
+    let value = match parse_string(value) {
+        Some(value) => value,
+        None if is_env => {
+            return Err(format!(
+                "`{}` should be a string, like `{} = \"1\"`, got `{}`",
+                key, key, value
+            ))
+        }
+        None => value
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    };
+    let index = match presets.iter().position(|x| x.name == name) {
+        Some(index) => index,
+        None => {
+            presets.push(Preset::new(name));
+            presets.len() - 1
+        }
+    };
+    let preset = &mut presets[index];
+    if is_env {
+        preset.env.push((key.to_string(), value));
+        return Ok(());
+    }
+    preset.set(key, &value)
+}
+
+/// The closest of `candidates`, if it is close enough to be a typo
+fn did_you_mean<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|x| (typosquat::distance(name, x), *x))
+        .filter(|(distance, x)| *distance <= 2.max(x.len() / 4))
+        .min()
+        .map(|(_, x)| x)
+}
+
+/// Parse a quoted string, possibly followed by a comment
+fn parse_string(value: &str) -> Option<String> {
+    let quote = value.chars().next()?;
+    if quote != '"' && quote != '\'' {
+        return None;
+    }
+    let rest = &value[1..];
+    let end = rest.find(quote)?;
+    let after = rest[end + 1..].trim();
+    if !after.is_empty() && !after.starts_with('#') {
+        return None;
+    }
+    Some(rest[..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_string(contents: &str) -> Result<ProjectConfig, Vec<String>> {
+        let (config, problems) = parse(contents, SCHEMA);
+        if problems.is_empty() {
+            return Ok(config);
+        }
+        Err(problems
+            .iter()
+            .map(|x| format!("{}:{}: {}", x.line, x.column, x.message))
+            .collect())
+    }
+
+    #[test]
+    fn test_python_version() {
+        let contents = "# Python used by the project\npython = \"3.11\"  # for match\n";
+        let config = from_string(contents).unwrap();
+        assert_eq!(config.python.unwrap(), "3.11");
+    }
+
+    #[test]
+    fn test_project_type() {
+        let config = from_string("project_type = \"requirements\"\n").unwrap();
+        assert_eq!(config.project_type, Some(ProjectType::Requirements));
+        let errors = from_string("project_type = \"notebook\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:16: unknown project_type \"notebook\", expected one of: package, requirements"]
+        );
+        let errors = from_string("project_type = \"requirement\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:16: unknown project_type \"requirement\", did you mean \"requirements\"?"]
+        );
+    }
+
+    #[test]
+    fn test_max_release_age() {
+        let config = from_string("max_release_age = \"18 months\"\n").unwrap();
+        assert_eq!(config.freshness_policy.unwrap().max_age_days, 540);
+        let errors = from_string("max_release_age = \"18\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:19: invalid age '18', expected a number of days, weeks, months or years, like '18 months'"]
+        );
+    }
+
+    #[test]
+    fn test_forbid_vcs_in_production() {
+        let config = from_string("forbid_vcs_in_production = true  # see #42\n").unwrap();
+        assert!(config.forbid_vcs_in_production);
+        let config = from_string("forbid_vcs_in_production = false\n").unwrap();
+        assert!(!config.forbid_vcs_in_production);
+        let errors = from_string("forbid_vcs_in_production = \"yes\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:28: `forbid_vcs_in_production` should be true or false, like `forbid_vcs_in_production = true`, got `\"yes\"`"]
+        );
+    }
+
+    #[test]
+    fn test_package_policy() {
+        let contents = "deny_packages = \"pycrypto, reqeusts*\"\nallow_packages = \"django*\"\n";
+        let config = from_string(contents).unwrap();
+        assert_eq!(config.deny_packages.as_deref(), Some("pycrypto, reqeusts*"));
+        assert_eq!(config.allow_packages.as_deref(), Some("django*"));
+    }
+
+    #[test]
+    fn test_hash_algorithm() {
+        let config = from_string("hash_algorithm = \"sha512\"\n").unwrap();
+        assert_eq!(config.hash_algorithm, Some(Algorithm::Sha512));
+        let errors = from_string("hash_algorithm = \"sha521\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:18: unknown hash_algorithm \"sha521\", did you mean \"sha512\"?"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_tables() {
+        let contents = "[tool.other]\npython = \"3.6\"\n";
+        let config = from_string(contents).unwrap();
+        assert_eq!(config, ProjectConfig::default());
+
+        let contents = "\
+[overides]
+foo = \"foo-fork==1.0\"
+[platform_dev_dependecies]
+pywin32 = \"win32\"
+[preset.worker]
+nice = 10
+[scripts]
+test = \"pytest\"
+";
+        let (config, problems) = parse(contents, SCHEMA);
+        assert_eq!(config, ProjectConfig::default());
+        assert!(problems.iter().all(|x| !x.fatal));
+        let messages: Vec<_> = problems
+            .iter()
+            .map(|x| format!("{}:{}: {}", x.line, x.column, x.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "1:1: unknown table `[overides]`, did you mean `[overrides]`? - its contents are ignored",
+                "3:1: unknown table `[platform_dev_dependecies]`, did you mean `[platform_dev_dependencies]`? - its contents are ignored",
+                "5:1: unknown table `[preset.worker]`, did you mean `[presets.worker]`? - its contents are ignored",
+                "7:1: unknown table `[scripts]`, expected one of: variants, overrides, platform_dev_dependencies, presets.<name>, tool.<name> - its contents are ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_variants() {
+        let contents = "\
+variant = \"cpu\"
+
+[variants]
+# Builds of torch
+cpu = \"torch=https://download.pytorch.org/whl/cpu\"
+cu121 = \"torch,torchvision=https://download.pytorch.org/whl/cu121\"
+
+[tool.other]
+rocm = 42
+";
+        let config = from_string(contents).unwrap();
+        assert_eq!(config.variant.unwrap(), "cpu");
+        let names: Vec<_> = config.variants.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["cpu", "cu121"]);
+
+        let errors = from_string("[variants]\ncu121 = \"torch=nowhere\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["2:1: invalid variant `cu121`: invalid index URL: 'nowhere'"]
+        );
+    }
+
+    #[test]
+    fn test_overrides() {
+        let contents = "\
+[overrides]
+foo = \"git+https://github.com/me/foo-fork@fix-42#egg=foo-fork\"  # until 1.0
+bar = \"bar==1.2\"
+";
+        let config = from_string(contents).unwrap();
+        assert_eq!(config.overrides.len(), 2);
+        assert_eq!(config.overrides[0].replacement_name(), "foo-fork");
+        assert_eq!(config.overrides[1].requirement, "bar==1.2");
+
+        let errors =
+            from_string("[overrides]\nfoo = \"git+https://github.com/me/foo-fork\"\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["2:1: override of `foo` should end with `#egg=<name>`, like `git+https://github.com/me/foo-fork#egg=foo`"]
+        );
+    }
+
+    #[test]
+    fn test_platform_dev_dependencies() {
+        let contents =
+            "[platform_dev_dependencies]\npywin32 = \"win32\"\nuvloop = \"linux, darwin\"\n";
+        let config = from_string(contents).unwrap();
+        let names: Vec<_> = config
+            .platform_dev_dependencies
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["pywin32", "uvloop"]);
+
+        let errors = from_string("[platform_dev_dependencies]\npywin32 = true\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["2:1: platforms of `pywin32` should be a string, like `pywin32 = \"win32\"`, got `true`"]
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let contents = "\
+[presets.worker]
+dir = \"src\"
+nice = 10  # lower priority
+open_files = 4096
+
+[presets.worker.env]
+QUEUES = \"high\"
+";
+        let config = from_string(contents).unwrap();
+        let worker = &config.presets[0];
+        assert_eq!(worker.name, "worker");
+        assert_eq!(worker.dir.as_deref(), Some("src"));
+        assert_eq!(worker.nice, Some(10));
+        assert_eq!(worker.env, vec![("QUEUES".to_string(), "high".to_string())]);
+
+        let errors = from_string("[presets.worker]\nnice = \"low\"\n").unwrap_err();
+        assert_eq!(errors, vec!["2:1: `nice` should be a number, got `low`"]);
+    }
+
+    #[test]
+    fn test_not_a_string() {
+        let errors = from_string("python =  3.11\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:11: `python` should be a string, like `python = \"3.11\"`, got `3.11`"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let contents = "python = \"3.11\"\n  pyhton = \"3.11\"\nvenv_outside_projet = true\n";
+        let errors = from_string(contents).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "2:3: unknown key `pyhton`, did you mean `python`?",
+                "3:1: unknown key `venv_outside_projet`, expected one of: format, python, project_type, variant, max_release_age, forbid_vcs_in_production, deny_packages, allow_packages, hash_algorithm",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        from_string("format = 1  # see the docs\n").unwrap();
+        let errors = from_string("format = 2\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["1:10: format 2 is not supported by this version of dmenv (expected at most 1), please upgrade dmenv"]
+        );
+        from_string("format = \"1\"\n").unwrap_err();
+    }
+
+    #[test]
+    fn test_deprecated_keys() {
+        let schema = &[Key {
+            name: "python_version",
+            kind: Kind::String { example: "3.11" },
+            deprecated: Some("use `python` instead"),
+        }];
+        let (_, problems) = parse("python_version = \"3.11\"\n", schema);
+        assert_eq!(
+            problems,
+            vec![Problem {
+                line: 1,
+                column: 1,
+                message: "`python_version` is deprecated: use `python` instead".to_string(),
+                fatal: false,
+            }]
+        );
+    }
+}
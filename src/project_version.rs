@@ -0,0 +1,107 @@
+use crate::error::Error;
+
+/// What `dmenv version` should do with the version of the project
+#[derive(Debug, PartialEq)]
+pub enum VersionBump {
+    Major,
+    Minor,
+    Patch,
+    Explicit(String),
+}
+
+impl std::str::FromStr for VersionBump {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s {
+            "major" => VersionBump::Major,
+            "minor" => VersionBump::Minor,
+            "patch" => VersionBump::Patch,
+            _ => VersionBump::Explicit(s.to_string()),
+        };
+        Ok(res)
+    }
+}
+
+/// Compute the new version. `current` must look like `X.Y.Z` unless
+/// the new version is explicit.
+pub fn bump(current: &str, bump: &VersionBump) -> Result<String, Error> {
+    if let VersionBump::Explicit(version) = bump {
+        return Ok(version.to_string());
+    }
+    let parts: Vec<_> = current.split('.').map(|x| x.parse::<u64>()).collect();
+    let (major, minor, patch) = match parts.as_slice() {
+        [Ok(major), Ok(minor), Ok(patch)] => (*major, *minor, *patch),
+        [Ok(major), Ok(minor)] => (*major, *minor, 0),
+        _ => {
+            return Err(Error::Other {
+                message: format!(
+                "Can't bump '{}': expected a version like 1.2.3. Use an explicit version instead",
+                current
+            ),
+            })
+        }
+    };
+    let res = match bump {
+        VersionBump::Major => format!("{}.0.0", major + 1),
+        VersionBump::Minor => format!("{}.{}.0", major, minor + 1),
+        VersionBump::Patch => format!("{}.{}.{}", major, minor, patch + 1),
+        VersionBump::Explicit(_) => unreachable!(),
+    };
+    Ok(res)
+}
+
+/// Replace the version in setup.py or pyproject.toml contents.
+//
+// Note: `old` is the version returned by the project metadata, so we
+// look for it as a quoted string instead of trying to parse the file. There must
+// be exactly one match, otherwise we don't know which one to change.
+pub fn replace(contents: &str, old: &str, new: &str) -> Result<String, Error> {
+    let mut res = None;
+    for quote in &['"', '\''] {
+        let needle = format!("{}{}{}", quote, old, quote);
+        let count = contents.matches(&needle).count();
+        if count == 0 {
+            continue;
+        }
+        if count > 1 || res.is_some() {
+            return Err(Error::Other {
+                message: format!("version '{}' found more than once", old),
+            });
+        }
+        let replacement = format!("{}{}{}", quote, new, quote);
+        res = Some(contents.replace(&needle, &replacement));
+    }
+    res.ok_or_else(|| Error::Other {
+        message: format!("version '{}' not found. Is it computed in setup.py?", old),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump() {
+        assert_eq!(bump("1.2.3", &VersionBump::Patch).unwrap(), "1.2.4");
+        assert_eq!(bump("1.2.3", &VersionBump::Minor).unwrap(), "1.3.0");
+        assert_eq!(bump("1.2", &VersionBump::Major).unwrap(), "2.0.0");
+        let explicit = VersionBump::Explicit("2.0.0rc1".to_string());
+        assert_eq!(bump("1.2.3", &explicit).unwrap(), "2.0.0rc1");
+        bump("1.2.3b1", &VersionBump::Patch).unwrap_err();
+    }
+
+    #[test]
+    fn test_replace() {
+        let setup_py = "setup(\n    name='foo',\n    version='0.1.0',\n)\n";
+        let actual = replace(setup_py, "0.1.0", "0.2.0").unwrap();
+        assert!(actual.contains("version='0.2.0'"));
+    }
+
+    #[test]
+    fn test_replace_ambiguous() {
+        let setup_py = "setup(version=\"1.0\", install_requires=[\"1.0\"])";
+        replace(setup_py, "1.0", "1.1").unwrap_err();
+        replace(setup_py, "2.0", "2.1").unwrap_err();
+    }
+}
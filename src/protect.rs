@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Name of the marker file written in virtualenvs made read-only
+/// by `dmenv venv freeze-perms`
+pub const FROZEN_FILENAME: &str = "dmenv-frozen";
+
+pub fn is_frozen(venv: &Path) -> bool {
+    venv.join(FROZEN_FILENAME).exists()
+}
+
+/// Whether the command given to `dmenv run` would change the packages
+/// installed in the virtualenv, for instance `pip install foo` or
+/// `python -m pip uninstall foo`
+pub fn is_venv_mutation(cmd: &[String]) -> bool {
+    match pip_command(cmd) {
+        Some((command, _)) => ["install", "uninstall"].contains(&command),
+        None => false,
+    }
+}
+
+/// Options of `pip install` followed by a value, which is not a requirement
+const PIP_INSTALL_OPTIONS_WITH_VALUE: &[&str] = &[
+    "-r",
+    "--requirement",
+    "-c",
+    "--constraint",
+    "-e",
+    "--editable",
+    "-i",
+    "--index-url",
+    "--extra-index-url",
+    "-f",
+    "--find-links",
+    "-t",
+    "--target",
+    "--prefix",
+    "--root",
+    "--platform",
+    "--python-version",
+    "--implementation",
+    "--abi",
+    "--upgrade-strategy",
+    "--no-binary",
+    "--only-binary",
+    "--progress-bar",
+    "--trusted-host",
+    "--cert",
+    "--proxy",
+    "--log",
+    "--config-settings",
+    "--global-option",
+    "--src",
+];
+
+/// Requirements given by name to `pip install`, like `requests>=2.22`. Files,
+/// URLs, and requirements given with `-r` or `-e` are skipped
+pub fn pip_install_requirements(cmd: &[String]) -> Vec<String> {
+    let args = match pip_command(cmd) {
+        Some(("install", args)) => args,
+        _ => return vec![],
+    };
+    let mut res = vec![];
+    let mut skip_next = false;
+    for arg in args {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        if arg.starts_with('-') {
+            // Note: `--index-url=...` carries its value
+            skip_next = PIP_INSTALL_OPTIONS_WITH_VALUE.contains(&arg);
+            continue;
+        }
+        let is_path = arg.contains('/') || arg.contains('\\') || arg.starts_with('.');
+        if !is_path {
+            res.push(arg.to_string());
+        }
+    }
+    res
+}
+
+/// The pip command (`install`, `list` ...) and its arguments, if `cmd` runs pip
+fn pip_command(cmd: &[String]) -> Option<(&str, Vec<&str>)> {
+    let args: Vec<_> = cmd.iter().map(|x| x.as_str()).collect();
+    let pip_args = match args.as_slice() {
+        [binary, rest @ ..] if is_pip(binary) => rest,
+        [binary, "-m", "pip", rest @ ..] if is_python(binary) => rest,
+        _ => return None,
+    };
+    // Skip options like `--quiet` or `--verbose`, placed before the pip command
+    let index = pip_args.iter().position(|x| !x.starts_with('-'))?;
+    Some((pip_args[index], pip_args[index + 1..].to_vec()))
+}
+
+fn is_pip(binary: &str) -> bool {
+    let name = binary.trim_end_matches(".exe");
+    name == "pip" || name.starts_with("pip3")
+}
+
+fn is_python(binary: &str) -> bool {
+    let name = binary.trim_end_matches(".exe");
+    name == "python" || name.starts_with("python3")
+}
+
+/// Make all the files in the virtualenv read-only (or writable again),
+/// and write (or remove) the marker file
+pub fn set_frozen(venv: &Path, frozen: bool) -> Result<(), Error> {
+    let marker = venv.join(FROZEN_FILENAME);
+    if frozen {
+        std::fs::write(&marker, "").map_err(|e| Error::WriteError {
+            path: marker.to_path_buf(),
+            io_error: e,
+        })?;
+        set_read_only(venv, true)
+    } else {
+        set_read_only(venv, false)?;
+        std::fs::remove_file(&marker).map_err(|e| Error::Other {
+            message: format!("could not remove {}: {}", marker.display(), e),
+        })
+    }
+}
+
+// Note: symlinks are skipped, because changing permissions on them
+// would change the permissions of their target (like the system's
+// Python interpreter)
+fn set_read_only(path: &Path, read_only: bool) -> Result<(), Error> {
+    let metadata = std::fs::symlink_metadata(path).map_err(|e| Error::ReadError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })?;
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+    // Directories must be made writable before their contents,
+    // and read-only after
+    if metadata.is_dir() && !read_only {
+        set_permissions(path, &metadata, read_only)?;
+    }
+    if metadata.is_dir() {
+        let entries = std::fs::read_dir(path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        for entry in entries.filter_map(Result::ok) {
+            set_read_only(&entry.path(), read_only)?;
+        }
+    }
+    if !metadata.is_dir() || read_only {
+        set_permissions(path, &metadata, read_only)?;
+    }
+    Ok(())
+}
+
+fn set_permissions(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    read_only: bool,
+) -> Result<(), Error> {
+    let mut permissions = metadata.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Only give write access back to the owner
+        let mode = permissions.mode();
+        let mode = if read_only {
+            mode & !0o222
+        } else {
+            mode | 0o200
+        };
+        permissions.set_mode(mode);
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(read_only);
+    std::fs::set_permissions(path, permissions).map_err(|e| Error::Other {
+        message: format!("could not change permissions of {}: {}", path.display(), e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_mutation(cmd: &[&str]) -> bool {
+        let cmd: Vec<_> = cmd.iter().map(|x| x.to_string()).collect();
+        is_venv_mutation(&cmd)
+    }
+
+    #[test]
+    fn test_is_venv_mutation() {
+        assert!(is_mutation(&["pip", "install", "foo"]));
+        assert!(is_mutation(&["pip3.7", "--quiet", "uninstall", "foo"]));
+        assert!(is_mutation(&["python", "-m", "pip", "install", "foo"]));
+        assert!(!is_mutation(&["pip", "list"]));
+        assert!(!is_mutation(&["pip", "--version"]));
+        assert!(!is_mutation(&["pytest", "install"]));
+        assert!(!is_mutation(&["python", "-m", "pytest"]));
+    }
+
+    #[test]
+    fn test_pip_install_requirements() {
+        let cmd: Vec<_> = [
+            "python",
+            "-m",
+            "pip",
+            "install",
+            "-U",
+            "requests>=2.22",
+            "-i",
+            "https://pypi.acme.corp/simple",
+            "-r",
+            "requirements.txt",
+            "./vendor/foo.whl",
+            "--index-url=https://pypi.org/simple",
+            "attrs",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+        assert_eq!(
+            pip_install_requirements(&cmd),
+            vec!["requests>=2.22", "attrs"]
+        );
+        let cmd = vec![
+            "pip".to_string(),
+            "uninstall".to_string(),
+            "attrs".to_string(),
+        ];
+        assert!(pip_install_requirements(&cmd).is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+/// Get a string value from the `[project]` table of a pyproject.toml file,
+/// like `name` or `version`
+pub fn project_value(contents: &str, key: &str) -> Option<String> {
+    table_value(contents, "project", key)
+}
+
+/// Get a string value from the `[tool.dmenv]` table of a pyproject.toml file
+pub fn dmenv_value(contents: &str, key: &str) -> Option<String> {
+    table_value(contents, "tool.dmenv", key)
+}
+
+// Note: unlike setup.py, pyproject.toml is static, so there's no need to ask
+// Python. We only handle `key = "value"` on a single line, though.
+fn table_value(contents: &str, table: &str, key: &str) -> Option<String> {
+    let header = format!("[{}]", table);
+    let mut in_table = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_table = line == header;
+            continue;
+        }
+        if !in_table {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        if parts.next().map(|x| x.trim()) != Some(key) {
+            continue;
+        }
+        let value = parts.next()?.trim();
+        return Some(value.trim_matches(|c| c == '"' || c == '\'').to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_value() {
+        let pyproject =
+            "[build-system]\nversion = \"0\"\n\n[project]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        assert_eq!(project_value(pyproject, "version").unwrap(), "1.2.0");
+        assert_eq!(project_value(pyproject, "name").unwrap(), "foo");
+        assert!(project_value(pyproject, "license").is_none());
+    }
+
+    #[test]
+    fn test_dmenv_value() {
+        let pyproject =
+            "[project]\nname = \"foo\"\n\n[tool.dmenv]\nrequired_version = \">=0.14,<0.16\"\n";
+        assert_eq!(
+            dmenv_value(pyproject, "required_version").unwrap(),
+            ">=0.14,<0.16"
+        );
+        assert!(dmenv_value(pyproject, "name").is_none());
+    }
+}
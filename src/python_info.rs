@@ -1,37 +1,39 @@
 use crate::error::Error;
-use std::path::PathBuf;
+use crate::paths::get_cache_root;
+use crate::runner::{CommandRunner, Process};
+use std::path::{Path, PathBuf};
+
+/// Where PythonInfo is cached, in the dmenv cache. See `PythonInfo::cached()`
+const CACHE_FILENAME: &str = "pythons.txt";
 
 /// Represent output of the info.py script
 /// This allows dmenv to know details about
 /// the Python intrepreter it is using.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct PythonInfo {
     pub binary: PathBuf,
     pub version: String,
     pub platform: String,
+    /// `CPython`, `PyPy` ...
+    pub implementation: String,
 }
 
 impl PythonInfo {
     #[allow(clippy::new_ret_no_self)]
-    pub fn new(python: &Option<String>) -> Result<Self, Error> {
+    pub fn new(python: &Option<String>, runner: &dyn CommandRunner) -> Result<Self, Error> {
         let binary = get_python_binary(&python)?;
         let info_script = include_str!("info.py");
 
-        let command = std::process::Command::new(&binary)
-            .args(&["-c", info_script])
-            .output();
-        let command = command.map_err(|e| Error::ProcessOutError { io_error: e })?;
-        if !command.status.success() {
+        let output = runner.output(&Process::new(&binary).args(&["-c", info_script]))?;
+        if !output.success {
             return Err(Error::Other {
-                message: format!(
-                    "Failed to run info script: {}",
-                    String::from_utf8_lossy(&command.stderr)
-                ),
+                message: format!("Failed to run info script: {}", output.stderr),
             });
         }
-        let info_out = String::from_utf8_lossy(&command.stdout);
+        let info_out = output.stdout;
         let lines: Vec<_> = info_out.split('\n').collect();
-        let expected_lines = 3; // Keep this in sync with src/info.py
-        if lines.len() != 3 {
+        let expected_lines = 4; // Keep this in sync with src/info.py
+        if lines.len() != expected_lines {
             return Err(Error::Other {
                 message: format!(
                     "Expected {} lines in info_out, got: {}",
@@ -42,12 +44,241 @@ impl PythonInfo {
         }
         let version = lines[0].trim().to_string();
         let platform = lines[1].trim().to_string();
-        Ok(PythonInfo {
+        let implementation = lines[2].trim().to_string();
+        let info = PythonInfo {
             binary,
             version,
             platform,
+            implementation,
+        };
+        // Note: the cache is only an optimization for `dmenv query`, ignore errors
+        let _ = info.write_cache();
+        Ok(info)
+    }
+
+    /// What `PythonInfo::new()` found the last time it ran with the same binary,
+    /// without starting Python. Returns None if it never did, or if the binary
+    /// changed since then.
+    //
+    // Note: shims (like the ones of pyenv) don't change when the Python they
+    // run does, so the result may be out of date. This is why only `dmenv query`
+    // uses it.
+    pub fn cached(python: &Option<String>) -> Option<Self> {
+        let binary = get_python_binary(&python).ok()?;
+        let key = cache_key(&binary)?;
+        let contents = std::fs::read_to_string(get_cache_root().ok()?.join(CACHE_FILENAME)).ok()?;
+        let (_, mut info) = contents
+            .lines()
+            .filter_map(parse_cache_line)
+            .find(|(line_key, _)| *line_key == key)?;
+        info.binary = binary;
+        Some(info)
+    }
+
+    fn write_cache(&self) -> Result<(), Error> {
+        let key = match cache_key(&self.binary) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let root = get_cache_root()?;
+        let path = root.join(CACHE_FILENAME);
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+        let mut lines: Vec<_> = contents
+            .lines()
+            .filter(|x| parse_cache_line(x).map_or(false, |(line_key, _)| line_key != key))
+            .map(|x| x.to_string())
+            .collect();
+        lines.push(render_cache_line(&key, self));
+        std::fs::create_dir_all(&root).map_err(|e| Error::WriteError {
+            path: root.to_path_buf(),
+            io_error: e,
+        })?;
+        std::fs::write(&path, lines.join("\n") + "\n").map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
         })
     }
+
+    /// Identify the interpreter in virtualenv paths, cache keys and the like:
+    /// the version for CPython, prefixed by the implementation otherwise
+    /// (`pypy-3.9.16`), so that a PyPy virtualenv never gets mixed with a CPython one
+    pub fn tag(&self) -> String {
+        if self.implementation.is_empty() || self.implementation == "CPython" {
+            self.version.clone()
+        } else {
+            format!("{}-{}", self.implementation.to_lowercase(), self.version)
+        }
+    }
+}
+
+/// Look for a Python interpreter whose version matches `requested` (like `3.11`)
+/// among `python`, `python3` and `pythonX.Y` binaries found in PATH
+pub fn find_matching(requested: &str, runner: &dyn CommandRunner) -> Result<PythonInfo, Error> {
+    let mut available: Vec<String> = vec![];
+    for binary in discover_binaries() {
+        let info = match PythonInfo::new(&Some(binary.to_string_lossy().to_string()), runner) {
+            Ok(info) => info,
+            Err(_) => continue,
+        };
+        if version_matches(&info.version, requested) {
+            return Ok(info);
+        }
+        if !available.contains(&info.version) {
+            available.push(info.version);
+        }
+    }
+    Err(Error::NoMatchingPython {
+        requested: requested.to_string(),
+        available,
+    })
+}
+
+/// Like `find_matching()`, using only cached information, see `PythonInfo::cached()`
+pub fn find_matching_cached(requested: &str) -> Option<PythonInfo> {
+    discover_binaries()
+        .into_iter()
+        .filter_map(|x| PythonInfo::cached(&Some(x.to_string_lossy().to_string())))
+        .find(|x| version_matches(&x.version, requested))
+}
+
+/// Python binaries in PATH, with their version if it is in the cache, see `PythonInfo::cached()`
+pub fn discover_cached() -> Vec<(PathBuf, Option<String>)> {
+    discover_binaries()
+        .into_iter()
+        .map(|binary| {
+            let version =
+                PythonInfo::cached(&Some(binary.to_string_lossy().to_string())).map(|x| x.version);
+            (binary, version)
+        })
+        .collect()
+}
+
+/// Identify a binary in the cache: its canonical path and its modification time
+/// (so that upgrading the interpreter in place invalidates the entry)
+fn cache_key(binary: &Path) -> Option<String> {
+    let path = if binary.components().count() > 1 {
+        binary.to_path_buf()
+    } else {
+        which::which(binary).ok()?
+    };
+    let path = path.canonicalize().ok()?;
+    let modified = path.metadata().ok()?.modified().ok()?;
+    let modified = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(format!("{}@{}", path.display(), modified.as_secs()))
+}
+
+// Note: the key comes last, because it contains a path
+fn render_cache_line(key: &str, info: &PythonInfo) -> String {
+    format!(
+        "{}\t{}\t{}\t{}",
+        info.version, info.platform, info.implementation, key
+    )
+}
+
+fn parse_cache_line(line: &str) -> Option<(String, PythonInfo)> {
+    let mut parts = line.splitn(4, '\t');
+    let version = parts.next()?.to_string();
+    let platform = parts.next()?.to_string();
+    let implementation = parts.next()?.to_string();
+    let key = parts.next()?.to_string();
+    let info = PythonInfo {
+        binary: PathBuf::new(),
+        version,
+        platform,
+        implementation,
+    };
+    Some((key, info))
+}
+
+/// Python binaries in PATH, the most specific ones (`python3.11`) first
+fn discover_binaries() -> Vec<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut specific = vec![];
+    let mut generic = vec![];
+    for dir in std::env::split_paths(&path) {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            if cfg!(windows) && path.extension().map_or(true, |x| x != "exe") {
+                continue;
+            }
+            // Note: only strip `.exe`, `python3.11` has no extension
+            let stem = if cfg!(windows) {
+                path.file_stem()
+            } else {
+                path.file_name()
+            };
+            let stem = match stem {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if stem == "python" || stem == "python3" {
+                generic.push(path);
+            } else if is_versioned_binary(&stem) {
+                specific.push(path);
+            }
+        }
+    }
+    specific.extend(generic);
+    specific
+}
+
+// Note: `python3.7m` and `python3.7-config` are excluded on purpose
+fn is_versioned_binary(name: &str) -> bool {
+    if !name.starts_with("python") {
+        return false;
+    }
+    let version = &name["python".len()..];
+    !version.is_empty()
+        && version.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && version.split('.').all(|x| !x.is_empty())
+}
+
+/// Check if a full version (`3.11.4`) matches a requested one (`3`, `3.11`, `3.11.4`)
+fn version_matches(version: &str, requested: &str) -> bool {
+    let version: Vec<_> = version.split('.').collect();
+    let requested: Vec<_> = requested.split('.').collect();
+    requested.len() <= version.len() && requested.iter().zip(&version).all(|(r, v)| r == v)
+}
+
+/// Convert the `--python` option of `dmenv env create` to a binary:
+/// versions like `3.7` are converted to `python3.7`, anything else is
+/// used as is.
+pub fn binary_from_spec(spec: &str) -> String {
+    let is_version = !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit() || c == '.');
+    if is_version {
+        format!("python{}", spec)
+    } else {
+        spec.to_string()
+    }
+}
+
+/// Version of the Python used to create the virtualenv, read from its `pyvenv.cfg`
+/// (None if it can't be found)
+pub fn venv_python_version(venv: &Path) -> Option<String> {
+    let contents = std::fs::read_to_string(venv.join("pyvenv.cfg")).ok()?;
+    parse_pyvenv_version(&contents)
+}
+
+// Note: `venv` writes `version = 3.7.1`, `virtualenv` writes `version_info = 3.7.1.final.0`
+fn parse_pyvenv_version(contents: &str) -> Option<String> {
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next()?.trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => continue,
+        };
+        if key == "version" || key == "version_info" {
+            let components: Vec<_> = value.split('.').take(3).collect();
+            return Some(components.join("."));
+        }
+    }
+    None
 }
 
 /// Look for a suitable Python binary in PATH
@@ -66,3 +297,70 @@ fn get_python_binary(requested_python: &Option<String>) -> Result<PathBuf, Error
         message: "Neither `python3` nor `python` found in PATH".to_string(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pyvenv_version() {
+        let contents = "home = /usr/bin\ninclude-system-site-packages = false\nversion = 3.7.1\n";
+        assert_eq!(parse_pyvenv_version(contents).unwrap(), "3.7.1");
+        let contents = "home = /usr/bin\nversion_info = 3.10.4.final.0\n";
+        assert_eq!(parse_pyvenv_version(contents).unwrap(), "3.10.4");
+        assert!(parse_pyvenv_version("home = /usr/bin\n").is_none());
+    }
+
+    fn python_info(implementation: &str) -> PythonInfo {
+        PythonInfo {
+            binary: PathBuf::from("python3"),
+            version: "3.9.16".to_string(),
+            platform: "Linux".to_string(),
+            implementation: implementation.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_cache_line() {
+        let info = PythonInfo {
+            binary: PathBuf::new(),
+            ..python_info("PyPy")
+        };
+        let key = "/opt/my\tpython/bin/pypy3@1700000000";
+        let line = render_cache_line(key, &info);
+        assert_eq!(parse_cache_line(&line).unwrap(), (key.to_string(), info));
+        assert!(parse_cache_line("3.9.16\tLinux").is_none());
+    }
+
+    #[test]
+    fn test_tag() {
+        assert_eq!(python_info("CPython").tag(), "3.9.16");
+        assert_eq!(python_info("PyPy").tag(), "pypy-3.9.16");
+    }
+
+    #[test]
+    fn test_version_matches() {
+        assert!(version_matches("3.11.4", "3.11"));
+        assert!(version_matches("3.11.4", "3"));
+        assert!(version_matches("3.11.4", "3.11.4"));
+        assert!(!version_matches("3.1.4", "3.11"));
+        assert!(!version_matches("3.11", "3.11.4"));
+    }
+
+    #[test]
+    fn test_is_versioned_binary() {
+        assert!(is_versioned_binary("python3.11"));
+        assert!(is_versioned_binary("python2"));
+        assert!(!is_versioned_binary("python3.7m"));
+        assert!(!is_versioned_binary("python3.7-config"));
+        assert!(!is_versioned_binary("python3."));
+        assert!(!is_versioned_binary("pythonw"));
+    }
+
+    #[test]
+    fn test_binary_from_spec() {
+        assert_eq!(binary_from_spec("3.7"), "python3.7");
+        assert_eq!(binary_from_spec("/usr/bin/python3"), "/usr/bin/python3");
+        assert_eq!(binary_from_spec("pypy3"), "pypy3");
+    }
+}
@@ -0,0 +1,140 @@
+"""Find out how old locked releases are, the latest version of each package, and
+the files of the releases.
+
+Usage: python -c <this script> [--hashes|--files] <name>==<version> ...
+
+Print `ok <name> <age in days> <latest version>` for each package, with `-` for
+what is unknown, or `error <name> <message>`.
+
+With --hashes, print `hash <name> <version> sha256:<hex digest>` for each file
+of the releases instead, followed by the `ok` line. With --files, print
+`file <name> <version> <filename>` lines the same way.
+
+The JSON API of pypi.org is used, unless DMENV_RELEASE_INFO_INDEXES is set (to
+`<patterns>=<url>` entries separated by spaces, like DMENV_INSTALL_WHEELS_INDEXES
+for install_wheels.py). Indexes without a JSON API are skipped.
+"""
+import base64
+import datetime
+import fnmatch
+import json
+import os
+import re
+import sys
+import urllib.parse
+import urllib.request
+
+PYPI_JSON_URL = "https://pypi.org/pypi/{name}/json"
+
+TIMEOUT = 10
+
+# List of (patterns, url), see `index_urls()`
+INDEXES = []
+
+# Maps host names to the Authorization header to use, from index URLs
+# containing credentials
+AUTH_HEADERS = {}
+
+
+def canonical_name(name):
+    return re.sub(r"[-_.]+", "-", name).lower()
+
+
+def parse_index(arg):
+    patterns, url = arg.split("=", 1)
+    parts = urllib.parse.urlsplit(url)
+    if parts.username:
+        user_pass = "{}:{}".format(
+            urllib.parse.unquote(parts.username),
+            urllib.parse.unquote(parts.password or ""),
+        )
+        token = base64.b64encode(user_pass.encode("utf-8")).decode("ascii")
+        AUTH_HEADERS[parts.hostname] = "Basic " + token
+        netloc = parts.netloc.rsplit("@", 1)[1]
+        url = urllib.parse.urlunsplit(parts._replace(netloc=netloc))
+    return [canonical_name(x) for x in patterns.split(",")], url
+
+
+def urlopen(url):
+    request = urllib.request.Request(url)
+    auth_header = AUTH_HEADERS.get(urllib.parse.urlsplit(url).hostname)
+    if auth_header:
+        request.add_header("Authorization", auth_header)
+    return urllib.request.urlopen(request, timeout=TIMEOUT)
+
+
+def json_urls(name):
+    """URLs of the JSON API to query for this package, in order"""
+    if not INDEXES:
+        return [PYPI_JSON_URL.format(name=name)]
+
+    def matches(patterns):
+        return any(fnmatch.fnmatchcase(canonical_name(name), x) for x in patterns)
+
+    urls = [
+        url for patterns, url in INDEXES if "*" not in patterns and matches(patterns)
+    ]
+    if not urls:
+        urls = [url for patterns, url in INDEXES if "*" in patterns]
+    res = []
+    for url in urls:
+        # Indexes compatible with PyPI serve the JSON API next to the simple one
+        base, n = re.subn(r"/simple/?$", "/pypi", url)
+        if n:
+            res.append("{}/{}/json".format(base, name))
+    return res
+
+
+def age_in_days(files):
+    """Days since the first file of a release was uploaded"""
+    times = [x.get("upload_time_iso_8601") or x.get("upload_time") for x in files]
+    times = [x for x in times if x]
+    if not times:
+        return None
+    first = min(times)[:10]
+    uploaded = datetime.datetime.strptime(first, "%Y-%m-%d").date()
+    return (datetime.date.today() - uploaded).days
+
+
+def query_release(name, version):
+    """Files of the release, and latest version of the package"""
+    errors = []
+    for url in json_urls(name):
+        try:
+            with urlopen(url) as response:
+                project = json.loads(response.read().decode("utf-8"))
+        except Exception as e:
+            errors.append("could not query {}: {}".format(url, e))
+            continue
+        files = project.get("releases", {}).get(version, [])
+        latest = project.get("info", {}).get("version")
+        return files, latest
+    raise Exception("; ".join(errors) or "no index with a JSON API")
+
+
+def main():
+    for arg in os.environ.get("DMENV_RELEASE_INFO_INDEXES", "").split():
+        INDEXES.append(parse_index(arg))
+    args = sys.argv[1:]
+    mode = None
+    if args[:1] in (["--hashes"], ["--files"]):
+        mode = args.pop(0)
+    for arg in args:
+        name, version = arg.split("==", 1)
+        try:
+            files, latest = query_release(name, version)
+        except Exception as e:
+            print("error", name, " ".join(str(e).split()))
+            continue
+        for x in files:
+            digest = x.get("digests", {}).get("sha256")
+            if mode == "--hashes" and digest:
+                print("hash", name, version, "sha256:" + digest)
+            if mode == "--files" and x.get("filename"):
+                print("file", name, version, x["filename"])
+        age = age_in_days(files)
+        print("ok", name, "-" if age is None else age, latest or "-")
+
+
+if __name__ == "__main__":
+    main()
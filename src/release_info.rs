@@ -0,0 +1,175 @@
+//! Client for the JSON API of PyPI (and of the package indexes compatible
+//! with it), telling how old locked releases are and which version of each
+//! package is the latest. Used by `dmenv lock stats`, `dmenv lock --check`
+//! and `dmenv outdated`. Also lists the files of each release, with their
+//! hashes, for `dmenv lock --hashes` and `dmenv install --check-wheels`.
+//!
+//! The queries are made by the `release_info.py` script, run with the Python
+//! interpreter of the project.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::runner::{CommandRunner, Process};
+
+/// What the package index knows about a release of a package
+#[derive(Debug, PartialEq)]
+pub struct Release {
+    pub name: String,
+    pub age_days: Option<u64>,
+    pub latest: Option<String>,
+}
+
+/// Ask the package indexes about the releases, using the given Python
+/// interpreter. `indexes` are `<patterns>=<url>` entries, see `Index::to_arg()`.
+/// Packages the indexes could not tell anything about are left out
+pub fn query(
+    runner: &dyn CommandRunner,
+    python: &Path,
+    versions: &[(String, String)],
+    indexes: &[String],
+) -> Result<Vec<Release>, Error> {
+    let output = run_script(runner, python, versions, indexes, None)?;
+    Ok(parse_query_output(&output))
+}
+
+/// Like `query()`, for the sha256 hashes of all the files of the releases
+/// (wheels for every platform, and sdists), like `sha256:0123...`, by
+/// (name, version). Releases the indexes know nothing about are left out
+pub fn query_hashes(
+    runner: &dyn CommandRunner,
+    python: &Path,
+    versions: &[(String, String)],
+    indexes: &[String],
+) -> Result<BTreeMap<(String, String), Vec<String>>, Error> {
+    let output = run_script(runner, python, versions, indexes, Some("--hashes"))?;
+    Ok(parse_values(&output, "hash"))
+}
+
+/// Like `query_hashes()`, for the filenames of the wheels and sdists
+pub fn query_files(
+    runner: &dyn CommandRunner,
+    python: &Path,
+    versions: &[(String, String)],
+    indexes: &[String],
+) -> Result<BTreeMap<(String, String), Vec<String>>, Error> {
+    let output = run_script(runner, python, versions, indexes, Some("--files"))?;
+    Ok(parse_values(&output, "file"))
+}
+
+fn run_script(
+    runner: &dyn CommandRunner,
+    python: &Path,
+    versions: &[(String, String)],
+    indexes: &[String],
+    mode: Option<&str>,
+) -> Result<String, Error> {
+    let script = include_str!("release_info.py");
+    let mut args: Vec<_> = versions
+        .iter()
+        .map(|(name, version)| format!("{}=={}", name, version))
+        .collect();
+    if let Some(mode) = mode {
+        args.insert(0, mode.to_string());
+    }
+    let mut process = Process::new(python).arg("-c").arg(script).args(&args);
+    // Note: the URLs may contain credentials, so they are not given on the command line
+    if !indexes.is_empty() {
+        process = process.env("DMENV_RELEASE_INFO_INDEXES", &indexes.join(" "));
+    }
+    let output = runner.output(&process)?;
+    if !output.success {
+        return Err(Error::Other {
+            message: format!("Failed to run release_info script: {}", output.stderr),
+        });
+    }
+    Ok(output.stdout)
+}
+
+fn parse_query_output(output: &str) -> Vec<Release> {
+    let mut res = vec![];
+    for line in output.lines() {
+        let words: Vec<_> = line.split_whitespace().collect();
+        if let ["ok", name, age, latest] = words.as_slice() {
+            res.push(Release {
+                name: name.to_string(),
+                age_days: age.parse().ok(),
+                latest: if *latest == "-" {
+                    None
+                } else {
+                    Some(latest.to_string())
+                },
+            });
+        }
+    }
+    res
+}
+
+/// Values of the `<kind> <name> <version> <value>` lines, by (name, version)
+fn parse_values(output: &str, kind: &str) -> BTreeMap<(String, String), Vec<String>> {
+    let mut res: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for line in output.lines() {
+        let words: Vec<_> = line.split_whitespace().collect();
+        if let [line_kind, name, version, value] = words.as_slice() {
+            if *line_kind != kind {
+                continue;
+            }
+            let values = res
+                .entry((name.to_string(), version.to_string()))
+                .or_default();
+            values.push(value.to_string());
+        }
+    }
+    for values in res.values_mut() {
+        values.sort();
+        values.dedup();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query_output() {
+        let output = "ok attrs 1700 23.2.0\nok foo - -\nerror bar HTTP Error 404\n";
+        assert_eq!(
+            parse_query_output(output),
+            vec![
+                Release {
+                    name: "attrs".to_string(),
+                    age_days: Some(1700),
+                    latest: Some("23.2.0".to_string())
+                },
+                Release {
+                    name: "foo".to_string(),
+                    age_days: None,
+                    latest: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let output = "\
+hash attrs 19.1.0 sha256:f0b8
+hash attrs 19.1.0 sha256:0123
+file attrs 19.1.0 attrs-19.1.0.tar.gz
+ok attrs 1700 23.2.0
+error bar HTTP Error 404
+";
+        let hashes = parse_values(output, "hash");
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(
+            hashes[&("attrs".to_string(), "19.1.0".to_string())],
+            vec!["sha256:0123", "sha256:f0b8"]
+        );
+        let files = parse_values(output, "file");
+        assert_eq!(
+            files[&("attrs".to_string(), "19.1.0".to_string())],
+            vec!["attrs-19.1.0.tar.gz"]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Name of the file describing where a virtualenv was built, written
+/// inside the virtualenv when taking a snapshot
+pub const RELOCATION_FILENAME: &str = "dmenv-relocation";
+
+/// Where and for what a virtualenv was built. Used to check a snapshot can be
+/// restored, and to fix absolute paths when restoring it somewhere else.
+#[derive(Debug, PartialEq)]
+pub struct Relocation {
+    pub venv_path: String,
+    pub python_version: String,
+    pub platform: String,
+}
+
+impl fmt::Display for Relocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "venv_path = {}\npython_version = {}\nplatform = {}\n",
+            self.venv_path, self.python_version, self.platform
+        )
+    }
+}
+
+impl Relocation {
+    pub fn from_string(string: &str) -> Result<Self, Error> {
+        let mut values = BTreeMap::new();
+        for line in string.lines() {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or_default().trim();
+            if let Some(value) = parts.next() {
+                values.insert(key.to_string(), value.trim().to_string());
+            }
+        }
+        let get = |key: &str| {
+            values.get(key).cloned().ok_or_else(|| Error::Other {
+                message: format!("missing '{}' in {}", key, RELOCATION_FILENAME),
+            })
+        };
+        Ok(Relocation {
+            venv_path: get("venv_path")?,
+            python_version: get("python_version")?,
+            platform: get("platform")?,
+        })
+    }
+}
+
+/// Replace the old path of the virtualenv with the new one, if present.
+/// Returns None if there was nothing to replace.
+// Note: this fixes the shebangs of the scripts, but also the
+// `activate` scripts
+pub fn relocate_contents(contents: &str, old_path: &str, new_path: &str) -> Option<String> {
+    if !contents.contains(old_path) {
+        return None;
+    }
+    Some(contents.replace(old_path, new_path))
+}
+
+/// Fix absolute paths in the files of the binaries directory of a virtualenv.
+/// Returns the number of files changed.
+//
+// Note: binary files and symlinks (like `bin/python`) are left alone
+pub fn relocate_bin_dir(bin_path: &Path, old_path: &str, new_path: &str) -> Result<usize, Error> {
+    let entries = std::fs::read_dir(bin_path).map_err(|e| Error::ReadError {
+        path: bin_path.to_path_buf(),
+        io_error: e,
+    })?;
+    let mut res = 0;
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if !metadata.file_type().is_file() {
+            continue;
+        }
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        if let Some(new_contents) = relocate_contents(&contents, old_path, new_path) {
+            std::fs::write(&path, new_contents).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            res += 1;
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let relocation = Relocation {
+            venv_path: "/home/jane/demo/.venv/dev/3.7.1".to_string(),
+            python_version: "3.7.1".to_string(),
+            platform: "linux".to_string(),
+        };
+        let actual = Relocation::from_string(&relocation.to_string()).unwrap();
+        assert_eq!(actual, relocation);
+    }
+
+    #[test]
+    fn test_missing_key() {
+        Relocation::from_string("venv_path = /tmp\n").unwrap_err();
+    }
+
+    #[test]
+    fn test_relocate_contents() {
+        let script = "#!/old/venv/bin/python\nimport sys\n";
+        let actual = relocate_contents(script, "/old/venv", "/srv/venv").unwrap();
+        assert_eq!(actual, "#!/srv/venv/bin/python\nimport sys\n");
+        assert!(relocate_contents(&actual, "/old/venv", "/srv/venv").is_none());
+    }
+}
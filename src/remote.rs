@@ -0,0 +1,93 @@
+/// A machine reachable over SSH, as used by `dmenv remote`
+//
+// Note: we just run the `ssh` and `scp` binaries, so that the user's
+// SSH config (keys, agents, jump hosts ...) is used.
+pub struct Remote {
+    /// `user@server`, or anything `ssh` accepts
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl Remote {
+    /// Arguments for `ssh` to run the given shell command on the remote host
+    pub fn ssh_args(&self, command: &str) -> Vec<String> {
+        let mut res = vec![];
+        if let Some(port) = self.port {
+            res.push("-p".to_string());
+            res.push(port.to_string());
+        }
+        res.push(self.host.clone());
+        res.push(command.to_string());
+        res
+    }
+
+    /// Arguments for `scp` to copy a local file to the remote host
+    pub fn scp_args(&self, local_path: &str, remote_path: &str) -> Vec<String> {
+        let mut res = vec![];
+        // Note: scp uses -P, not -p
+        if let Some(port) = self.port {
+            res.push("-P".to_string());
+            res.push(port.to_string());
+        }
+        res.push(local_path.to_string());
+        res.push(format!("{}:{}", self.host, remote_path));
+        res
+    }
+}
+
+/// Shell command to create the virtualenv with the given Python binary,
+/// unless it already exists
+pub fn ensure_venv_command(python_binary: &str, venv_path: &str) -> String {
+    let python_binary = quote(python_binary);
+    let venv_path = quote(venv_path);
+    format!(
+        "test -x {venv}/bin/python || {python} -m venv {venv}",
+        python = python_binary,
+        venv = venv_path,
+    )
+}
+
+/// Quote a string for a POSIX shell
+pub fn quote(string: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:@+".contains(c);
+    if !string.is_empty() && string.chars().all(is_safe) {
+        return string.to_string();
+    }
+    format!("'{}'", string.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_args() {
+        let remote = Remote {
+            host: "jane@example.com".to_string(),
+            port: Some(2222),
+        };
+        assert_eq!(
+            remote.ssh_args("ls"),
+            vec!["-p", "2222", "jane@example.com", "ls"]
+        );
+        assert_eq!(
+            remote.scp_args("foo.lock", "app/foo.lock"),
+            vec!["-P", "2222", "foo.lock", "jane@example.com:app/foo.lock"]
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("dmenv/demo"), "dmenv/demo");
+        assert_eq!(quote("my app"), "'my app'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_ensure_venv_command() {
+        assert_eq!(
+            ensure_venv_command("python3.7", "app/.venv/dev/3.7.1"),
+            "test -x app/.venv/dev/3.7.1/bin/python || python3.7 -m venv app/.venv/dev/3.7.1"
+        );
+    }
+}
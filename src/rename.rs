@@ -0,0 +1,105 @@
+use crate::error::Error;
+
+/// Update setup.py or pyproject.toml contents after renaming the project:
+/// the `name` of the project, and the references to its top-level package
+/// (`packages`, `py_modules`, entry points ...)
+//
+// Note: like for `dmenv version`, we don't parse the file, so this only works
+// when the name is a literal string.
+pub fn rename_in_metadata(
+    contents: &str,
+    old_name: &str,
+    new_name: &str,
+    old_package: &str,
+    new_package: &str,
+) -> Result<String, Error> {
+    let (start, end, replacement) =
+        find_name(contents, old_name, new_name).ok_or_else(|| Error::Other {
+            message: format!("name '{}' not found. Is it computed in setup.py?", old_name),
+        })?;
+    Ok(format!(
+        "{}{}{}",
+        rename_package(&contents[..start], old_package, new_package),
+        replacement,
+        rename_package(&contents[end..], old_package, new_package)
+    ))
+}
+
+/// Find `name="old"` (setup.py) or `name = "old"` (pyproject.toml).
+/// Return where it starts and ends, and what to replace it with
+fn find_name(contents: &str, old: &str, new: &str) -> Option<(usize, usize, String)> {
+    for separator in &["=", " = "] {
+        for quote in &['"', '\''] {
+            let needle = format!("name{}{}{}{}", separator, quote, old, quote);
+            for (start, _) in contents.match_indices(&needle) {
+                // Skip things like `package_name="old"`
+                let before = contents[..start].chars().last();
+                if before.map_or(false, |c| c.is_alphanumeric() || c == '_') {
+                    continue;
+                }
+                let replacement = format!("name{}{}{}{}", separator, quote, new, quote);
+                return Some((start, start + needle.len(), replacement));
+            }
+        }
+    }
+    None
+}
+
+/// Replace `old` when it is used as a package or module name, like in
+/// `"old"`, `"old.sub"` or `cli = old.main:run`
+fn rename_package(line: &str, old: &str, new: &str) -> String {
+    let mut res = line.to_string();
+    for prefix in &["\"", "'", "= ", "="] {
+        for suffix in &["\"", "'", ".", ":"] {
+            let needle = format!("{}{}{}", prefix, old, suffix);
+            let replacement = format!("{}{}{}", prefix, new, suffix);
+            res = res.replace(&needle, &replacement);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_in_metadata_setup_py() {
+        let setup_py = r#"
+setup(
+    name="foo-cli",
+    packages=["foo_cli", "foo_cli.sub"],
+    install_requires=["foo_cli_utils"],
+    entry_points={"console_scripts": ["foo = foo_cli.main:run"]},
+)
+"#;
+        let actual = rename_in_metadata(setup_py, "foo-cli", "bar", "foo_cli", "bar").unwrap();
+        assert!(actual.contains("name=\"bar\""));
+        assert!(actual.contains("packages=[\"bar\", \"bar.sub\"]"));
+        assert!(actual.contains("\"foo_cli_utils\""));
+        assert!(actual.contains("\"foo = bar.main:run\""));
+    }
+
+    #[test]
+    fn test_rename_in_metadata_pyproject() {
+        let pyproject = "[project]\nname = \"foo\"\n\n[project.scripts]\nfoo = \"foo:main\"\n";
+        let actual = rename_in_metadata(pyproject, "foo", "bar", "foo", "bar").unwrap();
+        assert_eq!(
+            actual,
+            "[project]\nname = \"bar\"\n\n[project.scripts]\nfoo = \"bar:main\"\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_in_metadata_one_line() {
+        let setup_py = "setup(name='foo', py_modules=['foo'])\n";
+        let actual = rename_in_metadata(setup_py, "foo", "Bar", "foo", "bar").unwrap();
+        assert_eq!(actual, "setup(name='Bar', py_modules=['bar'])\n");
+    }
+
+    #[test]
+    fn test_rename_in_metadata_name_not_found() {
+        let setup_py = "setup(name=NAME)\n";
+        rename_in_metadata(setup_py, "foo", "bar", "foo", "bar").unwrap_err();
+    }
+}
@@ -0,0 +1,96 @@
+//! Version of dmenv required by a project, so that everyone in the team
+//! writes the lock the same way. It is read from `pyproject.toml`:
+//!
+//! ```text
+//! [tool.dmenv]
+//! required_version = ">=0.14,<0.16"
+//! ```
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::marker::compare_versions;
+use crate::pyproject;
+
+/// Fail if the project requires another version of dmenv
+pub fn check(project_path: &Path) -> Result<(), Error> {
+    let path = project_path.join("pyproject.toml");
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(()),
+    };
+    let required = match pyproject::dmenv_value(&contents, "required_version") {
+        Some(required) => required,
+        None => return Ok(()),
+    };
+    let current = env!("CARGO_PKG_VERSION");
+    let matching = matches(&required, current).map_err(|e| Error::Other {
+        message: format!("Invalid required_version in {}: {}", path.display(), e),
+    })?;
+    if !matching {
+        return Err(Error::UnsupportedDmenvVersion {
+            required,
+            current: current.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// True if `version` matches every clause of `spec`, like `>=0.14,<0.16`.
+/// Supports the operators of PEP 440, and `==0.14.*`
+pub fn matches(spec: &str, version: &str) -> Result<bool, String> {
+    let mut res = true;
+    for clause in spec.split(',') {
+        let clause = clause.trim();
+        let op_len = clause
+            .find(|c: char| c.is_ascii_digit())
+            .ok_or_else(|| format!("no version in '{}'", clause))?;
+        let (op, expected) = clause.split_at(op_len);
+        res &= match op.trim() {
+            "==" => match expected.strip_suffix(".*") {
+                Some(prefix) => is_prefix(prefix, version),
+                None => compare_versions(version, expected) == Ordering::Equal,
+            },
+            "!=" => compare_versions(version, expected) != Ordering::Equal,
+            ">=" => compare_versions(version, expected) != Ordering::Less,
+            "<=" => compare_versions(version, expected) != Ordering::Greater,
+            ">" => compare_versions(version, expected) == Ordering::Greater,
+            "<" => compare_versions(version, expected) == Ordering::Less,
+            "~=" => {
+                let dot = expected
+                    .rfind('.')
+                    .ok_or_else(|| format!("'{}' needs at least two components", clause))?;
+                let prefix = &expected[..dot];
+                compare_versions(version, expected) != Ordering::Less && is_prefix(prefix, version)
+            }
+            _ => return Err(format!("unknown operator in '{}'", clause)),
+        };
+    }
+    Ok(res)
+}
+
+/// True if the first components of `version` are the ones of `prefix`
+fn is_prefix(prefix: &str, version: &str) -> bool {
+    let len = prefix.split('.').count();
+    let start: Vec<_> = version.split('.').take(len).collect();
+    compare_versions(&start.join("."), prefix) == Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(">=0.14,<0.16", "0.15.2").unwrap());
+        assert!(!matches(">=0.14,<0.16", "0.16.0").unwrap());
+        assert!(!matches(">=0.14, <0.16", "0.12.0").unwrap());
+        assert!(matches("==0.12.*", "0.12.3").unwrap());
+        assert!(!matches("==0.12.*", "0.13.0").unwrap());
+        assert!(matches("~=0.12.1", "0.12.4").unwrap());
+        assert!(!matches("~=0.12.1", "0.13.0").unwrap());
+        assert!(matches("!=0.12.0", "0.12.1").unwrap());
+        assert!(matches("0.12", "0.12.0").is_err());
+        assert!(matches(">=", "0.12.0").is_err());
+    }
+}
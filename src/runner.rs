@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::events;
+use crate::logs;
+use crate::tty;
+
+/// A process started by dmenv (Python, pip, or one of the embedded scripts),
+/// see `CommandRunner`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Process {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub dir: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// When set, stdout and stderr go to this file instead of the terminal
+    pub log: Option<PathBuf>,
+    /// Whether the process is run for the user, and may read from the
+    /// terminal, see the `tty` module
+    pub interactive: bool,
+}
+
+impl Process {
+    pub fn new(program: &Path) -> Self {
+        Process {
+            program: program.to_path_buf(),
+            args: vec![],
+            dir: None,
+            env: vec![],
+            log: None,
+            interactive: false,
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args<S: AsRef<str>>(mut self, args: &[S]) -> Self {
+        self.args
+            .extend(args.iter().map(|x| x.as_ref().to_string()));
+        self
+    }
+
+    pub fn dir(mut self, dir: &Path) -> Self {
+        self.dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn log(mut self, log: Option<PathBuf>) -> Self {
+        self.log = log;
+        self
+    }
+
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+}
+
+/// Output of a process run with `CommandRunner::output()`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the Python processes needed by dmenv. The default implementation,
+/// `SystemRunner`, actually starts them. Other implementations can simulate
+/// them, see the `testing` module.
+pub trait CommandRunner {
+    /// Run the process, letting it write to the terminal (or to its log).
+    /// Returns whether it succeeded
+    fn status(&self, process: &Process) -> Result<bool, Error>;
+
+    /// Run the process, capturing its output
+    fn output(&self, process: &Process) -> Result<ProcessOutput, Error>;
+}
+
+/// Environment of processes whose output dmenv parses: messages in English,
+/// and output encoded in UTF-8 whatever the locale of the user. Set
+/// `DMENV_KEEP_LOCALE` to keep the user's locale instead
+const PARSING_ENV: &[(&str, &str)] = &[("LC_ALL", "C"), ("PYTHONIOENCODING", "utf-8")];
+
+pub struct SystemRunner;
+
+impl SystemRunner {
+    fn command(process: &Process, parsing: bool) -> Result<std::process::Command, Error> {
+        let mut command = std::process::Command::new(&process.program);
+        command.args(&process.args);
+        if let Some(dir) = &process.dir {
+            command.current_dir(dir);
+        }
+        if parsing && std::env::var("DMENV_KEEP_LOCALE").is_err() {
+            for (key, value) in PARSING_ENV {
+                command.env(key, value);
+            }
+        }
+        // Note: variables set on the process win over the ones above
+        for (key, value) in &process.env {
+            command.env(key, value);
+        }
+        if let Some(log) = &process.log {
+            let file = logs::open(log)?;
+            let file_err = file.try_clone().map_err(|e| Error::WriteError {
+                path: log.to_path_buf(),
+                io_error: e,
+            })?;
+            command.stdout(file).stderr(file_err);
+        }
+        Ok(command)
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn status(&self, process: &Process) -> Result<bool, Error> {
+        let mut command = Self::command(process, false)?;
+        let _guard = if process.interactive {
+            Some(tty::Guard::new())
+        } else {
+            None
+        };
+        // Note: stdout is reserved for events with `--event-stream stdout`,
+        // so forward the output of the process to stderr instead
+        if !events::on_stdout() || process.log.is_some() {
+            let status = command
+                .status()
+                .map_err(|e| Error::ProcessWaitError { io_error: e })?;
+            return Ok(status.success());
+        }
+        // Interactive programs get stderr itself, so that they still write to
+        // a terminal
+        #[cfg(unix)]
+        {
+            if process.interactive {
+                use std::os::unix::io::FromRawFd;
+                let stderr = unsafe { libc::dup(2) };
+                if stderr != -1 {
+                    let status = command
+                        .stdout(unsafe { std::process::Stdio::from_raw_fd(stderr) })
+                        .status()
+                        .map_err(|e| Error::ProcessWaitError { io_error: e })?;
+                    return Ok(status.success());
+                }
+            }
+        }
+        let mut child = command
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::ProcessWaitError { io_error: e })?;
+        if let Some(mut stdout) = child.stdout.take() {
+            std::io::copy(&mut stdout, &mut std::io::stderr())
+                .map_err(|e| Error::ProcessWaitError { io_error: e })?;
+        }
+        let status = child
+            .wait()
+            .map_err(|e| Error::ProcessWaitError { io_error: e })?;
+        Ok(status.success())
+    }
+
+    fn output(&self, process: &Process) -> Result<ProcessOutput, Error> {
+        let output = Self::command(process, true)?
+            .output()
+            .map_err(|e| Error::ProcessOutError { io_error: e })?;
+        Ok(ProcessOutput {
+            success: output.status.success(),
+            stdout: decode(&output.stdout),
+            stderr: decode(&output.stderr),
+        })
+    }
+}
+
+/// Decode the output of a process. It should be UTF-8, but a byte order mark,
+/// Windows line endings or bytes in a legacy encoding must not break parsing:
+/// the former are removed, and the latter replaced by U+FFFD
+fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).replace("\r\n", "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"3.7.1\r\nLinux\r\n"), "3.7.1\nLinux\n");
+        assert_eq!(decode(b"\xef\xbb\xbffoo==0.42\n"), "foo==0.42\n");
+        assert_eq!(
+            decode(b"-e file:///home/j\xe9r\xf4me/foo\n"),
+            "-e file:///home/j\u{fffd}r\u{fffd}me/foo\n"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::template::{self, Context};
+
+/// Everything needed to generate a new project, as asked by `dmenv init --interactive`
+pub struct Scaffold {
+    pub name: String,
+    pub version: String,
+    pub author: Option<String>,
+    /// Like `["3.6", "3.7"]`
+    pub python_versions: Vec<String>,
+    pub license: Option<String>,
+    /// Put the code in `src/<package>` instead of `<package>`
+    pub src_layout: bool,
+    /// Generate a `pyproject.toml` (PEP 621) instead of a `setup.py`
+    pub pyproject: bool,
+}
+
+impl Scaffold {
+    /// Name of the top-level Python package
+    pub fn package_name(&self) -> String {
+        package_name(&self.name)
+    }
+
+    /// Files to write, relative to the project path
+    pub fn files(&self, templates: &[PathBuf]) -> Result<Vec<(PathBuf, String)>, Error> {
+        let mut res = vec![];
+        if self.pyproject {
+            let contents = self.render_pyproject(templates)?;
+            res.push((PathBuf::from("pyproject.toml"), contents));
+        } else {
+            let contents = self.render_setup_py(templates)?;
+            res.push((PathBuf::from("setup.py"), contents));
+        }
+        let mut package_path = PathBuf::new();
+        if self.src_layout {
+            package_path.push("src");
+        }
+        package_path.push(self.package_name());
+        res.push((package_path.join("__init__.py"), String::new()));
+        Ok(res)
+    }
+
+    fn python_requires(&self) -> Option<String> {
+        self.python_versions.first().map(|x| format!(">={}", x))
+    }
+
+    fn classifiers(&self) -> Vec<String> {
+        let mut res: Vec<_> = self
+            .python_versions
+            .iter()
+            .map(|x| format!("Programming Language :: Python :: {}", x))
+            .collect();
+        if let Some(classifier) = self.license.as_ref().and_then(|x| license_classifier(x)) {
+            res.push(classifier.to_string());
+        }
+        res
+    }
+
+    fn context(&self) -> Context {
+        Context::new()
+            .text("name", &self.name)
+            .text("version", &self.version)
+            .text("author", self.author.as_deref().unwrap_or_default())
+            .text("license", self.license.as_deref().unwrap_or_default())
+            .text(
+                "python_requires",
+                &self.python_requires().unwrap_or_default(),
+            )
+            .bool("src_layout", self.src_layout)
+            .list("extras", &["dev".to_string()])
+            .list("classifiers", &self.classifiers())
+    }
+
+    pub fn render_setup_py(&self, templates: &[PathBuf]) -> Result<String, Error> {
+        template::render("setup.py", templates, &self.context())
+    }
+
+    pub fn render_pyproject(&self, templates: &[PathBuf]) -> Result<String, Error> {
+        template::render("pyproject.toml", templates, &self.context())
+    }
+}
+
+/// Name of the top-level Python package of a project, like `my_project` for `My-Project`
+pub fn package_name(project_name: &str) -> String {
+    project_name
+        .to_lowercase()
+        .replace('-', "_")
+        .replace('.', "_")
+}
+
+/// Parse a list of Python versions like `3.6, 3.7`
+pub fn parse_python_versions(string: &str) -> Vec<String> {
+    string
+        .split(|c| c == ',' || c == ' ')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn license_classifier(license: &str) -> Option<&'static str> {
+    let res = match license {
+        "MIT" => "License :: OSI Approved :: MIT License",
+        "BSD-3-Clause" => "License :: OSI Approved :: BSD License",
+        "Apache-2.0" => "License :: OSI Approved :: Apache Software License",
+        "GPL-3.0" => "License :: OSI Approved :: GNU General Public License v3 (GPLv3)",
+        _ => return None,
+    };
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn scaffold() -> Scaffold {
+        Scaffold {
+            name: "my-project".to_string(),
+            version: "0.1.0".to_string(),
+            author: Some("Jane Doe".to_string()),
+            python_versions: vec!["3.6".to_string(), "3.7".to_string()],
+            license: Some("MIT".to_string()),
+            src_layout: true,
+            pyproject: false,
+        }
+    }
+
+    #[test]
+    fn test_files() {
+        let files = scaffold().files(&[]).unwrap();
+        assert_eq!(files[0].0, PathBuf::from("setup.py"));
+        assert_eq!(
+            files[1].0,
+            Path::new("src").join("my_project").join("__init__.py")
+        );
+    }
+
+    #[test]
+    fn test_render_setup_py() {
+        let actual = scaffold().render_setup_py(&[]).unwrap();
+        assert!(actual.contains("    package_dir={\"\": \"src\"},\n"));
+        assert!(actual.contains("    python_requires=\">=3.6\",\n"));
+        assert!(actual.contains("\"Programming Language :: Python :: 3.7\",\n"));
+        assert!(actual.contains("\"License :: OSI Approved :: MIT License\",\n"));
+    }
+
+    #[test]
+    fn test_render_pyproject() {
+        let mut scaffold = scaffold();
+        scaffold.pyproject = true;
+        scaffold.src_layout = false;
+        let actual = scaffold.render_pyproject(&[]).unwrap();
+        assert!(actual.contains("authors = [{ name = \"Jane Doe\" }]\n"));
+        assert!(actual.contains("[project.optional-dependencies]\n"));
+        assert!(!actual.contains("[tool.setuptools.packages.find]"));
+    }
+
+    #[test]
+    fn test_parse_python_versions() {
+        assert_eq!(
+            parse_python_versions("3.6, 3.7 3.8"),
+            vec!["3.6", "3.7", "3.8"]
+        );
+        assert!(parse_python_versions("").is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::hash;
+use crate::paths::get_cache_path;
+
+/// Name of the file written in script virtualenvs once all the dependencies are installed
+pub const SCRIPT_READY_FILENAME: &str = "dmenv-script-ready";
+
+/// Inline metadata of a single-file script, as described in PEP 723:
+///
+/// ```text
+/// # /// script
+/// # requires-python = ">=3.8"
+/// # dependencies = [
+/// #   "requests<3",
+/// # ]
+/// # ///
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct ScriptMetadata {
+    pub dependencies: Vec<String>,
+    pub requires_python: Option<String>,
+}
+
+impl ScriptMetadata {
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        Self::from_string(&contents)
+    }
+
+    /// Returns default (empty) metadata if there is no `script` block
+    pub fn from_string(contents: &str) -> Result<Self, Error> {
+        let toml = match script_block(contents) {
+            Some(toml) => toml,
+            None => return Ok(ScriptMetadata::default()),
+        };
+        let mut res = ScriptMetadata::default();
+        if let Some(value) = toml_value(&toml, "dependencies") {
+            res.dependencies = parse_string_array(value).ok_or_else(|| Error::Other {
+                message: "`dependencies` in script metadata should be an array of strings"
+                    .to_string(),
+            })?;
+        }
+        if let Some(value) = toml_value(&toml, "requires-python") {
+            let value = value.lines().next().unwrap_or_default().trim();
+            res.requires_python = Some(value.trim_matches(|c| c == '"' || c == '\'').to_string());
+        }
+        Ok(res)
+    }
+
+    /// Where to put the virtualenv of the script: in the dmenv cache, so that
+    /// scripts with the same dependencies share it
+    pub fn venv_path(&self, python_version: &str) -> Result<PathBuf, Error> {
+        let mut dependencies = self.dependencies.clone();
+        dependencies.sort();
+        let data = format!(
+            "{}\npython_version = {}\n",
+            dependencies.join("\n"),
+            python_version
+        );
+        let key = hash::hash_hex(data.as_bytes());
+        Ok(get_cache_path("scripts")?.join(key))
+    }
+}
+
+/// Get the TOML contents of the `# /// script` block, without the leading `#`
+fn script_block(contents: &str) -> Option<String> {
+    let mut lines = contents
+        .lines()
+        .skip_while(|x| x.trim_end() != "# /// script");
+    lines.next()?;
+    let mut res = String::new();
+    for line in lines {
+        let line = line.trim_end();
+        if line == "# ///" {
+            return Some(res);
+        }
+        if line == "#" {
+            res.push('\n');
+        } else if line.starts_with("# ") {
+            res.push_str(&line[2..]);
+            res.push('\n');
+        } else {
+            // Not a comment anymore: the block was not closed
+            return None;
+        }
+    }
+    None
+}
+
+/// Return everything after `key =`, until the end of the TOML contents
+fn toml_value<'a>(toml: &'a str, key: &str) -> Option<&'a str> {
+    let mut offset = 0;
+    for line in toml.lines() {
+        let mut parts = line.splitn(2, '=');
+        if parts.next().map(|x| x.trim()) == Some(key) {
+            let value_start = offset + line.find('=')? + 1;
+            return Some(toml[value_start..].trim_start());
+        }
+        offset += line.len() + 1;
+    }
+    None
+}
+
+/// Parse an array of strings like `["foo", 'bar[baz]']`, possibly
+/// spanning several lines and with a trailing comma
+fn parse_string_array(value: &str) -> Option<Vec<String>> {
+    let mut chars = value.chars();
+    if chars.next()? != '[' {
+        return None;
+    }
+    let mut res = vec![];
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => return Some(res),
+            '"' | '\'' => {
+                let string: String = chars.by_ref().take_while(|x| *x != c).collect();
+                res.push(string);
+            }
+            '#' => {
+                // Comment until the end of the line
+                chars.by_ref().take_while(|x| *x != '\n').for_each(drop);
+            }
+            ',' => (),
+            c if c.is_whitespace() => (),
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"#!/usr/bin/env python
+# /// script
+# requires-python = ">=3.8"
+# dependencies = [
+#   "requests<3",
+#   'rich[jupyter]',  # for pretty output
+# ]
+# ///
+
+import requests
+"#;
+
+    #[test]
+    fn test_from_string() {
+        let metadata = ScriptMetadata::from_string(SCRIPT).unwrap();
+        assert_eq!(metadata.dependencies, vec!["requests<3", "rich[jupyter]"]);
+        assert_eq!(metadata.requires_python.unwrap(), ">=3.8");
+    }
+
+    #[test]
+    fn test_no_metadata() {
+        let metadata = ScriptMetadata::from_string("import sys\n").unwrap();
+        assert!(metadata.dependencies.is_empty());
+        assert!(metadata.requires_python.is_none());
+    }
+
+    #[test]
+    fn test_unclosed_block() {
+        let script = "# /// script\n# dependencies = [\"requests\"]\nimport requests\n";
+        let metadata = ScriptMetadata::from_string(script).unwrap();
+        assert!(metadata.dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_dependencies() {
+        let script = "# /// script\n# dependencies = \"requests\"\n# ///\n";
+        ScriptMetadata::from_string(script).unwrap_err();
+    }
+}
@@ -0,0 +1,34 @@
+""" Regenerate the console-script (and gui-script) wrappers listed in an
+entry_points.txt file, like `pip install` does.
+
+Usage:
+    scripts.py <entry_points.txt> <bin_dir>
+
+Prints the paths of the generated wrappers.
+"""
+import configparser
+import sys
+
+from pip._vendor.distlib.scripts import ScriptMaker
+
+
+def main():
+    entry_points_path, bin_dir = sys.argv[1:]
+    parser = configparser.ConfigParser(delimiters=("=",))
+    parser.optionxform = str
+    parser.read(entry_points_path)
+
+    maker = ScriptMaker(None, bin_dir)
+    maker.clobber = True
+    maker.variants = {""}
+    for section, gui in (("console_scripts", False), ("gui_scripts", True)):
+        if not parser.has_section(section):
+            continue
+        for name, value in parser.items(section):
+            spec = "%s = %s" % (name, value.strip())
+            for path in maker.make(spec, options={"gui": gui}):
+                print(path)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use crate::env_file::DEFAULT_PATH;
+use crate::error::Error;
+use crate::template::{self, Context};
+
+/// Formats supported by `dmenv service generate`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServiceFormat {
+    Systemd,
+    Supervisor,
+}
+
+impl std::str::FromStr for ServiceFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "systemd" => Ok(ServiceFormat::Systemd),
+            "supervisor" => Ok(ServiceFormat::Supervisor),
+            _ => Err(Error::Other {
+                message: format!("unknown format '{}', expected 'systemd' or 'supervisor'", s),
+            }),
+        }
+    }
+}
+
+/// Everything needed to run one of the virtualenv's scripts as a service
+pub struct Service<'a> {
+    pub name: &'a str,
+    pub project: &'a Path,
+    pub venv: &'a Path,
+    pub bin_path: &'a Path,
+    pub script: &'a Path,
+}
+
+impl<'a> Service<'a> {
+    pub fn render(&self, format: ServiceFormat, templates: &[PathBuf]) -> Result<String, Error> {
+        let name = match format {
+            ServiceFormat::Systemd => "systemd.service",
+            ServiceFormat::Supervisor => "supervisor.conf",
+        };
+        let context = Context::new()
+            .text("name", self.name)
+            .text("project", &self.project.display().to_string())
+            .text("venv", &self.venv.display().to_string())
+            .text("bin_path", &self.bin_path.display().to_string())
+            .text("default_path", DEFAULT_PATH)
+            .text("script", &self.script.display().to_string());
+        template::render(name, templates, &context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service<'static> {
+        Service {
+            name: "demo",
+            project: Path::new("/srv/demo"),
+            venv: Path::new("/srv/demo/.venv"),
+            bin_path: Path::new("/srv/demo/.venv/bin"),
+            script: Path::new("/srv/demo/.venv/bin/demo"),
+        }
+    }
+
+    #[test]
+    fn test_systemd() {
+        let actual = service().render(ServiceFormat::Systemd, &[]).unwrap();
+        assert!(actual.contains("ExecStart=/srv/demo/.venv/bin/demo\n"));
+        assert!(actual.contains("WorkingDirectory=/srv/demo\n"));
+    }
+
+    #[test]
+    fn test_supervisor() {
+        let actual = service().render(ServiceFormat::Supervisor, &[]).unwrap();
+        assert!(actual.contains("[program:demo]\n"));
+        assert!(actual.contains("command=/srv/demo/.venv/bin/demo\n"));
+    }
+}
@@ -1,4 +1,41 @@
-use crate::cmd::Command;
+use std::path::PathBuf;
+
+use crate::cmd::{print_warning, Command};
+use crate::error::Error;
+use crate::indexes::{self, Index};
+use crate::lock::Newline;
+use crate::lock_hashes::Algorithm;
+use crate::overrides::Override;
+use crate::platform_deps::PlatformDependency;
+use crate::policy::{FreshnessPolicy, PackagePolicy};
+use crate::presets::Preset;
+
+/// Kind of project managed by dmenv
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectType {
+    /// An installable package, with a setup.py or a pyproject.toml
+    Package,
+    /// A list of requirements in `requirements.in`, like
+    /// deployment scripts or data-science notebooks
+    Requirements,
+}
+
+impl std::str::FromStr for ProjectType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "package" => Ok(ProjectType::Package),
+            "requirements" => Ok(ProjectType::Requirements),
+            _ => Err(Error::Other {
+                message: format!(
+                    "unknown project type '{}', expected 'package' or 'requirements'",
+                    s
+                ),
+            }),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 /// Represent variables that change behavior of
@@ -8,6 +45,67 @@ pub struct Settings {
     pub venv_outside_project: bool,
     pub system_site_packages: bool,
     pub production: bool,
+    pub shared_store: bool,
+    pub detect_changes: bool,
+    /// Where to put virtualenvs shared by all the users of the machine
+    pub shared_venv_root: Option<PathBuf>,
+    /// Directory containing setup.py, relative to the project
+    pub package_dir: Option<PathBuf>,
+    /// Other directories containing a setup.py, relative to the project. Those packages
+    /// are also installed in development mode in the virtualenv
+    pub extra_package_dirs: Vec<PathBuf>,
+    pub project_type: ProjectType,
+    /// Where `dmenv tool install` exposes the commands of the tools
+    pub tools_bin_dir: Option<PathBuf>,
+    /// Templates overriding the ones used to generate files, see the `template` module
+    pub template_dir: Option<PathBuf>,
+    /// Commands run by `dmenv fmt`, `dmenv fmt --check` and `dmenv lint`
+    pub format_cmd: Vec<String>,
+    pub format_check_cmd: Vec<String>,
+    pub lint_cmd: Vec<String>,
+    /// Coverage configuration file, relative to the project. When set, `dmenv run`
+    /// measures coverage in sub-processes too
+    pub coverage_process_start: Option<PathBuf>,
+    /// URL of the package index used by pip, when it is set in the environment
+    pub index_url: Option<String>,
+    /// Command printing credentials for `index_url`
+    pub credential_helper: Vec<String>,
+    /// Ordered list of package indexes, see `indexes::parse()`
+    pub indexes: Vec<Index>,
+    /// Name of the variant whose indexes are used, see the `variants` module
+    pub variant: Option<String>,
+    /// Where pip looks for packages, besides the indexes, see the `find_links` module
+    pub find_links: Vec<String>,
+    /// Installed instead of what the project asks for, see the `overrides` module
+    pub overrides: Vec<Override>,
+    /// Development dependencies for some platforms only, see the `platform_deps` module
+    pub platform_dev_dependencies: Vec<PlatformDependency>,
+    /// Used by `dmenv run --preset`, see the `presets` module
+    pub presets: Vec<Preset>,
+    /// Used by `dmenv hash add`, see the `lock_hashes` module
+    pub hash_algorithm: Algorithm,
+    /// Make sure the package indexes can be reached before `install` and `lock`
+    pub check_indexes: bool,
+    /// Packages that must not be locked or installed
+    pub package_policy: PackagePolicy,
+    /// How old locked releases may be, see `dmenv lock --check`
+    pub freshness_policy: Option<FreshnessPolicy>,
+    /// Reject git and URL dependencies in the production lock, see `policy::check_no_vcs()`
+    pub forbid_vcs_in_production: bool,
+    /// Fail on lines of the lock that can't be parsed
+    pub strict_lock: bool,
+    /// Write `<lock>.changes.json` when the lock changes, see the `changeset` module
+    pub write_changeset: bool,
+    /// Write the output of pip to a log instead of the terminal
+    pub quiet: bool,
+    /// Line endings of the lock. When None, keep the existing ones
+    pub lock_newline: Option<Newline>,
+    /// Put the virtualenv outside the project when the project is on the other side of WSL
+    pub wsl_native_venv: bool,
+    /// Arguments of dmenv, including the program name, recorded in lock backups,
+    /// changesets and the history. Empty when dmenv is used as a library, see
+    /// `run_with_command_line()`
+    pub command_line: Vec<String>,
 }
 
 impl Default for Settings {
@@ -17,6 +115,37 @@ impl Default for Settings {
             venv_outside_project: false,
             system_site_packages: false,
             production: false,
+            shared_store: false,
+            detect_changes: false,
+            shared_venv_root: None,
+            package_dir: None,
+            extra_package_dirs: vec![],
+            project_type: ProjectType::Package,
+            tools_bin_dir: None,
+            template_dir: None,
+            format_cmd: split_cmd("black ."),
+            format_check_cmd: split_cmd("black --check ."),
+            lint_cmd: split_cmd("flake8"),
+            coverage_process_start: None,
+            index_url: None,
+            credential_helper: vec![],
+            indexes: vec![],
+            variant: None,
+            find_links: vec![],
+            overrides: vec![],
+            platform_dev_dependencies: vec![],
+            presets: vec![],
+            hash_algorithm: Algorithm::default(),
+            check_indexes: true,
+            package_policy: PackagePolicy::default(),
+            freshness_policy: None,
+            forbid_vcs_in_production: false,
+            strict_lock: false,
+            write_changeset: false,
+            quiet: false,
+            lock_newline: None,
+            wsl_native_venv: false,
+            command_line: vec![],
         }
     }
 }
@@ -31,6 +160,8 @@ impl Settings {
         let mut res = Settings {
             production: cmd.production,
             system_site_packages: cmd.system_site_packages,
+            strict_lock: cmd.strict,
+            quiet: cmd.quiet,
             ..Default::default()
         };
         if std::env::var("DMENV_NO_VENV_STDLIB").is_ok() {
@@ -39,6 +170,118 @@ impl Settings {
         if std::env::var("DMENV_VENV_OUTSIDE_PROJECT").is_ok() {
             res.venv_outside_project = true;
         }
+        if std::env::var("DMENV_SHARED_STORE").is_ok() {
+            res.shared_store = true;
+        }
+        if std::env::var("DMENV_DETECT_CHANGES").is_ok() {
+            res.detect_changes = true;
+        }
+        if std::env::var("DMENV_WSL_NATIVE_VENV").is_ok() {
+            res.wsl_native_venv = true;
+        }
+        if let Ok(shared_venv_root) = std::env::var("DMENV_SHARED_VENV_ROOT") {
+            res.shared_venv_root = Some(PathBuf::from(shared_venv_root));
+        }
+        if let Some(package_dir) = &cmd.package_dir {
+            res.package_dir = Some(PathBuf::from(package_dir));
+        } else if let Ok(package_dir) = std::env::var("DMENV_PACKAGE_DIR") {
+            res.package_dir = Some(PathBuf::from(package_dir));
+        }
+        if !cmd.extra_package_dirs.is_empty() {
+            res.extra_package_dirs = cmd.extra_package_dirs.iter().map(PathBuf::from).collect();
+        } else if let Some(extra_package_dirs) = std::env::var_os("DMENV_EXTRA_PACKAGE_DIRS") {
+            res.extra_package_dirs = std::env::split_paths(&extra_package_dirs).collect();
+        }
+        if let Ok(project_type) = std::env::var("DMENV_PROJECT_TYPE") {
+            match project_type.parse() {
+                Ok(project_type) => res.project_type = project_type,
+                Err(e) => print_warning(&format!("Ignoring DMENV_PROJECT_TYPE: {}", e)),
+            }
+        }
+        if let Ok(tools_bin_dir) = std::env::var("DMENV_TOOLS_BIN_DIR") {
+            res.tools_bin_dir = Some(PathBuf::from(tools_bin_dir));
+        }
+        if let Ok(template_dir) = std::env::var("DMENV_TEMPLATE_DIR") {
+            res.template_dir = Some(PathBuf::from(template_dir));
+        }
+        if let Ok(format_cmd) = std::env::var("DMENV_FORMAT_CMD") {
+            res.format_cmd = split_cmd(&format_cmd);
+        }
+        if let Ok(format_check_cmd) = std::env::var("DMENV_FORMAT_CHECK_CMD") {
+            res.format_check_cmd = split_cmd(&format_check_cmd);
+        }
+        if let Ok(lint_cmd) = std::env::var("DMENV_LINT_CMD") {
+            res.lint_cmd = split_cmd(&lint_cmd);
+        }
+        if let Ok(coverage_process_start) = std::env::var("DMENV_COVERAGE_PROCESS_START") {
+            res.coverage_process_start = Some(PathBuf::from(coverage_process_start));
+        }
+        if let Ok(index_url) = std::env::var("DMENV_INDEX_URL") {
+            res.index_url = Some(index_url);
+        } else if let Ok(index_url) = std::env::var("PIP_INDEX_URL") {
+            res.index_url = Some(index_url);
+        }
+        if let Ok(credential_helper) = std::env::var("DMENV_CREDENTIAL_HELPER") {
+            res.credential_helper = split_cmd(&credential_helper);
+        }
+        if let Ok(indexes) = std::env::var("DMENV_INDEXES") {
+            match indexes::parse(&indexes) {
+                Ok(indexes) => res.indexes = indexes,
+                Err(e) => print_warning(&format!("Ignoring DMENV_INDEXES: {}", e)),
+            }
+        }
+        // `--index-url` replaces the indexes from the environment
+        if let Some(index) = &cmd.index {
+            res.indexes = vec![index.clone()];
+        }
+        if let Ok(variant) = std::env::var("DMENV_VARIANT") {
+            res.variant = Some(variant);
+        }
+        if let Some(variant) = &cmd.variant {
+            res.variant = Some(variant.to_string());
+        }
+        if let Ok(find_links) = std::env::var("DMENV_FIND_LINKS") {
+            res.find_links = find_links
+                .split_whitespace()
+                .map(|x| x.to_string())
+                .collect();
+        }
+        res.find_links.extend(cmd.find_links.iter().cloned());
+        if let Ok(algorithm) = std::env::var("DMENV_HASH_ALGORITHM") {
+            match algorithm.parse() {
+                Ok(algorithm) => res.hash_algorithm = algorithm,
+                Err(e) => print_warning(&format!("Ignoring DMENV_HASH_ALGORITHM: {}", e)),
+            }
+        }
+        if std::env::var("DMENV_NO_INDEX_CHECK").is_ok() {
+            res.check_indexes = false;
+        }
+        if std::env::var("DMENV_CHANGESET").is_ok() {
+            res.write_changeset = true;
+        }
+        if let Ok(newline) = std::env::var("DMENV_LOCK_NEWLINE") {
+            match newline.parse() {
+                Ok(newline) => res.lock_newline = Some(newline),
+                Err(e) => print_warning(&format!("Ignoring DMENV_LOCK_NEWLINE: {}", e)),
+            }
+        }
+        if let Ok(patterns) = std::env::var("DMENV_DENY_PACKAGES") {
+            res.package_policy.deny(&patterns, "DMENV_DENY_PACKAGES");
+        }
+        if let Ok(patterns) = std::env::var("DMENV_ALLOW_PACKAGES") {
+            res.package_policy.allow(&patterns, "DMENV_ALLOW_PACKAGES");
+        }
+        if let Ok(age) = std::env::var("DMENV_MAX_RELEASE_AGE") {
+            match FreshnessPolicy::new(&age, "DMENV_MAX_RELEASE_AGE") {
+                Ok(policy) => res.freshness_policy = Some(policy),
+                Err(e) => print_warning(&format!("Ignoring DMENV_MAX_RELEASE_AGE: {}", e)),
+            }
+        }
         res
     }
 }
+
+// Note: no quoting support, arguments containing spaces can't be used
+fn split_cmd(cmd: &str) -> Vec<String> {
+    cmd.split_whitespace().map(|x| x.to_string()).collect()
+}
@@ -0,0 +1,180 @@
+/// Helpers to inspect and patch existing setup.py files.
+//
+// Note: setup.py is Python code, so we can't really parse it. This only
+// handles the common case where `extras_require` is given as a literal dict
+// directly in the call to `setup()`.
+
+/// Find the names of the extras declared in `extras_require`.
+/// Returns None if there is no `extras_require` at all.
+pub fn find_extras(contents: &str) -> Option<Vec<String>> {
+    let start = contents.find("extras_require")?;
+    let dict = literal_dict(&contents[start..])?;
+    let mut res = vec![];
+    let mut depth = 0;
+    let mut chars = dict.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            '#' => {
+                // Skip comments
+                while let Some((_, c)) = chars.peek() {
+                    if *c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '"' | '\'' => {
+                let end = match dict[i + 1..].find(c) {
+                    Some(end) => i + 1 + end,
+                    None => break,
+                };
+                let string = &dict[i + 1..end];
+                // Keys are the strings at depth 1 followed by a colon
+                let after = dict[end + 1..].trim_start();
+                if depth == 1 && after.starts_with(':') {
+                    res.push(string.to_string());
+                }
+                while let Some((j, _)) = chars.peek() {
+                    if *j > end {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            _ => (),
+        }
+    }
+    Some(res)
+}
+
+/// Return the `{ ... }` part of `extras_require={ ... }`
+fn literal_dict(string: &str) -> Option<&str> {
+    let start = string.find('{')?;
+    // Make sure the dict directly follows `extras_require =`
+    let between = string["extras_require".len()..start].trim();
+    if between != "=" {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in string[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&string[start..=start + i]);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Add an empty `dev` extra to the setup.py contents.
+/// Returns None if we don't know how to patch this setup.py
+pub fn add_dev_extra(contents: &str) -> Option<String> {
+    if let Some(start) = contents.find("extras_require") {
+        let dict = literal_dict(&contents[start..])?;
+        let dict_start = start + contents[start..].find(dict)?;
+        let insert_at = dict_start + 1;
+        return Some(format!(
+            "{}\n        \"dev\": [],{}",
+            &contents[..insert_at],
+            &contents[insert_at..]
+        ));
+    }
+    let start = contents.find("setup(")?;
+    let insert_at = start + "setup(".len();
+    Some(format!(
+        "{}\n    extras_require={{\"dev\": []}},{}",
+        &contents[..insert_at],
+        &contents[insert_at..]
+    ))
+}
+
+/// Add `requirement` to the list of the `extra` extra, which must exist.
+/// Returns None if we don't know how to patch this setup.py
+pub fn add_to_extra(contents: &str, extra: &str, requirement: &str) -> Option<String> {
+    let start = contents.find("extras_require")?;
+    let dict = literal_dict(&contents[start..])?;
+    let dict_start = start + contents[start..].find(dict)?;
+    for quote in &['"', '\''] {
+        let key = format!("{}{}{}", quote, extra, quote);
+        let key_end = match dict.find(&key) {
+            Some(index) => index + key.len(),
+            None => continue,
+        };
+        let value = dict[key_end..].trim_start().strip_prefix(':')?.trim_start();
+        if !value.starts_with('[') {
+            return None;
+        }
+        let insert_at = dict_start + dict.len() - value.len() + 1;
+        let is_empty = value[1..].trim_start().starts_with(']');
+        let separator = if is_empty { "" } else { ", " };
+        return Some(format!(
+            "{}{}{}{}{}{}",
+            &contents[..insert_at],
+            quote,
+            requirement,
+            quote,
+            separator,
+            &contents[insert_at..]
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETUP_PY: &str = r#"
+from setuptools import setup
+
+setup(
+    name="foo",
+    install_requires=["attrs"],
+    extras_require={
+        # Tests
+        "test": ["pytest", "pytest-cov[all]"],
+        'docs': {"sphinx": 1}.keys(),
+    },
+)
+"#;
+
+    #[test]
+    fn test_find_extras() {
+        assert_eq!(find_extras(SETUP_PY).unwrap(), vec!["test", "docs"]);
+    }
+
+    #[test]
+    fn test_find_extras_none() {
+        assert!(find_extras("setup(name=\"foo\")").is_none());
+    }
+
+    #[test]
+    fn test_add_dev_extra() {
+        let patched = add_dev_extra(SETUP_PY).unwrap();
+        assert!(patched.contains("extras_require={\n        \"dev\": [],\n        # Tests"));
+        assert_eq!(find_extras(&patched).unwrap(), vec!["dev", "test", "docs"]);
+    }
+
+    #[test]
+    fn test_add_to_extra() {
+        let patched = add_to_extra(SETUP_PY, "test", "hypothesis").unwrap();
+        assert!(patched.contains(r#""test": ["hypothesis", "pytest", "pytest-cov[all]"],"#));
+        let patched = add_to_extra("extras_require={'dev': []}", "dev", "black").unwrap();
+        assert_eq!(patched, "extras_require={'dev': ['black']}");
+        assert!(add_to_extra(SETUP_PY, "docs", "sphinx").is_none());
+        assert!(add_to_extra(SETUP_PY, "dev", "black").is_none());
+    }
+
+    #[test]
+    fn test_add_dev_extra_no_extras() {
+        let patched = add_dev_extra("setup(\n    name=\"foo\",\n)\n").unwrap();
+        assert_eq!(find_extras(&patched).unwrap(), vec!["dev"]);
+    }
+}
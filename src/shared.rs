@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Make sure files created by dmenv and its child processes (`python -m venv`, `pip` ...)
+/// are writable by the group, so that other users can update the shared virtualenv.
+/// No-op on Windows.
+pub fn set_group_umask() {
+    #[cfg(unix)]
+    unsafe {
+        libc::umask(0o002);
+    }
+}
+
+/// Make the directories of the virtualenv writable by the group, and on Unix, set
+/// the setgid bit so that new files belong to the group of the virtualenv instead
+/// of the primary group of the user who created them.
+/// No-op on Windows.
+pub fn make_group_writable(venv: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = std::fs::symlink_metadata(venv).map_err(|e| Error::ReadError {
+            path: venv.to_path_buf(),
+            io_error: e,
+        })?;
+        if !metadata.is_dir() {
+            return Ok(());
+        }
+        let mut permissions = metadata.permissions();
+        permissions.set_mode(permissions.mode() | 0o2070);
+        std::fs::set_permissions(venv, permissions).map_err(|e| Error::Other {
+            message: format!("could not change permissions of {}: {}", venv.display(), e),
+        })?;
+        let entries = std::fs::read_dir(venv).map_err(|e| Error::ReadError {
+            path: venv.to_path_buf(),
+            io_error: e,
+        })?;
+        for entry in entries.filter_map(Result::ok) {
+            make_group_writable(&entry.path())?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = venv;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Shims written by `dmenv shim`: small scripts in the tools bin directory
+//! running a command of the project through `dmenv run`. The virtualenv is
+//! found again each time, so shims keep working after `dmenv clean` or when
+//! the Python version changes.
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::remote::quote;
+
+/// Written in every shim, so that dmenv only ever replaces its own
+const MARKER: &str = "Generated by dmenv shim";
+
+pub fn file_name(command: &str) -> String {
+    if cfg!(windows) {
+        format!("{}.cmd", command)
+    } else {
+        command.to_string()
+    }
+}
+
+/// Arguments given to dmenv by the shim, before the ones of the command
+pub fn dmenv_args(project: &Path, production: bool, command: &str) -> Vec<String> {
+    let mut res = vec![
+        "--project".to_string(),
+        project.to_string_lossy().to_string(),
+    ];
+    if production {
+        res.push("--production".to_string());
+    }
+    res.push("run".to_string());
+    res.push(command.to_string());
+    res
+}
+
+pub fn unix_contents(dmenv: &Path, args: &[String]) -> String {
+    let mut line = vec![quote(&dmenv.to_string_lossy())];
+    line.extend(args.iter().map(|x| quote(x)));
+    format!("#!/bin/sh\n# {}\nexec {} \"$@\"\n", MARKER, line.join(" "))
+}
+
+pub fn windows_contents(dmenv: &Path, args: &[String]) -> String {
+    let mut line = vec![format!("\"{}\"", dmenv.display())];
+    line.extend(args.iter().map(|x| format!("\"{}\"", x)));
+    format!("@echo off\r\nrem {}\r\n{} %*\r\n", MARKER, line.join(" "))
+}
+
+/// True if `path` does not exist, or was written by `write()`
+pub fn can_replace(path: &Path) -> bool {
+    if std::fs::symlink_metadata(path).is_err() {
+        return true;
+    }
+    std::fs::read_to_string(path)
+        .map(|x| x.contains(MARKER))
+        .unwrap_or(false)
+}
+
+/// Write the shim for `command` in `bin_dir`, and return its path
+pub fn write(bin_dir: &Path, command: &str, args: &[String]) -> Result<PathBuf, Error> {
+    let dmenv = std::env::current_exe().map_err(|e| Error::Other {
+        message: format!("Could not find the path of dmenv: {}", e),
+    })?;
+    let path = bin_dir.join(file_name(command));
+    let contents = if cfg!(windows) {
+        windows_contents(&dmenv, args)
+    } else {
+        unix_contents(&dmenv, args)
+    };
+    std::fs::create_dir_all(bin_dir).map_err(|e| Error::Other {
+        message: format!("Could not create {}: {}", bin_dir.display(), e),
+    })?;
+    std::fs::write(&path, contents).map_err(|e| Error::WriteError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(0o755);
+        std::fs::set_permissions(&path, permissions).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contents() {
+        let args = dmenv_args(Path::new("/work/my project"), true, "foo-cli");
+        assert_eq!(
+            unix_contents(Path::new("/usr/bin/dmenv"), &args),
+            "#!/bin/sh\n# Generated by dmenv shim\n\
+             exec /usr/bin/dmenv --project '/work/my project' --production run foo-cli \"$@\"\n"
+        );
+        let args = dmenv_args(Path::new("C:\\work"), false, "foo-cli");
+        assert_eq!(
+            windows_contents(Path::new("C:\\bin\\dmenv.exe"), &args),
+            "@echo off\r\nrem Generated by dmenv shim\r\n\
+             \"C:\\bin\\dmenv.exe\" \"--project\" \"C:\\work\" \"run\" \"foo-cli\" %*\r\n"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use crate::json;
+
+/// Environment variables set while simulating a CI run: never prompt, and
+/// don't reuse anything downloaded by previous runs
+pub const CI_ENV: &[(&str, &str)] = &[
+    ("CI", "true"),
+    ("PIP_NO_INPUT", "1"),
+    ("PIP_NO_CACHE_DIR", "1"),
+    ("PIP_DISABLE_PIP_VERSION_CHECK", "1"),
+];
+
+/// One step of `dmenv simulate-ci`
+#[derive(Debug)]
+pub struct Step {
+    pub name: String,
+    pub duration: Duration,
+    /// None if the step succeeded
+    pub error: Option<String>,
+}
+
+/// What `dmenv simulate-ci` did, written as JSON with `--report`
+#[derive(Debug, Default)]
+pub struct Report {
+    pub lock: String,
+    pub venv: String,
+    pub python_version: String,
+    /// True when installing from a vendor directory instead of the indexes
+    pub offline: bool,
+    /// True when the lock has hashes, and pip was told to require them
+    pub hashes_checked: bool,
+    /// Steps that ran, in order. Steps after a failed one don't run
+    pub steps: Vec<Step>,
+}
+
+impl Report {
+    pub fn success(&self) -> bool {
+        self.steps.iter().all(|x| x.error.is_none())
+    }
+
+    pub fn to_json(&self) -> String {
+        let steps: Vec<_> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let error = match &step.error {
+                    Some(error) => json::string(error),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "    {{\"name\": {}, \"duration_ms\": {}, \"error\": {}}}",
+                    json::string(&step.name),
+                    step.duration.as_millis(),
+                    error
+                )
+            })
+            .collect();
+        let fields = [
+            ("success", self.success().to_string()),
+            ("lock", json::string(&self.lock)),
+            ("venv", json::string(&self.venv)),
+            ("python_version", json::string(&self.python_version)),
+            ("offline", self.offline.to_string()),
+            ("hashes_checked", self.hashes_checked.to_string()),
+            ("steps", format!("[\n{}\n  ]", steps.join(",\n"))),
+        ];
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_to_json() {
+        let report = Report {
+            lock: "production.lock".to_string(),
+            steps: vec![
+                Step {
+                    name: "create virtualenv".to_string(),
+                    duration: Duration::from_millis(1500),
+                    error: None,
+                },
+                Step {
+                    name: "install dependencies".to_string(),
+                    duration: Duration::from_millis(20),
+                    error: Some("command failed".to_string()),
+                },
+            ],
+            ..Default::default()
+        };
+        let json = report.to_json();
+        assert!(!report.success());
+        assert!(json.starts_with("{\n  \"success\": false,\n  \"lock\": \"production.lock\","));
+        assert!(json
+            .contains("{\"name\": \"create virtualenv\", \"duration_ms\": 1500, \"error\": null}"));
+        assert!(json.contains("\"error\": \"command failed\"}"));
+    }
+}
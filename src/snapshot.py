@@ -0,0 +1,57 @@
+""" Create or extract virtualenv snapshots.
+
+Usage:
+    snapshot.py create <venv> <archive>
+    snapshot.py extract <archive> <venv>
+
+Snapshots are .tar.gz archives containing the virtualenv directory,
+relocation is handled by dmenv itself.
+"""
+import os
+import sys
+import tarfile
+
+
+def create(venv, archive):
+    with tarfile.open(archive, "w:gz") as tar:
+        tar.add(venv, arcname=".")
+
+
+def is_safe(member):
+    path = os.path.normpath(member.name)
+    if os.path.isabs(path) or path.startswith(".."):
+        return False
+    # Symlinks are allowed to point outside (bin/python points to the
+    # system's interpreter), but hard links are not
+    if member.islnk() and os.path.normpath(member.linkname).startswith(".."):
+        return False
+    return True
+
+
+def extract(archive, venv):
+    with tarfile.open(archive, "r:gz") as tar:
+        members = tar.getmembers()
+        for member in members:
+            if not is_safe(member):
+                sys.exit("unsafe path in snapshot: %s" % member.name)
+        os.makedirs(venv)
+        kwargs = {}
+        # Note: the default filter of recent Python versions refuses
+        # absolute symlinks, and we've already checked the members
+        if hasattr(tarfile, "tar_filter"):
+            kwargs["filter"] = "tar"
+        tar.extractall(venv, members=members, **kwargs)
+
+
+def main():
+    if len(sys.argv) != 4 or sys.argv[1] not in ("create", "extract"):
+        sys.exit(__doc__)
+    action, source, dest = sys.argv[1:]
+    if action == "create":
+        create(source, dest)
+    else:
+        extract(source, dest)
+
+
+if __name__ == "__main__":
+    main()
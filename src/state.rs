@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::digest::VenvDigest;
+use crate::error::Error;
+use crate::settings::Settings;
+
+/// Name of the state file, inside the virtualenv
+pub const STATE_FILENAME: &str = "dmenv-state";
+
+/// Provenance of a virtualenv, written by `install` and `lock`.
+///
+/// Stored as `key = value` lines, for instance:
+/// ```text
+/// dmenv_version = 0.12.0
+/// lock_hash = 9f3c6a82b1e04d57
+/// ...
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct VenvState {
+    pub dmenv_version: String,
+    pub python_version: String,
+    /// Hash of the lock that was last installed (see `hash::hash_hex()`)
+    pub lock_hash: String,
+    /// Seconds since UNIX epoch
+    pub created: u64,
+    pub updated: u64,
+    /// Snapshot of the Settings used
+    pub settings: BTreeMap<String, String>,
+    /// Installed packages, to detect changes made without dmenv
+    /// (see `digest::VenvDigest`)
+    pub packages: Vec<String>,
+    pub records_hash: String,
+}
+
+impl fmt::Display for VenvState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# Written by dmenv. Do not edit")?;
+        writeln!(f, "dmenv_version = {}", self.dmenv_version)?;
+        writeln!(f, "python_version = {}", self.python_version)?;
+        writeln!(f, "lock_hash = {}", self.lock_hash)?;
+        writeln!(f, "created = {}", self.created)?;
+        writeln!(f, "updated = {}", self.updated)?;
+        for (key, value) in &self.settings {
+            writeln!(f, "settings.{} = {}", key, value)?;
+        }
+        writeln!(f, "packages = {}", self.packages.join(", "))?;
+        writeln!(f, "records_hash = {}", self.records_hash)
+    }
+}
+
+impl VenvState {
+    /// Build a new state, keeping the creation time of
+    /// `previous` if any
+    pub fn new(
+        python_version: &str,
+        lock_hash: &str,
+        settings: &Settings,
+        digest: VenvDigest,
+        previous: Option<VenvState>,
+    ) -> Self {
+        let now = now();
+        let created = previous.map(|x| x.created).unwrap_or(now);
+        VenvState {
+            dmenv_version: env!("CARGO_PKG_VERSION").to_string(),
+            python_version: python_version.to_string(),
+            lock_hash: lock_hash.to_string(),
+            created,
+            updated: now,
+            settings: settings_snapshot(settings),
+            packages: digest.packages,
+            records_hash: digest.records_hash,
+        }
+    }
+
+    pub fn path(venv: &Path) -> PathBuf {
+        venv.join(STATE_FILENAME)
+    }
+
+    /// Read the state file from the given virtualenv.
+    /// Returns None if there is no state file (for instance if the virtualenv
+    /// was created by an older dmenv version)
+    pub fn read(venv: &Path) -> Result<Option<Self>, Error> {
+        let path = Self::path(venv);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        Self::from_string(&contents).map(Some)
+    }
+
+    pub fn write(&self, venv: &Path) -> Result<(), Error> {
+        let path = Self::path(venv);
+        std::fs::write(&path, self.to_string()).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+    }
+
+    pub fn from_string(string: &str) -> Result<Self, Error> {
+        let mut values = BTreeMap::new();
+        for (i, line) in string.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or_default().trim();
+            let value = parts.next().ok_or_else(|| Error::Other {
+                message: format!("malformed {} at line {}", STATE_FILENAME, i + 1),
+            })?;
+            values.insert(key.to_string(), value.trim().to_string());
+        }
+
+        let get = |key: &str| values.get(key).cloned().unwrap_or_default();
+        let get_time = |key: &str| get(key).parse::<u64>().unwrap_or_default();
+        let settings = values
+            .iter()
+            .filter(|(k, _)| k.starts_with("settings."))
+            .map(|(k, v)| (k.trim_start_matches("settings.").to_string(), v.clone()))
+            .collect();
+        let packages = get("packages")
+            .split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect();
+        Ok(VenvState {
+            dmenv_version: get("dmenv_version"),
+            python_version: get("python_version"),
+            lock_hash: get("lock_hash"),
+            created: get_time("created"),
+            updated: get_time("updated"),
+            settings,
+            packages,
+            records_hash: get("records_hash"),
+        })
+    }
+}
+
+/// Settings that have an effect on the contents of the virtualenv
+pub fn settings_snapshot(settings: &Settings) -> BTreeMap<String, String> {
+    let mut res = BTreeMap::new();
+    let mut add = |key: &str, value: bool| res.insert(key.to_string(), value.to_string());
+    add("production", settings.production);
+    add("shared_store", settings.shared_store);
+    add("system_site_packages", settings.system_site_packages);
+    add("venv_from_stdlib", settings.venv_from_stdlib);
+    add("venv_outside_project", settings.venv_outside_project);
+    res
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest() -> VenvDigest {
+        VenvDigest {
+            packages: vec!["attrs==19.1.0".to_string(), "pytest==4.6.2".to_string()],
+            records_hash: "0123456789abcdef".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = VenvState::new("3.7.1", "abcdef", &Settings::default(), digest(), None);
+        let actual = VenvState::from_string(&state.to_string()).unwrap();
+        assert_eq!(actual, state);
+        assert_eq!(actual.settings["production"], "false");
+        assert_eq!(actual.packages.len(), 2);
+    }
+
+    #[test]
+    fn test_keep_creation_time() {
+        let mut previous = VenvState::new("3.7.1", "abcdef", &Settings::default(), digest(), None);
+        previous.created = 42;
+        let state = VenvState::new(
+            "3.7.1",
+            "012345",
+            &Settings::default(),
+            digest(),
+            Some(previous),
+        );
+        assert_eq!(state.created, 42);
+        assert_eq!(state.lock_hash, "012345");
+    }
+
+    #[test]
+    fn test_malformed() {
+        VenvState::from_string("lock_hash\n").unwrap_err();
+    }
+}
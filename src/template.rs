@@ -0,0 +1,422 @@
+//! A small template engine for the files generated by dmenv (`setup.py`,
+//! service units, CI snippets ...).
+//!
+//! ```text
+//! name="{{ name }}",
+//! {% if author %}
+//! author="{{ author }}",
+//! {% endif %}
+//! {% for classifier in classifiers %}
+//! "{{ classifier }}",
+//! {% endfor %}
+//! ```
+//!
+//! Supported tags are `{% if x %}`, `{% if not x %}`, `{% else %}`, `{% endif %}`,
+//! `{% for x in list %}`, `{% endfor %}` and `{% raw %}` ... `{% endraw %}` (for
+//! files using `{{` themselves, like GitHub workflows). A line containing only a
+//! tag is removed from the output.
+//!
+//! Every template shipped with dmenv can be overridden by a file with the same
+//! name in one of the template directories, see `VenvManager::template_dirs()`
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::error::Error;
+
+/// Templates shipped with dmenv, by name
+const BUILTINS: &[(&str, &str)] = &[
+    ("init-setup.py", include_str!("templates/init-setup.py")),
+    ("setup.py", include_str!("templates/setup.py")),
+    ("pyproject.toml", include_str!("templates/pyproject.toml")),
+    ("systemd.service", include_str!("templates/systemd.service")),
+    ("supervisor.conf", include_str!("templates/supervisor.conf")),
+    (
+        "github-steps.yml",
+        include_str!("templates/github-steps.yml"),
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+impl Value {
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Text(x) => !x.is_empty(),
+            Value::Bool(x) => *x,
+            Value::List(x) => !x.is_empty(),
+        }
+    }
+}
+
+/// Values available to a template
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    values: BTreeMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, key: &str, value: &str) -> Self {
+        self.values
+            .insert(key.to_string(), Value::Text(value.to_string()));
+        self
+    }
+
+    pub fn bool(mut self, key: &str, value: bool) -> Self {
+        self.values.insert(key.to_string(), Value::Bool(value));
+        self
+    }
+
+    pub fn list(mut self, key: &str, values: &[String]) -> Self {
+        self.values
+            .insert(key.to_string(), Value::List(values.to_vec()));
+        self
+    }
+}
+
+/// Render the template called `name`: the first file with this name in
+/// `dirs`, or the one shipped with dmenv
+pub fn render(name: &str, dirs: &[PathBuf], context: &Context) -> Result<String, Error> {
+    for dir in dirs {
+        let path = dir.join(name);
+        if path.is_file() {
+            let template = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            return render_str(&template, context).map_err(|e| Error::Other {
+                message: format!("Invalid template {}: {}", path.display(), e),
+            });
+        }
+    }
+    let template = BUILTINS
+        .iter()
+        .find(|(x, _)| *x == name)
+        .map(|(_, x)| x)
+        .ok_or_else(|| Error::Other {
+            message: format!("No template named {}", name),
+        })?;
+    render_str(template, context).map_err(|e| Error::Other {
+        message: format!("Invalid template {}: {}", name, e),
+    })
+}
+
+pub fn render_str(template: &str, context: &Context) -> Result<String, String> {
+    let tokens = tokenize(template)?;
+    let mut tokens = tokens.into_iter();
+    let (nodes, end) = parse(&mut tokens)?;
+    if let Some((tag, line)) = end {
+        return Err(format!("line {}: unexpected {{% {} %}}", line, tag));
+    }
+    let mut res = String::new();
+    let mut scope = Scope {
+        context,
+        locals: vec![],
+    };
+    render_nodes(&nodes, &mut scope, &mut res)?;
+    Ok(res)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    /// Contents of `{{ ... }}`, and its line
+    Var(String, usize),
+    /// Contents of `{% ... %}`, and its line
+    Tag(String, usize),
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, String> {
+    let mut res = vec![];
+    let mut pos = 0;
+    let mut text_start = 0;
+    let mut raw = false;
+    while let Some(offset) = template[pos..].find('{') {
+        let start = pos + offset;
+        let rest = &template[start..];
+        let close = if rest.starts_with("{{") && !raw {
+            "}}"
+        } else if rest.starts_with("{%") {
+            "%}"
+        } else {
+            pos = start + 1;
+            continue;
+        };
+        let line = template[..start].matches('\n').count() + 1;
+        let len = rest
+            .find(close)
+            .ok_or_else(|| format!("line {}: unclosed {}", line, &rest[..2]))?;
+        let contents = rest[2..len].trim().to_string();
+        let mut end = start + len + 2;
+        if close == "}}" {
+            res.push(Token::Text(template[text_start..start].to_string()));
+            res.push(Token::Var(contents, line));
+            pos = end;
+            text_start = end;
+            continue;
+        }
+        if raw && contents != "endraw" {
+            pos = end;
+            continue;
+        }
+        // Remove lines containing only a tag
+        let mut text_end = start;
+        let line_start = template[..start].rfind('\n').map(|x| x + 1).unwrap_or(0);
+        let line_end = template[end..]
+            .find('\n')
+            .map(|x| end + x + 1)
+            .unwrap_or_else(|| template.len());
+        if line_start >= text_start
+            && template[line_start..start].trim().is_empty()
+            && template[end..line_end].trim().is_empty()
+        {
+            text_end = line_start;
+            end = line_end;
+        }
+        res.push(Token::Text(template[text_start..text_end].to_string()));
+        match contents.as_str() {
+            "raw" => raw = true,
+            "endraw" => raw = false,
+            _ => res.push(Token::Tag(contents, line)),
+        }
+        pos = end;
+        text_start = end;
+    }
+    if raw {
+        return Err("missing {% endraw %}".to_string());
+    }
+    res.push(Token::Text(template[text_start..].to_string()));
+    Ok(res)
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var(String, usize),
+    If {
+        key: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        var: String,
+        key: String,
+        line: usize,
+        body: Vec<Node>,
+    },
+}
+
+/// A closing tag (`else`, `endif` or `endfor`) and its line
+type ClosingTag = Option<(String, usize)>;
+
+/// Parse nodes until the end of the tokens, or until a closing tag
+fn parse(tokens: &mut impl Iterator<Item = Token>) -> Result<(Vec<Node>, ClosingTag), String> {
+    let mut res = vec![];
+    while let Some(token) = tokens.next() {
+        let (tag, line) = match token {
+            Token::Text(text) => {
+                res.push(Node::Text(text));
+                continue;
+            }
+            Token::Var(key, line) => {
+                res.push(Node::Var(key, line));
+                continue;
+            }
+            Token::Tag(tag, line) => (tag, line),
+        };
+        let words: Vec<_> = tag.split_whitespace().collect();
+        match words.as_slice() {
+            ["if", key] | ["if", "not", key] => {
+                let negated = words.len() == 3;
+                let (then, end) = parse(tokens)?;
+                let (otherwise, end) = match end {
+                    Some((ref x, _)) if x == "else" => parse(tokens)?,
+                    _ => (vec![], end),
+                };
+                match end {
+                    Some((ref x, _)) if x == "endif" => (),
+                    _ => return Err(format!("line {}: missing {{% endif %}}", line)),
+                }
+                res.push(Node::If {
+                    key: key.to_string(),
+                    negated,
+                    then,
+                    otherwise,
+                });
+            }
+            ["for", var, "in", key] => {
+                let (body, end) = parse(tokens)?;
+                match end {
+                    Some((ref x, _)) if x == "endfor" => (),
+                    _ => return Err(format!("line {}: missing {{% endfor %}}", line)),
+                }
+                res.push(Node::For {
+                    var: var.to_string(),
+                    key: key.to_string(),
+                    line,
+                    body,
+                });
+            }
+            ["else"] | ["endif"] | ["endfor"] => return Ok((res, Some((tag, line)))),
+            _ => return Err(format!("line {}: unknown tag {{% {} %}}", line, tag)),
+        }
+    }
+    Ok((res, None))
+}
+
+struct Scope<'a> {
+    context: &'a Context,
+    /// Loop variables, innermost last
+    locals: Vec<(String, Value)>,
+}
+
+impl<'a> Scope<'a> {
+    fn get(&self, key: &str, line: usize) -> Result<&Value, String> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(x, _)| x == key)
+            .map(|(_, x)| x)
+            .or_else(|| self.context.values.get(key))
+            .ok_or_else(|| format!("line {}: unknown variable '{}'", line, key))
+    }
+}
+
+fn render_nodes(nodes: &[Node], scope: &mut Scope, out: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(key, line) => match scope.get(key, *line)? {
+                Value::Text(x) => out.push_str(x),
+                Value::Bool(x) => out.push_str(&x.to_string()),
+                Value::List(_) => {
+                    return Err(format!(
+                        "line {}: '{}' is a list, use a for loop",
+                        line, key
+                    ))
+                }
+            },
+            Node::If {
+                key,
+                negated,
+                then,
+                otherwise,
+            } => {
+                // Note: unknown variables are false, so that a template can test
+                // for values added by newer versions of dmenv
+                let value = scope.get(key, 0).map(|x| x.is_true()).unwrap_or(false);
+                if value != *negated {
+                    render_nodes(then, scope, out)?;
+                } else {
+                    render_nodes(otherwise, scope, out)?;
+                }
+            }
+            Node::For {
+                var,
+                key,
+                line,
+                body,
+            } => {
+                let items = match scope.get(key, *line)? {
+                    Value::List(x) => x.clone(),
+                    _ => return Err(format!("line {}: '{}' is not a list", line, key)),
+                };
+                for item in items {
+                    scope.locals.push((var.to_string(), Value::Text(item)));
+                    let res = render_nodes(body, scope, out);
+                    scope.locals.pop();
+                    res?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        Context::new()
+            .text("name", "foo")
+            .text("author", "")
+            .bool("src_layout", true)
+            .list("classifiers", &["A".to_string(), "B".to_string()])
+    }
+
+    #[test]
+    fn test_variables() {
+        let actual = render_str("name=\"{{ name }}\" {{src_layout}}\n", &context()).unwrap();
+        assert_eq!(actual, "name=\"foo\" true\n");
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let template =
+            "a\n{% if author %}\nby {{ author }}\n{% else %}\nanonymous\n{% endif %}\nb\n";
+        assert_eq!(
+            render_str(template, &context()).unwrap(),
+            "a\nanonymous\nb\n"
+        );
+        let template = "author=\"{% if not author %}<AUTHOR>{% endif %}\"\n";
+        assert_eq!(
+            render_str(template, &context()).unwrap(),
+            "author=\"<AUTHOR>\"\n"
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let template = "[\n  {% for x in classifiers %}\n  \"{{ x }}\",\n  {% endfor %}\n]\n";
+        assert_eq!(
+            render_str(template, &context()).unwrap(),
+            "[\n  \"A\",\n  \"B\",\n]\n"
+        );
+    }
+
+    #[test]
+    fn test_raw() {
+        let template = "{% raw %}\nkey: ${{ runner.os }}-{% if %}\n{% endraw %}\n{{ name }}";
+        assert_eq!(
+            render_str(template, &context()).unwrap(),
+            "key: ${{ runner.os }}-{% if %}\nfoo"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let context = context();
+        let error = render_str("a\n{{ nope }}", &context).unwrap_err();
+        assert_eq!(error, "line 2: unknown variable 'nope'");
+        let error = render_str("{% if name %}\nfoo\n", &context).unwrap_err();
+        assert_eq!(error, "line 1: missing {% endif %}");
+        let error = render_str("{% endfor %}", &context).unwrap_err();
+        assert_eq!(error, "line 1: unexpected {% endfor %}");
+        assert!(render_str("{{ name", &context).is_err());
+    }
+
+    #[test]
+    fn test_override() {
+        let tmp_dir = tempdir::TempDir::new("test-dmenv").unwrap();
+        let dirs = vec![tmp_dir.path().join("missing"), tmp_dir.path().to_path_buf()];
+        let context = Context::new().text("python_version", "3.7");
+        std::fs::write(
+            tmp_dir.path().join("github-steps.yml"),
+            "python: {{ python_version }}\n",
+        )
+        .unwrap();
+        let actual = render("github-steps.yml", &dirs, &context).unwrap();
+        assert_eq!(actual, "python: 3.7\n");
+        assert!(render("no-such-template", &dirs, &context).is_err());
+    }
+}
@@ -6,10 +6,10 @@ if sys.version_info.major < 3:
 
 
 setup(
-    name="<NAME>",
-    version="<VERSION>",
+    name="{{ name }}",
+    version="{{ version }}",
     description="<DESCRIPTION>",
-    author="<AUTHOR>",
+    author="{% if author %}{{ author }}{% else %}<AUTHOR>{% endif %}",
     packages=find_packages(),
     # If you have just one file, remove the line above
     # and add it in the list below, *without* the .py
@@ -0,0 +1,37 @@
+from setuptools import setup, find_packages
+
+setup(
+    name="{{ name }}",
+    version="{{ version }}",
+    description="",
+{% if author %}
+    author="{{ author }}",
+{% endif %}
+{% if license %}
+    license="{{ license }}",
+{% endif %}
+{% if src_layout %}
+    packages=find_packages("src"),
+    package_dir={"": "src"},
+{% else %}
+    packages=find_packages(),
+{% endif %}
+{% if python_requires %}
+    python_requires="{{ python_requires }}",
+{% endif %}
+    install_requires=[
+        # Put your dependencies here
+    ],
+    extras_require={
+{% for extra in extras %}
+        "{{ extra }}": [
+            # Put your {{ extra }} dependencies here
+        ],
+{% endfor %}
+    },
+    classifiers=[
+{% for classifier in classifiers %}
+        "{{ classifier }}",
+{% endfor %}
+    ],
+)
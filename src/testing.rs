@@ -0,0 +1,227 @@
+//! Fakes to simulate dmenv commands without a Python interpreter or a
+//! network connection, and a local package index to run them offline with
+//! a real one. Only available with the `testing` feature.
+//!
+//! ```ignore
+//! let runner = FakeRunner::new().with_frozen(&["foo==0.42"]);
+//! dmenv::run_with_runner(cmd, vec![], Box::new(runner.clone()))?;
+//! assert!(runner.calls().iter().any(|x| x.args.starts_with(&["freeze".to_string()])));
+//! ```
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::Error;
+use crate::runner::{CommandRunner, Process, ProcessOutput};
+
+/// A `CommandRunner` that pretends to be Python and pip.
+///
+/// * the info script reports the configured Python version, on Linux
+/// * `python -m venv <path>` creates a fake virtualenv, see `create_fake_venv()`
+/// * `pip freeze` prints the configured frozen dependencies
+/// * the index check reports every index as reachable
+/// * everything else succeeds without output
+///
+/// Clones share the list of calls, so keep a clone to inspect it after the run
+#[derive(Clone, Debug)]
+pub struct FakeRunner {
+    python_version: String,
+    frozen: Vec<String>,
+    calls: Rc<RefCell<Vec<Process>>>,
+}
+
+impl Default for FakeRunner {
+    fn default() -> Self {
+        FakeRunner {
+            python_version: "3.7.1".to_string(),
+            frozen: vec![],
+            calls: Rc::new(RefCell::new(vec![])),
+        }
+    }
+}
+
+impl FakeRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_python_version(mut self, version: &str) -> Self {
+        self.python_version = version.to_string();
+        self
+    }
+
+    /// Lines printed by `pip freeze`, like `foo==0.42`
+    pub fn with_frozen(mut self, frozen: &[&str]) -> Self {
+        self.frozen = frozen.iter().map(|x| x.to_string()).collect();
+        self
+    }
+
+    /// Every process started so far, in order
+    pub fn calls(&self) -> Vec<Process> {
+        self.calls.borrow().clone()
+    }
+
+    fn respond(&self, process: &Process) -> Result<ProcessOutput, Error> {
+        let args: Vec<_> = process.args.iter().map(|x| x.as_str()).collect();
+        let stdout = match args.as_slice() {
+            ["-c", script, ..] if *script == include_str!("info.py") => {
+                format!("{}\nLinux\nCPython\n", self.python_version)
+            }
+            ["-c", script, urls @ ..] if *script == include_str!("check_indexes.py") => {
+                urls.iter().map(|x| format!("ok {}\n", x)).collect()
+            }
+            ["-m", "venv", path, ..] | ["-m", "virtualenv", path, ..] => {
+                create_fake_venv(Path::new(path), &self.python_version)?;
+                String::new()
+            }
+            ["freeze", ..] | ["-m", "pip", "freeze", ..] => {
+                self.frozen.iter().map(|x| format!("{}\n", x)).collect()
+            }
+            _ => String::new(),
+        };
+        Ok(ProcessOutput {
+            success: true,
+            stdout,
+            stderr: String::new(),
+        })
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn status(&self, process: &Process) -> Result<bool, Error> {
+        self.calls.borrow_mut().push(process.clone());
+        let output = self.respond(process)?;
+        print!("{}", output.stdout);
+        Ok(output.success)
+    }
+
+    fn output(&self, process: &Process) -> Result<ProcessOutput, Error> {
+        self.calls.borrow_mut().push(process.clone());
+        self.respond(process)
+    }
+}
+
+/// Create the files dmenv expects in a virtualenv: a `pyvenv.cfg`, and
+/// empty `python` and `pip` binaries
+pub fn create_fake_venv(path: &Path, python_version: &str) -> Result<(), Error> {
+    #[cfg(windows)]
+    let (bin_dir, suffix) = (path.join("Scripts"), ".exe");
+    #[cfg(not(windows))]
+    let (bin_dir, suffix) = (path.join("bin"), "");
+
+    std::fs::create_dir_all(&bin_dir).map_err(|e| Error::WriteError {
+        path: bin_dir.to_path_buf(),
+        io_error: e,
+    })?;
+    let cfg = format!(
+        "home = /usr/bin\ninclude-system-site-packages = false\nversion = {}\n",
+        python_version
+    );
+    let mut files = vec![(path.join("pyvenv.cfg"), cfg)];
+    for name in &["python", "pip"] {
+        files.push((bin_dir.join(format!("{}{}", name, suffix)), String::new()));
+    }
+    for (file, contents) in files {
+        std::fs::write(&file, contents).map_err(|e| Error::WriteError {
+            path: file.to_path_buf(),
+            io_error: e,
+        })?;
+    }
+    Ok(())
+}
+
+/// A package index serving the files of a local directory over HTTP, on
+/// 127.0.0.1. Pass `url()` to `--index-url`.
+///
+/// The directory must follow the layout of a static "simple" index, where
+/// directories are served through their `index.html`: `simple/index.html`,
+/// `simple/<name>/index.html` with links to the wheels, and the wheels
+/// themselves (see tests/fixtures/make_index.py)
+pub struct LocalIndex {
+    port: u16,
+}
+
+impl LocalIndex {
+    /// Start serving `root` in a background thread, until the process exits
+    pub fn serve(root: &Path) -> Result<Self, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| Error::Other {
+            message: format!("could not start local index: {}", e),
+        })?;
+        let port = listener
+            .local_addr()
+            .map_err(|e| Error::Other {
+                message: format!("could not start local index: {}", e),
+            })?
+            .port();
+        let root = root.to_path_buf();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().filter_map(Result::ok) {
+                // Note: errors only concern the current request, keep serving
+                let _ = serve_file(&root, stream);
+            }
+        });
+        Ok(LocalIndex { port })
+    }
+
+    /// URL of the simple API of the index
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/simple/", self.port)
+    }
+}
+
+fn serve_file(root: &Path, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or_default();
+    let path = words.next().unwrap_or_default();
+    let body = local_path(root, path).and_then(|x| std::fs::read(x).ok());
+    let (status, body) = match body {
+        Some(body) => ("200 OK", body),
+        None => ("404 Not Found", vec![]),
+    };
+    let content_type = if path.ends_with(".whl") {
+        "application/octet-stream"
+    } else {
+        "text/html"
+    };
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    if method != "HEAD" {
+        stream.write_all(&body)?;
+    }
+    Ok(())
+}
+
+/// The file to serve for the path of a request, if any
+fn local_path(root: &Path, path: &str) -> Option<PathBuf> {
+    // Ignore the query string and the fragment, and don't go outside the root
+    let path = path.split(|c| c == '?' || c == '#').next()?;
+    let parts: Vec<_> = path.split('/').filter(|x| !x.is_empty()).collect();
+    if parts.iter().any(|x| x.starts_with('.')) {
+        return None;
+    }
+    let mut res = root.to_path_buf();
+    res.extend(parts);
+    if res.is_dir() {
+        res.push("index.html");
+    }
+    if res.is_file() {
+        Some(res)
+    } else {
+        None
+    }
+}
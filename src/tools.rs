@@ -0,0 +1,224 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::dedupe::site_packages_dirs;
+use crate::dependencies::canonical_name;
+use crate::error::Error;
+use crate::paths::get_cache_path;
+
+/// Name of the file, written in the virtualenv of a tool, listing the
+/// commands exposed in the tools bin directory
+pub const TOOL_FILENAME: &str = "dmenv-tool";
+
+/// Tools installed with `dmenv tool install` each have their own virtualenv
+/// in the dmenv cache, named after the package
+pub fn venv_path(name: &str) -> Result<PathBuf, Error> {
+    Ok(get_cache_path("tools")?.join(canonical_name(name)))
+}
+
+/// Where the commands of the tools are exposed when `DMENV_TOOLS_BIN_DIR` is not set:
+/// `~/.local/bin`, which is usually in PATH already
+pub fn default_bin_dir() -> Result<PathBuf, Error> {
+    #[cfg(not(windows))]
+    let home = std::env::var_os("HOME");
+    #[cfg(windows)]
+    let home = std::env::var_os("USERPROFILE");
+
+    let home = home.ok_or_else(|| Error::Other {
+        message: "Could not find home directory. Set DMENV_TOOLS_BIN_DIR".to_string(),
+    })?;
+    Ok(PathBuf::from(home).join(".local").join("bin"))
+}
+
+/// Names of the installed tools, sorted
+pub fn installed() -> Result<Vec<String>, Error> {
+    let tools_path = get_cache_path("tools")?;
+    let mut res: Vec<_> = file_names(&tools_path)?
+        .into_iter()
+        .filter(|x| tools_path.join(x).join(TOOL_FILENAME).exists())
+        .collect();
+    res.sort();
+    Ok(res)
+}
+
+/// Names of the entries in `dir`, used to find out which scripts
+/// were added to the virtualenv by `pip install`
+pub fn file_names(dir: &Path) -> Result<BTreeSet<String>, Error> {
+    if !dir.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::ReadError {
+        path: dir.to_path_buf(),
+        io_error: e,
+    })?;
+    let res = entries
+        .filter_map(Result::ok)
+        .map(|x| x.file_name().to_string_lossy().to_string())
+        .collect();
+    Ok(res)
+}
+
+pub fn read_exposed(venv: &Path) -> Result<Vec<String>, Error> {
+    let path = venv.join(TOOL_FILENAME);
+    let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })?;
+    Ok(exposed_from_string(&contents))
+}
+
+pub fn write_exposed(venv: &Path, exposed: &[String]) -> Result<(), Error> {
+    let path = venv.join(TOOL_FILENAME);
+    let mut contents = String::new();
+    for name in exposed {
+        contents.push_str(name);
+        contents.push('\n');
+    }
+    std::fs::write(&path, contents).map_err(|e| Error::WriteError {
+        path: path.to_path_buf(),
+        io_error: e,
+    })
+}
+
+fn exposed_from_string(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/// A tool pinned in `tools.lock`, with a line like `black==22.3.0`
+#[derive(Debug, PartialEq)]
+pub struct PinnedTool {
+    pub name: String,
+    pub version: String,
+}
+
+impl PinnedTool {
+    pub fn spec(&self) -> String {
+        format!("{}=={}", self.name, self.version)
+    }
+}
+
+pub fn parse_lock(contents: &str) -> Result<Vec<PinnedTool>, Error> {
+    let mut res = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.splitn(2, "==");
+        match (words.next(), words.next()) {
+            (Some(name), Some(version)) if !name.is_empty() && !version.is_empty() => {
+                res.push(PinnedTool {
+                    name: name.trim().to_string(),
+                    version: version.trim().to_string(),
+                })
+            }
+            _ => {
+                return Err(Error::MalformedLock {
+                    line: i + 1,
+                    details: format!("expected a line like 'name==version', got '{}'", line),
+                })
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Version of the package installed in the virtualenv, found by
+/// looking at the `.dist-info` directories in site-packages
+pub fn installed_version(venv: &Path, name: &str) -> Result<Option<String>, Error> {
+    for site_packages in site_packages_dirs(venv) {
+        for file_name in file_names(&site_packages)? {
+            if let Some((dist_name, version)) = parse_dist_info(&file_name) {
+                if canonical_name(&dist_name) == canonical_name(name) {
+                    return Ok(Some(version));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Parse names like `black-22.3.0.dist-info`. Dashes in the name are
+/// escaped, so everything after the first one is the version
+fn parse_dist_info(file_name: &str) -> Option<(String, String)> {
+    if !file_name.ends_with(".dist-info") {
+        return None;
+    }
+    let stem = &file_name[..file_name.len() - ".dist-info".len()];
+    let mut parts = stem.splitn(2, '-');
+    let name = parts.next()?;
+    let version = parts.next()?;
+    Some((name.to_string(), version.to_string()))
+}
+
+/// Make `source` (a script in the virtualenv of the tool) available as `dest`.
+// Note: scripts generated by pip use an absolute path to the interpreter of the
+// virtualenv, so on Windows, where symlinks require special privileges, a copy works too
+pub fn expose(source: &Path, dest: &Path) -> Result<(), Error> {
+    #[cfg(not(windows))]
+    let res = std::os::unix::fs::symlink(source, dest);
+    #[cfg(windows)]
+    let res = std::fs::copy(source, dest).map(|_| ());
+
+    res.map_err(|e| Error::Other {
+        message: format!(
+            "could not expose {} as {}: {}",
+            source.display(),
+            dest.display(),
+            e
+        ),
+    })
+}
+
+/// True if `dest` was created by `expose()` for a script in `venv`
+pub fn is_exposed_from(dest: &Path, venv: &Path) -> bool {
+    #[cfg(not(windows))]
+    {
+        match std::fs::read_link(dest) {
+            Ok(target) => target.starts_with(venv),
+            Err(_) => false,
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let _ = venv;
+        dest.exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposed_from_string() {
+        assert_eq!(
+            exposed_from_string("black\n\nblackd\n"),
+            vec!["black", "blackd"]
+        );
+    }
+    #[test]
+    fn test_parse_lock() {
+        let contents = "# Formatters\nblack==22.3.0\n\nflake8 == 4.0.1\n";
+        let actual = parse_lock(contents).unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[1].name, "flake8");
+        assert_eq!(actual[1].spec(), "flake8==4.0.1");
+        parse_lock("black\n").unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_dist_info() {
+        assert_eq!(
+            parse_dist_info("flake8_docstrings-1.6.0.dist-info"),
+            Some(("flake8_docstrings".to_string(), "1.6.0".to_string()))
+        );
+        assert_eq!(parse_dist_info("black"), None);
+    }
+}
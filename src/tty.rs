@@ -0,0 +1,140 @@
+//! Let interactive programs (ipython, pdb, ...) started by `dmenv run`
+//! without execv() behave as if they had been started from the shell.
+//!
+//! The child inherits the terminal, and thus its window size and the SIGWINCH
+//! signals sent when it changes, since it stays in the foreground process
+//! group. While it runs, dmenv leaves the keys sending signals (Ctrl-C,
+//! Ctrl-\, Ctrl-Break on Windows) to the child, like a shell does, instead of
+//! dying and taking the child with it. Once the child exits, the terminal
+//! settings are restored, in case it left them modified (for instance in raw
+//! mode after a crash).
+
+#![allow(nonstandard_style)]
+
+/// Restores the signal handlers and the terminal settings when dropped
+pub struct Guard {
+    #[cfg(unix)]
+    handlers: Vec<(libc::c_int, libc::sighandler_t)>,
+    #[cfg(unix)]
+    termios: Option<libc::termios>,
+    #[cfg(windows)]
+    input_mode: Option<DWORD>,
+}
+
+// Note: a handler doing nothing rather than SIG_IGN, because ignored signals
+// stay ignored in the child after exec(), whereas handlers are reset
+#[cfg(unix)]
+extern "C" fn ignore_signal(_: libc::c_int) {}
+
+#[cfg(unix)]
+impl Guard {
+    pub fn new() -> Self {
+        let handler = ignore_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let handlers = [libc::SIGINT, libc::SIGQUIT]
+            .iter()
+            .map(|&signal| (signal, unsafe { libc::signal(signal, handler) }))
+            .collect();
+        let termios = unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            // Note: changing the settings from the background would stop dmenv
+            if libc::isatty(0) == 1
+                && libc::tcgetpgrp(0) == libc::getpgrp()
+                && libc::tcgetattr(0, &mut termios) == 0
+            {
+                Some(termios)
+            } else {
+                None
+            }
+        };
+        Guard { handlers, termios }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(termios) = &self.termios {
+                libc::tcsetattr(0, libc::TCSANOW, termios);
+            }
+            for (signal, handler) in &self.handlers {
+                libc::signal(*signal, *handler);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+type HANDLE = *mut u8;
+#[cfg(windows)]
+type BOOL = i32;
+#[cfg(windows)]
+type DWORD = u32;
+
+#[cfg(windows)]
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+#[cfg(windows)]
+const STD_INPUT_HANDLE: DWORD = -10i32 as DWORD;
+#[cfg(windows)]
+const CTRL_C_EVENT: DWORD = 0;
+#[cfg(windows)]
+const CTRL_BREAK_EVENT: DWORD = 1;
+
+#[cfg(windows)]
+extern "system" {
+    fn GetStdHandle(nStdHandle: DWORD) -> HANDLE;
+    fn GetConsoleMode(hConsoleHandle: HANDLE, lpMode: *mut DWORD) -> BOOL;
+    fn SetConsoleMode(hConsoleHandle: HANDLE, dwMode: DWORD) -> BOOL;
+    fn SetConsoleCtrlHandler(
+        HandlerRoutine: Option<unsafe extern "system" fn(DWORD) -> BOOL>,
+        Add: BOOL,
+    ) -> BOOL;
+}
+
+// Note: a handler rather than SetConsoleCtrlHandler(NULL, TRUE), because the
+// latter is inherited by the child, which would then ignore Ctrl-C too.
+// Closing the console is not handled, so that the job kills the child
+#[cfg(windows)]
+unsafe extern "system" fn ignore_ctrl(ctrl_type: DWORD) -> BOOL {
+    (ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT) as BOOL
+}
+
+#[cfg(windows)]
+unsafe fn console_input() -> Option<HANDLE> {
+    let handle = GetStdHandle(STD_INPUT_HANDLE);
+    if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+        None
+    } else {
+        Some(handle)
+    }
+}
+
+#[cfg(windows)]
+impl Guard {
+    pub fn new() -> Self {
+        unsafe {
+            SetConsoleCtrlHandler(Some(ignore_ctrl), 1);
+            let input_mode = console_input().and_then(|handle| {
+                let mut mode: DWORD = 0;
+                if GetConsoleMode(handle, &mut mode) == 0 {
+                    None
+                } else {
+                    Some(mode)
+                }
+            });
+            Guard { input_mode }
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe {
+            if let (Some(mode), Some(handle)) = (self.input_mode, console_input()) {
+                SetConsoleMode(handle, mode);
+            }
+            SetConsoleCtrlHandler(Some(ignore_ctrl), 0);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::dependencies::canonical_name;
+
+/// Names of popular packages, bundled in the binary
+const POPULAR_PACKAGES: &str = include_str!("popular_packages.txt");
+
+/// If `name` looks like the name of a popular package but is not that package,
+/// return the name of the popular package.
+// Note: this is only a heuristic. Some legitimate packages have names close to
+// popular ones (`attr` and `attrs`, for instance), so only warn about it.
+pub fn lookalike(name: &str) -> Option<&'static str> {
+    let name = canonical_name(name);
+    let popular: Vec<_> = popular_packages().collect();
+    if popular.contains(&name.as_str()) {
+        return None;
+    }
+    popular.into_iter().find(|popular| {
+        // Short names are too likely to be close to each other
+        if popular.len() < 4 {
+            return false;
+        }
+        let max_distance = if popular.len() >= 9 { 2 } else { 1 };
+        distance(&name, popular) <= max_distance
+    })
+}
+
+fn popular_packages() -> impl Iterator<Item = &'static str> {
+    POPULAR_PACKAGES
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+}
+
+/// Number of insertions, deletions, substitutions and transpositions of
+/// adjacent characters needed to go from `a` to `b`
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<_> = a.chars().collect();
+    let b: Vec<_> = b.chars().collect();
+    // d[i][j] is the distance between the first i chars of a and the first j chars of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, value) in d[0].iter_mut().enumerate() {
+        *value = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut value = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                value = value.min(d[i - 2][j - 2] + 1);
+            }
+            d[i][j] = value;
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("requests", "requests"), 0);
+        assert_eq!(distance("reqeusts", "requests"), 1);
+        assert_eq!(distance("request", "requests"), 1);
+        assert_eq!(distance("djagno", "django"), 1);
+        assert_eq!(distance("flask", "click"), 3);
+    }
+
+    #[test]
+    fn test_lookalike() {
+        assert_eq!(lookalike("reqeusts"), Some("requests"));
+        assert_eq!(lookalike("python_dateutils"), Some("python-dateutil"));
+        assert_eq!(lookalike("Requests"), None);
+        assert_eq!(lookalike("my-internal-package"), None);
+        // Too short to tell
+        assert_eq!(lookalike("pyy"), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! Variant channels: named sets of package indexes for some packages, like the
+//! CUDA builds of `torch`. They are defined in the `[variants]` table of
+//! `dmenv.toml`, with the same syntax as `DMENV_INDEXES`:
+//!
+//! ```text
+//! variant = "cpu"
+//!
+//! [variants]
+//! cpu = "torch,torchvision=https://download.pytorch.org/whl/cpu"
+//! cu121 = "torch,torchvision=https://download.pytorch.org/whl/cu121"
+//! ```
+//!
+//! The indexes of the chosen variant are used on top of the other indexes,
+//! and its name is recorded in the lock header (`# Variant: cu121`), since the
+//! locked versions only make sense with these indexes.
+use std::path::Path;
+
+use crate::error::Error;
+use crate::indexes::{self, Index};
+use crate::pip_config::PipConfig;
+use crate::settings::Settings;
+
+pub const LOCK_PREFIX: &str = "# Variant: ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub indexes: Vec<Index>,
+}
+
+/// Name of the variant recorded in the header of the lock, if any
+pub fn from_lock(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .take_while(|x| x.starts_with('#'))
+        .find_map(|x| x.strip_prefix(LOCK_PREFIX))
+        .map(|x| x.trim().to_string())
+}
+
+pub fn lock_header(name: &str) -> String {
+    format!("{}{}\n", LOCK_PREFIX, name)
+}
+
+/// Choose the variant: the one given with `--variant` or `DMENV_VARIANT`, or
+/// the one of the lock, or the default one from `dmenv.toml`. Then add its
+/// indexes to the settings
+pub fn apply(
+    settings: &mut Settings,
+    variants: &[Variant],
+    default: Option<&str>,
+    lock_path: &Path,
+    venv_path: &Path,
+) -> Result<(), Error> {
+    let from_lock = if settings.variant.is_none() && lock_path.exists() {
+        let contents = std::fs::read_to_string(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        from_lock(&contents)
+    } else {
+        None
+    };
+    let name = match settings
+        .variant
+        .clone()
+        .or(from_lock)
+        .or_else(|| default.map(|x| x.to_string()))
+    {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let variant = find(variants, &name)?;
+    let base = if settings.indexes.is_empty() {
+        base_indexes(settings, venv_path)?
+    } else {
+        std::mem::take(&mut settings.indexes)
+    };
+    settings.indexes = variant.indexes.iter().cloned().chain(base).collect();
+    settings.variant = Some(name);
+    Ok(())
+}
+
+fn find<'a>(variants: &'a [Variant], name: &str) -> Result<&'a Variant, Error> {
+    variants.iter().find(|x| x.name == name).ok_or_else(|| {
+        let names: Vec<_> = variants.iter().map(|x| x.name.as_str()).collect();
+        let expected = if names.is_empty() {
+            "no variants are defined in dmenv.toml".to_string()
+        } else {
+            format!("expected one of: {}", names.join(", "))
+        };
+        Error::Other {
+            message: format!("unknown variant '{}', {}", name, expected),
+        }
+    })
+}
+
+/// The indexes pip would use without dmenv, for the packages outside of the variant
+fn base_indexes(settings: &Settings, venv_path: &Path) -> Result<Vec<Index>, Error> {
+    let urls = match &settings.index_url {
+        Some(url) => vec![url.to_string()],
+        None => PipConfig::load(venv_path)?.index_urls(),
+    };
+    if urls.is_empty() {
+        return Ok(vec![Index::from_url(indexes::DEFAULT_INDEX_URL)?]);
+    }
+    urls.iter().map(|x| Index::from_url(x)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants() -> Vec<Variant> {
+        vec![Variant {
+            name: "cu121".to_string(),
+            indexes: indexes::parse("torch,torchvision=https://download.pytorch.org/whl/cu121")
+                .unwrap(),
+        }]
+    }
+
+    #[test]
+    fn test_lock_header() {
+        let contents = format!(
+            "# Lock format: 2\n{}torch==2.1.0+cu121\n",
+            lock_header("cu121")
+        );
+        assert_eq!(from_lock(&contents).unwrap(), "cu121");
+        assert!(from_lock("# Variant\ntorch==2.1.0\n").is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut settings = Settings {
+            indexes: indexes::parse("https://pypi.acme.corp/simple").unwrap(),
+            variant: Some("cu121".to_string()),
+            ..Default::default()
+        };
+        let nowhere = Path::new("/does/not/exist");
+        apply(&mut settings, &variants(), None, nowhere, nowhere).unwrap();
+        let urls: Vec<_> = settings.indexes.iter().map(|x| x.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://download.pytorch.org/whl/cu121",
+                "https://pypi.acme.corp/simple"
+            ]
+        );
+        assert_eq!(
+            indexes::for_package(&settings.indexes, "torch")[0].url,
+            "https://download.pytorch.org/whl/cu121"
+        );
+    }
+
+    #[test]
+    fn test_unknown_variant() {
+        let mut settings = Settings::default();
+        let nowhere = Path::new("/does/not/exist");
+        let err = apply(&mut settings, &variants(), Some("rocm"), nowhere, nowhere).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown variant 'rocm', expected one of: cu121"
+        );
+    }
+}
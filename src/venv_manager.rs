@@ -1,18 +1,84 @@
 use colored::*;
-use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 #[cfg(unix)]
 use crate::execv::execv;
 #[cfg(windows)]
 use crate::win_job;
 
+use crate::bench::Stats;
+use crate::build_isolation::BuildSettings;
+use crate::bump_batch::{self, BumpResult, Instruction};
+use crate::bundle::{self, BundleFormat};
+use crate::cache;
+use crate::changeset::{self, Changeset};
+use crate::ci::GithubWorkflow;
 use crate::cmd::*;
-use crate::dependencies::FrozenDependency;
+use crate::coverage;
+use crate::dedupe;
+use crate::dependencies::{canonical_name, FrozenDependency, LockedDependency};
+use crate::digest::{self, VenvDigest};
+use crate::entry_points;
+use crate::env_file::{self, EnvFormat};
 use crate::error::*;
-use crate::lock::Lock;
-use crate::paths::Paths;
-use crate::python_info::PythonInfo;
-use crate::settings::Settings;
+use crate::events::{self, Event};
+use crate::external::ExternalCommand;
+use crate::extras;
+use crate::find_links::FindLinks;
+use crate::hash;
+use crate::history;
+use crate::index_auth;
+use crate::indexes::{self, Index};
+use crate::infer;
+use crate::lock::{self, InstallBatch, Lock, LockChange};
+use crate::lock_backup::{self, Backup};
+use crate::lock_hashes::{self, Algorithm};
+use crate::lock_stats;
+use crate::logs;
+use crate::manifest::{Manifest, Snapshot, MANIFEST_FILENAME};
+use crate::marker::compare_versions;
+use crate::migrate;
+use crate::msys::{self, PosixLayer};
+use crate::notices::{self, NOTICES_FILENAME};
+use crate::outdated;
+use crate::paths::{get_cache_path, get_cache_root, Paths, PathsResolver};
+use crate::paths::{
+    DEV_LOCK_FILENAME, DEV_REQUIREMENTS_IN_FILENAME, PROD_LOCK_FILENAME, REQUIREMENTS_IN_FILENAME,
+    TOOLS_LOCK_FILENAME,
+};
+use crate::pip_config::PipConfig;
+use crate::plugin::{Context, Plugin};
+use crate::policy;
+use crate::precommit;
+use crate::project_config;
+use crate::project_version::{self, VersionBump};
+use crate::protect;
+use crate::pyproject;
+use crate::python_info::{self, PythonInfo};
+use crate::release_info::{self, Release};
+use crate::relocate::{self, Relocation, RELOCATION_FILENAME};
+use crate::remote::{self, Remote};
+use crate::rename;
+use crate::run_env::{self, Change, Explanation};
+use crate::runner::{CommandRunner, Process, SystemRunner};
+use crate::scaffold::{self, Scaffold};
+use crate::script_metadata::{ScriptMetadata, SCRIPT_READY_FILENAME};
+use crate::service::{Service, ServiceFormat};
+use crate::settings::{ProjectType, Settings};
+use crate::setup_py;
+use crate::shared;
+use crate::shim;
+use crate::simulate_ci::{self, Report, Step};
+use crate::state::{self, VenvState};
+use crate::template;
+use crate::tools::{self, PinnedTool, TOOL_FILENAME};
+use crate::typosquat;
+use crate::variants;
+use crate::watch::Watcher;
+use crate::wheel_check;
 
 struct LockMetadata {
     dmenv_version: String,
@@ -26,6 +92,80 @@ struct LockMetadata {
 pub struct LockOptions {
     pub python_version: Option<String>,
     pub sys_platform: Option<String>,
+    /// Allow changing the version of pinned dependencies
+    pub override_pins: bool,
+    /// See `VenvManager::check_foreign_venv()`
+    pub allow_foreign_venv: bool,
+    /// See the `build_isolation` module
+    pub no_build_isolation: bool,
+    pub build_requirements: Vec<String>,
+    /// Extras to lock besides the ones already locked, see the `extras` module
+    pub extras: Vec<String>,
+    /// Add hashes to the lock, see `VenvManager::add_index_hashes()`
+    pub hashes: bool,
+}
+
+/// How dependencies from the lock get installed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Installer {
+    /// Run `pip install --requirement` on the lock
+    Pip,
+    /// Experimental: install pure-Python wheels directly, and
+    /// use pip for everything else
+    Native,
+}
+
+impl Default for Installer {
+    fn default() -> Self {
+        Installer::Pip
+    }
+}
+
+impl std::str::FromStr for Installer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pip" => Ok(Installer::Pip),
+            "native" => Ok(Installer::Native),
+            _ => Err(Error::Other {
+                message: format!("unknown installer '{}', expected 'pip' or 'native'", s),
+            }),
+        }
+    }
+}
+
+/// A value used to generate setup.py, see `VenvManager::init()`
+struct InitValue {
+    label: &'static str,
+    value: String,
+    /// True if the value was not given on the command line
+    inferred: bool,
+}
+
+/// Represents options passed to `dmenv bundle`,
+/// see `cmd::SubCommand::Bundle`
+pub struct BundleOptions {
+    pub format: BundleFormat,
+    pub entry_point: Option<String>,
+    pub output: Option<String>,
+    pub platform: String,
+    pub layer: bool,
+}
+
+/// Options of `dmenv run`, and of its shortcuts `dmenv python` and `dmenv pip`
+#[derive(Default)]
+pub struct RunOptions {
+    /// See `VenvManager::run_no_exec()`
+    pub no_exec: bool,
+    pub reinstall_if_stale: bool,
+    pub fail_if_stale: bool,
+    /// Allow commands like `pip install`, see `protect::is_venv_mutation()`
+    pub allow_venv_mutation: bool,
+    /// See `VenvManager::apply_preset()`
+    pub preset: Option<String>,
+    /// Show the command, working directory and environment first, see the `run_env` module
+    pub explain: bool,
 }
 
 #[derive(Default)]
@@ -33,12 +173,45 @@ pub struct LockOptions {
 /// see `cmd::SubCommand::Install`
 pub struct InstallOptions {
     pub develop: bool,
+    pub installer: Installer,
+    /// See `VenvManager::check_foreign_venv()`
+    pub allow_foreign_venv: bool,
+    /// Build packages in the virtualenv, whatever the lock says
+    pub no_build_isolation: bool,
+    /// Also install the locks of these extras
+    pub extras: Vec<String>,
+    /// Check the age of the locked releases, see `check_lock_freshness()`
+    pub enforce_policy: bool,
+    /// See `check_wheels()`
+    pub check_wheels: bool,
 }
 
 pub struct VenvManager {
     paths: Paths,
     python_info: PythonInfo,
     settings: Settings,
+    plugins: Vec<Box<dyn Plugin>>,
+    /// Set once the credential helper has been run, see `index_auth_url()`
+    index_auth: RefCell<Option<Option<String>>>,
+    /// See `reachable_indexes()`
+    reachable_indexes: RefCell<Option<Vec<(Index, String)>>>,
+    /// See `pip_find_links()`
+    find_links: RefCell<Option<FindLinks>>,
+    /// False when pip builds packages in the virtualenv, see `build_settings()`
+    build_isolation: Cell<bool>,
+    /// Log of the pip commands when running with `--quiet`, see `logs::new_log_path()`
+    pip_log: RefCell<Option<PathBuf>>,
+    /// Working directory of `dmenv run --no-exec`, see `apply_preset()`
+    run_dir: RefCell<Option<PathBuf>>,
+    /// True while running the command of `dmenv run --no-exec`, see `run_no_exec()`
+    interactive: Cell<bool>,
+    /// Environment variables of the command given to `dmenv run`, see `apply_preset()`
+    /// and `setup_subprocess_coverage()`
+    run_env: RefCell<Vec<(String, String)>>,
+    /// Environment variables of the processes started in the virtualenv, see `bench()`
+    /// and `simulate_ci()`
+    venv_env: RefCell<Vec<(String, String)>>,
+    runner: Box<dyn CommandRunner>,
 }
 
 impl VenvManager {
@@ -47,29 +220,165 @@ impl VenvManager {
             paths,
             settings,
             python_info,
+            plugins: vec![],
+            index_auth: RefCell::new(None),
+            reachable_indexes: RefCell::new(None),
+            find_links: RefCell::new(None),
+            build_isolation: Cell::new(true),
+            pip_log: RefCell::new(None),
+            run_dir: RefCell::new(None),
+            interactive: Cell::new(false),
+            run_env: RefCell::new(vec![]),
+            venv_env: RefCell::new(vec![]),
+            runner: Box::new(SystemRunner),
+        }
+    }
+
+    /// Use another way to run Python processes, see `CommandRunner`
+    pub fn with_runner(mut self, runner: Box<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    pub fn with_plugins(mut self, plugins: Vec<Box<dyn Plugin>>) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    fn plugin_context(&self) -> Context<'_> {
+        Context {
+            project: &self.paths.project,
+            venv: &self.paths.venv,
+            lock: &self.paths.lock,
+            python_version: &self.python_info.version,
+            production: self.settings.production,
         }
     }
 
+    /// Call the same hook for every plugin, in order
+    fn run_hooks<F>(&self, hook: F) -> Result<(), Error>
+    where
+        F: Fn(&dyn Plugin, &Context) -> Result<(), Error>,
+    {
+        let context = self.plugin_context();
+        for plugin in &self.plugins {
+            hook(plugin.as_ref(), &context).map_err(|e| Error::Other {
+                message: format!("plugin {}: {}", plugin.name(), e),
+            })?;
+        }
+        Ok(())
+    }
+
     /// Clean virtualenv. No-op if the virtualenv does not exist
     pub fn clean(&self) -> Result<(), Error> {
         print_info_1(&format!("Cleaning {}", &self.paths.venv.display()));
         if !self.paths.venv.exists() {
             return Ok(());
         }
+        // Read-only files can't be removed on Windows, and neither can
+        // the contents of read-only directories on Unix
+        if protect::is_frozen(&self.paths.venv) {
+            protect::set_frozen(&self.paths.venv, false)?;
+        }
         std::fs::remove_dir_all(&self.paths.venv).map_err(|e| Error::Other {
             message: format!("could not remove {}: {}", &self.paths.venv.display(), e),
         })
     }
 
     /// Runs `python setup.py` develop. Also called by `install` (unless InstallOptions.develop is false)
+    /// What was added to the virtualenv is recorded, so that `develop --uninstall` can remove it
     // Note: `lock()` will use `pip install --editable .` to achieve the same effect
     pub fn develop(&self) -> Result<(), Error> {
+        if self.settings.project_type == ProjectType::Requirements {
+            return Err(Error::Other {
+                message: "Nothing to develop: the project only has requirements".to_string(),
+            });
+        }
+        self.expect_venv()?;
+        let before = Snapshot::take(&self.paths.venv, None)?;
+        self.run_develop()?;
+        let after = Snapshot::take(&self.paths.venv, Some(&before))?;
+        self.record_develop_manifest(Manifest::diff(&before, &after))
+    }
+
+    /// Remove everything `develop` added to the virtualenv (egg-link, .pth entries,
+    /// entry point scripts ...)
+    pub fn develop_uninstall(&self) -> Result<(), Error> {
+        self.expect_venv()?;
+        let path = self.paths.venv.join(MANIFEST_FILENAME);
+        if !path.exists() {
+            return Err(Error::Other {
+                message: format!(
+                    "{} not found. Was the project installed with `dmenv develop`?",
+                    path.display()
+                ),
+            });
+        }
+        print_info_1("Removing project from the virtualenv");
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let manifest = Manifest::from_string(&contents)?;
+        for changed in manifest.remove(&self.paths.venv)? {
+            print_info_2(&format!("Cleaned {}", changed.display()));
+        }
+        std::fs::remove_file(&path).map_err(|e| Error::Other {
+            message: format!("could not remove {}: {}", path.display(), e),
+        })?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    fn record_develop_manifest(&self, manifest: Manifest) -> Result<(), Error> {
+        let path = self.paths.venv.join(MANIFEST_FILENAME);
+        let mut res = if path.exists() {
+            let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            Manifest::from_string(&contents)?
+        } else {
+            Manifest::default()
+        };
+        res.merge(manifest);
+        if res.is_empty() {
+            return Ok(());
+        }
+        std::fs::write(&path, res.to_string()).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+    }
+
+    fn run_develop(&self) -> Result<(), Error> {
+        for extra_package in &self.paths.extra_packages {
+            self.run_develop_in(extra_package)?;
+        }
+        self.run_develop_in(&self.paths.package)
+    }
+
+    fn run_develop_in(&self, package: &Path) -> Result<(), Error> {
+        let setup_py = package.join("setup.py");
+        if !setup_py.exists() && package.join("pyproject.toml").exists() {
+            // No setup.py to run, but pip knows how to install the project
+            // in development mode (see PEP 660)
+            print_info_2(&format!(
+                "Installing {} in development mode",
+                package.display()
+            ));
+            return self.run_cmd_in_venv_from(
+                "python",
+                vec!["-m", "pip", "install", "--no-deps", "--editable", "."],
+                package,
+            );
+        }
         print_info_2("Running setup_py.py develop");
-        if !self.paths.setup_py.exists() {
+        if !setup_py.exists() {
             return Err(Error::MissingSetupPy {});
         }
 
-        self.run_cmd_in_venv("python", vec!["setup.py", "develop", "--no-deps"])
+        self.run_cmd_in_venv_from("python", vec!["setup.py", "develop", "--no-deps"], package)
     }
 
     /// Install dependencies from lock file (production.lock or requirements.lock), depending
@@ -84,190 +393,3146 @@ impl VenvManager {
             });
         }
 
+        self.check_lock_format()?;
+        self.check_lock_variant()?;
+        self.check_lock_policy()?;
+        if install_options.enforce_policy {
+            self.check_lock_freshness()?;
+        }
+        self.check_foreign_venv(install_options.allow_foreign_venv)?;
+        self.check_extra_locks(&install_options.extras)?;
+        self.run_hooks(|plugin, context| plugin.pre_install(context))?;
+        self.preflight_indexes()?;
         self.ensure_venv()?;
-        self.install_from_lock()?;
-
-        if install_options.develop {
-            self.develop()?;
+        self.check_out_of_band_changes()?;
+        let build_settings = self.build_settings(install_options.no_build_isolation, &[])?;
+        self.install_build_requirements(&build_settings)?;
+        if install_options.check_wheels {
+            self.check_wheels()?;
         }
-        Ok(())
-    }
-
-    /// Run a program from the virtualenv, making sure it dies
-    /// when we get killed and that the exit code is forwarded
-    pub fn run(&self, args: &[String]) -> Result<(), Error> {
-        #[cfg(windows)]
-        {
-            unsafe {
-                win_job::setup();
-            }
-            self.run_no_exec(args)
+        if self.settings.shared_store && install_options.installer != Installer::Native {
+            print_warning("The shared store is only used with `--installer native`");
+        }
+        match install_options.installer {
+            Installer::Pip => self.install_from_lock()?,
+            Installer::Native => self.install_from_lock_native()?,
+        }
+        for extra in &install_options.extras {
+            self.install_from_extra_lock(extra)?;
         }
+        self.emit_installed_packages()?;
 
-        #[cfg(unix)]
-        {
-            let bin_path = &self.get_path_in_venv(&args[0])?;
-            let bin_path_str = bin_path.to_str().ok_or(Error::Other {
-                message: "Could not convert binary path to String".to_string(),
-            })?;
-            let mut fixed_args: Vec<String> = args.to_vec();
-            fixed_args[0] = bin_path_str.to_string();
-            execv(bin_path_str, fixed_args)
+        // Note: requirements-only projects have nothing to install in development mode
+        if install_options.develop && self.settings.project_type == ProjectType::Package {
+            self.develop()?;
         }
+        self.write_state()?;
+        self.run_hooks(|plugin, context| plugin.post_install(context))
     }
 
-    /// On Windows:
-    ///   - same as run
-    /// On Linux:
-    ///   - same as run, but create a new process instead of using execv()
-    // Note: mostly for tests. We want to *check* the return code of
-    // `dmenv run` and so we need a child process
-    pub fn run_no_exec(&self, args: &[String]) -> Result<(), Error> {
+    /// Make sure the lock did not change since the last `install` or `lock`.
+    /// If it did, either run `install` again (if `reinstall` is true) or return
+    /// an error.
+    pub fn check_stale(&self, reinstall: bool) -> Result<(), Error> {
         self.expect_venv()?;
-        let cmd = args[0].clone();
-        let args: Vec<&str> = args.iter().skip(1).map(String::as_str).collect();
-        self.run_cmd_in_venv(&cmd, args)
+        if !self.is_stale()? {
+            return Ok(());
+        }
+        if !reinstall {
+            return Err(Error::StaleVenv {
+                lock_path: self.paths.lock.clone(),
+            });
+        }
+        print_warning(&format!(
+            "{} changed since last install",
+            self.paths.lock.display()
+        ));
+        self.install(&InstallOptions {
+            develop: true,
+            ..Default::default()
+        })
     }
 
-    /// (Re)generate the lock file
-    //
-    // Notes:
-    //
-    // * Abort if `setup.py` is not found
-    // * Create the virtualenv if required
-    // * Always upgrade pip :
-    //    * If that fails, we know if the virtualenv is broken
-    //    * Also, we know sure that `pip` can handle all the options
-    //      (such as `--local`, `--exclude-editable`) we use in the other functions
-    // * The path of the lock file is computed by PathsResolver.
-    //     See PathsResolver.paths() for details
-    //
-    // * Delegates the actual work to `write_lock()`
-    //
-    pub fn lock(&self, lock_options: &LockOptions) -> Result<(), Error> {
-        print_info_1("Locking dependencies");
-        if !self.paths.setup_py.exists() {
-            return Err(Error::MissingSetupPy {});
+    // Note: if there is no state file, we don't know what got installed
+    // and so we assume the virtualenv is stale
+    fn is_stale(&self) -> Result<bool, Error> {
+        match VenvState::read(&self.paths.venv)? {
+            None => Ok(true),
+            Some(state) => Ok(state.lock_hash != self.lock_hash()?),
         }
-
-        self.ensure_venv()?;
-        self.upgrade_pip()?;
-
-        self.install_editable()?;
-
-        self.write_lock(&lock_options)?;
-        Ok(())
     }
 
-    /// Show the dependencies inside the virtualenv.
-    // Note: Run `pip list` so we get what's *actually* installed, not just
-    // the contents of the lock file
-    pub fn show_deps(&self) -> Result<(), Error> {
-        self.run_cmd_in_venv("pip", vec!["list"])
+    fn lock_hash(&self) -> Result<String, Error> {
+        let lock_path = &self.paths.lock;
+        let contents = std::fs::read(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        Ok(hash::hash_hex(&contents))
     }
 
-    /// Show the resolved virtualenv path.
-    //
-    // See `PathsResolver.paths()` for details
-    pub fn show_venv_path(&self) -> Result<(), Error> {
-        println!("{}", self.paths.venv.display());
-        Ok(())
+    /// Key used to cache the virtualenv on CI, see `cache::key()`
+    fn cache_key(&self) -> Result<String, Error> {
+        let lock_path = &self.paths.lock;
+        let lock_contents = std::fs::read(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        Ok(cache::key(
+            &lock_contents,
+            &self.python_info.tag(),
+            &self.python_info.platform,
+            &self.settings,
+        ))
     }
 
-    /// Same has `show_venv_path`, but add the correct subfolder
-    /// (`bin` on Linux and macOS, `Scripts` on Windows).
-    pub fn show_venv_bin_path(&self) -> Result<(), Error> {
-        let bin_path = &self.get_venv_bin_path();
-        println!("{}", bin_path.display());
+    /// Show the key used to cache the virtualenv
+    pub fn show_cache_key(&self) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
+            });
+        }
+        println!("{}", self.cache_key()?);
         Ok(())
     }
 
-    /// Creates `setup.py` if it does not exist.
-    pub fn init(&self, name: &str, version: &str, author: &Option<String>) -> Result<(), Error> {
-        let path = &self.paths.setup_py;
-        if path.exists() {
-            return Err(Error::FileExists {
-                path: path.to_path_buf(),
+    /// Show the GitHub Actions steps needed to install the project, with
+    /// the virtualenv cached
+    pub fn ci_github(&self, set_output: bool) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
             });
         }
-        // Warning: make sure the source file in `src/setup.in.py` contains all those
-        // placeholders
-        let template = include_str!("setup.in.py");
-        let with_name = template.replace("<NAME>", name);
-        let with_version = with_name.replace("<VERSION>", version);
-        let to_write = if let Some(author) = author {
-            with_version.replace("<AUTHOR>", author)
+        let relative_to_project = |path: &Path| {
+            path.strip_prefix(&self.paths.project)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string()
+        };
+        let python_version = bundle::short_version(&self.python_info.version);
+        let venv = relative_to_project(&self.paths.venv);
+        let lock = relative_to_project(lock_path);
+        let cache_key = self.cache_key()?;
+        let workflow = GithubWorkflow {
+            python_version: &python_version,
+            venv: &venv,
+            lock: &lock,
+            cache_key: &cache_key,
+        };
+        if set_output {
+            print!("{}", workflow.render_outputs());
         } else {
-            with_version
+            print!("{}", workflow.render_steps(&self.template_dirs())?);
+        }
+        Ok(())
+    }
+
+    /// Record the hash of the lock, the settings and the dmenv version
+    /// in the virtualenv.
+    // Note: called when the virtualenv and the lock are known to be
+    // in sync, after `install` or `lock`
+    fn write_state(&self) -> Result<(), Error> {
+        let previous = VenvState::read(&self.paths.venv).unwrap_or(None);
+        let state = VenvState::new(
+            &self.python_info.tag(),
+            &self.lock_hash()?,
+            &self.settings,
+            VenvDigest::compute(&self.paths.venv),
+            previous,
+        );
+        state.write(&self.paths.venv)
+    }
+
+    /// Warn if packages were installed, removed or changed in the virtualenv
+    /// since the last `install` or `lock`, for instance with `dmenv run pip install`.
+    /// Only done when `settings.detect_changes` is true, because this requires reading
+    /// all the RECORD files
+    fn check_out_of_band_changes(&self) -> Result<(), Error> {
+        if !self.settings.detect_changes {
+            return Ok(());
+        }
+        let state = match VenvState::read(&self.paths.venv)? {
+            Some(state) => state,
+            None => return Ok(()),
         };
-        std::fs::write(&path, to_write).map_err(|e| Error::WriteError {
-            path: path.to_path_buf(),
-            io_error: e,
-        })?;
-        print_info_1("Generated a new setup.py");
+        // Note: states written by older dmenv versions have no records_hash
+        if state.records_hash.is_empty() {
+            return Ok(());
+        }
+        let digest = VenvDigest::compute(&self.paths.venv);
+        if digest.records_hash == state.records_hash {
+            return Ok(());
+        }
+        let (added, removed) = digest::diff(&state.packages, &digest.packages);
+        let mut message = "virtualenv was changed outside of dmenv.".to_string();
+        for package in &added {
+            message.push_str(&format!("\n + {}", package));
+        }
+        for package in &removed {
+            message.push_str(&format!("\n - {}", package));
+        }
+        message.push_str("\n Run `dmenv clean` and `dmenv install` to reconcile it with the lock");
+        print_warning(&message);
         Ok(())
     }
 
-    /// Bump a dependency in the lock file
-    //
-    // Note: most of the work is delegated to the Lock struct. Either `Lock.git_bump()`or
-    // `Lock.bump()` is called, depending on the value of the `git` argument.
-    pub fn bump_in_lock(&self, name: &str, version: &str, git: bool) -> Result<(), Error> {
-        print_info_1(&format!("Bumping {} to {} ...", name, version));
-        let path = &self.paths.lock;
-        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
-            path: path.to_path_buf(),
-            io_error: e,
-        })?;
-        let mut lock = Lock::from_string(&lock_contents)?;
-        let changed = if git {
-            lock.git_bump(name, version)
+    /// Show the state of the virtualenv, as recorded by `install` and `lock`
+    pub fn status(&self) -> Result<(), Error> {
+        self.expect_venv()?;
+        println!("virtualenv: {}", self.paths.venv.display());
+        println!("lock: {}", self.paths.lock.display());
+        let state = match VenvState::read(&self.paths.venv)? {
+            None => {
+                print_warning("No state recorded. Please run `dmenv install`");
+                return Ok(());
+            }
+            Some(state) => state,
+        };
+        println!("installed by: dmenv {}", state.dmenv_version);
+        println!("python version: {}", state.python_version);
+        println!("created: {} (UNIX time)", state.created);
+        println!("updated: {} (UNIX time)", state.updated);
+        for (key, value) in &state.settings {
+            println!("{}: {}", key, value);
+        }
+        if self.is_stale()? {
+            println!(
+                "{}",
+                "out of date: the lock changed since last install".yellow()
+            );
         } else {
-            lock.bump(name, version)
-        }?;
-        if !changed {
-            print_warning(&format!("Dependency {} already up-to-date", name.bold()));
-            return Ok(());
+            println!("{}", "up to date".green());
         }
-        let new_contents = lock.to_string();
-        std::fs::write(&path, &new_contents).map_err(|e| Error::WriteError {
-            path: path.to_path_buf(),
-            io_error: e,
-        })?;
-        println!("{}", "ok!".green());
         Ok(())
     }
 
-    /// Ensure the virtualenv exists
-    //
-    // Note: this is *only* called by `install()` and `lock()`.
-    // All the other methods requires the virtualenv to exist and
-    // won't create it.
-    fn ensure_venv(&self) -> Result<(), Error> {
+    /// Look for common problems: missing virtualenv or lock, unreachable
+    /// package indexes ...
+    pub fn doctor(&self) -> Result<(), Error> {
+        print_info_1("Checking project");
+        let mut problems = vec![];
+        println!(
+            "python: {} ({})",
+            self.python_info.binary.display(),
+            self.python_info.version
+        );
         if self.paths.venv.exists() {
-            print_info_2(&format!(
-                "Using existing virtualenv: {}",
+            println!("virtualenv: {}", self.paths.venv.display());
+        } else {
+            problems.push(format!(
+                "virtualenv {} does not exist. Run `dmenv install`",
                 self.paths.venv.display()
             ));
+        }
+        if self.paths.lock.exists() {
+            println!("lock: {}", self.paths.lock.display());
+            if self.paths.venv.exists() && self.is_stale()? {
+                problems.push("the lock changed since last install".to_string());
+            }
         } else {
-            self.create_venv()?;
+            problems.push(format!(
+                "{} does not exist. Run `dmenv lock`",
+                self.paths.lock.display()
+            ));
         }
-        Ok(())
-    }
 
-    /// Make sure the virtualenv exists, or return an error
-    //
-    // Note: this must be called by any method that requires the
-    // virtualenv to exist, like `show_deps` or `run`:
-    // this ensures that error messages printed when the
-    // virtualenv does not exist are consistent.
-    fn expect_venv(&self) -> Result<(), Error> {
-        if !self.paths.venv.exists() {
-            return Err(Error::MissingVenv {
-                path: self.paths.venv.clone(),
-            });
+        let statuses = indexes::check(
+            &*self.runner,
+            &self.python_info.binary,
+            &self.configured_index_urls()?,
+        )?;
+        for status in &statuses {
+            match &status.error {
+                None => println!("index: {} {}", status.url, "ok".green()),
+                Some(error) => {
+                    println!("index: {} {}", status.url, "unreachable".red());
+                    problems.push(format!("{} is unreachable: {}", status.url, error));
+                }
+            }
         }
-        Ok(())
+        for hint in indexes::proxy_hints() {
+            println!("proxy: {}", hint);
+        }
+        let (pip_config, pip_warnings) = self.effective_pip_config()?;
+        for setting in pip_config.settings() {
+            println!(
+                "pip: {} = {} ({})",
+                setting.key,
+                setting.display_value(),
+                setting.source
+            );
+        }
+        // Note: pip still works with these, so they are not problems
+        for warning in &pip_warnings {
+            print_warning(warning);
+        }
+
+        if problems.is_empty() {
+            println!("{}", "ok!".green());
+            return Ok(());
+        }
+        for problem in &problems {
+            print_warning(problem);
+        }
+        Err(Error::Other {
+            message: format!("{} problem(s) found", problems.len()),
+        })
+    }
+
+    /// Download everything listed in the lock into the wheels cache, so that
+    /// `install` does not need to download anything.
+    /// When `watch` is true, do it again each time the lock changes.
+    pub fn prefetch(&self, watch: bool) -> Result<(), Error> {
+        print_info_1("Prefetching dependencies");
+        self.prefetch_once()?;
+        if !watch {
+            return Ok(());
+        }
+
+        let mut watcher = Watcher::new(vec![self.paths.lock.clone()]);
+        loop {
+            Self::print_watching(&watcher);
+            watcher.wait_for_changes();
+            // Don't stop watching because of a transient error (network
+            // issue, lock being edited ...)
+            if let Err(e) = self.prefetch_once() {
+                print_error(&e.to_string());
+            }
+        }
+    }
+
+    /// Re-run `install` (or the given command from the virtualenv) each time
+    /// `setup.py`, `pyproject.toml` or the lock changes
+    pub fn watch(&self, cmd: &[String]) -> Result<(), Error> {
+        let to_watch = vec![
+            self.paths.setup_py.clone(),
+            self.pyproject_path(),
+            self.paths.lock.clone(),
+        ];
+        let mut watcher = Watcher::new(to_watch);
+        loop {
+            Self::print_watching(&watcher);
+            let changed = watcher.wait_for_changes();
+            let names: Vec<_> = changed
+                .iter()
+                .filter_map(|p| p.file_name())
+                .map(|n| n.to_string_lossy())
+                .collect();
+            print_info_1(&format!("Changed: {}", names.join(", ")));
+            let res = if cmd.is_empty() {
+                self.install(&InstallOptions {
+                    develop: true,
+                    ..Default::default()
+                })
+            } else {
+                self.run_no_exec(cmd)
+            };
+            match res {
+                Ok(()) => println!("{}", "ok!".green()),
+                Err(e) => print_error(&e.to_string()),
+            }
+        }
+    }
+
+    fn print_watching(watcher: &Watcher) {
+        let names: Vec<_> = watcher
+            .paths()
+            .iter()
+            .filter_map(|p| p.file_name())
+            .map(|n| n.to_string_lossy())
+            .collect();
+        print_info_1(&format!(
+            "Watching {} for changes (press Ctrl-C to stop)",
+            names.join(", ")
+        ));
+    }
+
+    fn prefetch_once(&self) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
+            });
+        }
+        print_info_2(&format!(
+            "Downloading dependencies from {}",
+            lock_path.display()
+        ));
+        // Note: use the "global" interpreter, so that prefetching works
+        // even before the virtualenv is created
+        let cache_path = get_cache_path("wheels")?;
+        let lock_str = lock_path.to_string_lossy();
+        let cache_str = cache_path.to_string_lossy();
+        let args = vec![
+            "-m",
+            "pip",
+            "download",
+            "--requirement",
+            &lock_str,
+            "--dest",
+            &cache_str,
+        ];
+        let python_binary = &self.python_info.binary;
+        Self::print_cmd(&python_binary.to_string_lossy(), &args);
+        let process = Process::new(python_binary)
+            .args(&args)
+            .dir(&self.paths.project);
+        if !self.runner.status(&process)? {
+            return Err(Error::Other {
+                message: "failed to download dependencies".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Archives `dmenv hash` looks at: the ones in the wheels cache, filled by
+    /// `dmenv prefetch`, and the ones in the vendor directory
+    fn hashed_archives(&self, vendor: &Option<String>) -> Result<Vec<PathBuf>, Error> {
+        let mut dirs = vec![get_cache_path("wheels")?];
+        if let Some(vendor) = vendor {
+            dirs.push(self.paths.project.join(vendor));
+        }
+        lock_hashes::find_archives(&dirs)
+    }
+
+    /// Write the hashes of the archives found for each entry of the lock
+    pub fn add_hashes(
+        &self,
+        algorithm: Option<Algorithm>,
+        vendor: &Option<String>,
+    ) -> Result<(), Error> {
+        let algorithm = algorithm.unwrap_or(self.settings.hash_algorithm);
+        print_info_1(&format!("Adding {} hashes to the lock", algorithm.name()));
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let entries = lock_hashes::entries(&lock_contents);
+        let archives = self.hashed_archives(vendor)?;
+        let digests = lock_hashes::digests(
+            self.runner.as_ref(),
+            &self.python_info.binary,
+            algorithm.name(),
+            &archives,
+        )?;
+        let mut hashes = std::collections::BTreeMap::new();
+        let mut missing = vec![];
+        for entry in &entries {
+            let mut entry_hashes: Vec<_> = digests
+                .iter()
+                .filter(|(path, _)| entry.matches(path))
+                .map(|(_, digest)| digest.to_string())
+                .collect();
+            if entry_hashes.is_empty() {
+                missing.push(entry.name.as_str());
+                continue;
+            }
+            entry_hashes.sort();
+            entry_hashes.dedup();
+            hashes.insert(
+                (entry.name.to_string(), entry.version.to_string()),
+                entry_hashes,
+            );
+        }
+        let new_contents = lock_hashes::with_hashes(&lock_contents, &hashes);
+        if !missing.is_empty() {
+            // Note: with --require-hashes, pip refuses to install a lock where
+            // some entries have no hashes
+            print_warning(&format!(
+                "No archive found for: {} - run `dmenv prefetch` or use --vendor",
+                missing.join(", ")
+            ));
+        }
+        if new_contents == lock_contents {
+            print_info_2("Lock already up-to-date");
+            return Ok(());
+        }
+        self.save_lock(Some(&lock_contents), &new_contents)?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Compute the hashes of the archives again and compare them to the ones
+    /// in the lock
+    pub fn verify_hashes(&self, vendor: &Option<String>) -> Result<(), Error> {
+        print_info_1("Verifying the hashes of the lock");
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let entries = lock_hashes::entries(&lock_contents);
+        let archives = self.hashed_archives(vendor)?;
+        let mut algorithms: Vec<_> = entries.iter().flat_map(|x| x.algorithms()).collect();
+        algorithms.sort();
+        algorithms.dedup();
+        let mut digests = vec![];
+        for algorithm in algorithms {
+            let paths: Vec<_> = archives
+                .iter()
+                .filter(|x| entries.iter().any(|e| e.matches(x)))
+                .cloned()
+                .collect();
+            let runner = self.runner.as_ref();
+            digests.extend(lock_hashes::digests(
+                runner,
+                &self.python_info.binary,
+                algorithm,
+                &paths,
+            )?);
+        }
+
+        let mut checked = 0;
+        let mut mismatches = vec![];
+        let mut not_found = vec![];
+        let mut unhashed = vec![];
+        for entry in &entries {
+            if entry.hashes.is_empty() {
+                unhashed.push(entry.name.as_str());
+                continue;
+            }
+            let entry_archives: Vec<_> = archives.iter().filter(|x| entry.matches(x)).collect();
+            if entry_archives.is_empty() {
+                not_found.push(entry.name.as_str());
+                continue;
+            }
+            for archive in entry_archives {
+                checked += 1;
+                let filename = archive.file_name().unwrap_or_default().to_string_lossy();
+                // Like pip, accept the archive if any of the hashes matches
+                let ok = digests
+                    .iter()
+                    .filter(|(path, _)| path == archive)
+                    .any(|(_, digest)| entry.hashes.contains(digest));
+                if ok {
+                    println!("{} {}", "ok".green(), filename);
+                } else {
+                    println!("{} {}", "mismatch".red(), filename);
+                    mismatches.push(archive.to_path_buf());
+                }
+            }
+        }
+        print_info_2(&format!("{} archive(s) checked", checked));
+        if !not_found.is_empty() {
+            print_warning(&format!("No archive found for: {}", not_found.join(", ")));
+        }
+        if !unhashed.is_empty() {
+            print_warning(&format!(
+                "No hashes in the lock for: {}",
+                unhashed.join(", ")
+            ));
+        }
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Other {
+            message: format!(
+                "{} archive(s) don't match the hashes of the lock",
+                mismatches.len()
+            ),
+        })
+    }
+
+    /// Run a program from the virtualenv, making sure it dies
+    /// when we get killed and that the exit code is forwarded
+    pub fn run(&self, args: &[String]) -> Result<(), Error> {
+        self.check_out_of_band_changes()?;
+        #[cfg(windows)]
+        {
+            unsafe {
+                win_job::setup();
+            }
+            self.run_no_exec(args)
+        }
+
+        #[cfg(unix)]
+        {
+            let bin_path = &self.get_path_in_venv(&args[0])?;
+            let bin_path_str = bin_path.to_str().ok_or(Error::Other {
+                message: "Could not convert binary path to String".to_string(),
+            })?;
+            let mut fixed_args: Vec<String> = args.to_vec();
+            fixed_args[0] = bin_path_str.to_string();
+            execv(bin_path_str, fixed_args, &self.run_env.borrow())
+        }
+    }
+
+    /// Check the virtualenv, then run the command, see `RunOptions`
+    pub fn run_command(&self, cmd: &[String], options: &RunOptions) -> Result<(), Error> {
+        let env_before = run_env::Snapshot::capture();
+        if !options.allow_venv_mutation && protect::is_venv_mutation(cmd) {
+            if is_interactive() {
+                return self.run_pip_and_reconcile(cmd);
+            }
+            return Err(Error::Other {
+                message: format!(
+                    "`{}` would change the virtualenv without updating the lock.\n \
+                     Edit setup.py and run `dmenv lock` instead, or use `--allow-venv-mutation`",
+                    cmd.join(" ")
+                ),
+            });
+        }
+        if options.reinstall_if_stale || options.fail_if_stale {
+            self.check_stale(options.reinstall_if_stale)?;
+        }
+        if let Some(preset) = &options.preset {
+            self.apply_preset(preset)?;
+        }
+        self.pre_run(cmd)?;
+        self.setup_subprocess_coverage()?;
+        let explanation = self.explain_run(cmd, options.no_exec, &env_before)?;
+        if options.explain {
+            for line in explanation.render() {
+                print_info_2(&line);
+            }
+        }
+        if let Err(e) = explanation.write_manifest(&self.paths.project) {
+            print_warning(&format!(
+                "Could not record the environment of the command: {}",
+                e
+            ));
+        }
+        if options.no_exec {
+            self.run_no_exec(cmd)
+        } else {
+            self.run(cmd)
+        }
+    }
+
+    /// What `run_command()` is about to run: the working directory and the
+    /// environment changed since `env_before`, see the `run_env` module
+    fn explain_run(
+        &self,
+        cmd: &[String],
+        no_exec: bool,
+        env_before: &run_env::Snapshot,
+    ) -> Result<Explanation, Error> {
+        self.expect_venv()?;
+        let mut changes = env_before.diff(&run_env::Snapshot::capture());
+        for (key, value) in self.run_env.borrow().iter() {
+            Change::apply(&mut changes, key, value);
+        }
+        let args: Vec<&str> = cmd.iter().skip(1).map(String::as_str).collect();
+        // Note: run() always starts a new process on Windows
+        if no_exec || cfg!(windows) {
+            let dir = match &*self.run_dir.borrow() {
+                Some(dir) => dir.to_path_buf(),
+                None => self.paths.project.to_path_buf(),
+            };
+            let process = self.venv_process(&cmd[0], args, &dir, None)?;
+            for (key, value) in &process.env {
+                Change::apply(&mut changes, key, value);
+            }
+            return Ok(Explanation {
+                program: process.program,
+                args: process.args,
+                cwd: dir,
+                changes,
+            });
+        }
+        let cwd = std::env::current_dir().map_err(|e| Error::Other {
+            message: format!("Could not get current directory: {}", e),
+        })?;
+        Ok(Explanation {
+            program: self.get_path_in_venv(&cmd[0])?,
+            args: args.iter().map(|x| x.to_string()).collect(),
+            cwd,
+            changes,
+        })
+    }
+
+    /// Run a pip command changing the virtualenv, then offer to record the changes
+    /// in setup.py and the lock, or to revert them
+    fn run_pip_and_reconcile(&self, cmd: &[String]) -> Result<(), Error> {
+        self.expect_venv()?;
+        let before = VenvDigest::compute(&self.paths.venv);
+        self.run_no_exec(cmd)?;
+        let after = VenvDigest::compute(&self.paths.venv);
+        let (added, removed) = digest::diff(&before.packages, &after.packages);
+        if added.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+        let mut message = "The virtualenv no longer matches the lock:".to_string();
+        for package in &added {
+            message.push_str(&format!("\n + {}", package));
+        }
+        for package in &removed {
+            message.push_str(&format!("\n - {}", package));
+        }
+        print_warning(&message);
+
+        let requirements = protect::pip_install_requirements(cmd);
+        let can_record = !requirements.is_empty() && !self.settings.production;
+        let (question, default) = if can_record {
+            let question = format!(
+                "Add {} to the dev dependencies and lock, revert the changes, or keep them? (record/revert/keep)",
+                requirements.join(", ")
+            );
+            (question, "record")
+        } else {
+            (
+                "Revert the changes, or keep them? (revert/keep)".to_string(),
+                "revert",
+            )
+        };
+        match ask(&question, default).as_str() {
+            "record" if can_record => {
+                self.add_dev_requirements(&requirements)?;
+                self.lock(&LockOptions::default())
+            }
+            "revert" => {
+                let name = |x: &String| x.split("==").next().unwrap_or_default().to_string();
+                let previous: Vec<_> = before.packages.iter().map(name).collect();
+                for package in added.iter().map(name) {
+                    if previous.contains(&package) {
+                        continue;
+                    }
+                    self.run_cmd_in_venv(
+                        "python",
+                        vec!["-m", "pip", "uninstall", "--yes", &package],
+                    )?;
+                }
+                self.install_from_lock()?;
+                println!("{}", "ok!".green());
+                Ok(())
+            }
+            _ => {
+                print_warning("Keeping the changes. Run `dmenv install` to go back to the lock");
+                Ok(())
+            }
+        }
+    }
+
+    /// Add requirements to the `dev` extra of setup.py, or to requirements-dev.in
+    fn add_dev_requirements(&self, requirements: &[String]) -> Result<(), Error> {
+        if self.settings.project_type == ProjectType::Requirements {
+            let path = self.paths.project.join(DEV_REQUIREMENTS_IN_FILENAME);
+            let mut contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            for requirement in requirements {
+                contents.push_str(&format!("{}\n", requirement));
+            }
+            print_info_2(&format!(
+                "Adding {} to {}",
+                requirements.join(", "),
+                DEV_REQUIREMENTS_IN_FILENAME
+            ));
+            return std::fs::write(&path, contents).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            });
+        }
+        let path = &self.paths.setup_py;
+        let mut contents = std::fs::read_to_string(path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        // Note: each requirement is inserted first in the list
+        for requirement in requirements.iter().rev() {
+            contents = setup_py::add_to_extra(&contents, "dev", requirement).ok_or_else(|| {
+                Error::Other {
+                    message: format!(
+                        "Could not add {} to the `dev` extra of setup.py. Please add it by hand and run `dmenv lock`",
+                        requirement
+                    ),
+                }
+            })?;
+        }
+        print_info_2(&format!(
+            "Adding {} to the `dev` extra of setup.py",
+            requirements.join(", ")
+        ));
+        std::fs::write(path, contents).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+    }
+
+    /// Called by `dmenv run` before running the command, with or without exec()
+    pub fn pre_run(&self, args: &[String]) -> Result<(), Error> {
+        self.run_hooks(|plugin, context| plugin.pre_run(context, args))
+    }
+
+    /// Make coverage.py measure sub-processes started by the command given to `dmenv run`:
+    /// install a `.pth` hook in the virtualenv, and set COVERAGE_PROCESS_START
+    // Note: do nothing unless `settings.coverage_process_start` is set
+    pub fn setup_subprocess_coverage(&self) -> Result<(), Error> {
+        let config = match &self.settings.coverage_process_start {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        self.expect_venv()?;
+        if coverage::install_hook(&self.paths.venv)? {
+            print_info_2(&format!(
+                "Installed {} in the virtualenv",
+                coverage::PTH_FILENAME
+            ));
+        }
+        // Note: only the command gets the variable, whether we exec or not
+        if std::env::var_os("COVERAGE_PROCESS_START").is_none() {
+            let config = self.paths.project.join(config);
+            self.run_env.borrow_mut().push((
+                "COVERAGE_PROCESS_START".to_string(),
+                config.to_string_lossy().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// On Windows:
+    ///   - same as run
+    /// On Linux:
+    ///   - same as run, but create a new process instead of using execv()
+    // Note: mostly for tests. We want to *check* the return code of
+    // `dmenv run` and so we need a child process
+    pub fn run_no_exec(&self, args: &[String]) -> Result<(), Error> {
+        self.expect_venv()?;
+        self.check_out_of_band_changes()?;
+        let cmd = args[0].clone();
+        let args: Vec<&str> = args.iter().skip(1).map(String::as_str).collect();
+        // Note: the command gets the terminal, like with execv(), see the `tty` module
+        self.interactive.set(true);
+        let res = match &*self.run_dir.borrow() {
+            Some(dir) => self.run_cmd_in_venv_from(&cmd, args, dir),
+            None => self.run_cmd_in_venv(&cmd, args),
+        };
+        self.interactive.set(false);
+        res
+    }
+
+    /// Apply a preset from `dmenv.toml` before `dmenv run`, see the `presets` module
+    pub fn apply_preset(&self, name: &str) -> Result<(), Error> {
+        let preset = match self.settings.presets.iter().find(|x| x.name == name) {
+            Some(preset) => preset,
+            None => {
+                let names: Vec<_> = self
+                    .settings
+                    .presets
+                    .iter()
+                    .map(|x| x.name.as_str())
+                    .collect();
+                return Err(Error::Other {
+                    message: format!(
+                        "no preset named `{}` in {}. Known presets: {}",
+                        name,
+                        project_config::CONFIG_FILENAME,
+                        if names.is_empty() {
+                            "none".to_string()
+                        } else {
+                            names.join(", ")
+                        }
+                    ),
+                });
+            }
+        };
+        if let Some(dir) = preset.working_dir(&self.paths.project) {
+            std::env::set_current_dir(&dir).map_err(|e| Error::Other {
+                message: format!("Could not change directory to {}: {}", dir.display(), e),
+            })?;
+            *self.run_dir.borrow_mut() = Some(dir);
+        }
+        self.run_env.borrow_mut().extend(preset.env.iter().cloned());
+        preset.apply()
+    }
+
+    /// Run the formatter, or check that the files are already formatted
+    pub fn fmt(&self, check: bool) -> Result<(), Error> {
+        if check {
+            self.run_checker("formatting check", &self.settings.format_check_cmd)
+        } else {
+            self.run_checker("formatting", &self.settings.format_cmd)
+        }
+    }
+
+    pub fn lint(&self) -> Result<(), Error> {
+        self.run_checker("linting", &self.settings.lint_cmd)
+    }
+
+    // Note: unlike `run()`, don't exec, so that the error message
+    // says what failed
+    fn run_checker(&self, description: &str, cmd: &[String]) -> Result<(), Error> {
+        self.expect_venv()?;
+        let binary = cmd.first().ok_or_else(|| Error::Other {
+            message: format!("no command configured for {}", description),
+        })?;
+        self.get_path_in_venv(binary).map_err(|_| Error::Other {
+            message: format!(
+                "'{}' not found in the virtualenv. Add it to the dev dependencies",
+                binary
+            ),
+        })?;
+        let args: Vec<&str> = cmd.iter().skip(1).map(String::as_str).collect();
+        self.run_cmd_in_venv(binary, args).map_err(|e| match e {
+            Error::ProcessFailed { .. } => Error::ProcessFailed {
+                message: format!("{} failed", description),
+            },
+            e => e,
+        })
+    }
+
+    /// Run a single-file script declaring its dependencies with inline metadata (PEP 723).
+    /// The virtualenv is created (and the dependencies installed) the first time.
+    // Note: self.paths.venv is computed from the dependencies, see `ScriptMetadata::venv_path()`
+    pub fn run_script(&self, script: &str, args: &[String]) -> Result<(), Error> {
+        let script_path = self.paths.project.join(script);
+        let metadata = ScriptMetadata::from_path(&script_path)?;
+        if let Some(requires_python) = &metadata.requires_python {
+            self.check_requires_python(requires_python)?;
+        }
+        let ready_path = self.paths.venv.join(SCRIPT_READY_FILENAME);
+        if !ready_path.exists() {
+            print_info_1(&format!("Preparing virtualenv for {}", script));
+            // Start from scratch if a previous attempt failed
+            if self.paths.venv.exists() {
+                self.clean()?;
+            }
+            self.create_venv()?;
+            if !metadata.dependencies.is_empty() {
+                let mut args = vec!["-m", "pip", "install"];
+                args.extend(metadata.dependencies.iter().map(|x| x.as_str()));
+                self.run_cmd_in_venv("python", args)?;
+            }
+            std::fs::write(&ready_path, "").map_err(|e| Error::WriteError {
+                path: ready_path.to_path_buf(),
+                io_error: e,
+            })?;
+        }
+        let mut cmd = vec![
+            "python".to_string(),
+            script_path.to_string_lossy().to_string(),
+        ];
+        cmd.extend(args.iter().cloned());
+        self.run(&cmd)
+    }
+
+    // Note: only `>=` and `<` are checked, which covers most scripts in the wild
+    fn check_requires_python(&self, requires_python: &str) -> Result<(), Error> {
+        let version = &self.python_info.version;
+        for spec in requires_python.split(',') {
+            let spec = spec.trim();
+            let ok = if spec.starts_with(">=") {
+                compare_versions(version, spec[2..].trim()) != Ordering::Less
+            } else if spec.starts_with('<') && !spec.starts_with("<=") {
+                compare_versions(version, spec[1..].trim()) == Ordering::Less
+            } else {
+                true
+            };
+            if !ok {
+                return Err(Error::Other {
+                    message: format!(
+                        "script requires Python {}, but Python {} is used. Use --python",
+                        requires_python, version
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Install a command line tool in its own virtualenv, and expose its commands
+    /// in the tools bin directory
+    // Note: self.paths.venv is set to `tools::venv_path()`
+    pub fn tool_install(&self, name: &str) -> Result<(), Error> {
+        if self.paths.venv.join(TOOL_FILENAME).exists() {
+            return Err(Error::Other {
+                message: format!(
+                    "{} is already installed. Use `dmenv tool upgrade {}` instead",
+                    name, name
+                ),
+            });
+        }
+        Self::check_typosquatting(&[name.to_string()])?;
+        print_info_1(&format!("Installing {}", name));
+        // Start from scratch if a previous attempt failed
+        if self.paths.venv.exists() {
+            self.clean()?;
+        }
+        self.create_venv()?;
+        self.install_tool_package(name, &[], &["install", name])?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Make sure every tool listed in `tools.lock` is installed, with the exact version
+    pub fn tool_sync(&self) -> Result<(), Error> {
+        let lock_path = self.paths.project.join(TOOLS_LOCK_FILENAME);
+        let contents = std::fs::read_to_string(&lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let pinned_tools = tools::parse_lock(&contents)?;
+        for pinned in pinned_tools {
+            // Each tool has its own virtualenv
+            let mut paths = self.paths.clone();
+            paths.venv = tools::venv_path(&pinned.name)?;
+            let manager = VenvManager::new(paths, self.python_info.clone(), self.settings.clone());
+            manager.sync_tool(&pinned)?;
+        }
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    fn sync_tool(&self, pinned: &PinnedTool) -> Result<(), Error> {
+        let name = &pinned.name;
+        let spec = pinned.spec();
+        if !self.paths.venv.join(TOOL_FILENAME).exists() {
+            print_info_1(&format!("Installing {}", spec));
+            if self.paths.venv.exists() {
+                self.clean()?;
+            }
+            self.create_venv()?;
+            return self.install_tool_package(name, &[], &["install", &spec]);
+        }
+        let installed_version = tools::installed_version(&self.paths.venv, name)?;
+        if installed_version.as_ref() == Some(&pinned.version) {
+            print_info_1(&format!("{} is up to date", spec));
+            return Ok(());
+        }
+        print_info_1(&format!(
+            "Changing {} from {} to {}",
+            name,
+            installed_version.unwrap_or_else(|| "unknown version".to_string()),
+            pinned.version
+        ));
+        let exposed = tools::read_exposed(&self.paths.venv)?;
+        self.install_tool_package(name, &exposed, &["install", &spec])
+    }
+
+    pub fn tool_upgrade(&self, name: &str) -> Result<(), Error> {
+        self.expect_tool(name)?;
+        print_info_1(&format!("Upgrading {}", name));
+        let exposed = tools::read_exposed(&self.paths.venv)?;
+        self.install_tool_package(name, &exposed, &["install", "--upgrade", name])?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    pub fn tool_uninstall(&self, name: &str) -> Result<(), Error> {
+        self.expect_tool(name)?;
+        print_info_1(&format!("Uninstalling {}", name));
+        let bin_dir = self.tools_bin_dir()?;
+        for command in tools::read_exposed(&self.paths.venv)? {
+            let dest = bin_dir.join(&command);
+            if !tools::is_exposed_from(&dest, &self.paths.venv) {
+                continue;
+            }
+            print_info_2(&format!("Removing {}", dest.display()));
+            std::fs::remove_file(&dest).map_err(|e| Error::Other {
+                message: format!("could not remove {}: {}", dest.display(), e),
+            })?;
+        }
+        self.clean()?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    pub fn tool_list(&self) -> Result<(), Error> {
+        let installed = tools::installed()?;
+        if installed.is_empty() {
+            print_info_1("No tools installed");
+            return Ok(());
+        }
+        for name in installed {
+            let venv = tools::venv_path(&name)?;
+            let exposed = tools::read_exposed(&venv)?;
+            println!("{}: {}", name.as_str().bold(), exposed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Run `pip` in the virtualenv of the tool, then expose the scripts it added
+    /// (in addition to the ones already `exposed`)
+    fn install_tool_package(
+        &self,
+        name: &str,
+        exposed: &[String],
+        pip_args: &[&str],
+    ) -> Result<(), Error> {
+        let venv_bin_path = self.get_venv_bin_path();
+        let before = tools::file_names(&venv_bin_path)?;
+        let mut args = vec!["-m", "pip"];
+        args.extend(pip_args);
+        self.run_cmd_in_venv("python", args)?;
+        let after = tools::file_names(&venv_bin_path)?;
+
+        let bin_dir = self.tools_bin_dir()?;
+        std::fs::create_dir_all(&bin_dir).map_err(|e| Error::Other {
+            message: format!("Could not create {}: {}", bin_dir.display(), e),
+        })?;
+        let mut res = exposed.to_vec();
+        for command in after.difference(&before) {
+            let dest = bin_dir.join(command);
+            // Note: symlink_metadata() also finds dangling symlinks
+            if std::fs::symlink_metadata(&dest).is_ok() {
+                if !tools::is_exposed_from(&dest, &self.paths.venv) {
+                    print_warning(&format!(
+                        "Not exposing {}: {} already exists",
+                        command,
+                        dest.display()
+                    ));
+                    continue;
+                }
+                std::fs::remove_file(&dest).map_err(|e| Error::Other {
+                    message: format!("could not remove {}: {}", dest.display(), e),
+                })?;
+            }
+            print_info_2(&format!("Exposing {}", dest.display()));
+            tools::expose(&venv_bin_path.join(command), &dest)?;
+            if !res.contains(command) {
+                res.push(command.to_string());
+            }
+        }
+        if res.is_empty() {
+            print_warning(&format!("{} does not provide any command", name));
+        }
+        tools::write_exposed(&self.paths.venv, &res)?;
+
+        let in_path = std::env::var_os("PATH")
+            .map_or(false, |x| std::env::split_paths(&x).any(|x| x == bin_dir));
+        if !in_path {
+            print_warning(&format!("{} is not in PATH", bin_dir.display()));
+        }
+        Ok(())
+    }
+
+    fn expect_tool(&self, name: &str) -> Result<(), Error> {
+        if !self.paths.venv.join(TOOL_FILENAME).exists() {
+            return Err(Error::Other {
+                message: format!(
+                    "{} is not installed. Use `dmenv tool install {}` first",
+                    name, name
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Write a shim running `command` from the virtualenv, so that it can be
+    /// used from anywhere without `dmenv run`
+    pub fn shim(&self, command: &str) -> Result<(), Error> {
+        self.expect_venv()?;
+        self.get_path_in_venv(command)?;
+        let bin_dir = self.tools_bin_dir()?;
+        let dest = bin_dir.join(shim::file_name(command));
+        if !shim::can_replace(&dest) {
+            return Err(Error::FileExists { path: dest });
+        }
+        print_info_1(&format!("Writing {}", dest.display()));
+        // Note: the shim runs from anywhere, so `--project` must be absolute
+        let project = std::env::current_dir()
+            .map(|x| x.join(&self.paths.project))
+            .unwrap_or_else(|_| self.paths.project.clone());
+        let args = shim::dmenv_args(&project, self.settings.production, command);
+        shim::write(&bin_dir, command, &args)?;
+        let in_path = std::env::var_os("PATH")
+            .map_or(false, |x| std::env::split_paths(&x).any(|x| x == bin_dir));
+        if !in_path {
+            print_warning(&format!("{} is not in PATH", bin_dir.display()));
+        }
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    fn tools_bin_dir(&self) -> Result<PathBuf, Error> {
+        match &self.settings.tools_bin_dir {
+            Some(bin_dir) => Ok(bin_dir.to_path_buf()),
+            None => tools::default_bin_dir(),
+        }
+    }
+
+    /// (Re)generate the lock file
+    //
+    // Notes:
+    //
+    // * Abort if `setup.py` is not found
+    // * Create the virtualenv if required
+    // * Always upgrade pip :
+    //    * If that fails, we know if the virtualenv is broken
+    //    * Also, we know sure that `pip` can handle all the options
+    //      (such as `--local`, `--exclude-editable`) we use in the other functions
+    // * The path of the lock file is computed by PathsResolver.
+    //     See PathsResolver.paths() for details
+    //
+    // * Delegates the actual work to `write_lock()`
+    //
+    pub fn lock(&self, lock_options: &LockOptions) -> Result<(), Error> {
+        print_info_1("Locking dependencies");
+        let requirements_in = self.paths.project.join(REQUIREMENTS_IN_FILENAME);
+        match self.settings.project_type {
+            ProjectType::Package => {
+                if !self.paths.setup_py.exists() && !self.pyproject_path().exists() {
+                    return Err(Error::MissingSetupPy {});
+                }
+            }
+            ProjectType::Requirements => {
+                if !requirements_in.exists() {
+                    return Err(Error::MissingRequirementsIn {
+                        expected_path: requirements_in,
+                    });
+                }
+            }
+        }
+
+        self.check_foreign_venv(lock_options.allow_foreign_venv)?;
+        self.run_hooks(|plugin, context| plugin.pre_lock(context))?;
+        self.preflight_indexes()?;
+        self.ensure_venv()?;
+        self.upgrade_pip()?;
+        let build_settings = self.build_settings(
+            lock_options.no_build_isolation,
+            &lock_options.build_requirements,
+        )?;
+        self.install_build_requirements(&build_settings)?;
+
+        match self.settings.project_type {
+            ProjectType::Package => self.install_editable()?,
+            ProjectType::Requirements => self.install_requirements_in()?,
+        }
+        self.install_overrides()?;
+        self.install_platform_dev_dependencies()?;
+
+        self.write_lock(&lock_options, build_settings)?;
+        self.lock_extras(&lock_options.extras)?;
+        self.write_state()?;
+        self.run_hooks(|plugin, context| plugin.post_lock(context))
+    }
+
+    /// Show the dependencies inside the virtualenv.
+    // Note: Run `pip list` so we get what's *actually* installed, not just
+    // the contents of the lock file
+    pub fn show_deps(&self) -> Result<(), Error> {
+        self.run_cmd_in_venv("pip", vec!["list"])
+    }
+
+    /// Show the resolved virtualenv path.
+    //
+    // See `PathsResolver.paths()` for details
+    /// Show stable `key=value` lines, so that scripts don't have to
+    /// guess where dmenv puts things
+    pub fn info(&self, paths_only: bool) -> Result<(), Error> {
+        for (key, value) in self.info_entries(paths_only)? {
+            println!("{}={}", key, value);
+        }
+        Ok(())
+    }
+
+    fn info_entries(&self, paths_only: bool) -> Result<Vec<(&'static str, String)>, Error> {
+        #[cfg(windows)]
+        let python = "python.exe";
+        #[cfg(not(windows))]
+        let python = "python";
+
+        let mut res = vec![
+            ("project", self.paths.project.to_path_buf()),
+            ("package", self.paths.package.to_path_buf()),
+            ("venv", self.paths.venv.to_path_buf()),
+            ("bin", self.get_venv_bin_path()),
+            ("python", self.get_venv_bin_path().join(python)),
+            ("base_python", self.python_info.binary.to_path_buf()),
+            ("lock", self.paths.lock.to_path_buf()),
+            ("setup_py", self.paths.setup_py.to_path_buf()),
+            ("cache", get_cache_root()?),
+        ]
+        .into_iter()
+        .map(|(key, path)| (key, path.to_string_lossy().to_string()))
+        .collect::<Vec<_>>();
+        if !paths_only {
+            res.push(("python_version", self.python_info.version.to_string()));
+            res.push((
+                "python_implementation",
+                self.python_info.implementation.to_string(),
+            ));
+            res.push(("platform", self.python_info.platform.to_string()));
+            res.push(("venv_exists", self.paths.venv.exists().to_string()));
+        }
+        Ok(res)
+    }
+
+    /// Run a `dmenv-<name>` executable. The keys shown by `dmenv info` are given
+    /// as environment variables, like `DMENV_PLUGIN_VENV`
+    pub fn run_external(&self, external: &ExternalCommand) -> Result<(), Error> {
+        let mut env: Vec<_> = self
+            .info_entries(false)?
+            .into_iter()
+            .map(|(key, value)| (format!("DMENV_PLUGIN_{}", key.to_uppercase()), value))
+            .collect();
+        env.push((
+            "DMENV_PLUGIN_PRODUCTION".to_string(),
+            self.settings.production.to_string(),
+        ));
+        #[cfg(unix)]
+        {
+            let mut args = vec![external.path.to_string_lossy().to_string()];
+            args.extend(external.args.iter().cloned());
+            execv(&external.path, args, &env)
+        }
+
+        #[cfg(windows)]
+        {
+            unsafe {
+                win_job::setup();
+            }
+            let _guard = crate::tty::Guard::new();
+            let status = std::process::Command::new(&external.path)
+                .args(&external.args)
+                .envs(env)
+                .status()
+                .map_err(|e| Error::ProcessWaitError { io_error: e })?;
+            if !status.success() {
+                return Err(Error::ProcessFailed {
+                    message: format!("dmenv-{} failed", external.name),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    /// Run `operation` `iterations` times, both "cold" (without the virtualenv
+    /// and without pip's cache) and "warm" (right after the cold run), and show
+    /// statistics about the timings.
+    // Note: the lock is restored at the end, so that benchmarking `lock` has
+    // no lasting effect
+    pub fn bench<F>(&self, name: &str, iterations: usize, operation: F) -> Result<(), Error>
+    where
+        F: Fn() -> Result<(), Error>,
+    {
+        if iterations == 0 {
+            return Err(Error::Usage {
+                message: "--iterations must be at least 1".to_string(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(&self.paths.lock).ok();
+        let mut cold = vec![];
+        let mut warm = vec![];
+        for i in 1..=iterations {
+            print_info_1(&format!("{} {}/{}: cold", name, i, iterations));
+            self.clean()?;
+            *self.venv_env.borrow_mut() = vec![("PIP_NO_CACHE_DIR".to_string(), "1".to_string())];
+            let start = Instant::now();
+            let res = operation();
+            self.venv_env.borrow_mut().clear();
+            res?;
+            cold.push(start.elapsed());
+
+            print_info_1(&format!("{} {}/{}: warm", name, i, iterations));
+            let start = Instant::now();
+            operation()?;
+            warm.push(start.elapsed());
+        }
+        if let Some(lock_contents) = lock_contents {
+            std::fs::write(&self.paths.lock, lock_contents).map_err(|e| Error::WriteError {
+                path: self.paths.lock.to_path_buf(),
+                io_error: e,
+            })?;
+        }
+        print_info_1(&format!("{}: {} iteration(s)", name, iterations));
+        for (kind, timings) in &[("cold", cold), ("warm", warm)] {
+            if let Some(stats) = Stats::new(timings) {
+                println!("{}  {}", kind, stats.render());
+            }
+        }
+        Ok(())
+    }
+
+    /// Install the production lock in a scratch virtualenv the way CI does: from
+    /// scratch, without prompts nor pip cache, requiring hashes when the lock has
+    /// some, and offline from `vendor` if given. Stops at the first failing step.
+    /// Both paths are relative to the project
+    pub fn simulate_ci(
+        &self,
+        vendor: &Option<String>,
+        report_path: &Option<String>,
+    ) -> Result<(), Error> {
+        print_info_1("Simulating a CI run");
+        let vendor = vendor.as_ref().map(|x| self.paths.project.join(x));
+        let report_path = report_path.as_ref().map(|x| self.paths.project.join(x));
+        let lock_path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(lock_path).map_err(|_| Error::MissingLock {
+            expected_path: lock_path.to_path_buf(),
+        })?;
+        let vendor_str = vendor.as_ref().map(|x| x.to_string_lossy());
+        let mut install_args = vec!["--no-input"];
+        if let Some(vendor_str) = &vendor_str {
+            install_args.extend(&["--no-index", "--find-links", vendor_str]);
+        }
+        let hashes_checked = lock_contents.contains("--hash=");
+        if hashes_checked {
+            install_args.push("--require-hashes");
+        }
+        let mut report = Report {
+            lock: lock_path.to_string_lossy().to_string(),
+            venv: self.paths.venv.to_string_lossy().to_string(),
+            python_version: self.python_info.version.to_string(),
+            offline: vendor.is_some(),
+            hashes_checked,
+            steps: vec![],
+        };
+
+        type Operation<'a> = Box<dyn Fn() -> Result<(), Error> + 'a>;
+        let mut steps: Vec<(&str, Operation)> = vec![
+            ("check lock policy", Box::new(|| self.check_lock_policy())),
+            (
+                "create virtualenv",
+                Box::new(|| {
+                    self.clean()?;
+                    self.create_venv()
+                }),
+            ),
+            (
+                "install dependencies",
+                Box::new(|| self.pip_install_requirements(lock_path, &install_args)),
+            ),
+        ];
+        if vendor.is_none() {
+            steps.insert(
+                1,
+                (
+                    "check package indexes",
+                    Box::new(|| self.preflight_indexes()),
+                ),
+            );
+        }
+        if self.settings.project_type == ProjectType::Package {
+            steps.push(("install project", Box::new(|| self.develop())));
+        }
+        steps.push((
+            "check dependencies",
+            Box::new(|| self.run_cmd_in_venv("python", vec!["-m", "pip", "check"])),
+        ));
+
+        *self.venv_env.borrow_mut() = simulate_ci::CI_ENV
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        for (name, operation) in &steps {
+            print_info_1(&format!("simulate-ci: {}", name));
+            let start = Instant::now();
+            let error = operation().err().map(|e| e.to_string());
+            let failed = error.is_some();
+            report.steps.push(Step {
+                name: name.to_string(),
+                duration: start.elapsed(),
+                error,
+            });
+            if failed {
+                break;
+            }
+        }
+        self.venv_env.borrow_mut().clear();
+
+        if let Some(report_path) = &report_path {
+            std::fs::write(report_path, report.to_json()).map_err(|e| Error::WriteError {
+                path: report_path.to_path_buf(),
+                io_error: e,
+            })?;
+        }
+        print_info_1("simulate-ci summary");
+        for step in &report.steps {
+            let outcome = match &step.error {
+                Some(_) => "failed".red(),
+                None => "ok".green(),
+            };
+            println!(
+                "{:<24} {:>8.2}s  {}",
+                step.name,
+                step.duration.as_millis() as f64 / 1000.0,
+                outcome
+            );
+        }
+        println!("Virtualenv kept in {}", self.paths.venv.display());
+        match report.steps.iter().find(|x| x.error.is_some()) {
+            Some(step) => Err(Error::Other {
+                message: format!(
+                    "simulated CI run failed at '{}': {}",
+                    step.name,
+                    step.error.as_ref().unwrap()
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Append the current command and its outcome to the history of the project.
+    // Note: failing to write the history is not worth failing the command
+    pub fn record_history(&self, result: &Result<(), Error>) {
+        let args = self.command_line();
+        let lock_hash = match std::fs::read(&self.paths.lock) {
+            Ok(contents) => hash::hash_hex(&contents),
+            Err(_) => "-".to_string(),
+        };
+        let outcome = match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!(
+                "failed: {}",
+                e.to_string().lines().next().unwrap_or_default()
+            ),
+        };
+        let entry = history::Entry {
+            timestamp: state::now(),
+            dmenv_version: env!("CARGO_PKG_VERSION").to_string(),
+            args: args.join(" "),
+            lock_hash,
+            outcome,
+        };
+        if let Err(e) = history::record(&self.paths.project, &entry) {
+            print_warning(&format!("Could not record history: {}", e));
+        }
+    }
+
+    /// Show the history of the project, oldest entries first
+    pub fn history(&self) -> Result<(), Error> {
+        let entries = history::read(&self.paths.project)?;
+        if entries.is_empty() {
+            print_info_1("No history yet");
+            return Ok(());
+        }
+        for entry in entries {
+            let outcome = if entry.outcome == "ok" {
+                entry.outcome.as_str().green()
+            } else {
+                entry.outcome.as_str().red()
+            };
+            println!(
+                "{} dmenv {} lock: {} {}",
+                history::format_timestamp(entry.timestamp).as_str().blue(),
+                entry.dmenv_version,
+                entry.lock_hash,
+                outcome
+            );
+            println!("    {}", entry.args);
+        }
+        Ok(())
+    }
+
+    /// Show the log written by the last run with `--quiet`
+    pub fn logs(&self) -> Result<(), Error> {
+        let log = match logs::list(&self.paths.venv).pop() {
+            Some(log) => log,
+            None => {
+                return Err(Error::Other {
+                    message: format!(
+                        "no logs found in {}. Logs are written when running with --quiet",
+                        logs::logs_dir(&self.paths.venv).display()
+                    ),
+                })
+            }
+        };
+        let contents = std::fs::read_to_string(&log).map_err(|e| Error::ReadError {
+            path: log.to_path_buf(),
+            io_error: e,
+        })?;
+        print_info_1(&format!("Showing {}", log.display()));
+        print!("{}", contents);
+        Ok(())
+    }
+
+    pub fn show_venv_path(&self) -> Result<(), Error> {
+        println!("{}", msys::shell_path(&self.paths.venv));
+        Ok(())
+    }
+
+    /// Same has `show_venv_path`, but add the correct subfolder
+    /// (`bin` on Linux and macOS, `Scripts` on Windows).
+    pub fn show_venv_bin_path(&self) -> Result<(), Error> {
+        let bin_path = &self.get_venv_bin_path();
+        println!("{}", msys::shell_path(bin_path));
+        Ok(())
+    }
+
+    /// Print the packages of the lock and their versions (if any), without
+    /// starting Python. Print nothing if there is no lock yet
+    pub fn query_packages(&self) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        let lock_contents = match std::fs::read_to_string(&lock_path) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(()),
+        };
+        let lock = Lock::from_string(&lock_contents)?;
+        let versions = lock.versions();
+        let mut names = lock.names();
+        names.dedup();
+        for name in names {
+            match versions.get(&name.to_lowercase()) {
+                Some(version) => println!("{}\t{}", name, version),
+                None => println!("{}", name),
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the executables of the virtualenv, as used by `dmenv run`
+    pub fn query_scripts(&self) -> Result<(), Error> {
+        let bin_path = self.get_venv_bin_path();
+        let entries = match std::fs::read_dir(&bin_path) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        let mut names: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter_map(|x| script_name(&x))
+            .collect();
+        names.sort();
+        for name in names {
+            println!("{}", name);
+        }
+        Ok(())
+    }
+
+    /// Print the Python interpreters found in PATH, with their version when
+    /// dmenv already used them
+    pub fn query_pythons(&self) -> Result<(), Error> {
+        for (binary, version) in python_info::discover_cached() {
+            match version {
+                Some(version) => println!("{}\t{}", binary.display(), version),
+                None => println!("{}", binary.display()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Report packages installed in several virtualenvs: the ones
+    /// of the project, and the ones in the dmenv cache.
+    // Note: actually deduplicating is done by the shared store,
+    // see `install_from_lock_native()`
+    pub fn dedupe(&self, report: bool) -> Result<(), Error> {
+        if !report {
+            return Err(Error::Other {
+                message: "only `--report` is supported. \
+                          Use DMENV_SHARED_STORE with `install --installer native` \
+                          to share packages between virtualenvs"
+                    .to_string(),
+            });
+        }
+        // Note: the parent of the virtualenv contains the ones of the other Python
+        // versions, when the virtualenv is in the project or in a shared root
+        let venv_root = self.paths.venv.parent().unwrap_or(&self.paths.venv);
+        let roots = vec![venv_root.to_path_buf(), get_cache_path("venv")?];
+        dedupe::print_report(&roots)
+    }
+
+    /// Write the environment variables needed to use the virtualenv
+    /// without dmenv, either to stdout or to the `output` file
+    /// (relative to the project path).
+    pub fn env(&self, output: &Option<String>, format: EnvFormat) -> Result<(), Error> {
+        let contents = env_file::render(
+            format,
+            &self.paths.project,
+            &self.paths.venv,
+            &self.get_venv_bin_path(),
+        );
+        match output {
+            None => print!("{}", contents),
+            Some(output) => {
+                let output = self.paths.project.join(output);
+                std::fs::write(&output, &contents).map_err(|e| Error::WriteError {
+                    path: output.to_path_buf(),
+                    io_error: e,
+                })?;
+                print_info_1(&format!("Environment written to {}", output.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate a systemd unit or a supervisord config running the given
+    /// script from the virtualenv.
+    pub fn generate_service(
+        &self,
+        entry_point: &str,
+        format: ServiceFormat,
+        output: &Option<String>,
+    ) -> Result<(), Error> {
+        let script = self.get_path_in_venv(entry_point)?;
+        let service = Service {
+            name: entry_point,
+            project: &self.paths.project,
+            venv: &self.paths.venv,
+            bin_path: &self.get_venv_bin_path(),
+            script: &script,
+        };
+        let contents = service.render(format, &self.template_dirs())?;
+        match output {
+            None => print!("{}", contents),
+            Some(output) => {
+                let output = self.paths.project.join(output);
+                std::fs::write(&output, &contents).map_err(|e| Error::WriteError {
+                    path: output.to_path_buf(),
+                    io_error: e,
+                })?;
+                print_info_1(&format!("Service written to {}", output.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the lock to a remote host, and install it in a virtualenv there.
+    /// `remote_path` is relative to the home directory of the remote user, and defaults
+    /// to `dmenv/<project name>`.
+    //
+    // Note: the virtualenv is created with `pythonX.Y`, where X.Y is the local Python
+    // version, and its path is computed as if it were inside the project
+    pub fn remote_install(
+        &self,
+        remote: &Remote,
+        remote_path: &Option<String>,
+    ) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
+            });
+        }
+        let project_dir = match remote_path {
+            Some(path) => path.trim_end_matches('/').to_string(),
+            None => {
+                let project_name = self.paths.project.file_name().ok_or_else(|| Error::Other {
+                    message: "project path has no file name".to_string(),
+                })?;
+                format!("dmenv/{}", project_name.to_string_lossy())
+            }
+        };
+        let lock_name = lock_path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let remote_lock = format!("{}/{}", project_dir, lock_name);
+        let resolver = PathsResolver::new(
+            PathBuf::from(&project_dir),
+            &self.python_info.tag(),
+            &self.settings,
+        );
+        let remote_venv = format!("{}/{}", project_dir, resolver.remote_venv_path());
+        let python_binary = format!("python{}", bundle::short_version(&self.python_info.version));
+
+        print_info_1(&format!("Installing {} on {}", lock_name, remote.host));
+        let mkdir = format!("mkdir -p {}", remote::quote(&project_dir));
+        self.run_ssh_cmd("ssh", remote.ssh_args(&mkdir))?;
+        let lock_str = lock_path.to_string_lossy();
+        self.run_ssh_cmd("scp", remote.scp_args(&lock_str, &remote_lock))?;
+
+        print_info_2(&format!("Using virtualenv in {}", remote_venv));
+        let ensure_venv = remote::ensure_venv_command(&python_binary, &remote_venv);
+        self.run_ssh_cmd("ssh", remote.ssh_args(&ensure_venv))?;
+        let install = format!(
+            "{}/bin/python -m pip install --requirement {}",
+            remote::quote(&remote_venv),
+            remote::quote(&remote_lock)
+        );
+        self.run_ssh_cmd("ssh", remote.ssh_args(&install))?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    fn run_ssh_cmd(&self, binary: &str, args: Vec<String>) -> Result<(), Error> {
+        let args: Vec<_> = args.iter().map(|x| x.as_str()).collect();
+        Self::print_cmd(binary, &args);
+        let status = std::process::Command::new(binary)
+            .args(&args)
+            .current_dir(&self.paths.project)
+            .status();
+        let status = status.map_err(|e| Error::ProcessWaitError { io_error: e })?;
+        if !status.success() {
+            return Err(Error::ProcessFailed {
+                message: format!("{} failed", binary),
+            });
+        }
+        Ok(())
+    }
+
+    /// Archive the virtualenv (including its state) in a .tar.gz file, along
+    /// with what is needed to restore it somewhere else
+    pub fn snapshot(&self, output: &Option<String>) -> Result<(), Error> {
+        self.expect_venv()?;
+        let output = match output {
+            Some(output) => self.paths.project.join(output),
+            None => self.paths.project.join(format!(
+                "dmenv-snapshot-{}-py{}.tar.gz",
+                self.python_info.platform, self.python_info.version
+            )),
+        };
+        print_info_1(&format!("Taking snapshot of {}", self.paths.venv.display()));
+        let relocation = Relocation {
+            venv_path: self.paths.venv.to_string_lossy().to_string(),
+            python_version: self.python_info.version.clone(),
+            platform: self.python_info.platform.clone(),
+        };
+        let relocation_path = self.paths.venv.join(RELOCATION_FILENAME);
+        std::fs::write(&relocation_path, relocation.to_string()).map_err(|e| {
+            Error::WriteError {
+                path: relocation_path.to_path_buf(),
+                io_error: e,
+            }
+        })?;
+        let venv_str = self.paths.venv.to_string_lossy();
+        let output_str = output.to_string_lossy();
+        let res = self.run_snapshot_script(vec!["create", &venv_str, &output_str]);
+        // Note: the relocation file only makes sense inside the snapshot
+        let _ = std::fs::remove_file(&relocation_path);
+        res?;
+        print_info_1(&format!("Snapshot written to {}", output.display()));
+        Ok(())
+    }
+
+    /// Restore a snapshot created by `snapshot()`, fixing absolute paths if the
+    /// virtualenv path changed.
+    /// The snapshot must have been created with the same Python version on the
+    /// same platform.
+    pub fn restore(&self, archive: &str) -> Result<(), Error> {
+        let archive = self.paths.project.join(archive);
+        if self.paths.venv.exists() {
+            return Err(Error::Other {
+                message: format!(
+                    "{} already exists. Run `dmenv clean` first",
+                    self.paths.venv.display()
+                ),
+            });
+        }
+        print_info_1(&format!("Restoring {}", archive.display()));
+        let archive_str = archive.to_string_lossy();
+        let venv_str = self.paths.venv.to_string_lossy();
+        self.run_snapshot_script(vec!["extract", &archive_str, &venv_str])?;
+
+        let res = self.relocate_snapshot();
+        if res.is_err() {
+            // Don't leave a broken virtualenv behind
+            let _ = std::fs::remove_dir_all(&self.paths.venv);
+        }
+        res
+    }
+
+    fn relocate_snapshot(&self) -> Result<(), Error> {
+        let relocation_path = self.paths.venv.join(RELOCATION_FILENAME);
+        let contents = std::fs::read_to_string(&relocation_path).map_err(|e| Error::ReadError {
+            path: relocation_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let relocation = Relocation::from_string(&contents)?;
+        if relocation.python_version != self.python_info.version
+            || relocation.platform != self.python_info.platform
+        {
+            return Err(Error::Other {
+                message: format!(
+                    "snapshot was taken with Python {} on {}, but Python {} on {} is used",
+                    relocation.python_version,
+                    relocation.platform,
+                    self.python_info.version,
+                    self.python_info.platform
+                ),
+            });
+        }
+        let new_path = self.paths.venv.to_string_lossy();
+        if relocation.venv_path != new_path {
+            print_info_2(&format!(
+                "Relocating from {} to {}",
+                relocation.venv_path, new_path
+            ));
+            let changed = relocate::relocate_bin_dir(
+                &self.get_venv_bin_path(),
+                &relocation.venv_path,
+                &new_path,
+            )?;
+            print_info_2(&format!("{} file(s) changed", changed));
+        }
+        std::fs::remove_file(&relocation_path).map_err(|e| Error::Other {
+            message: format!("could not remove {}: {}", relocation_path.display(), e),
+        })?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    // Note: the virtualenv may not exist yet, so use the Python
+    // interpreter used to create it
+    fn run_snapshot_script(&self, args: Vec<&str>) -> Result<(), Error> {
+        let script = include_str!("snapshot.py");
+        let python_binary = &self.python_info.binary;
+        println!(
+            "{} {} snapshot.py {}",
+            "$".blue(),
+            python_binary.display(),
+            args.join(" ")
+        );
+        let process = Process::new(python_binary)
+            .arg("-c")
+            .arg(script)
+            .args(&args)
+            .dir(&self.paths.project);
+        if !self.runner.status(&process)? {
+            return Err(Error::ProcessFailed {
+                message: "snapshot.py failed".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Regenerate the wrappers of the project's entry points in the virtualenv,
+    /// after they were changed in setup.py.
+    // Note: this only runs `setup.py egg_info` to update entry_points.txt,
+    // instead of a full `setup.py develop`
+    pub fn regen_scripts(&self) -> Result<(), Error> {
+        if !self.paths.setup_py.exists() {
+            if self.pyproject_path().exists() {
+                return Err(Error::Other {
+                    message: "scripts regen needs a setup.py. Run `dmenv develop` instead"
+                        .to_string(),
+                });
+            }
+            return Err(Error::MissingSetupPy {});
+        }
+        let (_, name) = self.project_metadata("name")?;
+        print_info_1("Regenerating scripts");
+        let old_names = match entry_points::find_egg_info(&self.paths.package, &name) {
+            Some(egg_info) => self.read_script_names(&egg_info.join("entry_points.txt"))?,
+            None => vec![],
+        };
+        self.run_cmd_in_package("python", vec!["setup.py", "--quiet", "egg_info"])?;
+        let egg_info =
+            entry_points::find_egg_info(&self.paths.package, &name).ok_or_else(|| {
+                Error::Other {
+                    message: format!("could not find the .egg-info directory of {}", name),
+                }
+            })?;
+        let entry_points_path = egg_info.join("entry_points.txt");
+        let new_names = self.read_script_names(&entry_points_path)?;
+
+        let bin_path = self.get_venv_bin_path();
+        for stale in old_names.iter().filter(|x| !new_names.contains(x)) {
+            #[cfg(windows)]
+            let candidates = vec![format!("{}.exe", stale), format!("{}-script.py", stale)];
+            #[cfg(not(windows))]
+            let candidates = vec![stale.to_string()];
+            for candidate in candidates {
+                let path = bin_path.join(candidate);
+                if !path.exists() {
+                    continue;
+                }
+                std::fs::remove_file(&path).map_err(|e| Error::Other {
+                    message: format!("could not remove {}: {}", path.display(), e),
+                })?;
+                print_info_2(&format!("Removed {}", path.display()));
+            }
+        }
+        if new_names.is_empty() {
+            print_info_2("No scripts to generate");
+            println!("{}", "ok!".green());
+            return Ok(());
+        }
+
+        let script = include_str!("scripts.py");
+        let entry_points_str = entry_points_path.to_string_lossy();
+        let bin_path_str = bin_path.to_string_lossy();
+        let args = vec![entry_points_str.as_ref(), bin_path_str.as_ref()];
+        self.run_script_in_venv("scripts.py", script, args)?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    fn read_script_names(&self, entry_points_path: &Path) -> Result<Vec<String>, Error> {
+        if !entry_points_path.exists() {
+            return Ok(vec![]);
+        }
+        let contents =
+            std::fs::read_to_string(entry_points_path).map_err(|e| Error::ReadError {
+                path: entry_points_path.to_path_buf(),
+                io_error: e,
+            })?;
+        Ok(entry_points::script_names(&contents))
+    }
+
+    /// Make the virtualenv read-only (or writable again), so that it can only
+    /// be changed by dmenv
+    pub fn freeze_perms(&self, frozen: bool) -> Result<(), Error> {
+        self.expect_venv()?;
+        if protect::is_frozen(&self.paths.venv) == frozen {
+            let state = if frozen { "read-only" } else { "writable" };
+            print_warning(&format!(
+                "{} is already {}",
+                self.paths.venv.display(),
+                state
+            ));
+            return Ok(());
+        }
+        if frozen {
+            print_info_1(&format!("Making {} read-only", self.paths.venv.display()));
+        } else {
+            print_info_1(&format!("Making {} writable", self.paths.venv.display()));
+        }
+        protect::set_frozen(&self.paths.venv, frozen)
+    }
+
+    /// Pin pre-commit hooks using the versions from the lock,
+    /// see `precommit::sync()`
+    pub fn precommit_sync(&self, check: bool) -> Result<(), Error> {
+        let config_path = self.paths.project.join(precommit::CONFIG_FILENAME);
+        if !config_path.exists() {
+            return Err(Error::Other {
+                message: format!("{} not found", config_path.display()),
+            });
+        }
+        let lock_path = &self.paths.lock;
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path.to_path_buf(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(&lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let lock = self.parse_lock(&lock_contents)?;
+        let config = std::fs::read_to_string(&config_path).map_err(|e| Error::ReadError {
+            path: config_path.to_path_buf(),
+            io_error: e,
+        })?;
+
+        let (new_config, changes) = precommit::sync(&config, &lock.versions());
+        if changes.is_empty() {
+            print_info_1(&format!(
+                "{} already in sync with {}",
+                precommit::CONFIG_FILENAME,
+                self.paths.lock.display()
+            ));
+            return Ok(());
+        }
+        for change in &changes {
+            print_info_2(&format!(
+                "line {}: {} -> {}",
+                change.line,
+                change.old,
+                change.new.as_str().bold()
+            ));
+        }
+        if check {
+            return Err(Error::Other {
+                message: format!(
+                    "{} is not in sync with the lock.\n Run `dmenv precommit sync` to fix this",
+                    precommit::CONFIG_FILENAME
+                ),
+            });
+        }
+        std::fs::write(&config_path, &new_config).map_err(|e| Error::WriteError {
+            path: config_path.to_path_buf(),
+            io_error: e,
+        })?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Bundle the project and its locked dependencies
+    //
+    // Notes:
+    // * Everything is installed in a build directory inside the virtualenv
+    // * The output path is relative to the project, and defaults to
+    //   <project name>.<extension>
+    pub fn bundle(&self, bundle_options: &BundleOptions) -> Result<(), Error> {
+        print_info_1("Bundling project");
+        let format = bundle_options.format;
+        // Lambda functions are for production, so always use the production lock
+        let lock_path = match format {
+            BundleFormat::Zipapp => self.paths.lock.clone(),
+            BundleFormat::Lambda => self.paths.project.join(PROD_LOCK_FILENAME),
+        };
+        if !lock_path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: lock_path,
+            });
+        }
+        self.ensure_venv()?;
+
+        let build_path = self.paths.venv.join("dmenv-bundle");
+        if build_path.exists() {
+            std::fs::remove_dir_all(&build_path).map_err(|e| Error::Other {
+                message: format!("could not remove {}: {}", build_path.display(), e),
+            })?;
+        }
+
+        let output = match &bundle_options.output {
+            Some(output) => self.paths.project.join(output),
+            None => {
+                let project_name = self.paths.project.file_name().ok_or_else(|| Error::Other {
+                    message: "project path has no file name".to_string(),
+                })?;
+                let name = format!("{}.{}", project_name.to_string_lossy(), format.extension());
+                self.paths.project.join(name)
+            }
+        };
+        match format {
+            BundleFormat::Zipapp => {
+                let entry_point =
+                    bundle_options
+                        .entry_point
+                        .as_ref()
+                        .ok_or_else(|| Error::Other {
+                            message: "zipapp bundles require an `--entry-point`".to_string(),
+                        })?;
+                bundle::check_entry_point(entry_point)?;
+                self.build_zipapp(&build_path, entry_point, &output)?
+            }
+            BundleFormat::Lambda => {
+                self.build_lambda(&build_path, &lock_path, bundle_options, &output)?
+            }
+        }
+        print_info_1(&format!("Bundle written to {}", output.display()));
+        Ok(())
+    }
+
+    /// Install the project (but not its dependencies) in the given directory
+    fn install_project_in(&self, target: &Path) -> Result<(), Error> {
+        print_info_2(&format!("Installing project in {}", target.display()));
+        let target_str = target.to_string_lossy();
+        #[rustfmt::skip]
+        let args = vec![
+            "-m", "pip", "install",
+            "--no-deps",
+            "--target", &target_str,
+            ".",
+        ];
+        self.run_cmd_in_package("python", args)
+    }
+
+    // Note: dependencies are installed with the native installer
+    fn build_zipapp(
+        &self,
+        build_path: &Path,
+        entry_point: &str,
+        output: &Path,
+    ) -> Result<(), Error> {
+        print_info_2(&format!(
+            "Installing dependencies in {}",
+            build_path.display()
+        ));
+        self.install_wheels(Some(build_path))?;
+        self.write_notices(build_path, &[build_path])?;
+        self.install_project_in(build_path)?;
+
+        let extensions = bundle::find_extension_modules(build_path);
+        if !extensions.is_empty() {
+            let names: Vec<_> = extensions
+                .iter()
+                .map(|x| x.strip_prefix(build_path).unwrap_or(x).to_string_lossy())
+                .collect();
+            return Err(Error::Other {
+                message: format!(
+                    "zipapp bundles can only contain pure-Python code. Found:\n{}",
+                    names.join("\n")
+                ),
+            });
+        }
+        let build_str = build_path.to_string_lossy();
+        let output_str = output.to_string_lossy();
+        #[rustfmt::skip]
+        let args = vec![
+            "-m", "zipapp", &build_str,
+            "--output", &output_str,
+            "--python", "/usr/bin/env python3",
+            "--main", entry_point,
+        ];
+        self.run_cmd_in_venv("python", args)
+    }
+
+    /// Build a zip file suitable for AWS Lambda: dependencies and code at the root
+    /// of the archive.
+    /// If `bundle_options.layer` is true, dependencies are put in a separate
+    /// archive (in a `python/` folder, as expected by Lambda layers)
+    //
+    // Note: dependencies are installed by pip, using binary wheels for the
+    // target platform (and not the current one)
+    fn build_lambda(
+        &self,
+        build_path: &Path,
+        lock_path: &Path,
+        bundle_options: &BundleOptions,
+        output: &Path,
+    ) -> Result<(), Error> {
+        let function_path = build_path.join("function");
+        let deps_path = if bundle_options.layer {
+            build_path.join("layer").join("python")
+        } else {
+            function_path.clone()
+        };
+
+        print_info_2(&format!(
+            "Installing dependencies for {} in {}",
+            bundle_options.platform,
+            deps_path.display()
+        ));
+        let python_version = bundle::short_version(&self.python_info.version);
+        let lock_str = lock_path.to_string_lossy();
+        let deps_str = deps_path.to_string_lossy();
+        #[rustfmt::skip]
+        let args = vec![
+            "-m", "pip", "install",
+            "--requirement", &lock_str,
+            "--target", &deps_str,
+            "--platform", &bundle_options.platform,
+            "--python-version", &python_version,
+            "--implementation", "cp",
+            "--only-binary=:all:",
+            "--no-deps",
+        ];
+        self.run_cmd_in_venv("python", args)?;
+        let mut notices_dirs = vec![function_path.as_path()];
+        if bundle_options.layer {
+            notices_dirs.push(deps_path.parent().unwrap_or(&deps_path));
+        }
+        self.write_notices(&deps_path, &notices_dirs)?;
+        self.install_project_in(&function_path)?;
+
+        self.zip_dir(&function_path, output)?;
+        if bundle_options.layer {
+            let layer_output = bundle::layer_path(output);
+            self.zip_dir(&build_path.join("layer"), &layer_output)?;
+            print_info_1(&format!("Layer written to {}", layer_output.display()));
+        }
+        Ok(())
+    }
+
+    /// Write the license texts of the dependencies installed in `deps_dir`
+    /// to the THIRD_PARTY_NOTICES file of each of the `roots` of the bundle
+    fn write_notices(&self, deps_dir: &Path, roots: &[&Path]) -> Result<(), Error> {
+        print_info_2("Collecting license files");
+        let project = self
+            .paths
+            .project
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut missing = vec![];
+        for root in roots {
+            std::fs::create_dir_all(root).map_err(|e| Error::WriteError {
+                path: root.to_path_buf(),
+                io_error: e,
+            })?;
+            missing = notices::write(&project, deps_dir, &root.join(NOTICES_FILENAME))?;
+        }
+        if !missing.is_empty() {
+            print_warning(&format!(
+                "No license file found for: {}. Check their licenses before redistributing the bundle",
+                missing.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the contents of `dir` to the `output` zip file.
+    fn zip_dir(&self, dir: &Path, output: &Path) -> Result<(), Error> {
+        let dir_str = dir.to_string_lossy();
+        let output_str = output.to_string_lossy();
+        // Note: `python -m zipfile` would put `dir` itself in the archive
+        let code = "import shutil, sys; shutil.make_archive(sys.argv[2], 'zip', sys.argv[1])";
+        // make_archive() adds the `.zip` extension itself
+        let base_name = output_str.trim_end_matches(".zip");
+        self.run_cmd_in_venv("python", vec!["-c", code, &dir_str, base_name])
+    }
+
+    /// Creates `setup.py` if it does not exist.
+    pub fn init(
+        &self,
+        name: &Option<String>,
+        version: &Option<String>,
+        author: &Option<String>,
+        yes: bool,
+    ) -> Result<(), Error> {
+        let path = &self.paths.setup_py;
+        if path.exists() {
+            return Err(Error::FileExists {
+                path: path.to_path_buf(),
+            });
+        }
+        let values = self.infer_init_values(name, version, author);
+        let inferred: Vec<_> = values.iter().filter(|x| x.inferred).collect();
+        if !inferred.is_empty() {
+            print_info_1("Using inferred values:");
+            for value in &inferred {
+                print_info_2(&format!("{}: {}", value.label, value.value.as_str().bold()));
+            }
+            if !yes && !confirm("Generate setup.py with those values?") {
+                return Err(Error::Other {
+                    message: "Aborted. Use the command line options to set the values".to_string(),
+                });
+            }
+        }
+        // Note: the template keeps an <AUTHOR> placeholder when the author is empty
+        let context = template::Context::new()
+            .text("name", &values[0].value)
+            .text("version", &values[1].value)
+            .text("author", &values[2].value);
+        let to_write = template::render("init-setup.py", &self.template_dirs(), &context)?;
+        std::fs::write(&path, to_write).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        print_info_1("Generated a new setup.py");
+        Ok(())
+    }
+
+    /// Name, version and author for `init`, inferred when not given
+    /// on the command line (see the `infer` module)
+    fn infer_init_values(
+        &self,
+        name: &Option<String>,
+        version: &Option<String>,
+        author: &Option<String>,
+    ) -> Vec<InitValue> {
+        let project = &self.paths.project;
+        let value = |label, given: &Option<String>, inferred: Option<String>, default: &str| match (
+            given, inferred,
+        ) {
+            (Some(given), _) => InitValue {
+                label,
+                value: given.clone(),
+                inferred: false,
+            },
+            (None, Some(inferred)) => InitValue {
+                label,
+                value: inferred,
+                inferred: true,
+            },
+            (None, None) => InitValue {
+                label,
+                value: default.to_string(),
+                inferred: false,
+            },
+        };
+        // Note: keep the order, `init()` relies on it
+        vec![
+            value("name", name, infer::project_name(project), ""),
+            value("version", version, infer::version(project), "0.1.0"),
+            value("author", author, infer::author(project), ""),
+        ]
+    }
+
+    /// Ask questions about the project, then generate its files.
+    /// Answers given on the command line (or inferred) are used as defaults
+    pub fn init_interactive(
+        &self,
+        name: &Option<String>,
+        version: &Option<String>,
+        author: &Option<String>,
+    ) -> Result<(), Error> {
+        for path in &[&self.paths.setup_py, &self.pyproject_path()] {
+            if path.exists() {
+                return Err(Error::FileExists {
+                    path: path.to_path_buf(),
+                });
+            }
+        }
+        let values = self.infer_init_values(name, version, author);
+        let name = ask("Project name", &values[0].value);
+        if name.is_empty() {
+            return Err(Error::Other {
+                message: "Project name can not be empty".to_string(),
+            });
+        }
+        let version = ask("Version", &values[1].value);
+        let author = ask("Author", &values[2].value);
+        let default_python = bundle::short_version(&self.python_info.version);
+        let python_versions = ask("Supported Python versions", &default_python);
+        let license = ask("License (MIT, BSD-3-Clause, Apache-2.0, GPL-3.0 ...)", "");
+        let src_layout = confirm("Put the code in a `src` directory?");
+        let pyproject = confirm("Use pyproject.toml instead of setup.py?");
+
+        let non_empty = |x: String| if x.is_empty() { None } else { Some(x) };
+        let scaffold = Scaffold {
+            name,
+            version,
+            author: non_empty(author),
+            python_versions: scaffold::parse_python_versions(&python_versions),
+            license: non_empty(license),
+            src_layout,
+            pyproject,
+        };
+        for (relative_path, contents) in scaffold.files(&self.template_dirs())? {
+            let path = self.paths.package.join(&relative_path);
+            if path.exists() {
+                print_info_2(&format!(
+                    "Skipping {}: file exists",
+                    relative_path.display()
+                ));
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| Error::Other {
+                    message: format!("Could not create {}: {}", parent.display(), e),
+                })?;
+            }
+            std::fs::write(&path, contents).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            print_info_2(&format!("Generated {}", relative_path.display()));
+        }
+        print_info_1("All done. You may want to run `dmenv lock` now");
+        Ok(())
+    }
+
+    /// Where to look for templates overriding the ones shipped with dmenv:
+    /// `.dmenv/templates` in the project, then `DMENV_TEMPLATE_DIR`
+    fn template_dirs(&self) -> Vec<PathBuf> {
+        let mut res = vec![self.paths.project.join(".dmenv").join("templates")];
+        res.extend(self.settings.template_dir.clone());
+        res
+    }
+
+    fn pyproject_path(&self) -> PathBuf {
+        self.paths.package.join("pyproject.toml")
+    }
+
+    /// Start using dmenv in a project that already has a setup.py:
+    /// make sure there is a `dev` extra, then create the virtualenv and the lock.
+    /// Unless `yes` is true, ask before changing setup.py.
+    pub fn adopt(&self, yes: bool) -> Result<(), Error> {
+        let path = &self.paths.setup_py;
+        if !path.exists() {
+            return Err(Error::MissingSetupPy {});
+        }
+        print_info_1(&format!("Adopting {}", self.paths.project.display()));
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let extras = setup_py::find_extras(&contents).unwrap_or_default();
+        if extras.is_empty() {
+            print_info_2("No extras found in setup.py");
+        } else {
+            print_info_2(&format!("Found extras: {}", extras.join(", ")));
+        }
+        if !extras.contains(&"dev".to_string()) {
+            self.add_dev_extra(&contents, yes)?;
+        }
+
+        self.lock(&LockOptions::default())?;
+
+        print_info_1("All done! Next steps:");
+        println!(
+            "  * Add your dev dependencies (pytest, linters ...) to the `dev` extra in setup.py"
+        );
+        println!("  * Run `dmenv lock` each time you change setup.py");
+        println!(
+            "  * Commit {} and use `dmenv install` on the other machines",
+            self.paths.lock.display()
+        );
+        println!("  * Use `dmenv run` to run commands from the virtualenv");
+        Ok(())
+    }
+
+    fn add_dev_extra(&self, contents: &str, yes: bool) -> Result<(), Error> {
+        let path = &self.paths.setup_py;
+        let patched = match setup_py::add_dev_extra(contents) {
+            Some(patched) => patched,
+            None => {
+                print_warning(
+                    "No `dev` extra found, and setup.py could not be patched automatically.\n \
+                     dmenv uses it to install dev dependencies, please add it by hand",
+                );
+                return Ok(());
+            }
+        };
+        if !yes && !confirm("No `dev` extra found. Add one to setup.py?") {
+            print_warning("Not adding a `dev` extra: only the regular dependencies will be locked");
+            return Ok(());
+        }
+        std::fs::write(&path, patched).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        print_info_2("Added an empty `dev` extra to setup.py");
+        Ok(())
+    }
+
+    /// Rename the project: update setup.py or pyproject.toml, rename the top-level package
+    /// and the egg-info, then install the project again in development mode
+    pub fn rename(&self, new_name: &str) -> Result<(), Error> {
+        let (path, old_name) = self.project_metadata("name")?;
+        if old_name == new_name {
+            return Err(Error::Other {
+                message: format!("Project is already named {}", new_name),
+            });
+        }
+        let old_package = scaffold::package_name(&old_name);
+        let new_package = scaffold::package_name(new_name);
+        print_info_1(&format!(
+            "Renaming project from {} to {}",
+            old_name,
+            new_name.bold()
+        ));
+
+        let relative_to_project = |path: &Path| {
+            path.strip_prefix(&self.paths.project)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string()
+        };
+
+        // Check everything can be renamed before touching anything
+        let mut renames = vec![];
+        for base in &[self.paths.package.clone(), self.paths.package.join("src")] {
+            for (old, new) in &[
+                (old_package.clone(), new_package.clone()),
+                (format!("{}.py", old_package), format!("{}.py", new_package)),
+                (
+                    format!("{}.egg-info", old_package),
+                    format!("{}.egg-info", new_package),
+                ),
+            ] {
+                let old_path = base.join(old);
+                let new_path = base.join(new);
+                if !old_path.exists() || old_path == new_path {
+                    continue;
+                }
+                if new_path.exists() {
+                    return Err(Error::FileExists { path: new_path });
+                }
+                renames.push((old_path, new_path));
+            }
+        }
+
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let new_contents =
+            rename::rename_in_metadata(&contents, &old_name, new_name, &old_package, &new_package)?;
+        std::fs::write(&path, new_contents).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        print_info_2(&format!("Updated {}", relative_to_project(&path)));
+
+        for (old_path, new_path) in renames {
+            std::fs::rename(&old_path, &new_path).map_err(|e| Error::Other {
+                message: format!(
+                    "could not rename {} to {}: {}",
+                    old_path.display(),
+                    new_path.display(),
+                    e
+                ),
+            })?;
+            print_info_2(&format!(
+                "Renamed {} to {}",
+                relative_to_project(&old_path),
+                relative_to_project(&new_path)
+            ));
+        }
+
+        // The old distribution is still installed in the virtualenv
+        self.run_cmd_in_venv("python", vec!["-m", "pip", "uninstall", "--yes", &old_name])?;
+        self.develop()?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Change the version of the project in setup.py or pyproject.toml.
+    /// Optionally commit the change and create a tag, then build a wheel in `dist/`
+    pub fn bump_version(
+        &self,
+        version_bump: &VersionBump,
+        commit: bool,
+        build: bool,
+    ) -> Result<(), Error> {
+        let (path, current) = self.project_metadata("version")?;
+        let new = project_version::bump(&current, version_bump)?;
+        print_info_1(&format!(
+            "Bumping version from {} to {}",
+            current,
+            new.as_str().bold()
+        ));
+
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let new_contents = project_version::replace(&contents, &current, &new)?;
+        std::fs::write(&path, new_contents).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+
+        if commit {
+            let path_str = path.to_string_lossy();
+            let message = format!("Bump to {}", new);
+            let tag = format!("v{}", new);
+            // Note: only commit the version, not what the user may have staged
+            self.run_git(vec!["add", &path_str])?;
+            self.run_git(vec!["commit", "--message", &message, "--", &path_str])?;
+            self.run_git(vec!["tag", "--annotate", "--message", &tag, &tag])?;
+        }
+        if build {
+            print_info_2("Building wheel in dist/");
+            #[rustfmt::skip]
+            let args = vec![
+                "-m", "pip", "wheel",
+                "--no-deps",
+                "--wheel-dir", "dist",
+                ".",
+            ];
+            self.run_cmd_in_package("python", args)?;
+        }
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Return the path of setup.py (or pyproject.toml if there's no setup.py),
+    /// and the value of `field` in the project metadata, like `name` or `version`
+    fn project_metadata(&self, field: &str) -> Result<(PathBuf, String), Error> {
+        self.package_metadata(&self.paths.package, field)
+    }
+
+    /// Same as `project_metadata`, for any package directory
+    fn package_metadata(&self, package: &Path, field: &str) -> Result<(PathBuf, String), Error> {
+        let setup_py = package.join("setup.py");
+        if setup_py.exists() {
+            let value = self.get_setup_py_metadata(package, field)?;
+            return Ok((setup_py, value));
+        }
+        let path = package.join("pyproject.toml");
+        if !path.exists() {
+            return Err(Error::MissingSetupPy {});
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let value = pyproject::project_value(&contents, field).ok_or_else(|| Error::Other {
+            message: format!(
+                "No {} found in the [project] table of pyproject.toml",
+                field
+            ),
+        })?;
+        Ok((path, value))
+    }
+
+    /// Ask setuptools for a field of the project metadata, like `setup.py --version`
+    fn get_setup_py_metadata(&self, package: &Path, field: &str) -> Result<String, Error> {
+        self.expect_venv()?;
+        let python = self.get_path_in_venv("python")?;
+        let option = format!("--{}", field);
+        let process = Process::new(&python)
+            .args(&["setup.py", &option])
+            .dir(package);
+        let output = self.runner.output(&process)?;
+        if !output.success {
+            return Err(Error::ProcessFailed {
+                message: format!("setup.py {} failed: {}", option, output.stderr),
+            });
+        }
+        // Note: setup.py may print warnings before the value
+        let value = output.stdout.lines().last().unwrap_or_default().trim();
+        Ok(value.to_string())
+    }
+
+    fn run_git(&self, args: Vec<&str>) -> Result<(), Error> {
+        Self::print_cmd("git", &args);
+        let status = std::process::Command::new("git")
+            .args(&args)
+            .current_dir(&self.paths.project)
+            .status();
+        let status = status.map_err(|e| Error::ProcessWaitError { io_error: e })?;
+        if !status.success() {
+            return Err(Error::ProcessFailed {
+                message: "git failed".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Bump a dependency in the lock file
+    //
+    // Note: most of the work is delegated to the Lock struct. Either `Lock.git_bump()`or
+    // `Lock.bump()` is called, depending on the value of the `git` argument.
+    pub fn bump_in_lock(
+        &self,
+        name: &str,
+        version: &str,
+        git: bool,
+        marker: &Option<String>,
+        override_pins: bool,
+    ) -> Result<(), Error> {
+        print_info_1(&format!("Bumping {} to {} ...", name, version));
+        let path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let mut lock = self.parse_lock(&lock_contents)?;
+        if override_pins {
+            lock.override_pins();
+        }
+        let marker = marker.as_ref().map(|x| x.as_str());
+        let changed = if git {
+            lock.git_bump(name, version, marker)
+        } else {
+            lock.bump(name, version, marker)
+        }?;
+        if !changed {
+            print_warning(&format!("Dependency {} already up-to-date", name.bold()));
+            return Ok(());
+        }
+        self.save_lock(Some(&lock_contents), &lock.to_string())?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Apply a batch of bumps to the lock, all together or not at all.
+    /// Fails if the lock was changed by someone else in the meantime
+    pub fn bump_batch(&self, instructions: &[Instruction]) -> Result<Vec<BumpResult>, Error> {
+        let path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let mut lock = self.parse_lock(&lock_contents)?;
+        let (changed, results) = bump_batch::apply(&mut lock, instructions);
+        if !changed {
+            return Ok(results);
+        }
+        let current = std::fs::read_to_string(path).unwrap_or_default();
+        if current != lock_contents {
+            return Err(Error::Other {
+                message: format!("{} was changed while bumping. Try again", self.lock_name()),
+            });
+        }
+        self.save_lock(Some(&lock_contents), &lock.to_string())?;
+        Ok(results)
+    }
+
+    /// Record why `name` must stay at its locked version. `lock` and `bump-in-lock`
+    /// then refuse to change it, unless `--override-pin` is used
+    pub fn pin(&self, name: &str, reason: &str, marker: &Option<String>) -> Result<(), Error> {
+        print_info_1(&format!("Pinning {}", name));
+        let path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let mut lock = self.parse_lock(&lock_contents)?;
+        lock.pin(name, reason, marker.as_ref().map(|x| x.as_str()))?;
+        self.save_lock(Some(&lock_contents), &lock.to_string())?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Help debugging `MalformedLock` errors and markers
+    pub fn explain_lock_line(&self, number: usize) -> Result<(), Error> {
+        let path = &self.paths.lock;
+        if !path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: path.to_path_buf(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        for (key, value) in lock::explain_line(&lock_contents, number)? {
+            println!("{}: {}", key.bold(), value);
+        }
+        Ok(())
+    }
+
+    /// Upgrade the locks to the current format, and move the virtualenv
+    /// from where dmenv <= 0.11 put it. With `check`, only show what would
+    /// be done, and fail if anything would be
+    pub fn migrate(&self, check: bool) -> Result<(), Error> {
+        print_info_1("Looking for files to migrate");
+        let mut pending = 0;
+        for name in &[DEV_LOCK_FILENAME, PROD_LOCK_FILENAME] {
+            let path = self.paths.project.join(name);
+            if !path.exists() {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            let migrated = match migrate::migrate_lock(&contents)? {
+                None => continue,
+                Some(migrated) => migrated,
+            };
+            pending += 1;
+            print_info_2(&format!(
+                "{}: lock format {} -> {}",
+                name,
+                migrate::lock_format(&contents)?,
+                migrate::LOCK_FORMAT
+            ));
+            if !check {
+                std::fs::write(&path, &migrated).map_err(|e| Error::WriteError {
+                    path: path.to_path_buf(),
+                    io_error: e,
+                })?;
+            }
+        }
+
+        let resolver = PathsResolver::new(
+            self.paths.project.clone(),
+            &self.python_info.tag(),
+            &self.settings,
+        );
+        let legacy_venv = resolver.legacy_venv_path()?;
+        if let Some(legacy_venv) = legacy_venv.filter(|x| x.is_dir()) {
+            let venv = &self.paths.venv;
+            if venv.exists() {
+                print_warning(&format!(
+                    "{} is not used anymore, but {} already exists. Remove the former by hand",
+                    legacy_venv.display(),
+                    venv.display()
+                ));
+            } else {
+                pending += 1;
+                print_info_2(&format!("{} -> {}", legacy_venv.display(), venv.display()));
+                if !check {
+                    self.move_venv(&legacy_venv)?;
+                }
+            }
+        }
+
+        if pending == 0 {
+            print_info_1("Nothing to migrate");
+        } else if check {
+            return Err(Error::Other {
+                message: format!(
+                    "{} migration(s) needed.\n Run `dmenv migrate` to apply them",
+                    pending
+                ),
+            });
+        } else {
+            println!("{}", "ok!".green());
+        }
+        Ok(())
+    }
+
+    /// Move the virtualenv from `old_path` to its expected path, fixing the
+    /// absolute paths in its scripts
+    fn move_venv(&self, old_path: &Path) -> Result<(), Error> {
+        let venv = &self.paths.venv;
+        if let Some(parent) = venv.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::Other {
+                message: format!("Could not create {}: {}", parent.display(), e),
+            })?;
+        }
+        std::fs::rename(old_path, venv).map_err(|e| Error::Other {
+            message: format!(
+                "Could not move {} to {}: {}",
+                old_path.display(),
+                venv.display(),
+                e
+            ),
+        })?;
+        let changed = relocate::relocate_bin_dir(
+            &self.get_venv_bin_path(),
+            &old_path.to_string_lossy(),
+            &venv.to_string_lossy(),
+        )?;
+        print_info_2(&format!("{} file(s) changed", changed));
+        Ok(())
+    }
+
+    /// List the dependencies pinned with `dmenv pin`
+    pub fn pins(&self) -> Result<(), Error> {
+        let path = &self.paths.lock;
+        if !path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: path.to_path_buf(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let lock = self.parse_lock(&lock_contents)?;
+        for (line, reason) in lock.pins() {
+            println!("{}: {}", line.as_str().bold(), reason);
+        }
+        Ok(())
+    }
+
+    /// Show statistics about the lock, see the `lock_stats` module. Unless
+    /// `offline` is set, the package indexes are asked about the locked releases
+    pub fn lock_stats(&self, json: bool, offline: bool) -> Result<(), Error> {
+        let lock = self.read_lock()?;
+        let releases = if offline {
+            vec![]
+        } else {
+            self.query_releases(&lock)?
+        };
+        let stats = lock_stats::compute(&lock, &releases);
+        if json {
+            println!("{}", stats.to_json());
+        } else {
+            for line in stats.render() {
+                println!("{}", line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Show the locked releases behind the latest ones on the package index
+    pub fn outdated(&self, json: bool) -> Result<(), Error> {
+        let lock = self.read_lock()?;
+        let releases = self.query_releases(&lock)?;
+        let versions: Vec<_> = lock
+            .dependencies()
+            .iter()
+            .filter_map(|dep| match dep {
+                LockedDependency::Simple(x) => {
+                    Some((x.name.to_string(), x.version.value.to_string()))
+                }
+                _ => None,
+            })
+            .collect();
+        let rows = outdated::compare(&versions, &releases);
+        if json {
+            println!("{}", outdated::to_json(&rows));
+            return Ok(());
+        }
+        if rows.is_empty() {
+            print_info_1("All locked releases are up to date");
+            return Ok(());
+        }
+        for line in outdated::render(&rows) {
+            println!("{}", line);
+        }
+        Ok(())
+    }
+
+    /// Check the existing lock against the policies: forbidden packages, and
+    /// age of the locked releases
+    pub fn check_lock(&self) -> Result<(), Error> {
+        self.read_lock()?;
+        self.check_lock_policy()?;
+        self.check_lock_freshness()?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    /// Check the age of the locked releases against `settings.freshness_policy`,
+    /// if set. Releases the package index knows nothing about are skipped
+    fn check_lock_freshness(&self) -> Result<(), Error> {
+        let policy = match &self.settings.freshness_policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let lock = self.read_lock()?;
+        let versions = lock.versions();
+        let releases: Vec<_> = self
+            .query_releases(&lock)?
+            .into_iter()
+            .filter_map(|release| {
+                let version = versions.get(&release.name)?;
+                Some((release.name, version.to_string(), release.age_days?))
+            })
+            .collect();
+        policy.check(&releases, &lock.allowed_old())
+    }
+
+    fn read_lock(&self) -> Result<Lock, Error> {
+        let path = &self.paths.lock;
+        if !path.exists() {
+            return Err(Error::MissingLock {
+                expected_path: path.to_path_buf(),
+            });
+        }
+        let lock_contents = std::fs::read_to_string(path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        self.parse_lock(&lock_contents)
+    }
+
+    /// Ask the package indexes about the releases of the simple dependencies
+    /// of the lock, see `release_info::query()`
+    fn query_releases(&self, lock: &Lock) -> Result<Vec<Release>, Error> {
+        let versions: Vec<_> = lock.versions().into_iter().collect();
+        let indexes = self.release_info_indexes()?;
+        print_info_2(&format!(
+            "Querying the package index about {} release(s)",
+            versions.len()
+        ));
+        release_info::query(&*self.runner, &self.python_info.binary, &versions, &indexes)
+    }
+
+    /// Indexes the `release_info` module asks about releases, see `Index::to_arg()`
+    fn release_info_indexes(&self) -> Result<Vec<String>, Error> {
+        if !self.settings.indexes.is_empty() {
+            Ok(self
+                .reachable_indexes()?
+                .iter()
+                .map(|(index, url)| index.to_arg(url))
+                .collect())
+        } else if let Some(index_url) = &self.settings.index_url {
+            Ok(vec![format!("*={}", self.authenticated_url(index_url)?)])
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// Add the hashes of the files of the releases, as listed by the package
+    /// index, to the entries of the lock without hashes. Entries that already
+    /// have some keep them: their version did not change, see `Lock::freeze()`
+    // Note: pip refuses to install a lock where only some entries have hashes,
+    // so warn about the ones left without any
+    fn add_index_hashes(&self, contents: &str) -> Result<String, Error> {
+        let versions: Vec<_> = lock_hashes::entries(contents)
+            .into_iter()
+            .filter(|x| x.hashes.is_empty())
+            .map(|x| (x.name, x.version))
+            .collect();
+        if versions.is_empty() {
+            return Ok(contents.to_string());
+        }
+        let indexes = self.release_info_indexes()?;
+        print_info_2(&format!(
+            "Querying the package index about the hashes of {} release(s)",
+            versions.len()
+        ));
+        let hashes = release_info::query_hashes(
+            &*self.runner,
+            &self.python_info.binary,
+            &versions,
+            &indexes,
+        )?;
+        let res = lock_hashes::with_hashes(contents, &hashes);
+        let missing: Vec<_> = versions
+            .iter()
+            .filter(|x| !hashes.contains_key(x))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            print_warning(&format!(
+                "No hashes found on the package index for: {} - run `dmenv prefetch` then `dmenv hash add`",
+                missing.join(", ")
+            ));
+        }
+        Ok(res)
+    }
+
+    /// Warn about the locked releases without a wheel for the interpreter of
+    /// the virtualenv, before pip spends time building them (or fails to).
+    /// Entries whose marker does not match the current environment are skipped
+    fn check_wheels(&self) -> Result<(), Error> {
+        print_info_2("Checking the wheels available for this interpreter");
+        let lock_path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let entries = lock_hashes::entries(&lock_contents);
+        let mut markers: Vec<_> = entries.iter().filter_map(|x| x.marker.clone()).collect();
+        markers.sort();
+        markers.dedup();
+        let current_markers = if markers.is_empty() {
+            vec![]
+        } else {
+            self.get_current_markers(&markers)?
+        };
+        let versions: Vec<_> = entries
+            .into_iter()
+            .filter(|x| match &x.marker {
+                Some(marker) => current_markers.contains(marker),
+                None => true,
+            })
+            .map(|x| (x.name, x.version))
+            .collect();
+
+        let python = self.get_path_in_venv("python")?;
+        let script = include_str!("wheel_tags.py");
+        let output = self
+            .runner
+            .output(&Process::new(&python).arg("-c").arg(script))?;
+        if !output.success {
+            return Err(Error::ProcessFailed {
+                message: format!("could not get the wheel tags: {}", output.stderr),
+            });
+        }
+        let supported = output.stdout.lines().map(|x| x.to_string()).collect();
+
+        let indexes = self.release_info_indexes()?;
+        let files = release_info::query_files(&*self.runner, &python, &versions, &indexes)?;
+        let unknown: Vec<_> = versions
+            .iter()
+            .filter(|x| !files.contains_key(x))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            print_info_2(&format!(
+                "Not checked, unknown to the package index: {}",
+                unknown.join(", ")
+            ));
+        }
+        let problems = wheel_check::check(&versions, &files, &supported);
+        if problems.is_empty() {
+            print_info_2(&format!(
+                "Wheels found for {} release(s)",
+                versions.len() - unknown.len()
+            ));
+            return Ok(());
+        }
+        print_warning(&format!(
+            "{} locked package(s) without a wheel for Python {} on {}:\n{}",
+            problems.len(),
+            self.python_info.version,
+            self.python_info.platform,
+            wheel_check::render(&problems).join("\n")
+        ));
+        Ok(())
+    }
+
+    /// Ensure the virtualenv exists
+    //
+    // Note: this is *only* called by `install()` and `lock()`.
+    // All the other methods requires the virtualenv to exist and
+    // won't create it.
+    fn ensure_venv(&self) -> Result<(), Error> {
+        if protect::is_frozen(&self.paths.venv) {
+            return Err(Error::Other {
+                message: format!(
+                    "{} is read-only.\n Run `dmenv venv unfreeze-perms` first",
+                    self.paths.venv.display()
+                ),
+            });
+        }
+        if self.paths.venv.exists() {
+            print_info_2(&format!(
+                "Using existing virtualenv: {}",
+                self.paths.venv.display()
+            ));
+        } else {
+            self.create_venv()?;
+        }
+        Ok(())
+    }
+
+    /// Make sure the virtualenv exists, or return an error
+    //
+    // Note: this must be called by any method that requires the
+    // virtualenv to exist, like `show_deps` or `run`:
+    // this ensures that error messages printed when the
+    // virtualenv does not exist are consistent.
+    fn expect_venv(&self) -> Result<(), Error> {
+        if !self.paths.venv.exists() {
+            return Err(Error::MissingVenv {
+                path: self.paths.venv.clone(),
+            });
+        }
+        Ok(())
     }
 
     /// Create a new virtualenv
@@ -280,180 +3545,1378 @@ impl VenvManager {
             message: "venv_path has no parent".to_string(),
         })?;
         print_info_2(&format!(
-            "Creating virtualenv in: {}",
-            self.paths.venv.display()
+            "Creating virtualenv in: {}",
+            self.paths.venv.display()
+        ));
+        std::fs::create_dir_all(&parent_venv_path).map_err(|e| Error::Other {
+            message: format!("Could not create {}: {}", parent_venv_path.display(), e),
+        })?;
+
+        // Python -m venv should work in most cases (venv is in the stdlib since Python 3.3)
+        let venv_path = &self.paths.venv.to_string_lossy();
+        let mut args = vec!["-m"];
+        if self.settings.venv_from_stdlib {
+            args.push("venv")
+        } else {
+            // In case we can't or won't use venv from the stdlib, use `virtualenv` instead.
+            // Assume the virtualenv package is present on the system.
+            args.push("virtualenv")
+        };
+        args.push(venv_path);
+        if self.settings.system_site_packages {
+            args.push("--system-site-packages");
+        }
+        let python_binary = &self.python_info.binary;
+        Self::print_cmd(&python_binary.to_string_lossy(), &args);
+        let process = Process::new(python_binary)
+            .args(&args)
+            .dir(&self.paths.project);
+        if !self.runner.status(&process)? {
+            return Err(Error::ProcessFailed {
+                message: "failed to create virtualenv".to_string(),
+            });
+        }
+        if self.settings.shared_venv_root.is_some() {
+            shared::make_group_writable(&self.paths.venv)?;
+        }
+        Ok(())
+    }
+
+    // Actually write the lock file
+    // Delegates most of the work to the Lock struct.
+    fn write_lock(
+        &self,
+        lock_options: &LockOptions,
+        mut build_settings: BuildSettings,
+    ) -> Result<(), Error> {
+        let metadata = &self.get_metadata()?;
+
+        let lock_path = &self.paths.lock;
+        let previous_contents = if lock_path.exists() {
+            Some(
+                std::fs::read_to_string(&lock_path).map_err(|e| Error::ReadError {
+                    path: lock_path.to_owned(),
+                    io_error: e,
+                })?,
+            )
+        } else {
+            None
+        };
+        let lock_contents = previous_contents.clone().unwrap_or_default();
+        migrate::check_lock_format(&lock_contents)?;
+
+        // Note: the header is written again below, with the current metadata
+        let mut lock = self.parse_lock(migrate::strip_lock_header(&lock_contents))?;
+        if let Some(python_version) = &lock_options.python_version {
+            lock.python_version(&python_version);
+        }
+        if let Some(sys_platform) = &lock_options.sys_platform {
+            lock.sys_platform(&sys_platform);
+        }
+        if lock_options.override_pins {
+            lock.override_pins();
+        }
+        let markers = lock.ambiguous_markers();
+        if !markers.is_empty() {
+            let current_markers = self.get_current_markers(&markers)?;
+            lock.current_markers(&current_markers);
+        }
+        let frozen_deps = self.get_frozen_deps()?;
+        build_settings.pin(&frozen_deps)?;
+        let known_names = lock.names();
+        lock.remove_stale_overrides(&self.settings.overrides);
+        let changes = lock.freeze(&frozen_deps)?;
+        Self::print_lock_changes(&changes);
+        for dep in &self.settings.platform_dev_dependencies {
+            lock.add_marker(&dep.name, &dep.marker());
+        }
+        for item in &self.settings.overrides {
+            let line = item.lock_line(&frozen_deps).ok_or_else(|| Error::Other {
+                message: format!(
+                    "{} is not installed in the virtualenv, although it overrides {}",
+                    item.replacement_name(),
+                    item.name
+                ),
+            })?;
+            lock.apply_override(item, &line)?;
+        }
+        let new_names: Vec<_> = lock
+            .names()
+            .into_iter()
+            .filter(|x| !known_names.contains(x))
+            .collect();
+        Self::check_typosquatting(&new_names)?;
+        self.settings.package_policy.check(&lock.names())?;
+        if self.settings.production && self.settings.forbid_vcs_in_production {
+            policy::check_no_vcs(&lock)?;
+        }
+        let mut new_contents = lock.to_string();
+        // Once the lock has hashes, keep them up to date
+        if lock_options.hashes || lock_contents.contains("--hash=") {
+            new_contents = self.add_index_hashes(&new_contents)?;
+        }
+
+        let mut top_comment = self.lock_top_comment(metadata);
+        top_comment.push_str(&build_settings.header());
+
+        let to_write = top_comment + &new_contents;
+        self.save_lock(previous_contents.as_ref().map(|x| x.as_str()), &to_write)
+    }
+
+    /// Install the overrides from `dmenv.toml` on top of the dependencies of the
+    /// project, removing the packages they replace
+    fn install_overrides(&self) -> Result<(), Error> {
+        for item in &self.settings.overrides {
+            print_info_2(&format!(
+                "Overriding {} with {}",
+                item.name, item.requirement
+            ));
+            if item.replaces() {
+                self.run_cmd_in_venv(
+                    "python",
+                    vec!["-m", "pip", "uninstall", "--yes", &item.name],
+                )?;
+            }
+            self.run_cmd_in_venv("python", vec!["-m", "pip", "install", &item.requirement])?;
+        }
+        Ok(())
+    }
+
+    /// Install the development dependencies for the current platform. pip
+    /// ignores the other ones, because of their markers
+    fn install_platform_dev_dependencies(&self) -> Result<(), Error> {
+        let deps = &self.settings.platform_dev_dependencies;
+        if deps.is_empty() || self.settings.production {
+            return Ok(());
+        }
+        print_info_2("Installing development dependencies for this platform");
+        let requirements: Vec<_> = deps.iter().map(|x| x.requirement()).collect();
+        let mut args = vec!["-m", "pip", "install"];
+        args.extend(requirements.iter().map(|x| x.as_str()));
+        self.run_cmd_in_venv("python", args)
+    }
+
+    /// Header of the lock: versions used to generate it, and the variant if any
+    fn lock_top_comment(&self, metadata: &LockMetadata) -> String {
+        let LockMetadata {
+            dmenv_version,
+            python_version,
+            python_platform,
+        } = metadata;
+        let mut res = migrate::lock_header(&dmenv_version, &python_version, &python_platform);
+        if let Some(variant) = &self.settings.variant {
+            res.push_str(&variants::lock_header(variant));
+        }
+        res
+    }
+
+    /// Write the locks of the given extras, and the ones of the extras that
+    /// were already locked, see the `extras` module
+    fn lock_extras(&self, extras: &[String]) -> Result<(), Error> {
+        let mut extras: Vec<_> = extras::locked_extras(&self.paths.lock)?
+            .into_iter()
+            .chain(extras.iter().cloned())
+            .collect();
+        extras.sort();
+        extras.dedup();
+        if extras.is_empty() {
+            return Ok(());
+        }
+        if self.settings.project_type == ProjectType::Requirements {
+            return Err(Error::Other {
+                message: "Extras can't be locked: the project only has requirements".to_string(),
+            });
+        }
+        if let Ok(contents) = std::fs::read_to_string(&self.paths.setup_py) {
+            let known = setup_py::find_extras(&contents).unwrap_or_default();
+            if let Some(unknown) = extras.iter().find(|x| !known.contains(x)) {
+                print_warning(&format!("No `{}` extra found in setup.py", unknown));
+            }
+        }
+
+        let lock_path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let constraints_path = self.paths.venv.join("dmenv-constraints.txt");
+        std::fs::write(&constraints_path, extras::constraints(&lock_contents)).map_err(|e| {
+            Error::WriteError {
+                path: constraints_path.to_path_buf(),
+                io_error: e,
+            }
+        })?;
+        let res = extras
+            .iter()
+            .try_for_each(|x| self.lock_extra(x, &constraints_path));
+        let _ = std::fs::remove_file(&constraints_path);
+        res?;
+        self.check_extras(&extras)
+    }
+
+    /// Ask pip what it would install for the project and the extra, without
+    /// installing anything, then write the result to the lock of the extra
+    fn lock_extra(&self, extra: &str, constraints_path: &Path) -> Result<(), Error> {
+        let path = extras::lock_path(&self.paths.lock, extra);
+        print_info_2(&format!("Locking `{}` extra in {}", extra, path.display()));
+        let spec = if self.settings.production {
+            format!(".[{}]", extra)
+        } else {
+            format!(".[dev,{}]", extra)
+        };
+        let report_path = self.paths.venv.join("dmenv-report.json");
+        let report_str = report_path.to_string_lossy();
+        let constraints_str = constraints_path.to_string_lossy();
+        let args = vec![
+            "-m",
+            "pip",
+            "install",
+            "--dry-run",
+            "--ignore-installed",
+            "--quiet",
+            "--report",
+            &report_str,
+            "--constraint",
+            &constraints_str,
+            &spec,
+        ];
+        self.run_cmd_in_package("python", args)?;
+        let python = self.get_path_in_venv("python")?;
+        let lines = extras::read_report(self.runner.as_ref(), &python, &report_path);
+        let _ = std::fs::remove_file(&report_path);
+
+        let mut contents = self.lock_top_comment(&self.get_metadata()?);
+        contents.push_str(&extras::lock_header(extra));
+        for line in lines? {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        std::fs::write(&path, contents).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+    }
+
+    /// Make sure the locks of the extras agree with the lock, and with each other
+    fn check_extras(&self, extras: &[String]) -> Result<(), Error> {
+        let mut locks = vec![];
+        let paths = std::iter::once(self.paths.lock.clone()).chain(
+            extras
+                .iter()
+                .map(|x| extras::lock_path(&self.paths.lock, x)),
+        );
+        for path in paths {
+            let contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            locks.push((name.to_string(), contents));
+        }
+        let conflicts = extras::conflicts(&locks);
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        let details: Vec<_> = conflicts.iter().map(|x| format!("  {}", x)).collect();
+        Err(Error::Other {
+            message: format!(
+                "Locks of extras don't agree:\n{}\n Run `dmenv lock` again, or add these packages to the dependencies of the project",
+                details.join("\n")
+            ),
+        })
+    }
+
+    /// See `Settings::command_line`
+    fn command_line(&self) -> Vec<String> {
+        if self.settings.command_line.is_empty() {
+            vec!["dmenv".to_string()]
+        } else {
+            self.settings.command_line.clone()
+        }
+    }
+
+    /// Write the lock, keeping a backup of its previous contents for `dmenv undo`
+    fn save_lock(&self, previous: Option<&str>, contents: &str) -> Result<(), Error> {
+        let path = &self.paths.lock;
+        let args = self.command_line();
+        let backup = Backup::new(&self.lock_name(), previous, contents, &args.join(" "));
+        if let Err(e) = lock_backup::save(&self.paths.project, &backup) {
+            print_warning(&format!("Could not back up the lock: {}", e));
+        }
+        if self.settings.write_changeset {
+            if let Err(e) = self.write_changeset(previous, contents, &args) {
+                print_warning(&format!("Could not write the changeset: {}", e));
+            }
+        }
+        // Note: write next to the lock, then rename, so that the lock is never
+        // left half-written
+        let tmp_path = path.with_file_name(format!(".{}.tmp", self.lock_name()));
+        std::fs::write(&tmp_path, contents).map_err(|e| Error::WriteError {
+            path: tmp_path.to_path_buf(),
+            io_error: e,
+        })?;
+        std::fs::rename(&tmp_path, path).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+    }
+
+    fn write_changeset(
+        &self,
+        previous: Option<&str>,
+        contents: &str,
+        args: &[String],
+    ) -> Result<(), Error> {
+        let direct = self.direct_dependency_names();
+        let changes = changeset::diff(
+            previous.unwrap_or_default(),
+            contents,
+            args.get(1..).unwrap_or_default(),
+            direct.as_deref(),
+        )?;
+        let changeset = Changeset::new(&self.lock_name(), &args.join(" "), contents, changes);
+        let path = changeset::path(&self.paths.lock);
+        std::fs::write(&path, changeset.to_json()).map_err(|e| Error::WriteError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })
+    }
+
+    /// Names of the dependencies declared by the project, from `requirements.in`
+    /// and `requirements-dev.in`, or from the `.egg-info` directory written when
+    /// the project was installed in development mode
+    fn direct_dependency_names(&self) -> Option<Vec<String>> {
+        let paths = match self.settings.project_type {
+            ProjectType::Requirements => vec![
+                self.paths.project.join(REQUIREMENTS_IN_FILENAME),
+                self.paths.project.join(DEV_REQUIREMENTS_IN_FILENAME),
+            ],
+            ProjectType::Package => {
+                let (_, name) = self.project_metadata("name").ok()?;
+                let egg_info = entry_points::find_egg_info(&self.paths.package, &name)?;
+                vec![egg_info.join("requires.txt")]
+            }
+        };
+        let mut res = vec![];
+        for path in paths {
+            if let Ok(contents) = std::fs::read_to_string(&path) {
+                res.extend(changeset::requirement_names(&contents));
+            }
+        }
+        Some(res)
+    }
+
+    fn lock_name(&self) -> String {
+        self.paths
+            .lock
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Restore the lock as it was before the last command that changed it,
+    /// unless it was edited since
+    pub fn undo(&self) -> Result<(), Error> {
+        let lock_name = self.lock_name();
+        let (backup_path, backup) = match lock_backup::latest(&self.paths.project, &lock_name)? {
+            Some(latest) => latest,
+            None => {
+                return Err(Error::Other {
+                    message: format!("nothing to undo for {}", lock_name),
+                })
+            }
+        };
+        let path = &self.paths.lock;
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        let changeset_path = changeset::path(path);
+        let changeset = std::fs::read_to_string(&changeset_path)
+            .ok()
+            .and_then(|x| Changeset::from_json(&x).ok())
+            .filter(|x| x.after_hash == backup.after_hash);
+        if !backup.matches(&current) {
+            let reverted = changeset.and_then(|x| x.revert(&current));
+            let reverted = reverted.ok_or_else(|| Error::Other {
+                message: format!(
+                    "{} was changed after `{}`. Refusing to undo",
+                    lock_name, backup.args
+                ),
+            })?;
+            print_info_1(&format!(
+                "Undoing `{}`, keeping the changes made since",
+                backup.args
+            ));
+            std::fs::write(path, reverted).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+        } else {
+            print_info_1(&format!("Undoing `{}`", backup.args));
+            self.restore_backup(&backup)?;
+        }
+        // Note: the changeset describes the command being undone, or an older
+        // one. Either way, it no longer matches the restored lock
+        let _ = std::fs::remove_file(&changeset_path);
+        std::fs::remove_file(&backup_path).map_err(|e| Error::WriteError {
+            path: backup_path.to_path_buf(),
+            io_error: e,
+        })?;
+        println!("{}", "ok!".green());
+        Ok(())
+    }
+
+    fn restore_backup(&self, backup: &Backup) -> Result<(), Error> {
+        let path = &self.paths.lock;
+        match &backup.before {
+            Some(before) => std::fs::write(&path, before).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?,
+            None => std::fs::remove_file(&path).map_err(|e| Error::WriteError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?,
+        }
+        Ok(())
+    }
+
+    /// Get the list of the *actual* deps in the virtualenv by calling `pip freeze`.
+    /// Ask the Python in the virtualenv which of the given environment markers
+    /// match the current environment
+    fn get_current_markers(&self, markers: &[String]) -> Result<Vec<String>, Error> {
+        let script = include_str!("markers.py");
+        let python = self.get_path_in_venv("python")?;
+        let process = Process::new(&python)
+            .arg("-c")
+            .arg(script)
+            .args(markers)
+            .dir(&self.paths.project);
+        let output = self.runner.output(&process)?;
+        if !output.success {
+            return Err(Error::ProcessFailed {
+                message: format!("could not evaluate markers: {}", output.stderr),
+            });
+        }
+        Ok(output.stdout.lines().map(|x| x.to_string()).collect())
+    }
+
+    fn get_frozen_deps(&self) -> Result<Vec<FrozenDependency>, Error> {
+        let local_names = self.get_extra_package_names()?;
+        let freeze_output = self.run_pip_freeze()?;
+        let mut res = vec![];
+        for line in freeze_output.lines() {
+            // Recent versions of pip freeze packages installed from git as
+            // `name @ git+...`. The lines of the overrides are written from dmenv.toml
+            let url_name = line.splitn(2, " @ ").next().unwrap_or_default();
+            let overridden = self.settings.overrides.iter().any(|x| {
+                x.is_url() && canonical_name(&x.replacement_name()) == canonical_name(url_name)
+            });
+            if line.contains(" @ ") && overridden {
+                continue;
+            }
+            let frozen_dep = FrozenDependency::from_string(&line)?;
+            // Filter out pkg-resources. This works around
+            // a Debian bug in pip: https://bugs.debian.org/cgi-bin/bugreport.cgi?bug=871790
+            if frozen_dep.name == "pkg-resources" {
+                continue;
+            }
+            // Editable packages are already excluded by `pip freeze --exclude-editable`,
+            // but not always (for instance when one of the packages was installed
+            // as a regular dependency before), so check their names too.
+            if local_names.contains(&canonical_name(&frozen_dep.name)) {
+                continue;
+            }
+            res.push(frozen_dep);
+        }
+
+        Ok(res)
+    }
+
+    /// Names of the packages from `extra_package_dirs`, as found in their metadata
+    fn get_extra_package_names(&self) -> Result<Vec<String>, Error> {
+        let mut res = vec![];
+        for extra_package in &self.paths.extra_packages {
+            let (_, name) = self.package_metadata(extra_package, "name")?;
+            res.push(canonical_name(&name));
+        }
+        Ok(res)
+    }
+
+    fn run_pip_freeze(&self) -> Result<String, Error> {
+        let lock_path = &self.paths.lock;
+        print_info_2(&format!("Generating {}", lock_path.display()));
+        let pip = self.get_path_in_venv("pip")?;
+        let pip_str = pip.to_string_lossy().to_string();
+        let args = vec!["freeze", "--exclude-editable", "--all", "--local"];
+        Self::print_cmd(&pip_str, &args);
+        let process = Process::new(&pip).args(&args).dir(&self.paths.project);
+        let output = self.runner.output(&process)?;
+        if !output.success {
+            return Err(Error::ProcessFailed {
+                message: format!("pip freeze failed: {}", output.stderr),
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    fn get_metadata(&self) -> Result<LockMetadata, Error> {
+        let dmenv_version = env!("CARGO_PKG_VERSION");
+        let python_platform = &self.python_info.platform;
+        let python_version = &self.python_info.version;
+        Ok(LockMetadata {
+            dmenv_version: dmenv_version.to_string(),
+            python_platform: python_platform.to_string(),
+            python_version: python_version.to_string(),
+        })
+    }
+
+    fn install_from_lock(&self) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        print_info_2(&format!(
+            "Installing dependencies from {}",
+            lock_path.display()
+        ));
+        // Use packages downloaded by `dmenv prefetch` or the native installer, if any
+        let cache_path = get_cache_path("wheels")?;
+        let cache_str = cache_path.to_string_lossy();
+        let lock_contents = std::fs::read_to_string(lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let lock = self.parse_lock(&lock_contents)?;
+        let batches = lock.install_batches()?;
+        if batches.is_empty() {
+            return self.pip_install_requirements(lock_path, &["--find-links", &cache_str]);
+        }
+        // Note: without constraints, pip would install the latest versions of the
+        // dependencies of a batch, instead of the ones in the lock
+        let constraints_path = self.paths.venv.join("dmenv-constraints.lock");
+        std::fs::write(&constraints_path, lock.constraints()).map_err(|e| Error::WriteError {
+            path: constraints_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let res = self.install_batches(&batches, &cache_str, &constraints_path);
+        let _ = std::fs::remove_file(&constraints_path);
+        res
+    }
+
+    fn install_batches(
+        &self,
+        batches: &[InstallBatch],
+        cache_str: &str,
+        constraints_path: &Path,
+    ) -> Result<(), Error> {
+        let constraints_str = constraints_path.to_string_lossy();
+        for (i, batch) in batches.iter().enumerate() {
+            let mut message = format!(
+                "Installing batch {}/{} (order {}",
+                i + 1,
+                batches.len(),
+                batch.order
+            );
+            if batch.no_deps {
+                message.push_str(", without dependencies");
+            }
+            message.push_str(&format!("): {}", batch.names.join(", ")));
+            print_info_2(&message);
+            let batch_path = self.paths.venv.join(format!("dmenv-batch-{}.lock", i));
+            std::fs::write(&batch_path, &batch.contents).map_err(|e| Error::WriteError {
+                path: batch_path.to_path_buf(),
+                io_error: e,
+            })?;
+            let mut args = vec!["--find-links", cache_str, "--constraint", &constraints_str];
+            if batch.no_deps {
+                args.push("--no-deps");
+            }
+            let res = self.pip_install_requirements(&batch_path, &args);
+            let _ = std::fs::remove_file(&batch_path);
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Install what the extra needs besides the lock. Note: the locks agree,
+    /// see `check_extra_locks()`
+    fn install_from_extra_lock(&self, extra: &str) -> Result<(), Error> {
+        let lock_path = extras::lock_path(&self.paths.lock, extra);
+        print_info_2(&format!(
+            "Installing `{}` extra from {}",
+            extra,
+            lock_path.display()
         ));
-        std::fs::create_dir_all(&parent_venv_path).map_err(|e| Error::Other {
-            message: format!("Could not create {}: {}", parent_venv_path.display(), e),
+        let cache_path = get_cache_path("wheels")?;
+        let cache_str = cache_path.to_string_lossy();
+        self.pip_install_requirements(&lock_path, &["--find-links", &cache_str])
+    }
+
+    fn check_extra_locks(&self, extras: &[String]) -> Result<(), Error> {
+        if extras.is_empty() {
+            return Ok(());
+        }
+        for extra in extras {
+            let lock_path = extras::lock_path(&self.paths.lock, extra);
+            if !lock_path.exists() {
+                return Err(Error::Other {
+                    message: format!(
+                        "{} not found. Run `dmenv lock --extra {}` first",
+                        lock_path.display(),
+                        extra
+                    ),
+                });
+            }
+        }
+        self.check_extras(extras)
+    }
+
+    /// Tell frontends which packages were installed from the lock, see `--event-stream`
+    fn emit_installed_packages(&self) -> Result<(), Error> {
+        if !events::enabled() {
+            return Ok(());
+        }
+        let lock_path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(&lock_path).map_err(|e| Error::ReadError {
+            path: lock_path.to_path_buf(),
+            io_error: e,
+        })?;
+        let lock = self.parse_lock(&lock_contents)?;
+        for (name, version) in lock.versions() {
+            events::emit(&Event::PackageInstalled {
+                name: &name,
+                version: &version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Install pure-Python wheels without pip, then let pip
+    /// handle the rest of the lock.
+    fn install_from_lock_native(&self) -> Result<(), Error> {
+        print_info_2(&format!(
+            "Installing wheels from {} (native installer)",
+            self.paths.lock.display()
+        ));
+        self.install_wheels(None)
+    }
+
+    /// Install dependencies from the lock using the native installer, either in the
+    /// virtualenv or in the `target` directory.
+    //
+    // Note: the actual work is done by the `install_wheels.py` script,
+    // which writes the lines it could not handle in a "fallback" lock.
+    fn install_wheels(&self, target: Option<&Path>) -> Result<(), Error> {
+        let lock_path = &self.paths.lock;
+        let cache_path = get_cache_path("wheels")?;
+        let fallback_path = self.paths.venv.join("dmenv-fallback.lock");
+        let lock_str = lock_path.to_string_lossy();
+        let cache_str = cache_path.to_string_lossy();
+        let fallback_str = fallback_path.to_string_lossy();
+        let mut args = vec![
+            &*lock_str,
+            "--cache",
+            &*cache_str,
+            "--fallback",
+            &*fallback_str,
+        ];
+        // When using the shared store, files are written once in the dmenv
+        // cache and hard-linked into the virtualenv
+        let store_path = if self.settings.shared_store && target.is_none() {
+            Some(get_cache_path("store")?)
+        } else {
+            None
+        };
+        let store_str = store_path.as_ref().map(|x| x.to_string_lossy());
+        if let Some(store_str) = &store_str {
+            args.extend(&["--store", store_str]);
+        }
+        let target_str = target.map(|x| x.to_string_lossy());
+        if let Some(target_str) = &target_str {
+            args.extend(&["--target", target_str]);
+        }
+        // Note: the URLs may contain credentials, so they are not given on the command line.
+        // Without them, the script uses pypi.org
+        let mut env = vec![];
+        let indexes = self.release_info_indexes()?;
+        if !indexes.is_empty() {
+            env.push(("DMENV_INSTALL_WHEELS_INDEXES", indexes.join(" ")));
+        }
+        let script = include_str!("install_wheels.py");
+        self.run_script_in_venv_with_env("install_wheels.py", script, args, &env)?;
+
+        let fallback = std::fs::read_to_string(&fallback_path).map_err(|e| Error::ReadError {
+            path: fallback_path.to_path_buf(),
+            io_error: e,
         })?;
+        let res = if fallback.trim().is_empty() {
+            Ok(())
+        } else {
+            print_info_2("Installing remaining dependencies with pip");
+            let mut args = vec![];
+            if let Some(target_str) = &target_str {
+                args.extend(&["--no-deps", "--target", target_str]);
+            }
+            self.pip_install_requirements(&fallback_path, &args)
+        };
+        let _ = std::fs::remove_file(&fallback_path);
+        res
+    }
+
+    pub fn upgrade_pip(&self) -> Result<(), Error> {
+        print_info_2("Upgrading pip");
+        let args = vec!["-m", "pip", "install", "pip", "--upgrade"];
+        self.run_cmd_in_venv("python", args)
+            .map_err(|_| Error::PipUpgradeFailed {})
+    }
+
+    /// Install dependencies from `requirements.in` (and `requirements-dev.in`, unless
+    /// in production mode) for projects that are not packages
+    fn install_requirements_in(&self) -> Result<(), Error> {
+        let mut message = format!("Installing deps from {}", REQUIREMENTS_IN_FILENAME);
+        let dev_requirements_in = self.paths.project.join(DEV_REQUIREMENTS_IN_FILENAME);
+        let with_dev = !self.settings.production && dev_requirements_in.exists();
+        if with_dev {
+            message.push_str(&format!(" and {}", DEV_REQUIREMENTS_IN_FILENAME));
+        }
+        print_info_2(&message);
+
+        let mut args = vec![
+            "-m",
+            "pip",
+            "install",
+            "--requirement",
+            REQUIREMENTS_IN_FILENAME,
+        ];
+        if with_dev {
+            args.extend(&["--requirement", DEV_REQUIREMENTS_IN_FILENAME]);
+        }
+        self.run_cmd_in_venv("python", args)
+    }
+
+    fn install_editable(&self) -> Result<(), Error> {
+        let mut message = "Installing deps from setup.py".to_string();
+        if self.settings.production {
+            message.push_str("(ignoring dev dependencies)");
+        }
+        print_info_2(&message);
+
+        // Install the other packages first, in case the project depends on them
+        for extra_package in &self.paths.extra_packages {
+            let args = vec!["-m", "pip", "install", "--editable", "."];
+            self.run_cmd_in_venv_from("python", args, extra_package)?;
+        }
+
+        let mut args = vec!["-m", "pip", "install", "--editable"];
+        if self.settings.production {
+            args.push(".")
+        } else {
+            args.push(".[dev]")
+        }
+        self.run_cmd_in_package("python", args)
+    }
+
+    fn run_cmd_in_venv(&self, name: &str, args: Vec<&str>) -> Result<(), Error> {
+        self.run_cmd_in_venv_from(name, args, &self.paths.project)
+    }
 
-        // Python -m venv should work in most cases (venv is in the stdlib since Python 3.3)
-        let venv_path = &self.paths.venv.to_string_lossy();
-        let mut args = vec!["-m"];
-        if self.settings.venv_from_stdlib {
-            args.push("venv")
+    /// Like `run_cmd_in_venv`, but from the directory containing setup.py
+    fn run_cmd_in_package(&self, name: &str, args: Vec<&str>) -> Result<(), Error> {
+        self.run_cmd_in_venv_from(name, args, &self.paths.package)
+    }
+
+    fn run_cmd_in_venv_from(&self, name: &str, args: Vec<&str>, dir: &Path) -> Result<(), Error> {
+        self.run_cmd_in_venv_with_indexes(name, args, dir, None)
+    }
+
+    /// When running pip, use the given index URLs instead of the configured ones
+    // Note: the URLs are given with environment variables, so that
+    // credentials don't end up in the output
+    fn run_cmd_in_venv_with_indexes(
+        &self,
+        name: &str,
+        args: Vec<&str>,
+        dir: &Path,
+        index_urls: Option<&[String]>,
+    ) -> Result<(), Error> {
+        let is_pip = name == "pip" || args.starts_with(&["-m", "pip"]);
+        let log = if self.settings.quiet && is_pip {
+            Some(self.pip_log()?)
         } else {
-            // In case we can't or won't use venv from the stdlib, use `virtualenv` instead.
-            // Assume the virtualenv package is present on the system.
-            args.push("virtualenv")
+            None
         };
-        args.push(venv_path);
-        if self.settings.system_site_packages {
-            args.push("--system-site-packages");
+        let mut process = self
+            .venv_process(name, args, dir, index_urls)?
+            .log(log.clone())
+            .interactive(self.interactive.get());
+        if process.interactive {
+            for (key, value) in self.run_env.borrow().iter() {
+                process = process.env(key, value);
+            }
         }
-        let python_binary = &self.python_info.binary;
-        Self::print_cmd(&python_binary.to_string_lossy(), &args);
-        let status = std::process::Command::new(&python_binary)
-            .current_dir(&self.paths.project)
-            .args(&args)
-            .status();
-        let status = status.map_err(|e| Error::ProcessWaitError { io_error: e })?;
-        if !status.success() {
-            return Err(Error::Other {
-                message: "failed to create virtualenv".to_string(),
-            });
+        let args: Vec<&str> = process.args.iter().map(String::as_str).collect();
+        Self::print_cmd(&process.program.to_string_lossy(), &args);
+        if !self.runner.status(&process)? {
+            let message = match &log {
+                Some(log) => format!("command failed. See {} for details", log.display()),
+                None => "command failed".to_string(),
+            };
+            return Err(Error::ProcessFailed { message });
         }
+
         Ok(())
     }
 
-    // Actually write the lock file
-    // Delegates most of the work to the Lock struct.
-    fn write_lock(&self, lock_options: &LockOptions) -> Result<(), Error> {
-        let metadata = &self.get_metadata()?;
+    /// The process started by `run_cmd_in_venv_with_indexes()`, without its log
+    fn venv_process(
+        &self,
+        name: &str,
+        args: Vec<&str>,
+        dir: &Path,
+        index_urls: Option<&[String]>,
+    ) -> Result<Process, Error> {
+        let bin_path = &self.get_path_in_venv(name)?;
+        let is_pip = name == "pip" || args.starts_with(&["-m", "pip"]);
+        let mut args = args;
+        if is_pip && !self.build_isolation.get() && args.contains(&"install") {
+            args.push("--no-build-isolation");
+        }
+        let mut process = Process::new(bin_path).args(&args).dir(dir);
+        for (key, value) in self.venv_env.borrow().iter() {
+            process = process.env(key, value);
+        }
+        // The activated virtualenv may use a POSIX path, which would confuse Python
+        if PosixLayer::detect().is_some() && std::env::var_os("VIRTUAL_ENV").is_some() {
+            process = process.env("VIRTUAL_ENV", &self.paths.venv.to_string_lossy());
+        }
+        if is_pip {
+            let index_urls = match index_urls {
+                Some(index_urls) => index_urls.to_vec(),
+                None => self.pip_index_urls()?,
+            };
+            if let Some((first, rest)) = index_urls.split_first() {
+                process = process.env("PIP_INDEX_URL", first);
+                if !rest.is_empty() {
+                    process = process.env("PIP_EXTRA_INDEX_URL", &rest.join(" "));
+                }
+            }
+            if let Some(find_links) = self.pip_find_links()? {
+                process = process.env("PIP_FIND_LINKS", &find_links);
+            }
+        }
+        Ok(process)
+    }
 
-        let lock_path = &self.paths.lock;
-        let lock_contents = if lock_path.exists() {
-            std::fs::read_to_string(&lock_path).map_err(|e| Error::ReadError {
-                path: lock_path.to_owned(),
-                io_error: e,
-            })?
-        } else {
-            String::new()
-        };
+    /// Show what `Lock::freeze()` changed: added dependencies in green,
+    /// changed ones in yellow
+    fn print_lock_changes(changes: &[LockChange]) {
+        let width = changes
+            .iter()
+            .map(|x| x.name().len())
+            .max()
+            .unwrap_or_default();
+        for change in changes {
+            let line = change.render(width);
+            match change {
+                LockChange::Added { .. } => println!("{}", line.as_str().green()),
+                LockChange::Changed { .. } => println!("{}", line.as_str().yellow()),
+            }
+        }
+    }
 
-        let mut lock = Lock::from_string(&lock_contents)?;
-        if let Some(python_version) = &lock_options.python_version {
-            lock.python_version(&python_version);
+    /// Path of the log for pip commands, created on first use
+    fn pip_log(&self) -> Result<PathBuf, Error> {
+        if let Some(log) = &*self.pip_log.borrow() {
+            return Ok(log.to_path_buf());
         }
-        if let Some(sys_platform) = &lock_options.sys_platform {
-            lock.sys_platform(&sys_platform);
+        logs::prune(&self.paths.venv);
+        let log = logs::new_log_path(&self.paths.venv, state::now());
+        print_info_2(&format!("Writing pip output to {}", log.display()));
+        self.pip_log.replace(Some(log.to_path_buf()));
+        Ok(log)
+    }
+
+    /// Warn about new dependencies with names close to the names of popular packages,
+    /// and ask for confirmation when possible
+    fn check_typosquatting(names: &[String]) -> Result<(), Error> {
+        for name in names {
+            let popular = match typosquat::lookalike(name) {
+                None => continue,
+                Some(popular) => popular,
+            };
+            print_warning(&format!(
+                "{} looks a lot like {}, a popular package. Make sure this is not a typo",
+                name.as_str().bold(),
+                popular.bold()
+            ));
+            if is_interactive() && !confirm(&format!("Use {} anyway?", name)) {
+                return Err(Error::Other {
+                    message: format!("Aborted: did you mean '{}'?", popular),
+                });
+            }
         }
-        let frozen_deps = self.get_frozen_deps()?;
-        lock.freeze(&frozen_deps);
-        let new_contents = lock.to_string();
+        Ok(())
+    }
 
-        let LockMetadata {
-            dmenv_version,
-            python_version,
-            python_platform,
-        } = metadata;
-        let top_comment = format!(
-            "# Generated with dmenv {}, python {}, on {}\n",
-            dmenv_version, &python_version, &python_platform
-        );
+    fn parse_lock(&self, lock_contents: &str) -> Result<Lock, Error> {
+        migrate::check_lock_format(lock_contents)?;
+        let mut lock = if self.settings.strict_lock {
+            Lock::from_string_strict(lock_contents)?
+        } else {
+            Lock::from_string(lock_contents)?
+        };
+        lock.drop_stale_hashes();
+        if let Some(newline) = self.settings.lock_newline {
+            lock.newline(newline);
+        }
+        Ok(lock)
+    }
 
-        let to_write = top_comment + &new_contents;
-        std::fs::write(&lock_path, &to_write).map_err(|e| Error::WriteError {
-            path: lock_path.to_path_buf(),
-            io_error: e,
+    /// When a virtualenv is activated, dmenv uses it instead of its own. Refuse to
+    /// use it if it looks like it belongs to another project, or if it uses another
+    /// Python version, because the lock would silently be wrong
+    fn check_foreign_venv(&self, allow: bool) -> Result<(), Error> {
+        let active = match std::env::var("VIRTUAL_ENV") {
+            Err(_) => return Ok(()),
+            Ok(active) => msys::native_path(&active),
+        };
+        // Some commands choose the virtualenv themselves
+        if active != self.paths.venv {
+            return Ok(());
+        }
+        let mut details = vec![];
+        let resolver = PathsResolver::new(
+            self.paths.project.to_path_buf(),
+            &self.python_info.tag(),
+            &self.settings,
+        );
+        let expected = resolver.default_venv_path()?;
+        if !active.starts_with(&self.paths.project) && active != expected {
+            details.push(format!(
+                "it is not in the project, and dmenv would use {}",
+                expected.display()
+            ));
+        }
+        if let Some(version) = python_info::venv_python_version(&active) {
+            if version != self.python_info.version {
+                details.push(format!(
+                    "it uses Python {}, but dmenv uses Python {} ({})",
+                    version,
+                    self.python_info.version,
+                    self.python_info.binary.display()
+                ));
+            }
+        }
+        if details.is_empty() {
+            return Ok(());
+        }
+        if allow {
+            for detail in &details {
+                print_warning(&format!("Using {} anyway: {}", active.display(), detail));
+            }
+            return Ok(());
+        }
+        Err(Error::ForeignVenv {
+            path: active,
+            details,
         })
     }
 
-    /// Get the list of the *actual* deps in the virtualenv by calling `pip freeze`.
-    fn get_frozen_deps(&self) -> Result<Vec<FrozenDependency>, Error> {
-        let freeze_output = self.run_pip_freeze()?;
-        let mut res = vec![];
-        for line in freeze_output.lines() {
-            let frozen_dep = FrozenDependency::from_string(&line)?;
-            // Filter out pkg-resources. This works around
-            // a Debian bug in pip: https://bugs.debian.org/cgi-bin/bugreport.cgi?bug=871790
-            if frozen_dep.name != "pkg-resources" {
-                res.push(frozen_dep);
-            }
+    /// Refuse to install from a lock containing packages denied by the settings
+    /// Warn about locks written in an older format
+    fn check_lock_format(&self) -> Result<(), Error> {
+        let path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        if let Some(deprecation) = migrate::check_lock_format(&lock_contents)? {
+            print_deprecation(&deprecation);
         }
+        Ok(())
+    }
 
+    /// Build settings from the lock, if any, overridden by the options of the
+    /// command. Pip is then told whether to use build isolation
+    fn build_settings(
+        &self,
+        no_build_isolation: bool,
+        requirements: &[String],
+    ) -> Result<BuildSettings, Error> {
+        let path = &self.paths.lock;
+        let mut res = if path.exists() {
+            let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+                path: path.to_path_buf(),
+                io_error: e,
+            })?;
+            BuildSettings::from_lock(&lock_contents)?
+        } else {
+            BuildSettings::default()
+        };
+        if no_build_isolation {
+            res.isolation = false;
+        }
+        if !requirements.is_empty() {
+            res.requirements = requirements.to_vec();
+        }
+        if res.isolation && !res.requirements.is_empty() {
+            print_warning("Build requirements are only used with --no-build-isolation");
+        }
+        self.build_isolation.set(res.isolation);
         Ok(res)
     }
 
-    fn run_pip_freeze(&self) -> Result<String, Error> {
-        let lock_path = &self.paths.lock;
-        print_info_2(&format!("Generating {}", lock_path.display()));
-        let pip = self.get_path_in_venv("pip")?;
-        let pip_str = pip.to_string_lossy().to_string();
-        let args = vec!["freeze", "--exclude-editable", "--all", "--local"];
-        Self::print_cmd(&pip_str, &args);
-        let command = std::process::Command::new(pip)
-            .current_dir(&self.paths.project)
-            .args(args)
-            .output();
-        let command = command.map_err(|e| Error::ProcessOutError { io_error: e })?;
-        if !command.status.success() {
-            return Err(Error::Other {
+    /// Install the build requirements first, so that they are there when pip
+    /// builds packages without isolation
+    fn install_build_requirements(&self, build_settings: &BuildSettings) -> Result<(), Error> {
+        if build_settings.requirements.is_empty() {
+            return Ok(());
+        }
+        print_info_2("Installing build requirements");
+        let mut args = vec!["-m", "pip", "install"];
+        args.extend(build_settings.requirements.iter().map(|x| x.as_str()));
+        self.run_cmd_in_venv("python", args)
+    }
+
+    /// The locked versions only make sense with the indexes of the variant
+    /// used by `dmenv lock`
+    fn check_lock_variant(&self) -> Result<(), Error> {
+        let path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let locked = variants::from_lock(&lock_contents);
+        match (&locked, &self.settings.variant) {
+            (Some(locked), Some(variant)) if locked != variant => Err(Error::Other {
                 message: format!(
-                    "pip freeze failed: {}",
-                    String::from_utf8_lossy(&command.stderr)
+                    "{} was generated for the '{}' variant, not '{}'. Run `dmenv --variant {} lock` to switch variants",
+                    path.display(),
+                    locked,
+                    variant,
+                    variant
                 ),
-            });
+            }),
+            _ => Ok(()),
         }
-        Ok(String::from_utf8_lossy(&command.stdout).to_string())
     }
 
-    fn get_metadata(&self) -> Result<LockMetadata, Error> {
-        let dmenv_version = env!("CARGO_PKG_VERSION");
-        let python_platform = &self.python_info.platform;
-        let python_version = &self.python_info.version;
-        Ok(LockMetadata {
-            dmenv_version: dmenv_version.to_string(),
-            python_platform: python_platform.to_string(),
-            python_version: python_version.to_string(),
+    fn check_lock_policy(&self) -> Result<(), Error> {
+        let forbid_vcs = self.settings.production && self.settings.forbid_vcs_in_production;
+        if self.settings.package_policy.is_empty() && !forbid_vcs {
+            return Ok(());
+        }
+        let path = &self.paths.lock;
+        let lock_contents = std::fs::read_to_string(&path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let lock = self.parse_lock(&lock_contents)?;
+        self.settings.package_policy.check(&lock.names())?;
+        if forbid_vcs {
+            policy::check_no_vcs(&lock)?;
+        }
+        Ok(())
+    }
+
+    /// URLs of the indexes pip will use, without credentials
+    fn configured_index_urls(&self) -> Result<Vec<String>, Error> {
+        if !self.settings.indexes.is_empty() {
+            return Ok(self
+                .settings
+                .indexes
+                .iter()
+                .map(|x| x.url.to_string())
+                .collect());
+        }
+        if let Some(url) = &self.settings.index_url {
+            return Ok(vec![url.to_string()]);
+        }
+        let res = PipConfig::load(&self.paths.venv)?.index_urls();
+        if res.is_empty() {
+            return Ok(vec![indexes::DEFAULT_INDEX_URL.to_string()]);
+        }
+        Ok(res)
+    }
+
+    /// The configuration of pip, with the index settings dmenv passes to pip
+    /// on top of it, and warnings about the settings that conflict
+    fn effective_pip_config(&self) -> Result<(PipConfig, Vec<String>), Error> {
+        let mut config = PipConfig::load(&self.paths.venv)?;
+        let mut warnings = vec![];
+        if !self.settings.indexes.is_empty() {
+            let urls: Vec<_> = self
+                .settings
+                .indexes
+                .iter()
+                .map(|x| x.url.clone())
+                .collect();
+            warnings = config.override_indexes(&urls, "DMENV_INDEXES");
+            if urls.len() == 1 {
+                if let Some(extra) = config.get("extra-index-url") {
+                    warnings.push(format!(
+                        "extra-index-url = {} from {} is used too, in addition to DMENV_INDEXES",
+                        extra.display_value(),
+                        extra.source
+                    ));
+                }
+            }
+        } else if let Some(index_url) = &self.settings.index_url {
+            if !self.settings.credential_helper.is_empty() {
+                warnings =
+                    config.override_indexes(std::slice::from_ref(index_url), "DMENV_INDEX_URL");
+            } else if let Some(pip_index) = config.get("index-url") {
+                if &pip_index.value != index_url {
+                    warnings.push(format!(
+                        "pip uses index-url = {} from {}, not DMENV_INDEX_URL",
+                        pip_index.display_value(),
+                        pip_index.source
+                    ));
+                }
+            }
+        }
+        if config.no_index() {
+            let source = &config
+                .get("no-index")
+                .map(|x| x.source.as_str())
+                .unwrap_or_default();
+            warnings.push(format!(
+                "no-index is set in {}: pip won't use any index",
+                source
+            ));
+        }
+        Ok((config, warnings))
+    }
+
+    /// Fail early, with a clear message, when no package index can be reached,
+    /// instead of letting pip retry for a while and print a long traceback.
+    // Note: when some of the indexes can't be reached, `reachable_indexes()` skips them
+    fn preflight_indexes(&self) -> Result<(), Error> {
+        if !self.settings.check_indexes {
+            return Ok(());
+        }
+        print_info_2("Checking package indexes");
+        let statuses = indexes::check(
+            &*self.runner,
+            &self.python_info.binary,
+            &self.configured_index_urls()?,
+        )?;
+        if statuses.iter().any(|x| x.error.is_none()) {
+            return Ok(());
+        }
+        Err(Error::IndexesUnreachable {
+            details: statuses
+                .iter()
+                .map(|x| format!("{}: {}", x.url, x.error.as_deref().unwrap_or_default()))
+                .collect(),
+            hints: indexes::proxy_hints(),
         })
     }
 
-    fn install_from_lock(&self) -> Result<(), Error> {
-        let lock_path = &self.paths.lock;
-        print_info_2(&format!(
-            "Installing dependencies from {}",
-            lock_path.display()
-        ));
-        let as_str = &self.paths.lock.to_string_lossy();
-        let args = vec!["-m", "pip", "install", "--requirement", as_str];
-        self.run_cmd_in_venv("python", args)
+    /// Index URLs for pip: the reachable indexes from `settings.indexes`, those used
+    /// for every package first. When no indexes are configured, only the credential
+    /// helper is used, if any
+    fn pip_index_urls(&self) -> Result<Vec<String>, Error> {
+        if self.settings.indexes.is_empty() {
+            return Ok(self.index_auth_url()?.into_iter().collect());
+        }
+        let reachable = self.reachable_indexes()?;
+        let unscoped = reachable.iter().filter(|(index, _)| !index.is_scoped());
+        let scoped = reachable.iter().filter(|(index, _)| index.is_scoped());
+        Ok(unscoped
+            .chain(scoped)
+            .map(|(_, url)| url.to_string())
+            .collect())
     }
 
-    pub fn upgrade_pip(&self) -> Result<(), Error> {
-        print_info_2("Upgrading pip");
-        let args = vec!["-m", "pip", "install", "pip", "--upgrade"];
-        self.run_cmd_in_venv("python", args)
-            .map_err(|_| Error::PipUpgradeFailed {})
+    /// Value of `PIP_FIND_LINKS`, with URLs signed for the buckets in
+    /// `settings.find_links`. Only computed once
+    fn pip_find_links(&self) -> Result<Option<String>, Error> {
+        if self.settings.find_links.is_empty() {
+            return Ok(None);
+        }
+        if let Some(find_links) = self.find_links.borrow().as_ref() {
+            return Ok(Some(find_links.pip_value()));
+        }
+        print_info_2("Resolving find-links locations");
+        let find_links = FindLinks::resolve(&self.settings.find_links)?;
+        let res = find_links.pip_value();
+        self.find_links.replace(Some(find_links));
+        Ok(Some(res))
     }
 
-    fn install_editable(&self) -> Result<(), Error> {
-        let mut message = "Installing deps from setup.py".to_string();
-        if self.settings.production {
-            message.push_str("(ignoring dev dependencies)");
+    /// Indexes from `settings.indexes` that can be reached, along with the URL to use
+    /// (including credentials, if any). Only computed once
+    fn reachable_indexes(&self) -> Result<Vec<(Index, String)>, Error> {
+        if let Some(res) = self.reachable_indexes.borrow().as_ref() {
+            return Ok(res.clone());
         }
-        print_info_2(&message);
+        let mut res = vec![];
+        for index in &self.settings.indexes {
+            if !index.is_reachable() {
+                print_warning(&format!("{} is unreachable, skipping it", index.url));
+                continue;
+            }
+            let url = self.authenticated_url(&index.url)?;
+            res.push((index.clone(), url));
+        }
+        if res.is_empty() {
+            return Err(Error::Other {
+                message: "none of the configured package indexes can be reached".to_string(),
+            });
+        }
+        let urls: Vec<_> = res.iter().map(|(index, _)| index.url.as_str()).collect();
+        print_info_2(&format!("Using package indexes: {}", urls.join(", ")));
+        *self.reachable_indexes.borrow_mut() = Some(res.clone());
+        Ok(res)
+    }
 
-        let mut args = vec!["-m", "pip", "install", "--editable"];
-        if self.settings.production {
-            args.push(".")
+    /// Run `pip install --requirement` on a lock-like file. When some indexes are
+    /// scoped, run pip once per set of indexes, so that each package only comes
+    /// from the indexes it is allowed to come from
+    fn pip_install_requirements(&self, path: &Path, extra_args: &[&str]) -> Result<(), Error> {
+        let reachable = if self.settings.indexes.is_empty() {
+            vec![]
         } else {
-            args.push(".[dev]")
+            self.reachable_indexes()?
+        };
+        let path_str = path.to_string_lossy();
+        if !reachable.iter().any(|(index, _)| index.is_scoped()) {
+            let mut args = vec!["-m", "pip", "install", "--requirement", &path_str];
+            args.extend(extra_args);
+            return self.run_cmd_in_venv("python", args);
         }
-        self.run_cmd_in_venv("python", args)
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let indexes: Vec<_> = reachable.iter().map(|(index, _)| index.clone()).collect();
+        let url_for = |index: &Index| {
+            let (_, url) = reachable.iter().find(|(x, _)| x == index).unwrap();
+            url.to_string()
+        };
+        // Lines grouped by the URLs of the indexes to use
+        let mut groups: Vec<(Vec<String>, Vec<String>)> = vec![];
+        for line in logical_lines(&contents) {
+            // Note: options like `--index-url` would conflict with the indexes we set
+            if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
+                continue;
+            }
+            let name = requirement_name(&line);
+            let urls: Vec<_> = indexes::for_package(&indexes, name)
+                .into_iter()
+                .map(url_for)
+                .collect();
+            if urls.is_empty() {
+                return Err(Error::Other {
+                    message: format!("no reachable package index for '{}'", name),
+                });
+            }
+            match groups.iter_mut().find(|(x, _)| x == &urls) {
+                Some((_, lines)) => lines.push(line),
+                None => groups.push((urls, vec![line])),
+            }
+        }
+        for (i, (urls, lines)) in groups.iter().enumerate() {
+            let group_path = self.paths.venv.join(format!("dmenv-index-{}.lock", i));
+            std::fs::write(&group_path, lines.join("\n")).map_err(|e| Error::WriteError {
+                path: group_path.to_path_buf(),
+                io_error: e,
+            })?;
+            let group_str = group_path.to_string_lossy();
+            // Note: the lock contains every dependency, and they may come from other indexes
+            let mut args = vec![
+                "-m",
+                "pip",
+                "install",
+                "--no-deps",
+                "--requirement",
+                &group_str,
+            ];
+            args.extend(extra_args);
+            let res =
+                self.run_cmd_in_venv_with_indexes("python", args, &self.paths.project, Some(urls));
+            let _ = std::fs::remove_file(&group_path);
+            res?;
+        }
+        Ok(())
     }
 
-    fn run_cmd_in_venv(&self, name: &str, args: Vec<&str>) -> Result<(), Error> {
-        let bin_path = &self.get_path_in_venv(name)?;
-        Self::print_cmd(&bin_path.to_string_lossy(), &args);
-        let command = std::process::Command::new(bin_path)
-            .args(args)
-            .current_dir(&self.paths.project)
-            .status();
-        let command = command.map_err(|e| Error::ProcessWaitError { io_error: e })?;
-        if !command.success() {
-            return Err(Error::Other {
-                message: "command failed".to_string(),
+    fn authenticated_url(&self, url: &str) -> Result<String, Error> {
+        if self.settings.credential_helper.is_empty() {
+            return Ok(url.to_string());
+        }
+        match index_auth::get_credentials(&self.settings.credential_helper, url)? {
+            Some(credentials) => index_auth::with_credentials(url, &credentials),
+            None => Ok(url.to_string()),
+        }
+    }
+
+    /// The index URL with the credentials printed by the credential helper, given
+    /// to pip as PIP_INDEX_URL on its process only. Only computed once, and only
+    /// when pip is about to run
+    // Note: don't print the URL, it contains the credentials
+    fn index_auth_url(&self) -> Result<Option<String>, Error> {
+        if let Some(url) = self.index_auth.borrow().as_ref() {
+            return Ok(url.clone());
+        }
+        let url = self.get_index_auth_url()?;
+        self.index_auth.replace(Some(url.clone()));
+        Ok(url)
+    }
+
+    fn get_index_auth_url(&self) -> Result<Option<String>, Error> {
+        if self.settings.credential_helper.is_empty() {
+            return Ok(None);
+        }
+        let index_url = match &self.settings.index_url {
+            Some(index_url) => index_url,
+            None => {
+                print_warning("DMENV_CREDENTIAL_HELPER is set but no index URL is configured");
+                return Ok(None);
+            }
+        };
+        print_info_2("Getting credentials for the package index");
+        let credentials = index_auth::get_credentials(&self.settings.credential_helper, index_url)?;
+        match credentials {
+            Some(credentials) => Ok(Some(index_auth::with_credentials(index_url, &credentials)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Run one of the Python scripts embedded in dmenv with the
+    /// virtualenv's interpreter
+    // Note: the script is passed with `-c`, so only print its name
+    fn run_script_in_venv(&self, name: &str, script: &str, args: Vec<&str>) -> Result<(), Error> {
+        self.run_script_in_venv_with_env(name, script, args, &[])
+    }
+
+    /// Like `run_script_in_venv`, with environment variables set for the script only
+    fn run_script_in_venv_with_env(
+        &self,
+        name: &str,
+        script: &str,
+        args: Vec<&str>,
+        env: &[(&str, String)],
+    ) -> Result<(), Error> {
+        let python = &self.get_path_in_venv("python")?;
+        println!(
+            "{} {} {} {}",
+            "$".blue(),
+            python.display(),
+            name,
+            args.join(" ")
+        );
+        let mut process = Process::new(python)
+            .arg("-c")
+            .arg(script)
+            .args(&args)
+            .dir(&self.paths.project);
+        for (key, value) in self.venv_env.borrow().iter() {
+            process = process.env(key, value);
+        }
+        for (key, value) in env {
+            process = process.env(key, value);
+        }
+        if !self.runner.status(&process)? {
+            return Err(Error::ProcessFailed {
+                message: format!("{} failed", name),
             });
         }
 
@@ -485,18 +4948,80 @@ impl VenvManager {
         #[cfg(not(windows))]
         let suffix = "";
 
-        let name = format!("{}{}", name, suffix);
         let bin_path = &self.get_venv_bin_path();
-        let path = self.paths.venv.join(bin_path).join(name);
-        if !path.exists() {
-            return Err(Error::Other {
-                message: format!("Cannot run: '{}' does not exist", &path.display()),
-            });
+        let candidates: Vec<_> = executable_names(name)
+            .iter()
+            .map(|x| bin_path.join(format!("{}{}", x, suffix)))
+            .collect();
+        match candidates.iter().find(|x| x.exists()) {
+            Some(path) => Ok(path.to_path_buf()),
+            None => Err(Error::Other {
+                message: format!("Cannot run: '{}' does not exist", &candidates[0].display()),
+            }),
         }
-        Ok(path)
     }
 
     fn print_cmd(bin_path: &str, args: &[&str]) {
         println!("{} {} {}", "$".blue(), bin_path, args.join(" "));
     }
 }
+
+/// Name to give to `dmenv run` to run the file from the binaries directory
+/// of the virtualenv, if it is executable (this excludes activation scripts)
+fn script_name(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+    let name = path.file_name()?.to_string_lossy().to_string();
+    #[cfg(windows)]
+    {
+        name.strip_suffix(".exe").map(|x| x.to_string())
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = path.metadata().ok()?.permissions().mode();
+        if mode & 0o111 == 0 {
+            return None;
+        }
+        Some(name)
+    }
+}
+
+/// Names to look for in the binaries directory of the virtualenv. Some
+/// implementations (like PyPy on Windows) may only provide `pypy3` instead of `python`
+fn executable_names(name: &str) -> Vec<&str> {
+    match name {
+        "python" => vec!["python", "python3", "pypy3", "pypy"],
+        "python3" => vec!["python3", "pypy3"],
+        _ => vec![name],
+    }
+}
+
+/// Name of the package in a line of a lock or requirements file
+/// Lines of a requirements file, with lines ending with `\\` (usually followed
+/// by `--hash=...` lines) joined to the next ones
+fn logical_lines(contents: &str) -> Vec<String> {
+    let mut res: Vec<String> = vec![];
+    let mut continued = false;
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        if continued {
+            if let Some(last) = res.last_mut() {
+                last.push('\n');
+                last.push_str(line);
+            }
+        } else {
+            res.push(line.trim().to_string());
+        }
+        continued = line.trim_end().ends_with('\\');
+    }
+    res
+}
+
+fn requirement_name(line: &str) -> &str {
+    let end = line
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or(line.len());
+    &line[..end]
+}
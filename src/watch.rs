@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Changes are only reported once files stopped changing for that long
+// Note: this prevents running things twice when an editor or `git`
+// writes several files in a row.
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+
+/// Watch a set of files for changes, by polling their modification time.
+/// Files that do not exist are watched too, and are reported when they get created.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    last_modified: Vec<Option<SystemTime>>,
+}
+
+impl Watcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let last_modified = paths.iter().map(|p| modification_time(p)).collect();
+        Watcher {
+            paths,
+            last_modified,
+        }
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Block until at least one of the files changes, and return the
+    /// list of files that changed
+    pub fn wait_for_changes(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let new_changes = self.poll();
+            if new_changes.is_empty() && !changed.is_empty() {
+                break;
+            }
+            for path in new_changes {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+            if !changed.is_empty() {
+                std::thread::sleep(DEBOUNCE_DELAY);
+            }
+        }
+        changed
+    }
+
+    fn poll(&mut self) -> Vec<PathBuf> {
+        let mut res = vec![];
+        for (path, last_modified) in self.paths.iter().zip(self.last_modified.iter_mut()) {
+            let modified = modification_time(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                res.push(path.to_path_buf());
+            }
+        }
+        res
+    }
+}
+
+/// Return the last modification time of a file, if any
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,203 @@
+//! Check the files the package index has for each locked release against the
+//! wheel tags supported by the interpreter of the virtualenv, for
+//! `dmenv install --check-wheels`.
+//!
+//! Without a compatible wheel, pip builds the package from its sdist, which
+//! may take a long time and fail in confusing ways (missing compiler, missing
+//! headers ...), or fails to install it at all. Knowing it before installing
+//! anything turns these failures into an immediate report.
+use std::collections::{BTreeMap, HashSet};
+
+/// What pip will be able to install for a release
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Availability {
+    Wheel,
+    /// No compatible wheel, so pip has to build the sdist
+    SourceOnly,
+    /// Neither a compatible wheel nor an sdist
+    Nothing,
+}
+
+/// A locked release without a compatible wheel
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub name: String,
+    pub version: String,
+    pub availability: Availability,
+}
+
+/// Tags of a wheel, like `cp311-cp311-manylinux_2_17_x86_64`, from its filename.
+/// Compressed tag sets, like `py2.py3-none-any`, are expanded
+fn wheel_tags(filename: &str) -> Option<Vec<String>> {
+    let stem = filename.strip_suffix(".whl")?;
+    // {name}-{version}(-{build})?-{python}-{abi}-{platform}
+    let parts: Vec<_> = stem.split('-').collect();
+    if parts.len() != 5 && parts.len() != 6 {
+        return None;
+    }
+    let (pythons, abis, platforms) = match parts[parts.len() - 3..] {
+        [pythons, abis, platforms] => (pythons, abis, platforms),
+        _ => return None,
+    };
+    let mut res = vec![];
+    for python in pythons.split('.') {
+        for abi in abis.split('.') {
+            for platform in platforms.split('.') {
+                res.push(format!("{}-{}-{}", python, abi, platform));
+            }
+        }
+    }
+    Some(res)
+}
+
+fn is_sdist(filename: &str) -> bool {
+    [".tar.gz", ".zip", ".tar.bz2"]
+        .iter()
+        .any(|x| filename.ends_with(x))
+}
+
+/// What pip can install for a release with the given files
+pub fn availability(files: &[String], supported: &HashSet<String>) -> Availability {
+    let compatible_wheel = files
+        .iter()
+        .filter_map(|x| wheel_tags(x))
+        .any(|tags| tags.iter().any(|x| supported.contains(x)));
+    if compatible_wheel {
+        Availability::Wheel
+    } else if files.iter().any(|x| is_sdist(x)) {
+        Availability::SourceOnly
+    } else {
+        Availability::Nothing
+    }
+}
+
+/// Check the (name, version) releases, in order. Releases missing from
+/// `files` (the package index knows nothing about them) are left out
+pub fn check(
+    versions: &[(String, String)],
+    files: &BTreeMap<(String, String), Vec<String>>,
+    supported: &HashSet<String>,
+) -> Vec<Problem> {
+    let mut res = vec![];
+    for (name, version) in versions {
+        let release_files = match files.get(&(name.to_string(), version.to_string())) {
+            Some(release_files) => release_files,
+            None => continue,
+        };
+        let availability = availability(release_files, supported);
+        if availability != Availability::Wheel {
+            res.push(Problem {
+                name: name.to_string(),
+                version: version.to_string(),
+                availability,
+            });
+        }
+    }
+    res
+}
+
+/// One line for each problem, the releases without anything to install first
+pub fn render(problems: &[Problem]) -> Vec<String> {
+    let nothing = problems
+        .iter()
+        .filter(|x| x.availability == Availability::Nothing)
+        .map(|x| {
+            format!(
+                " * {}=={}: no wheel for this interpreter, and no sdist",
+                x.name, x.version
+            )
+        });
+    let source_only = problems
+        .iter()
+        .filter(|x| x.availability == Availability::SourceOnly)
+        .map(|x| {
+            format!(
+                " * {}=={}: no wheel for this interpreter, will be built from source",
+                x.name, x.version
+            )
+        });
+    nothing.chain(source_only).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn supported() -> HashSet<String> {
+        strings(&[
+            "cp311-cp311-manylinux_2_17_x86_64",
+            "cp311-abi3-manylinux_2_17_x86_64",
+            "py3-none-any",
+        ])
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_wheel_tags() {
+        assert_eq!(
+            wheel_tags("six-1.16.0-py2.py3-none-any.whl").unwrap(),
+            vec!["py2-none-any", "py3-none-any"]
+        );
+        assert_eq!(
+            wheel_tags("foo-1.0-1build-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl")
+                .unwrap(),
+            vec![
+                "cp311-cp311-manylinux_2_17_x86_64",
+                "cp311-cp311-manylinux2014_x86_64"
+            ]
+        );
+        assert!(wheel_tags("foo-1.0.tar.gz").is_none());
+        assert!(wheel_tags("foo-1.0.whl").is_none());
+    }
+
+    #[test]
+    fn test_availability() {
+        let supported = supported();
+        let files = strings(&[
+            "numpy-1.26.0-cp39-cp39-win_amd64.whl",
+            "numpy-1.26.0-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl",
+            "numpy-1.26.0.tar.gz",
+        ]);
+        assert_eq!(availability(&files, &supported), Availability::Wheel);
+        let files = strings(&["numpy-1.21.0-cp39-cp39-win_amd64.whl", "numpy-1.21.0.zip"]);
+        assert_eq!(availability(&files, &supported), Availability::SourceOnly);
+        let files = strings(&["pywin32-306-cp311-cp311-win_amd64.whl"]);
+        assert_eq!(availability(&files, &supported), Availability::Nothing);
+    }
+
+    #[test]
+    fn test_check() {
+        let versions = vec![
+            ("attrs".to_string(), "19.1.0".to_string()),
+            ("numpy".to_string(), "1.21.0".to_string()),
+            ("private".to_string(), "0.1".to_string()),
+            ("pywin32".to_string(), "306".to_string()),
+        ];
+        let mut files = BTreeMap::new();
+        files.insert(
+            ("attrs".to_string(), "19.1.0".to_string()),
+            strings(&["attrs-19.1.0-py2.py3-none-any.whl"]),
+        );
+        files.insert(
+            ("numpy".to_string(), "1.21.0".to_string()),
+            strings(&["numpy-1.21.0.zip"]),
+        );
+        files.insert(
+            ("pywin32".to_string(), "306".to_string()),
+            strings(&["pywin32-306-cp311-cp311-win_amd64.whl"]),
+        );
+        let problems = check(&versions, &files, &supported());
+        assert_eq!(
+            render(&problems),
+            vec![
+                " * pywin32==306: no wheel for this interpreter, and no sdist",
+                " * numpy==1.21.0: no wheel for this interpreter, will be built from source",
+            ]
+        );
+    }
+}
@@ -0,0 +1,16 @@
+""" Print the wheel tags supported by the current interpreter, most specific
+first, one per line, like `cp311-cp311-manylinux_2_17_x86_64`.
+
+Usage:
+    wheel_tags.py
+"""
+from pip._vendor.packaging.tags import sys_tags
+
+
+def main():
+    for tag in sys_tags():
+        print(tag)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,88 @@
+//! Make the Windows console display dmenv's output correctly: ANSI escape
+//! sequences for colors, and UTF-8 for non-ASCII characters.
+//!
+//! Both are enabled on a best effort basis: when stdout is redirected, or on
+//! Windows versions without virtual terminal processing (before Windows 10),
+//! things are left as they are and colors are disabled instead.
+
+#![allow(nonstandard_style)]
+
+type HANDLE = *mut u8;
+type BOOL = i32;
+type DWORD = u32;
+type UINT = u32;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const STD_OUTPUT_HANDLE: DWORD = -11i32 as DWORD;
+const STD_ERROR_HANDLE: DWORD = -12i32 as DWORD;
+const ENABLE_VIRTUAL_TERMINAL_PROCESSING: DWORD = 0x0004;
+const CP_UTF8: UINT = 65001;
+
+extern "system" {
+    fn GetStdHandle(nStdHandle: DWORD) -> HANDLE;
+    fn GetConsoleMode(hConsoleHandle: HANDLE, lpMode: *mut DWORD) -> BOOL;
+    fn SetConsoleMode(hConsoleHandle: HANDLE, dwMode: DWORD) -> BOOL;
+    fn GetConsoleOutputCP() -> UINT;
+    fn SetConsoleOutputCP(wCodePageID: UINT) -> BOOL;
+}
+
+/// Restores the previous code page of the console when dropped, so that
+/// programs run after dmenv in the same console are not affected
+pub struct Console {
+    previous_code_page: Option<UINT>,
+}
+
+impl Console {
+    pub fn setup() -> Self {
+        let mut colors = true;
+        for std_handle in &[STD_OUTPUT_HANDLE, STD_ERROR_HANDLE] {
+            match unsafe { enable_virtual_terminal(*std_handle) } {
+                Some(true) => (),
+                Some(false) => colors = false,
+                // Not a console: nothing to do
+                None => (),
+            }
+        }
+        if !colors {
+            colored::control::set_override(false);
+        }
+
+        let previous_code_page = unsafe {
+            let previous = GetConsoleOutputCP();
+            // Note: GetConsoleOutputCP() returns 0 when there is no console
+            if previous == 0 || previous == CP_UTF8 || SetConsoleOutputCP(CP_UTF8) == 0 {
+                None
+            } else {
+                Some(previous)
+            }
+        };
+        Console { previous_code_page }
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        if let Some(code_page) = self.previous_code_page {
+            unsafe {
+                SetConsoleOutputCP(code_page);
+            }
+        }
+    }
+}
+
+/// Returns None if the handle is not a console, otherwise whether virtual
+/// terminal processing could be enabled
+unsafe fn enable_virtual_terminal(std_handle: DWORD) -> Option<bool> {
+    let handle = GetStdHandle(std_handle);
+    if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+        return None;
+    }
+    let mut mode: DWORD = 0;
+    if GetConsoleMode(handle, &mut mode) == 0 {
+        return None;
+    }
+    if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 {
+        return Some(true);
+    }
+    Some(SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0)
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+/// Projects living on the filesystem of the "other side" of WSL. File access is
+/// much slower there, and symlinks created by `venv` may not work
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrossMount {
+    /// Running inside WSL, on a Windows drive (`/mnt/c/...`)
+    WindowsFromWsl,
+    /// Running on Windows, on the WSL filesystem (`\\wsl$\...`)
+    WslFromWindows,
+}
+
+impl CrossMount {
+    pub fn detect(project: &Path) -> Option<Self> {
+        let path = project.to_string_lossy();
+        if cfg!(windows) && is_wsl_share(&path) {
+            return Some(CrossMount::WslFromWindows);
+        }
+        if cfg!(target_os = "linux") && is_windows_mount(&path) && in_wsl() {
+            return Some(CrossMount::WindowsFromWsl);
+        }
+        None
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            CrossMount::WindowsFromWsl => "on a Windows drive, but dmenv runs inside WSL",
+            CrossMount::WslFromWindows => "inside WSL, but dmenv runs on Windows",
+        }
+    }
+}
+
+fn in_wsl() -> bool {
+    if std::env::var_os("WSL_DISTRO_NAME").is_some() {
+        return true;
+    }
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|x| x.to_lowercase().contains("microsoft"))
+        .unwrap_or(false)
+}
+
+/// Windows drives are mounted at `/mnt/c`, `/mnt/d` ... by default
+fn is_windows_mount(path: &str) -> bool {
+    if !path.starts_with("/mnt/") {
+        return false;
+    }
+    let mut chars = path["/mnt/".len()..].chars();
+    let is_drive = chars.next().map_or(false, |x| x.is_ascii_alphabetic());
+    let tail = chars.as_str();
+    is_drive && (tail.is_empty() || tail.starts_with('/'))
+}
+
+fn is_wsl_share(path: &str) -> bool {
+    let path = path.to_lowercase().replace('/', "\\");
+    let path = path
+        .trim_start_matches(r"\\?\unc\")
+        .trim_start_matches(r"\\");
+    path.starts_with(r"wsl$\") || path.starts_with(r"wsl.localhost\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_windows_mount() {
+        assert!(is_windows_mount("/mnt/c/Users/jane/foo"));
+        assert!(is_windows_mount("/mnt/d"));
+        assert!(!is_windows_mount("/mnt/data/foo"));
+        assert!(!is_windows_mount("/home/jane/foo"));
+    }
+
+    #[test]
+    fn test_is_wsl_share() {
+        assert!(is_wsl_share(r"\\wsl$\Ubuntu\home\jane\foo"));
+        assert!(is_wsl_share(r"\\wsl.localhost\Ubuntu\home\jane"));
+        assert!(is_wsl_share(r"\\?\UNC\wsl$\Ubuntu\home\jane"));
+        assert!(is_wsl_share("//wsl$/Ubuntu/home/jane"));
+        assert!(!is_wsl_share(r"C:\Users\jane\foo"));
+        assert!(!is_wsl_share(r"\\server\share"));
+    }
+}
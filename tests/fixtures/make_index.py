@@ -0,0 +1,84 @@
+"""Generate the tiny package index in tests/fixtures/index, served by
+`dmenv::testing::LocalIndex` in the integration tests.
+
+Usage: python tests/fixtures/make_index.py
+
+The wheels are reproducible (fixed timestamps), so running this again
+does not change anything unless PACKAGES is edited.
+"""
+import base64
+import hashlib
+import os
+import shutil
+import zipfile
+
+# name, version, dependencies
+PACKAGES = [
+    ("dmenv-fixture-a", "1.0", ["dmenv-fixture-b"]),
+    ("dmenv-fixture-b", "1.0", []),
+    ("dmenv-fixture-b", "1.1", []),
+]
+
+ROOT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "index")
+
+
+def record_hash(data):
+    digest = hashlib.sha256(data).digest()
+    return "sha256=" + base64.urlsafe_b64encode(digest).decode("ascii").rstrip("=")
+
+
+def make_wheel(name, version, dependencies):
+    module = name.replace("-", "_")
+    dist_info = "{}-{}.dist-info".format(module, version)
+    metadata = "Metadata-Version: 2.1\nName: {}\nVersion: {}\n".format(name, version)
+    metadata += "".join("Requires-Dist: {}\n".format(x) for x in dependencies)
+    files = [
+        ("{}/__init__.py".format(module), 'VERSION = "{}"\n'.format(version)),
+        ("{}/METADATA".format(dist_info), metadata),
+        (
+            "{}/WHEEL".format(dist_info),
+            "Wheel-Version: 1.0\nGenerator: make_index.py\n"
+            "Root-Is-Purelib: true\nTag: py3-none-any\n",
+        ),
+    ]
+    files = [(path, contents.encode("utf-8")) for path, contents in files]
+    record = "".join(
+        "{},{},{}\n".format(path, record_hash(data), len(data)) for path, data in files
+    )
+    record += "{}/RECORD,,\n".format(dist_info)
+    files.append(("{}/RECORD".format(dist_info), record.encode("utf-8")))
+
+    filename = "{}-{}-py3-none-any.whl".format(module, version)
+    path = os.path.join(ROOT, "packages", filename)
+    with zipfile.ZipFile(path, "w") as archive:
+        for name_in_zip, data in files:
+            info = zipfile.ZipInfo(name_in_zip, date_time=(2019, 1, 1, 0, 0, 0))
+            info.external_attr = 0o644 << 16
+            archive.writestr(info, data)
+    with open(path, "rb") as f:
+        return filename, hashlib.sha256(f.read()).hexdigest()
+
+
+def write_page(path, links):
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    body = "".join('    <a href="{}">{}</a><br>\n'.format(href, text) for href, text in links)
+    with open(path, "w", newline="\n") as f:
+        f.write("<!DOCTYPE html>\n<html>\n  <body>\n{}  </body>\n</html>\n".format(body))
+
+
+def main():
+    shutil.rmtree(ROOT, ignore_errors=True)
+    os.makedirs(os.path.join(ROOT, "packages"))
+    links = {}
+    for name, version, dependencies in PACKAGES:
+        filename, digest = make_wheel(name, version, dependencies)
+        href = "../../packages/{}#sha256={}".format(filename, digest)
+        links.setdefault(name, []).append((href, filename))
+    for name, wheels in links.items():
+        write_page(os.path.join(ROOT, "simple", name, "index.html"), wheels)
+    projects = [("{}/".format(x), x) for x in links]
+    write_page(os.path.join(ROOT, "simple", "index.html"), projects)
+
+
+if __name__ == "__main__":
+    main()
@@ -53,13 +53,42 @@ impl TestApp {
     }
 
     pub fn run(&self, args: Vec<String>) -> Result<(), dmenv::Error> {
+        self.run_with_plugins(args, vec![])
+    }
+
+    pub fn run_with_plugins(
+        &self,
+        args: Vec<String>,
+        plugins: Vec<Box<dyn dmenv::Plugin>>,
+    ) -> Result<(), dmenv::Error> {
+        dmenv::run_with_plugins(self.command(args), plugins)
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn run_with_runner(
+        &self,
+        args: &[&str],
+        runner: Box<dyn dmenv::CommandRunner>,
+    ) -> Result<(), dmenv::Error> {
+        let args = to_string_args(args);
+        dmenv::run_with_runner(self.command(args), vec![], runner)
+    }
+
+    pub fn bump_in_lock_batch(
+        &self,
+        instructions: &[dmenv::BumpInstruction],
+    ) -> Result<Vec<dmenv::BumpResult>, dmenv::Error> {
+        let args = to_string_args(&["bump-in-lock", "--stdin-json"]);
+        dmenv::bump_in_lock_batch(&self.command(args), instructions)
+    }
+
+    fn command(&self, args: Vec<String>) -> dmenv::Command {
         let mut cmd = vec![];
         cmd.extend(vec!["dmenv".to_string()]);
         let tmp_path: String = self.path().to_string_lossy().into();
         cmd.extend(vec!["--project".to_string(), tmp_path]);
         cmd.extend(args);
-        let cmd = dmenv::Command::from_iter_safe(cmd).unwrap();
-        dmenv::run(cmd)
+        dmenv::Command::from_iter_safe(cmd).unwrap()
     }
 
     pub fn assert_run_ok(&self, args: &[&str]) {
@@ -91,15 +120,40 @@ impl TestApp {
         res.unwrap_err().to_string()
     }
 
+    pub fn assert_exit_code(&self, args: &[&str], expected: i32) {
+        let args = to_string_args(args);
+        let error = self.run(args).unwrap_err();
+        assert_eq!(error.exit_code(), expected, "{}", error);
+    }
+
     pub fn write_dev_lock(&self, contents: &str) {
         self.write_file(dmenv::DEV_LOCK_FILENAME, contents);
     }
 
     pub fn write_file(&self, name: &str, contents: &str) {
         let path = self.path().join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
         std::fs::write(path, &contents).unwrap();
     }
 
+    pub fn file_path(&self, name: &str) -> String {
+        self.path().join(name).to_string_lossy().into()
+    }
+
+    pub fn read_file(&self, name: &str) -> String {
+        let path = self.path().join(name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    /// Move a file to a sub-directory of the project, creating it if needed
+    pub fn move_file(&self, name: &str, dest_dir: &str) {
+        let dest_dir = self.path().join(dest_dir);
+        std::fs::create_dir_all(&dest_dir).unwrap();
+        std::fs::rename(self.path().join(name), dest_dir.join(name)).unwrap();
+    }
+
     pub fn remove_file(&self, name: &str) {
         let path = self.path().join(name);
         std::fs::remove_file(path).unwrap();
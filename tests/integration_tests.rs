@@ -7,6 +7,50 @@ fn show_venv_path() {
     test_app.assert_run_ok(&["show:venv_path"]);
 }
 
+#[test]
+fn info() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["info"]);
+    test_app.assert_run_ok(&["info", "--paths"]);
+}
+
+#[test]
+fn env_output() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["env", "--output", "env.list"]);
+    test_app.assert_file("env.list");
+    test_app.assert_run_ok(&["env", "--format", "shell"]);
+}
+
+#[test]
+fn cache_key() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["cache", "key"]);
+    test_app.remove_dev_lock();
+    test_app.assert_run_error(&["cache", "key"]);
+}
+
+#[test]
+fn ci_github() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["ci", "github"]);
+    test_app.assert_run_ok(&["ci", "github", "--set-output"]);
+    test_app.remove_dev_lock();
+    test_app.assert_run_error(&["ci", "github"]);
+}
+
+#[test]
+fn env_create() {
+    let test_app = TestApp::new();
+    #[rustfmt::skip]
+    test_app.assert_run_ok(&[
+        "env", "create",
+        "--path", ".tox/py3",
+        "--from-lock", dmenv::DEV_LOCK_FILENAME,
+    ]);
+    test_app.assert_file(".tox/py3/dmenv-state");
+}
+
 #[test]
 fn init_generates_setup_py() {
     let test_app = TestApp::new();
@@ -24,6 +68,121 @@ fn init_generates_setup_py() {
     assert!(written.contains("jane@corp.com"));
 }
 
+#[test]
+fn precommit_sync() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("black==19.3b0\n");
+    let config = "repos:\n-   repo: https://github.com/psf/black\n    rev: 19.1b0\n";
+    test_app.write_file(".pre-commit-config.yaml", config);
+
+    let error = test_app.assert_run_error(&["precommit", "sync", "--check"]);
+    assert!(error.contains("not in sync"));
+    test_app.assert_run_ok(&["precommit", "sync"]);
+
+    let written = test_app.read_file(".pre-commit-config.yaml");
+    assert!(written.contains("rev: 19.3b0"));
+    test_app.assert_run_ok(&["precommit", "sync", "--check"]);
+}
+
+#[test]
+fn init_infers_values() {
+    let test_app = TestApp::new();
+    test_app.remove_setup_py();
+    test_app.assert_run_ok(&["init", "--yes"]);
+    test_app.assert_setup_py();
+}
+
+#[test]
+fn init_existing() {
+    let test_app = TestApp::new();
+    test_app.remove_dev_lock();
+    let setup_py = test_app.read_setup_py();
+    let start = setup_py.find("    extras_require").unwrap();
+    let end = setup_py.find("    classifiers").unwrap();
+    let without_extras = format!("{}{}", &setup_py[..start], &setup_py[end..]);
+    test_app.write_file("setup.py", &without_extras);
+
+    test_app.assert_run_ok(&["init", "--existing", "--yes"]);
+
+    let written = test_app.read_setup_py();
+    assert!(written.contains("\"dev\": []"));
+    test_app.assert_file(dmenv::DEV_LOCK_FILENAME);
+}
+
+#[test]
+fn develop_uninstall() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+
+    test_app.assert_run_ok(&["develop", "--uninstall"]);
+    // The manifest is removed along with the editable install
+    test_app.assert_run_error(&["develop", "--uninstall"]);
+}
+
+#[test]
+fn scripts_regen() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+    let setup_py = test_app.read_setup_py();
+    let new_setup_py = setup_py.replace("[\"demo = demo:main\"]", "[\"demo2 = demo:main\"]");
+    test_app.write_file("setup.py", &new_setup_py);
+
+    test_app.assert_run_ok(&["scripts", "regen"]);
+    test_app.assert_run_ok(&["run", "--no-exec", "demo2"]);
+    test_app.assert_run_error(&["run", "--no-exec", "demo"]);
+}
+
+#[test]
+fn rename() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+
+    test_app.assert_run_ok(&["rename", "my-demo"]);
+
+    let written = test_app.read_setup_py();
+    assert!(written.contains("name=\"my-demo\""));
+    assert!(written.contains("py_modules=[\"my_demo\"]"));
+    assert!(written.contains("\"demo = my_demo:main\""));
+    test_app.assert_file("my_demo.py");
+}
+
+#[test]
+fn version_bump() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+
+    test_app.assert_run_ok(&["version", "minor"]);
+
+    let written = test_app.read_setup_py();
+    assert!(written.contains("version=\"0.7.0\""));
+}
+
+#[test]
+fn package_dir() {
+    let test_app = TestApp::new();
+    test_app.move_file("setup.py", "python");
+    test_app.move_file("demo.py", "python");
+
+    test_app.assert_run_ok(&["--package-dir", "python", "install"]);
+    test_app.assert_run_ok(&["--package-dir", "python", "lock"]);
+    test_app.assert_file(dmenv::DEV_LOCK_FILENAME);
+    test_app.assert_run_ok(&["--package-dir", "python", "run", "--no-exec", "demo"]);
+}
+
+#[test]
+fn extra_package_dirs() {
+    let test_app = TestApp::new();
+    test_app.write_file(
+        "helper/setup.py",
+        "from setuptools import setup\n\nsetup(name=\"demo-helper\", version=\"0.1\")\n",
+    );
+
+    test_app.assert_run_ok(&["--extra-package-dir", "helper", "lock"]);
+
+    let lock = test_app.read_dev_lock();
+    assert!(!lock.contains("demo-helper"));
+}
+
 #[test]
 fn bump_in_lock_simple() {
     let test_app = TestApp::new();
@@ -36,6 +195,161 @@ fn bump_in_lock_simple() {
     assert_eq!(actual_contents, expected_contents);
 }
 
+#[test]
+fn bump_in_lock_with_marker() {
+    let test_app = TestApp::new();
+    let lock_contents =
+        "foo==0.42 ; python_version < '3.10'\nfoo==0.50 ; python_version >= '3.10'\n";
+    test_app.write_dev_lock(&lock_contents);
+
+    test_app.assert_run_error(&["bump-in-lock", "foo", "0.43"]);
+    test_app.assert_run_ok(&[
+        "bump-in-lock",
+        "foo",
+        "0.43",
+        "--marker",
+        "python_version < '3.10'",
+    ]);
+    let actual_contents = test_app.read_dev_lock();
+    let expected_contents = lock_contents.replace("0.42", "0.43");
+    assert_eq!(actual_contents, expected_contents);
+}
+
+#[test]
+fn pinned_dependencies_are_not_bumped() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("bar==1.3\nfoo==0.42\n");
+
+    test_app.assert_run_ok(&["pin", "foo", "--reason", "CVE-2021-1234"]);
+    let lock_contents = test_app.read_dev_lock();
+    assert!(lock_contents.contains("foo==0.42  # pin: CVE-2021-1234"));
+
+    test_app.assert_run_error(&["bump-in-lock", "foo", "0.43"]);
+    test_app.assert_run_ok(&["bump-in-lock", "foo", "0.43", "--override-pin"]);
+    let actual_contents = test_app.read_dev_lock();
+    assert_eq!(actual_contents, lock_contents.replace("0.42", "0.43"));
+}
+
+#[test]
+fn history() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("bar==1.3\n");
+    test_app.assert_run_ok(&["bump-in-lock", "bar", "1.4"]);
+    test_app.assert_run_error(&["bump-in-lock", "foo", "0.43"]);
+
+    let history = test_app.read_file(".dmenv/history");
+    let lines: Vec<_> = history.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("\tok"));
+    assert!(lines[1].contains("\tfailed: 'foo' not found in lock"));
+    test_app.assert_run_ok(&["history"]);
+}
+
+#[test]
+fn undo_bump_in_lock() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("bar==1.3\n");
+    test_app.assert_run_error(&["undo"]);
+
+    test_app.assert_run_ok(&["bump-in-lock", "bar", "1.4"]);
+    test_app.assert_run_ok(&["undo"]);
+    assert_eq!(test_app.read_dev_lock(), "bar==1.3\n");
+
+    test_app.assert_run_ok(&["bump-in-lock", "bar", "1.4"]);
+    test_app.write_dev_lock("bar==1.5\n");
+    test_app.assert_run_error(&["undo"]);
+}
+
+#[test]
+fn bump_in_lock_batch() {
+    let test_app = TestApp::new();
+    let lock_contents = "bar==1.3\nfoo==0.42\n";
+    test_app.write_dev_lock(lock_contents);
+    let bump = |name: &str, version: &str| dmenv::BumpInstruction {
+        name: name.to_string(),
+        version: version.to_string(),
+        git: false,
+        marker: None,
+        override_pin: false,
+    };
+
+    let results = test_app
+        .bump_in_lock_batch(&[bump("bar", "1.4"), bump("baz", "2.0")])
+        .unwrap();
+    assert_eq!(results[0].status, dmenv::BumpStatus::Aborted);
+    assert_eq!(test_app.read_dev_lock(), lock_contents);
+
+    let results = test_app
+        .bump_in_lock_batch(&[bump("bar", "1.4"), bump("foo", "0.42")])
+        .unwrap();
+    assert_eq!(results[0].status, dmenv::BumpStatus::Bumped);
+    assert_eq!(results[1].status, dmenv::BumpStatus::UpToDate);
+    assert_eq!(test_app.read_dev_lock(), "bar==1.4\nfoo==0.42\n");
+}
+
+#[test]
+fn required_dmenv_version() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("bar==1.3\n");
+    test_app.write_file(
+        "pyproject.toml",
+        "[tool.dmenv]\nrequired_version = \">=99.0\"\n",
+    );
+    let message = test_app.assert_run_error(&["bump-in-lock", "bar", "1.4"]);
+    assert!(message.contains("requires dmenv >=99.0"));
+
+    let current = env!("CARGO_PKG_VERSION");
+    let pyproject = format!("[tool.dmenv]\nrequired_version = \"=={}\"\n", current);
+    test_app.write_file("pyproject.toml", &pyproject);
+    test_app.assert_run_ok(&["bump-in-lock", "bar", "1.4"]);
+}
+
+#[test]
+fn lock_check() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("attrs==19.1.0\n");
+    test_app.assert_run_ok(&["lock", "--check"]);
+    assert_eq!(test_app.read_dev_lock(), "attrs==19.1.0\n");
+
+    test_app.write_file("dmenv.toml", "max_release_age = \"18\"\n");
+    let message = test_app.assert_run_error(&["lock", "--check"]);
+    assert!(message.contains("invalid age '18'"));
+}
+
+#[test]
+fn forbid_vcs_in_production() {
+    let test_app = TestApp::new();
+    let git_line = "git+https://github.com/acme/foo@0.42#egg=foo";
+    test_app.write_dev_lock(&format!("attrs==19.1.0\n{}\n", git_line));
+    test_app.write_file(
+        dmenv::PROD_LOCK_FILENAME,
+        &format!("attrs==19.1.0\n{}\n", git_line),
+    );
+    test_app.write_file("dmenv.toml", "forbid_vcs_in_production = true\n");
+    test_app.assert_run_ok(&["lock", "--check"]);
+    let message = test_app.assert_run_error(&["--production", "lock", "--check"]);
+    assert!(message.contains(git_line));
+}
+
+#[test]
+fn explain_lock_line() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("bar==1.3\nfoo\n");
+
+    test_app.assert_run_ok(&["explain-lock-line", "1"]);
+    test_app.assert_run_ok(&["explain-lock-line", "2"]);
+    test_app.assert_run_error(&["explain-lock-line", "3"]);
+}
+
+#[test]
+fn python_version_from_config() {
+    let test_app = TestApp::new();
+    test_app.write_file("dmenv.toml", "python = \"1.42\"\n");
+
+    let error = test_app.assert_run_error(&["show:venv_path"]);
+    assert!(error.contains("no Python 1.42 found"));
+}
+
 #[test]
 fn init_does_not_overwrite_existing_setup_py() {
     let test_app = TestApp::new();
@@ -50,6 +364,385 @@ fn lock_complains_if_setup_py_does_not_exist() {
     test_app.assert_run_error(&["lock"]);
 }
 
+#[test]
+#[cfg(feature = "testing")]
+fn lock_with_fake_runner() {
+    let test_app = TestApp::new();
+    let runner = dmenv::testing::FakeRunner::new().with_frozen(&["foo==0.42", "bar==1.3"]);
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "lock"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+
+    let lock_contents = test_app.read_dev_lock();
+    assert!(lock_contents.contains("foo==0.42\n"));
+    assert!(lock_contents.contains("bar==1.3\n"));
+    let calls = runner.calls();
+    assert_eq!(calls[0].program.to_string_lossy(), "fake-python");
+    assert!(calls
+        .iter()
+        .any(|x| x.args.contains(&"--editable".to_string())));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn lock_without_build_isolation() {
+    let test_app = TestApp::new();
+    let runner = dmenv::testing::FakeRunner::new().with_frozen(&["Cython==0.29.36", "foo==0.42"]);
+    let args = &[
+        "--python",
+        "fake-python",
+        "lock",
+        "--no-build-isolation",
+        "--build-requirement",
+        "cython<3",
+    ];
+    test_app
+        .run_with_runner(args, Box::new(runner.clone()))
+        .unwrap();
+
+    let lock_contents = test_app.read_dev_lock();
+    assert!(
+        lock_contents.contains("# Build isolation: off\n# Build requirements: Cython==0.29.36\n")
+    );
+    let no_isolation = |x: &dmenv::Process| x.args.contains(&"--no-build-isolation".to_string());
+    assert!(runner.calls().iter().any(no_isolation));
+
+    // The settings of the lock are used by install
+    let runner = dmenv::testing::FakeRunner::new();
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "install"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+    let calls = runner.calls();
+    let pinned = calls
+        .iter()
+        .find(|x| x.args.contains(&"Cython==0.29.36".to_string()))
+        .unwrap();
+    assert!(no_isolation(pinned));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn install_batches() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("# install: no-deps, order=-1\nlegacy==0.1\nfoo==0.42\n");
+    let runner = dmenv::testing::FakeRunner::new();
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "install"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+    let batches: Vec<_> = runner
+        .calls()
+        .into_iter()
+        .filter(|x| x.args.iter().any(|arg| arg.contains("dmenv-batch-")))
+        .collect();
+    assert_eq!(batches.len(), 2);
+    let no_deps = |x: &dmenv::Process| x.args.contains(&"--no-deps".to_string());
+    assert!(no_deps(&batches[0]));
+    assert!(!no_deps(&batches[1]));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn python_shortcut_forwards_version_and_help() {
+    let test_app = TestApp::new();
+    let runner = dmenv::testing::FakeRunner::new();
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "install"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+    for flag in &["--version", "--help", "-h"] {
+        let runner = dmenv::testing::FakeRunner::new();
+        test_app
+            .run_with_runner(
+                &["--python", "fake-python", "python", "--no-exec", flag],
+                Box::new(runner.clone()),
+            )
+            .unwrap();
+        let calls = runner.calls();
+        let last = calls.last().unwrap();
+        assert_eq!(last.program.file_stem().unwrap(), "python");
+        assert_eq!(last.args, vec![flag.to_string()]);
+    }
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn lock_extras() {
+    let test_app = TestApp::new();
+    let runner = dmenv::testing::FakeRunner::new().with_frozen(&["foo==0.42"]);
+    let args = &["--python", "fake-python", "lock", "--extra", "postgres"];
+    test_app
+        .run_with_runner(args, Box::new(runner.clone()))
+        .unwrap();
+    let extra_lock = test_app.read_file("requirements-postgres.lock");
+    assert!(extra_lock.contains("# Extra: postgres\n"));
+    let dry_run = |x: &dmenv::Process| x.args.contains(&"--dry-run".to_string());
+    let calls = runner.calls();
+    let resolved = calls.iter().find(|x| dry_run(x)).unwrap();
+    assert!(resolved.args.contains(&".[dev,postgres]".to_string()));
+
+    // Locks of extras must agree with the lock
+    test_app.write_file("requirements-postgres.lock", &(extra_lock + "foo==0.43\n"));
+    let args = &["--python", "fake-python", "install", "--extra", "postgres"];
+    let error = test_app
+        .run_with_runner(args, Box::new(dmenv::testing::FakeRunner::new()))
+        .unwrap_err();
+    assert!(error.to_string().contains("foo: 0.42 in requirements.lock"));
+
+    // Existing locks of extras are generated again
+    let runner = dmenv::testing::FakeRunner::new().with_frozen(&["foo==0.42"]);
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "lock"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+    assert!(runner.calls().iter().any(dry_run));
+    let extra_lock = test_app.read_file("requirements-postgres.lock");
+    assert!(!extra_lock.contains("foo==0.43"));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn lock_with_overrides() {
+    let test_app = TestApp::new();
+    let config = "[overrides]\nfoo = \"git+https://github.com/me/foo-fork@fix-42#egg=foo-fork\"\nbar = \"bar==1.2\"\n";
+    test_app.write_file("dmenv.toml", config);
+    let runner = dmenv::testing::FakeRunner::new().with_frozen(&["bar==1.2", "foo==0.42"]);
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "lock"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+
+    let lock_contents = test_app.read_dev_lock();
+    assert!(lock_contents.contains("bar==1.2  # override: bar==1.2\n"));
+    assert!(lock_contents.contains(
+        "git+https://github.com/me/foo-fork@fix-42#egg=foo-fork  # override: replaces foo\n"
+    ));
+    assert!(!lock_contents.contains("foo==0.42"));
+    let uninstall = |x: &dmenv::Process| {
+        x.args
+            .ends_with(&["uninstall".into(), "--yes".into(), "foo".into()])
+    };
+    assert!(runner.calls().iter().any(uninstall));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn lock_with_platform_dev_dependencies() {
+    let test_app = TestApp::new();
+    test_app.write_file(
+        "dmenv.toml",
+        "[platform_dev_dependencies]\npywin32 = \"win32\"\n",
+    );
+    let runner = dmenv::testing::FakeRunner::new().with_frozen(&["foo==0.42", "pywin32==306"]);
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "lock"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+
+    let requirement = "pywin32 ; sys_platform == 'win32'".to_string();
+    assert!(runner.calls().iter().any(|x| x.args.contains(&requirement)));
+    let lock_contents = test_app.read_dev_lock();
+    assert!(lock_contents.contains("pywin32==306 ; sys_platform == 'win32'\n"));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn install_with_event_stream() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("foo==0.42\n");
+    let events_path = test_app.file_path("events.json");
+    let runner = dmenv::testing::FakeRunner::new();
+    test_app
+        .run_with_runner(
+            &[
+                "--python",
+                "fake-python",
+                "--event-stream",
+                &events_path,
+                "install",
+            ],
+            Box::new(runner),
+        )
+        .unwrap();
+
+    let events = test_app.read_file("events.json");
+    let events: Vec<_> = events.lines().collect();
+    assert!(events
+        .contains(&r#"{"event": "phase_started", "phase": "Preparing project for development"}"#));
+    assert!(events.contains(&r#"{"event": "package_installed", "name": "foo", "version": "0.42"}"#));
+    assert!(events.last().unwrap().contains(r#""success": true"#));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn migrate() {
+    let test_app = TestApp::new();
+    test_app.write_dev_lock("foo==0.42\n");
+    let legacy_venv = test_app.file_path(".venv/3.7.1");
+    dmenv::testing::create_fake_venv(std::path::Path::new(&legacy_venv), "3.7.1").unwrap();
+    let runner = dmenv::testing::FakeRunner::new();
+    let check_args = &["--python", "fake-python", "migrate", "--check"];
+    test_app
+        .run_with_runner(check_args, Box::new(runner.clone()))
+        .unwrap_err();
+
+    test_app
+        .run_with_runner(
+            &["--python", "fake-python", "migrate"],
+            Box::new(runner.clone()),
+        )
+        .unwrap();
+    assert!(test_app.read_dev_lock().starts_with("# Lock format: 2\n"));
+    test_app.assert_file(".venv/dev/3.7.1/pyvenv.cfg");
+    test_app
+        .run_with_runner(check_args, Box::new(runner))
+        .unwrap();
+}
+
+/// Serve the tiny index of tests/fixtures, so that lock and install can run offline
+#[cfg(feature = "testing")]
+fn local_index() -> dmenv::testing::LocalIndex {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/index");
+    dmenv::testing::LocalIndex::serve(&root).unwrap()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn lock_from_local_index() {
+    let index = local_index();
+    let test_app = TestApp::new();
+    test_app.write_file("dmenv.toml", "project_type = \"requirements\"\n");
+    test_app.write_file("requirements.in", "dmenv-fixture-a\n");
+    test_app.assert_run_ok(&["--index-url", &index.url(), "lock"]);
+
+    let lock_contents = test_app.read_dev_lock();
+    assert!(lock_contents.contains("dmenv-fixture-a==1.0\n"));
+    assert!(lock_contents.contains("dmenv-fixture-b==1.1\n"));
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn install_from_local_index() {
+    let index = local_index();
+    for installer in &["pip", "native"] {
+        let test_app = TestApp::new();
+        test_app.write_dev_lock("dmenv-fixture-a==1.0\ndmenv-fixture-b==1.0\n");
+        #[rustfmt::skip]
+        test_app.assert_run_ok(&[
+            "--index-url", &index.url(),
+            "install", "--no-develop", "--installer", installer,
+        ]);
+        #[rustfmt::skip]
+        test_app.assert_run_ok(&[
+            "run", "--no-exec", "--", "python", "-c",
+            "import dmenv_fixture_b; assert dmenv_fixture_b.VERSION == '1.0'",
+        ]);
+        // Note: the native installer records itself as the installer, like pip does
+        let installer = if *installer == "native" { "dmenv" } else { "pip" };
+        let check = format!(
+            "from importlib import metadata; \
+             assert metadata.distribution('dmenv-fixture-b').read_text('INSTALLER').strip() == '{}'",
+            installer
+        );
+        test_app.assert_run_ok(&["run", "--no-exec", "--", "python", "-c", &check]);
+    }
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn simulate_ci() {
+    let index = local_index();
+    let test_app = TestApp::new();
+    test_app.write_file("dmenv.toml", "project_type = \"requirements\"\n");
+    test_app.write_file(dmenv::PROD_LOCK_FILENAME, "dmenv-fixture-a==1.0\n");
+    #[rustfmt::skip]
+    test_app.assert_run_ok(&[
+        "--index-url", &index.url(),
+        "simulate-ci", "--report", "report.json",
+    ]);
+    assert!(test_app
+        .read_file("report.json")
+        .contains("\"success\": true"));
+
+    test_app.write_file(dmenv::PROD_LOCK_FILENAME, "dmenv-fixture-a==4.2\n");
+    #[rustfmt::skip]
+    let error = test_app.assert_run_error(&[
+        "--index-url", &index.url(),
+        "simulate-ci", "--report", "report.json",
+    ]);
+    assert!(error.contains("failed at 'install dependencies'"));
+    assert!(test_app
+        .read_file("report.json")
+        .contains("\"success\": false"));
+}
+
+struct FailingPlugin {}
+
+impl dmenv::Plugin for FailingPlugin {
+    fn name(&self) -> &str {
+        "failing"
+    }
+
+    fn pre_lock(&self, _context: &dmenv::Context) -> Result<(), dmenv::Error> {
+        Err(dmenv::Error::Other {
+            message: "not today".to_string(),
+        })
+    }
+}
+
+#[test]
+fn plugin_can_abort_lock() {
+    let test_app = TestApp::new();
+    let lock_contents = test_app.read_dev_lock();
+    let args = helpers::to_string_args(&["lock"]);
+    let error = test_app
+        .run_with_plugins(args, vec![Box::new(FailingPlugin {})])
+        .unwrap_err();
+    assert!(error.to_string().contains("plugin failing: not today"));
+    assert_eq!(test_app.read_dev_lock(), lock_contents);
+}
+
+#[test]
+fn python_and_pip_shortcuts() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+    test_app.assert_run_ok(&["python", "--no-exec", "-c", "import demo"]);
+    test_app.assert_run_ok(&["pip", "--no-exec", "show", "demo"]);
+    let message = test_app.assert_run_error(&["pip", "--no-exec", "install", "attrs"]);
+    assert!(message.contains("would change the virtualenv"));
+}
+
+#[test]
+fn run_records_environment() {
+    let test_app = TestApp::new();
+    test_app.write_file("dmenv.toml", "[presets.dev.env]\nDEMO_MODE = \"debug\"\n");
+    test_app.assert_run_ok(&["install"]);
+    #[rustfmt::skip]
+    test_app.assert_run_ok(&[
+        "run", "--no-exec", "--explain", "--preset", "dev", "--", "python", "-c",
+        "import os; assert os.environ['DEMO_MODE'] == 'debug'",
+    ]);
+    let manifest = test_app.read_file(".dmenv/run-env");
+    assert!(manifest.contains("# command: "));
+    assert!(manifest.contains("\nDEMO_MODE=debug\n"));
+}
+
 #[test]
 fn lock_workflow() {
     let test_app = TestApp::new();
@@ -82,6 +775,40 @@ fn install_workflow_basic() {
     test_app.assert_run_ok(&["install"]);
     test_app.assert_run_ok(&["run", "--no-exec", "demo"]);
     test_app.assert_run_ok(&["run", "--no-exec", "pytest"]);
+    test_app.assert_run_ok(&["status"]);
+    test_app.assert_run_ok(&["service", "generate", "demo"]);
+    test_app.assert_run_error(&["service", "generate", "no-such-script"]);
+}
+
+#[test]
+fn snapshot_and_restore() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+    test_app.assert_run_ok(&["snapshot", "--output", "snapshot.tar.gz"]);
+    test_app.assert_file("snapshot.tar.gz");
+    test_app.assert_run_error(&["restore", "snapshot.tar.gz"]);
+
+    test_app.assert_run_ok(&["clean"]);
+    test_app.assert_run_ok(&["restore", "snapshot.tar.gz"]);
+    test_app.assert_run_ok(&["run", "--no-exec", "demo"]);
+    test_app.assert_run_ok(&["run", "--fail-if-stale", "--no-exec", "demo"]);
+}
+
+#[test]
+fn protect_venv() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+    let error = test_app.assert_run_error(&["run", "--no-exec", "pip", "install", "foo"]);
+    assert!(error.contains("--allow-venv-mutation"));
+
+    test_app.assert_run_ok(&["venv", "freeze-perms"]);
+    test_app.assert_run_error(&["install"]);
+    test_app.assert_run_ok(&["run", "--no-exec", "demo"]);
+    test_app.assert_run_ok(&["venv", "unfreeze-perms"]);
+    test_app.assert_run_ok(&["install"]);
+
+    test_app.assert_run_ok(&["venv", "freeze-perms"]);
+    test_app.assert_run_ok(&["clean"]);
 }
 
 #[test]
@@ -93,6 +820,19 @@ fn install_workflow_step_by_step() {
     test_app.assert_run_ok(&["run", "--no-exec", "pytest"]);
 }
 
+#[test]
+fn run_if_stale() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["install"]);
+    test_app.assert_run_ok(&["run", "--fail-if-stale", "--no-exec", "demo"]);
+
+    let lock_contents = test_app.read_dev_lock();
+    test_app.write_dev_lock(&format!("# edited\n{}", lock_contents));
+    test_app.assert_run_error(&["run", "--fail-if-stale", "--no-exec", "demo"]);
+    test_app.assert_run_ok(&["run", "--reinstall-if-stale", "--no-exec", "demo"]);
+    test_app.assert_run_ok(&["run", "--fail-if-stale", "--no-exec", "demo"]);
+}
+
 #[test]
 fn install_without_lock() {
     let test_app = TestApp::new();
@@ -100,6 +840,48 @@ fn install_without_lock() {
     test_app.assert_run_error(&["install"]);
 }
 
+#[test]
+fn ascii_output() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["--ascii", "explain-exit-codes"]);
+}
+
+#[test]
+fn exit_codes() {
+    let test_app = TestApp::new();
+    test_app.assert_exit_code(&["run"], dmenv::exit_code::USAGE);
+    test_app.assert_exit_code(&["run", "python"], dmenv::exit_code::MISSING_FILE);
+    test_app.remove_dev_lock();
+    test_app.assert_exit_code(&["install"], dmenv::exit_code::MISSING_FILE);
+}
+
+#[test]
+fn query_without_python() {
+    let test_app = TestApp::new();
+    // Note: queries never start Python, so this does not fail
+    test_app.assert_run_ok(&["--python", "no-such-python", "query", "packages"]);
+    test_app.assert_run_ok(&["--python", "no-such-python", "query", "scripts"]);
+}
+
+#[test]
+fn foreach() {
+    let test_app = TestApp::new();
+    let root = test_app.file_path(".");
+    test_app.assert_run_ok(&["foreach", "--root", &root, "--list"]);
+    test_app.assert_exit_code(&["foreach", "--root", &root], dmenv::exit_code::USAGE);
+
+    let report = test_app.file_path("report.json");
+    test_app.assert_run_ok(&["foreach", "--root", &root, "--report", &report]);
+    let report = test_app.read_file("report.json");
+    assert!(report.contains(r#""lock": "requirements.lock""#));
+}
+
+#[test]
+fn logs_without_quiet_run() {
+    let test_app = TestApp::new();
+    test_app.assert_run_error(&["logs"]);
+}
+
 #[test]
 fn run_without_args() {
     let test_app = TestApp::new();
@@ -111,3 +893,16 @@ fn run_without_virtualenv() {
     let test_app = TestApp::new();
     test_app.assert_run_error(&["run", "python"]);
 }
+
+#[test]
+fn status_without_virtualenv() {
+    let test_app = TestApp::new();
+    test_app.assert_run_error(&["status"]);
+}
+
+#[test]
+fn venv_dedupe_report() {
+    let test_app = TestApp::new();
+    test_app.assert_run_ok(&["venv", "dedupe", "--report"]);
+    test_app.assert_run_error(&["venv", "dedupe"]);
+}